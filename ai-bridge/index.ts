@@ -21,8 +21,10 @@
 import { createInterface } from "readline";
 import { AsyncLocalStorage } from "async_hooks";
 
-// Per-request workspace context — threaded through async call chains
-interface WorkspaceCtx { id?: string; path?: string; }
+// Per-request workspace context — threaded through async call chains.
+// executionId groups the ECP callbacks of one bridge request (one agent run)
+// so the Rust change journal can revert them together.
+interface WorkspaceCtx { id?: string; path?: string; executionId?: string; }
 const workspaceContext = new AsyncLocalStorage<WorkspaceCtx>();
 
 // Import existing TypeScript services from the parent project
@@ -62,6 +64,7 @@ interface BridgeCallbackRequest {
   method: string;
   params?: Record<string, unknown>;
   _workspaceId?: string;
+  _executionId?: string;
 }
 
 interface BridgeCallbackResponse {
@@ -99,6 +102,9 @@ export async function callECPMethod(
     if (ctx?.id) {
       request._workspaceId = ctx.id;
     }
+    if (ctx?.executionId) {
+      request._executionId = ctx.executionId;
+    }
     process.stdout.write(JSON.stringify(request) + "\n");
   });
 }
@@ -311,7 +317,9 @@ async function handleRequest(req: BridgeRequest): Promise<BridgeResponse> {
 
     // Run dispatch within workspace context so callbacks include _workspaceId
     // and adapter methods use the correct workspace path
-    const ctx: WorkspaceCtx = { id: wsId, path: wsPath };
+    const executionId =
+      (params.executionId as string | undefined) ?? `exec-${req.id}-${Date.now()}`;
+    const ctx: WorkspaceCtx = { id: wsId, path: wsPath, executionId };
     const result = await workspaceContext.run(ctx, () =>
       dispatch(req.method, params)
     );
@@ -13,19 +13,26 @@
 //! 3. **Callbacks** (Bridge→Rust→Bridge): `{ callbackId, method, params }` — Rust executes against
 //!    its own router and returns `{ callbackId, result/error }`. This is how the Agent SDK calls
 //!    ECP tools (file/read, git/status, etc.) during agentic execution.
+//!
+//! ## Restarts
+//!
+//! [`AIBridge::supervise`] watches the subprocess and relaunches it after an
+//! unexpected exit according to the current [`RestartPolicy`], which can be
+//! swapped at runtime (e.g. when `~/.ultra/server.toml` changes).
 
 use std::future::Future;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::process::Stdio;
-use std::sync::{Arc, OnceLock};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
 
 use ecp_protocol::{ECPError, ECPNotification, HandlerResult, RequestContext};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, Command};
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot, Notify};
 use tracing::{debug, info, warn};
 
 /// Configuration for the AI bridge subprocess.
@@ -52,6 +59,27 @@ impl Default for AIBridgeConfig {
     }
 }
 
+/// What to do when the bridge subprocess exits unexpectedly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Relaunch the subprocess after a crash
+    pub on_failure: bool,
+    /// Give up after this many restarts (over the server's lifetime)
+    pub max_restarts: u32,
+    /// Delay before each relaunch
+    pub delay_ms: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            on_failure: true,
+            max_restarts: 5,
+            delay_ms: 1_000,
+        }
+    }
+}
+
 /// Callback handler type — executes a method against the ECP server's router.
 pub type CallbackHandler = Arc<
     dyn Fn(&str, Option<Value>, RequestContext) -> Pin<Box<dyn Future<Output = HandlerResult> + Send>>
@@ -84,6 +112,10 @@ struct BridgeCallback {
     params: Option<Value>,
     #[serde(rename = "_workspaceId", default)]
     workspace_id: Option<String>,
+    /// Agent run that issued the callback — forwarded to services as
+    /// `_executionId` so file mutations can be journaled per execution.
+    #[serde(rename = "_executionId", default)]
+    execution_id: Option<String>,
 }
 
 /// Callback response sent back to the bridge.
@@ -113,13 +145,19 @@ struct BridgeError {
 /// The AI Bridge — manages communication with the TypeScript subprocess.
 pub struct AIBridge {
     /// Channel to send requests to the subprocess writer task
-    request_tx: Option<mpsc::Sender<WriterMessage>>,
-    /// Child process handle
-    child: Option<Child>,
+    request_tx: Mutex<Option<mpsc::Sender<WriterMessage>>>,
+    /// Kills the child process (held by the exit monitor task)
+    kill_tx: Mutex<Option<oneshot::Sender<()>>>,
+    /// Config the subprocess was started with (reused for restarts)
+    config: Mutex<Option<AIBridgeConfig>>,
+    /// Restart policy applied by [`AIBridge::supervise`]
+    restart_policy: Mutex<RestartPolicy>,
+    /// Signaled when the subprocess exits without being asked to
+    exited: Arc<Notify>,
     /// Next request ID
     next_id: std::sync::atomic::AtomicU64,
     /// Whether the bridge is running
-    running: std::sync::atomic::AtomicBool,
+    running: Arc<std::sync::atomic::AtomicBool>,
     /// Notification broadcast sender — bridge notifications go here
     notification_tx: Option<broadcast::Sender<String>>,
     /// Callback handler — executes methods against the ECP router.
@@ -136,10 +174,13 @@ enum WriterMessage {
 impl AIBridge {
     pub fn new() -> Self {
         Self {
-            request_tx: None,
-            child: None,
+            request_tx: Mutex::new(None),
+            kill_tx: Mutex::new(None),
+            config: Mutex::new(None),
+            restart_policy: Mutex::new(RestartPolicy::default()),
+            exited: Arc::new(Notify::new()),
             next_id: std::sync::atomic::AtomicU64::new(1),
-            running: std::sync::atomic::AtomicBool::new(false),
+            running: Arc::new(std::sync::atomic::AtomicBool::new(false)),
             notification_tx: None,
             callback_handler: Arc::new(OnceLock::new()),
         }
@@ -156,8 +197,63 @@ impl AIBridge {
         let _ = self.callback_handler.set(handler);
     }
 
+    /// Replace the restart policy. Takes effect at the next unexpected exit.
+    pub fn set_restart_policy(&self, policy: RestartPolicy) {
+        *self.restart_policy.lock().unwrap() = policy;
+    }
+
+    pub fn restart_policy(&self) -> RestartPolicy {
+        *self.restart_policy.lock().unwrap()
+    }
+
     /// Start the bridge subprocess (compiled binary or TypeScript via runtime).
     pub async fn start(&mut self, config: AIBridgeConfig) -> Result<(), Box<dyn std::error::Error>> {
+        *self.config.lock().unwrap() = Some(config.clone());
+        self.launch(&config).await.map_err(Into::into)
+    }
+
+    /// Relaunch the subprocess after unexpected exits, following the current
+    /// [`RestartPolicy`]. Holds only a weak reference to the bridge.
+    pub fn supervise(self: &Arc<Self>) {
+        let bridge = Arc::downgrade(self);
+        let exited = self.exited.clone();
+        tokio::spawn(async move {
+            let mut restarts = 0u32;
+            loop {
+                exited.notified().await;
+                // A failed relaunch leaves a stale signal behind once a later one succeeds
+                if Weak::upgrade(&bridge).is_some_and(|b| b.is_running()) {
+                    continue;
+                }
+                // Keep trying while launches fail outright (no process to exit)
+                while let Some(this) = Weak::upgrade(&bridge) {
+                    let policy = this.restart_policy();
+                    if !policy.on_failure {
+                        warn!("AI bridge exited; restart policy is \"never\"");
+                        break;
+                    }
+                    if restarts >= policy.max_restarts {
+                        warn!("AI bridge exited; giving up after {restarts} restarts");
+                        break;
+                    }
+                    restarts += 1;
+                    tokio::time::sleep(Duration::from_millis(policy.delay_ms)).await;
+
+                    let Some(config) = this.config.lock().unwrap().clone() else { break };
+                    info!("Restarting AI bridge (attempt {restarts}/{})", policy.max_restarts);
+                    match this.launch(&config).await {
+                        Ok(()) => break,
+                        Err(e) => warn!("AI bridge restart failed: {e}"),
+                    }
+                }
+                if bridge.strong_count() == 0 {
+                    return;
+                }
+            }
+        });
+    }
+
+    async fn launch(&self, config: &AIBridgeConfig) -> Result<(), String> {
         let mut child = if let Some(ref bin) = config.compiled_binary {
             info!("Starting AI bridge (compiled): {}", bin.display());
             Command::new(bin)
@@ -246,12 +342,10 @@ impl AIBridge {
                 };
 
                 // Check for ready signal
-                if let Some(method) = parsed.get("method").and_then(|m| m.as_str()) {
-                    if method == "ai/bridge/ready" {
-                        if let Some(tx) = ready_tx.lock().unwrap().take() {
-                            let _ = tx.send(());
-                        }
-                    }
+                if let Some(method) = parsed.get("method").and_then(|m| m.as_str())
+                    && method == "ai/bridge/ready"
+                    && let Some(tx) = ready_tx.lock().unwrap().take() {
+                    let _ = tx.send(());
                 }
 
                 if parsed.get("callbackId").is_some() {
@@ -265,8 +359,18 @@ impl AIBridge {
                                     client_id: "ai-bridge".into(),
                                     workspace_id: cb.workspace_id.clone(),
                                 };
+                                let params = match (cb.params, cb.execution_id) {
+                                    (Some(Value::Object(mut map)), Some(exec_id)) => {
+                                        map.entry("_executionId").or_insert(Value::String(exec_id));
+                                        Some(Value::Object(map))
+                                    }
+                                    (None, Some(exec_id)) => {
+                                        Some(serde_json::json!({ "_executionId": exec_id }))
+                                    }
+                                    (params, _) => params,
+                                };
                                 let resp = if let Some(handler) = handler.get() {
-                                    match handler(&cb.method, cb.params, context).await {
+                                    match handler(&cb.method, params, context).await {
                                         Ok(result) => BridgeCallbackResponse {
                                             callback_id: cb.callback_id,
                                             result: Some(result),
@@ -358,8 +462,8 @@ impl AIBridge {
             }
         });
 
-        self.request_tx = Some(writer_tx);
-        self.child = Some(child);
+        *self.request_tx.lock().unwrap() = Some(writer_tx);
+        self.spawn_exit_monitor(child);
 
         // Wait for the bridge to signal readiness (ai/bridge/ready notification)
         match tokio::time::timeout(std::time::Duration::from_secs(10), ready_rx).await {
@@ -372,7 +476,7 @@ impl AIBridge {
             Ok(Err(_)) => {
                 // Channel dropped — bridge process exited before sending ready
                 warn!("AI bridge process exited before signaling ready");
-                self.request_tx = None;
+                *self.request_tx.lock().unwrap() = None;
                 Err("AI bridge process exited before signaling ready".into())
             }
             Err(_) => {
@@ -386,11 +490,35 @@ impl AIBridge {
         }
     }
 
+    /// Own the child process: signal `exited` if it dies on its own, or kill
+    /// it when `shutdown()` asks.
+    fn spawn_exit_monitor(&self, mut child: Child) {
+        let (kill_tx, kill_rx) = oneshot::channel::<()>();
+        *self.kill_tx.lock().unwrap() = Some(kill_tx);
+        let exited = self.exited.clone();
+        let running = self.running.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                status = child.wait() => {
+                    warn!("AI bridge process exited: {status:?}");
+                    running.store(false, std::sync::atomic::Ordering::Relaxed);
+                    exited.notify_one();
+                }
+                _ = kill_rx => {
+                    let _ = child.kill().await;
+                    info!("AI bridge subprocess terminated");
+                }
+            }
+        });
+    }
+
     /// Send a request to the AI bridge and wait for a response.
     pub async fn request(&self, method: &str, params: Option<Value>) -> HandlerResult {
         let tx = self
             .request_tx
-            .as_ref()
+            .lock()
+            .unwrap()
+            .clone()
             .ok_or_else(|| ECPError::server_error("AI bridge not started"))?;
 
         let id = self
@@ -419,14 +547,14 @@ impl AIBridge {
     }
 
     /// Shutdown the bridge subprocess.
-    pub async fn shutdown(&mut self) {
+    pub async fn shutdown(&self) {
         self.running
             .store(false, std::sync::atomic::Ordering::Relaxed);
-        self.request_tx = None;
+        *self.request_tx.lock().unwrap() = None;
+        *self.config.lock().unwrap() = None;
 
-        if let Some(mut child) = self.child.take() {
-            let _ = child.kill().await;
-            info!("AI bridge subprocess terminated");
+        if let Some(kill_tx) = self.kill_tx.lock().unwrap().take() {
+            let _ = kill_tx.send(());
         }
     }
 }
@@ -114,6 +114,47 @@ impl ECPError {
         Self::new(ECPErrorCode::Custom(-32021), format!("Workspace not found: {id}"))
     }
 
+    /// The target changed underneath the request (e.g. a journal revert
+    /// would overwrite newer edits). `data` describes the conflicting state.
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(ECPErrorCode::Custom(-32030), message)
+    }
+
+    /// A per-workspace or per-client resource quota would be exceeded.
+    /// `scope` is `"workspace"` or `"client"`.
+    pub fn quota_exceeded(resource: &str, scope: &str, limit: usize, used: usize) -> Self {
+        Self::new(
+            ECPErrorCode::Custom(-32031),
+            format!("Quota exceeded: {resource} ({used}/{limit} per {scope})"),
+        )
+        .with_data(serde_json::json!({
+            "resource": resource,
+            "scope": scope,
+            "limit": limit,
+            "used": used,
+        }))
+    }
+
+    /// A path resolves outside the workspace sandbox. `resolved` is the
+    /// path after following symlinks.
+    pub fn access_denied(path: &str, resolved: &str) -> Self {
+        Self::new(
+            ECPErrorCode::Custom(-32032),
+            format!("Access denied: {path} is outside the workspace sandbox"),
+        )
+        .with_data(serde_json::json!({
+            "path": path,
+            "resolved": resolved,
+        }))
+    }
+
+    /// An edit could not be applied: its anchor text is missing or
+    /// ambiguous, a range is out of bounds, or a patch hunk doesn't match.
+    /// `data` identifies the failing edit.
+    pub fn edit_failed(message: impl Into<String>) -> Self {
+        Self::new(ECPErrorCode::Custom(-32033), message)
+    }
+
     pub fn error_code(&self) -> ECPErrorCode {
         ECPErrorCode::from_code(self.code)
     }
@@ -70,6 +70,25 @@ pub struct ECPNotification {
     pub params: Option<serde_json::Value>,
 }
 
+/// A serialized notification on a workspace channel, either for every client
+/// in the workspace or only for some.
+#[derive(Debug, Clone)]
+pub struct AddressedNotification {
+    pub json: String,
+    /// `None` delivers to every client
+    pub clients: Option<std::sync::Arc<[String]>>,
+}
+
+impl AddressedNotification {
+    pub fn broadcast(json: String) -> Self {
+        Self { json, clients: None }
+    }
+
+    pub fn is_for(&self, client_id: &str) -> bool {
+        self.clients.as_ref().is_none_or(|clients| clients.iter().any(|c| c == client_id))
+    }
+}
+
 /// Result from a service adapter handler.
 pub type HandlerResult = Result<serde_json::Value, ECPError>;
 
@@ -14,7 +14,7 @@ pub mod context;
 pub use error::{ECPError, ECPErrorCode};
 pub use jsonrpc::{
     ECPRequest, ECPResponse, ECPSuccessResponse, ECPErrorResponse,
-    ECPNotification, ECPCaller, HandlerResult,
+    ECPNotification, ECPCaller, HandlerResult, AddressedNotification,
 };
 pub use methods::{Methods, MethodName};
 pub use notifications::{Notifications, NotificationName};
@@ -52,10 +52,19 @@ impl Methods {
     pub const FILE_CREATE_DIR: &str = "file/createDir";
     pub const FILE_DELETE_DIR: &str = "file/deleteDir";
     pub const FILE_BROWSE_DIR: &str = "file/browseDir";
+    pub const FILE_TREE: &str = "file/tree";
     pub const FILE_SEARCH: &str = "file/search";
     pub const FILE_GLOB: &str = "file/glob";
     pub const FILE_GREP: &str = "file/grep";
     pub const FILE_EDIT: &str = "file/edit";
+    pub const FILE_REPLACE_ALL: &str = "file/replaceAll";
+    pub const FILE_HISTORY_LIST: &str = "file/history/list";
+    pub const FILE_HISTORY_GET: &str = "file/history/get";
+    pub const FILE_HISTORY_DIFF: &str = "file/history/diff";
+    pub const FILE_HISTORY_RESTORE: &str = "file/history/restore";
+    pub const FILE_TRASH_LIST: &str = "file/trash/list";
+    pub const FILE_TRASH_RESTORE: &str = "file/trash/restore";
+    pub const FILE_TRASH_EMPTY: &str = "file/trash/empty";
     pub const FILE_WATCH: &str = "file/watch";
     pub const FILE_UNWATCH: &str = "file/unwatch";
     pub const FILE_PATH_TO_URI: &str = "file/pathToUri";
@@ -64,6 +73,11 @@ impl Methods {
     pub const FILE_GET_BASENAME: &str = "file/getBasename";
     pub const FILE_JOIN: &str = "file/join";
 
+    // ── Journal ─────────────────────────────────────────────────────────
+    pub const JOURNAL_LIST: &str = "journal/list";
+    pub const JOURNAL_DIFF: &str = "journal/diff";
+    pub const JOURNAL_REVERT: &str = "journal/revert";
+
     // ── Git ─────────────────────────────────────────────────────────────
     pub const GIT_IS_REPO: &str = "git/isRepo";
     pub const GIT_GET_ROOT: &str = "git/getRoot";
@@ -122,6 +136,8 @@ impl Methods {
     // ── Terminal ────────────────────────────────────────────────────────
     pub const TERMINAL_CREATE: &str = "terminal/create";
     pub const TERMINAL_ATTACH_TMUX: &str = "terminal/attachTmux";
+    pub const TERMINAL_ATTACH: &str = "terminal/attach";
+    pub const TERMINAL_DETACH: &str = "terminal/detach";
     pub const TERMINAL_CLOSE: &str = "terminal/close";
     pub const TERMINAL_CLOSE_ALL: &str = "terminal/closeAll";
     pub const TERMINAL_WRITE: &str = "terminal/write";
@@ -133,7 +149,9 @@ impl Methods {
     pub const TERMINAL_LIST: &str = "terminal/list";
     pub const TERMINAL_EXISTS: &str = "terminal/exists";
     pub const TERMINAL_IS_RUNNING: &str = "terminal/isRunning";
+    pub const TERMINAL_COMMANDS: &str = "terminal/commands";
     pub const TERMINAL_EXECUTE: &str = "terminal/execute";
+    pub const TERMINAL_EXECUTE_KILL: &str = "terminal/execute/kill";
     pub const TERMINAL_SPAWN: &str = "terminal/spawn";
 
     // ── LSP ─────────────────────────────────────────────────────────────
@@ -252,6 +270,16 @@ impl Methods {
     pub const MODELS_LIST: &str = "models/list";
     pub const MODELS_REFRESH: &str = "models/refresh";
 
+    // ── Workspace ───────────────────────────────────────────────────────
+    pub const WORKSPACE_OPEN: &str = "workspace/open";
+    pub const WORKSPACE_CLOSE: &str = "workspace/close";
+    pub const WORKSPACE_LIST: &str = "workspace/list";
+    pub const WORKSPACE_RESOURCES: &str = "workspace/resources";
+
+    // ── Server ──────────────────────────────────────────────────────────
+    pub const SERVER_CLIENTS: &str = "server/clients";
+    pub const SERVER_SHUTDOWN: &str = "server/shutdown";
+
     // ── Layout ──────────────────────────────────────────────────────────
     pub const LAYOUT_ADD_TAB: &str = "layout/addTab";
     pub const LAYOUT_SPLIT_TILE: &str = "layout/splitTile";
@@ -269,16 +297,16 @@ impl Methods {
 /// Returns true if the given string is a known ECP method.
 pub fn is_known_method(method: &str) -> bool {
     // Route by namespace prefix for O(1) dispatch
-    match method.split('/').next() {
-        Some("document") | Some("file") | Some("git") | Some("config") |
+    matches!(
+        method.split('/').next(),
+        Some("document") | Some("file") | Some("journal") | Some("git") | Some("config") |
         Some("session") | Some("keybindings") | Some("commands") |
         Some("theme") | Some("workspace") | Some("systemPrompt") |
         Some("terminal") | Some("lsp") | Some("syntax") | Some("secret") |
         Some("database") | Some("ai") | Some("chat") | Some("workflow") |
         Some("agent") | Some("auth") | Some("models") | Some("layout") |
-        Some("shell") => true,
-        _ => false,
-    }
+        Some("shell") | Some("server")
+    )
 }
 
 /// Type alias — the method name is always a `&str` at the protocol level.
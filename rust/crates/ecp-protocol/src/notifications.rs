@@ -12,16 +12,24 @@ impl Notifications {
 
     // ── Server lifecycle ────────────────────────────────────────────────
     pub const SERVER_CONNECTED: &str = "server/connected";
+    pub const SERVER_CONFIG_CHANGED: &str = "server/configChanged";
 
     // ── File system ─────────────────────────────────────────────────────
     pub const FILE_DID_CHANGE: &str = "file/didChange";
     pub const FILE_DID_CREATE: &str = "file/didCreate";
     pub const FILE_DID_DELETE: &str = "file/didDelete";
+    pub const FILE_DID_RENAME: &str = "file/didRename";
+    pub const FILE_DID_CHANGE_BATCH: &str = "file/didChangeBatch";
+    pub const FILE_GREP_RESULT: &str = "file/grep/result";
+    pub const FILE_READ_CHUNK: &str = "file/read/chunk";
 
     // ── Terminal ────────────────────────────────────────────────────────
     pub const TERMINAL_OUTPUT: &str = "terminal/output";
     pub const TERMINAL_EXIT: &str = "terminal/exit";
     pub const TERMINAL_TITLE: &str = "terminal/title";
+    pub const TERMINAL_COMMAND_FINISHED: &str = "terminal/commandFinished";
+    pub const TERMINAL_EXECUTE_OUTPUT: &str = "terminal/execute/output";
+    pub const TERMINAL_EXECUTE_EXIT: &str = "terminal/execute/exit";
 
     // ── Document ────────────────────────────────────────────────────────
     pub const DOCUMENT_DID_CHANGE: &str = "document/didChange";
@@ -237,6 +237,20 @@ mod tests {
 
         let e = ECPError::server_error("disk full");
         assert_eq!(e.code, -32000);
+
+        let e = ECPError::conflict("file changed");
+        assert_eq!(e.code, -32030);
+
+        let e = ECPError::quota_exceeded("terminals", "client", 4, 4);
+        assert_eq!(e.code, -32031);
+        assert_eq!(e.data.as_ref().unwrap()["scope"], "client");
+
+        let e = ECPError::access_denied("../secret", "/home/secret");
+        assert_eq!(e.code, -32032);
+        assert_eq!(e.data.as_ref().unwrap()["resolved"], "/home/secret");
+
+        let e = ECPError::edit_failed("oldString not found");
+        assert_eq!(e.code, -32033);
     }
 
     #[test]
@@ -323,6 +337,9 @@ mod tests {
         assert!(is_known_method("secret/get"));
         assert!(is_known_method("lsp/start"));
         assert!(is_known_method("ai/message/send"));
+        assert!(is_known_method("journal/revert"));
+        assert!(is_known_method("server/clients"));
+        assert!(is_known_method("server/shutdown"));
     }
 
     #[test]
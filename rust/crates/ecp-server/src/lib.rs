@@ -13,7 +13,11 @@ pub mod router;
 pub mod middleware;
 pub mod workspace;
 pub mod registry;
+pub mod quota;
+pub mod sandbox;
 
 pub use router::ECPServer;
 pub use workspace::WorkspaceContext;
 pub use registry::WorkspaceRegistry;
+pub use quota::{Quotas, QuotaLimits, SharedQuotas};
+pub use sandbox::{SandboxConfig, SharedSandbox};
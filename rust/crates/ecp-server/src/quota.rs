@@ -0,0 +1,354 @@
+//! Resource quotas — per-workspace and per-client limits on long-lived
+//! resources (terminals, language servers, database connections, watches)
+//! and on concurrent `terminal/execute` calls.
+//!
+//! Accounting happens in the router: each request is [`classify`]-ed, a slot
+//! is reserved against both limits before the request reaches the workspace,
+//! and the slot is kept under the requesting client's name once the request
+//! succeeds. Terminals, background executions, language servers and watches
+//! can go away on their own, so their usage is first brought in line with
+//! what the workspace's services still hold.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ecp_protocol::ECPError;
+use ecp_services::{HeldResource, ResourceKind};
+use parking_lot::{Mutex, RwLock};
+use serde_json::{json, Map, Value};
+
+/// A kind of quota-limited resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Terminals,
+    ConcurrentExecutes,
+    LspClients,
+    DbConnections,
+    Watches,
+}
+
+impl Resource {
+    pub const ALL: [Resource; 5] = [
+        Resource::Terminals,
+        Resource::ConcurrentExecutes,
+        Resource::LspClients,
+        Resource::DbConnections,
+        Resource::Watches,
+    ];
+
+    /// Resources whose services report what they hold.
+    pub(crate) const LIVE: [Resource; 4] = [
+        Resource::Terminals,
+        Resource::ConcurrentExecutes,
+        Resource::LspClients,
+        Resource::Watches,
+    ];
+
+    pub(crate) fn kind(self) -> Option<ResourceKind> {
+        match self {
+            Resource::Terminals => Some(ResourceKind::Terminal),
+            Resource::ConcurrentExecutes => Some(ResourceKind::Execution),
+            Resource::LspClients => Some(ResourceKind::LspClient),
+            Resource::Watches => Some(ResourceKind::Watch),
+            Resource::DbConnections => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Resource::Terminals => "terminals",
+            Resource::ConcurrentExecutes => "concurrentExecutes",
+            Resource::LspClients => "lspClients",
+            Resource::DbConnections => "dbConnections",
+            Resource::Watches => "watches",
+        }
+    }
+}
+
+/// Limits for one scope. 0 means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaLimits {
+    pub terminals: usize,
+    pub concurrent_executes: usize,
+    pub lsp_clients: usize,
+    pub db_connections: usize,
+    pub watches: usize,
+}
+
+impl QuotaLimits {
+    pub fn get(&self, resource: Resource) -> usize {
+        match resource {
+            Resource::Terminals => self.terminals,
+            Resource::ConcurrentExecutes => self.concurrent_executes,
+            Resource::LspClients => self.lsp_clients,
+            Resource::DbConnections => self.db_connections,
+            Resource::Watches => self.watches,
+        }
+    }
+}
+
+/// Limits applied to every workspace and to every client within it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quotas {
+    pub workspace: QuotaLimits,
+    pub client: QuotaLimits,
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Self {
+            workspace: QuotaLimits {
+                terminals: 32,
+                concurrent_executes: 8,
+                lsp_clients: 8,
+                db_connections: 16,
+                watches: 256,
+            },
+            client: QuotaLimits {
+                terminals: 16,
+                concurrent_executes: 4,
+                lsp_clients: 8,
+                db_connections: 8,
+                watches: 128,
+            },
+        }
+    }
+}
+
+/// Quotas shared by the server and whoever reloads configuration.
+pub type SharedQuotas = Arc<RwLock<Quotas>>;
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Request classification
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// How a request affects resource usage.
+pub(crate) enum Claim {
+    /// Creates a resource. The key is known up front (language ID, connection
+    /// ID) or read from `result_key` in the response.
+    Acquire {
+        resource: Resource,
+        key: Option<String>,
+        result_key: Option<&'static str>,
+    },
+    /// Uses a slot only while the request runs
+    Hold(Resource),
+    Release { resource: Resource, key: String },
+    ReleaseAll(Resource),
+}
+
+pub(crate) fn classify(method: &str, params: Option<&Value>) -> Option<Claim> {
+    let param = |names: &[&str]| {
+        names.iter()
+            .find_map(|n| params.and_then(|p| p.get(*n)).and_then(Value::as_str))
+            .map(String::from)
+    };
+
+    match method {
+        "terminal/create" | "terminal/spawn" | "terminal/attachTmux" => Some(Claim::Acquire {
+            resource: Resource::Terminals,
+            key: None,
+            result_key: Some("terminalId"),
+        }),
+        "terminal/close" => Some(Claim::Release {
+            resource: Resource::Terminals,
+            key: param(&["id", "terminalId"])?,
+        }),
+        "terminal/closeAll" => Some(Claim::ReleaseAll(Resource::Terminals)),
+        // In the background, the command keeps its slot until it ends
+        "terminal/execute" if params.and_then(|p| p.get("async")).and_then(Value::as_bool) == Some(true) => {
+            Some(Claim::Acquire {
+                resource: Resource::ConcurrentExecutes,
+                key: None,
+                result_key: Some("executionId"),
+            })
+        }
+        "terminal/execute" => Some(Claim::Hold(Resource::ConcurrentExecutes)),
+
+        // Only these two start a language server
+        "lsp/start" => Some(Claim::Acquire {
+            resource: Resource::LspClients,
+            key: Some(param(&["languageId"])?),
+            result_key: None,
+        }),
+        "lsp/documentOpen" => {
+            let language = param(&["languageId"])
+                .or_else(|| param(&["uri"]).map(|uri| ecp_services::lsp::detect_language(&uri)))?;
+            Some(Claim::Acquire { resource: Resource::LspClients, key: Some(language), result_key: None })
+        }
+        "lsp/stop" => Some(Claim::Release {
+            resource: Resource::LspClients,
+            key: param(&["languageId"])?,
+        }),
+
+        "database/connect" => Some(Claim::Acquire {
+            resource: Resource::DbConnections,
+            key: Some(param(&["connectionId"])?),
+            result_key: None,
+        }),
+        "database/disconnect" | "database/deleteConnection" => Some(Claim::Release {
+            resource: Resource::DbConnections,
+            key: param(&["connectionId"])?,
+        }),
+
+        "watch/start" | "file/watch" => Some(Claim::Acquire {
+            resource: Resource::Watches,
+            key: None,
+            result_key: Some("watchId"),
+        }),
+        "watch/stop" | "file/unwatch" => Some(Claim::Release {
+            resource: Resource::Watches,
+            key: param(&["watchId"])?,
+        }),
+
+        _ => None,
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Usage tracking
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Prefix of slots reserved for requests still running.
+const PENDING: &str = "pending:";
+
+/// Live resources in one workspace, keyed by resource ID → owning client,
+/// where known.
+#[derive(Default)]
+pub struct ResourceUsage {
+    entries: Mutex<HashMap<Resource, HashMap<String, Option<String>>>>,
+}
+
+impl ResourceUsage {
+    /// Reserve a slot for `client`, failing if the workspace or client limit
+    /// is reached. Returns `None` when `key` is already held — the request
+    /// reuses an existing resource (e.g. a running language server).
+    pub(crate) fn reserve(
+        &self,
+        resource: Resource,
+        key: Option<&str>,
+        client_id: &str,
+        quotas: &Quotas,
+    ) -> Result<Option<Reservation<'_>>, ECPError> {
+        let mut entries = self.entries.lock();
+        let held = entries.entry(resource).or_default();
+        if let Some(key) = key
+            && (held.contains_key(key) || held.contains_key(&format!("{PENDING}{key}")))
+        {
+            return Ok(None);
+        }
+
+        let limit = quotas.workspace.get(resource);
+        if limit > 0 && held.len() >= limit {
+            return Err(ECPError::quota_exceeded(resource.as_str(), "workspace", limit, held.len()));
+        }
+        let limit = quotas.client.get(resource);
+        let used = held.values().filter(|owner| owner.as_deref() == Some(client_id)).count();
+        if limit > 0 && used >= limit {
+            return Err(ECPError::quota_exceeded(resource.as_str(), "client", limit, used));
+        }
+
+        let slot = format!("{PENDING}{}", key.map_or_else(|| uuid::Uuid::new_v4().to_string(), String::from));
+        held.insert(slot.clone(), Some(client_id.to_string()));
+        Ok(Some(Reservation { usage: self, resource, slot, key: key.map(String::from), keep: false }))
+    }
+
+    pub(crate) fn release(&self, resource: Resource, key: &str) {
+        if let Some(held) = self.entries.lock().get_mut(&resource) {
+            held.remove(key);
+        }
+    }
+
+    pub(crate) fn release_all(&self, resource: Resource) {
+        self.entries.lock().remove(&resource);
+    }
+
+    /// Forget everything `client_id` held, when it closes the workspace or
+    /// disconnects. Resources that carry on without it (detached terminals)
+    /// come back unowned at the next [`ResourceUsage::sync`].
+    pub(crate) fn release_client(&self, client_id: &str) {
+        for held in self.entries.lock().values_mut() {
+            held.retain(|_, owner| owner.as_deref() != Some(client_id));
+        }
+    }
+
+    /// Bring `resource` in line with what the services hold: ones that
+    /// exited, crashed or were dropped stop counting, and ones started
+    /// before this workspace instance (terminals left running while it was
+    /// closed) start. Slots of requests still running are kept.
+    pub(crate) fn sync(&self, resource: Resource, live: &[HeldResource]) {
+        let mut entries = self.entries.lock();
+        let held = entries.entry(resource).or_default();
+        held.retain(|key, _| key.starts_with(PENDING) || live.iter().any(|r| r.id == *key));
+        for r in live {
+            let owner = held.entry(r.id.clone()).or_default();
+            if r.owner.is_some() {
+                owner.clone_from(&r.owner);
+            }
+        }
+    }
+
+    /// Usage against limits for the workspace and each client holding resources.
+    pub fn snapshot(&self, quotas: &Quotas) -> Value {
+        let entries = self.entries.lock();
+        let report = |used: &dyn Fn(Resource) -> usize, limits: &QuotaLimits| {
+            let mut map = Map::new();
+            for resource in Resource::ALL {
+                let limit = limits.get(resource);
+                map.insert(resource.as_str().into(), json!({
+                    "used": used(resource),
+                    "limit": if limit == 0 { Value::Null } else { json!(limit) },
+                }));
+            }
+            Value::Object(map)
+        };
+
+        let mut clients: Vec<&String> = entries.values().flat_map(|held| held.values().flatten()).collect();
+        clients.sort();
+        clients.dedup();
+
+        json!({
+            "resources": report(&|r| entries.get(&r).map_or(0, HashMap::len), &quotas.workspace),
+            "clients": clients.into_iter().map(|client| json!({
+                "clientId": client,
+                "resources": report(
+                    &|r| entries.get(&r).map_or(0, |held| held.values().filter(|o| o.as_ref() == Some(client)).count()),
+                    &quotas.client,
+                ),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// A reserved slot, released on drop unless [`Reservation::commit`]-ted.
+pub(crate) struct Reservation<'a> {
+    usage: &'a ResourceUsage,
+    resource: Resource,
+    slot: String,
+    /// Key the request asked for, if any
+    key: Option<String>,
+    keep: bool,
+}
+
+impl Reservation<'_> {
+    /// Keep the slot under the resource's real ID: the one given, or the
+    /// key it was reserved for.
+    pub(crate) fn commit(mut self, key: Option<&str>) {
+        self.keep = true;
+        let Some(key) = key.or(self.key.as_deref()) else { return };
+        let mut entries = self.usage.entries.lock();
+        if let Some(held) = entries.get_mut(&self.resource)
+            && let Some(owner) = held.remove(&self.slot)
+        {
+            held.insert(key.to_string(), owner);
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.keep {
+            self.usage.release(self.resource, &self.slot);
+        }
+    }
+}
@@ -4,22 +4,27 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use ecp_protocol::{ECPError, ECPErrorCode, ECPNotification, HandlerResult};
+use ecp_protocol::{AddressedNotification, ECPError, ECPErrorCode, ECPNotification, HandlerResult};
 use ecp_services::{
     chat::{ChatDb, ChatService},
     database::DatabaseService,
     file::FileService,
+    history::{FileHistory, SharedHistoryLimits},
     git::GitService,
-    lsp::LSPService,
+    journal::{Journal, JournalService, SharedJournalLimits},
+    lsp::{LSPService, SharedServerConfigs},
     session::SessionService,
-    terminal::TerminalService,
-    watch::WatchService,
+    terminal::{SharedTerminalLimits, SharedTerminalSessions, TerminalService},
+    terminal_supervisor::SupervisorClient,
+    watch::{NotifySender, WatchService, WatchSettings},
+    HeldResource, ResourceKind,
 };
 use parking_lot::{Mutex, RwLock};
-use serde_json::Value;
+use serde_json::{json, Value};
 use tokio::sync::broadcast;
 use tracing::{info, warn};
 
+use crate::quota::{Resource, ResourceUsage};
 use crate::router::ServiceDyn;
 
 /// Holds all per-workspace service instances and a notification channel.
@@ -27,7 +32,9 @@ pub struct WorkspaceServices {
     pub id: String,
     pub path: PathBuf,
     services: Vec<Box<dyn ServiceDyn>>,
-    pub notification_tx: broadcast::Sender<String>,
+    pub notification_tx: broadcast::Sender<AddressedNotification>,
+    /// Quota-limited resources currently held in this workspace
+    pub usage: ResourceUsage,
 }
 
 impl WorkspaceServices {
@@ -68,11 +75,35 @@ impl WorkspaceServices {
         }
     }
 
+    /// Let every service release what the client held.
+    pub fn client_disconnected(&self, client_id: &str) {
+        for service in &self.services {
+            service.client_disconnected_dyn(client_id);
+        }
+    }
+
+    /// Resources of `kind` the services hold right now.
+    pub async fn held_resources(&self, kind: ResourceKind) -> Vec<HeldResource> {
+        let mut held = Vec::new();
+        for service in &self.services {
+            held.extend(service.held_resources_dyn(kind).await);
+        }
+        held
+    }
+
+    /// Bring quota usage of `resource` in line with what the services hold.
+    pub(crate) async fn sync_usage(&self, resource: Resource) {
+        if let Some(kind) = resource.kind() {
+            let live = self.held_resources(kind).await;
+            self.usage.sync(resource, &live);
+        }
+    }
+
     /// Emit a notification on this workspace's channel.
     pub fn emit_notification(&self, method: &str, params: Option<Value>) {
         let notification = ECPNotification::new(method, params);
         if let Ok(json) = serde_json::to_string(&notification) {
-            let _ = self.notification_tx.send(json);
+            let _ = self.notification_tx.send(AddressedNotification::broadcast(json));
         }
     }
 }
@@ -97,6 +128,17 @@ pub struct WorkspaceRegistry {
     path_to_id: RwLock<HashMap<PathBuf, String>>,
     client_workspaces: RwLock<HashMap<String, String>>,
     global_chat_db: Arc<Mutex<ChatDb>>,
+    /// Capacity of each workspace's notification channel
+    notification_buffer: usize,
+    terminal_limits: SharedTerminalLimits,
+    /// Each workspace path's terminals, kept while it is closed so they
+    /// run on detached until it reopens
+    terminal_sessions: Mutex<HashMap<PathBuf, SharedTerminalSessions>>,
+    terminal_supervisor: Option<Arc<SupervisorClient>>,
+    history_limits: SharedHistoryLimits,
+    journal_limits: SharedJournalLimits,
+    watch_settings: WatchSettings,
+    lsp_servers: SharedServerConfigs,
 }
 
 impl WorkspaceRegistry {
@@ -106,9 +148,53 @@ impl WorkspaceRegistry {
             path_to_id: RwLock::new(HashMap::new()),
             client_workspaces: RwLock::new(HashMap::new()),
             global_chat_db,
+            notification_buffer: 256,
+            terminal_limits: SharedTerminalLimits::default(),
+            terminal_sessions: Mutex::new(HashMap::new()),
+            terminal_supervisor: None,
+            history_limits: SharedHistoryLimits::default(),
+            journal_limits: SharedJournalLimits::default(),
+            watch_settings: WatchSettings::default(),
+            lsp_servers: SharedServerConfigs::default(),
         }
     }
 
+    /// Set the notification channel capacity for workspaces opened from now on.
+    pub fn set_notification_buffer(&mut self, capacity: usize) {
+        self.notification_buffer = capacity.max(1);
+    }
+
+    /// Share terminal limits with every workspace's terminal service.
+    pub fn set_terminal_limits(&mut self, limits: SharedTerminalLimits) {
+        self.terminal_limits = limits;
+    }
+
+    /// Host workspaces' new terminals in a supervisor process so they
+    /// survive a server restart.
+    pub fn set_terminal_supervisor(&mut self, supervisor: Arc<SupervisorClient>) {
+        self.terminal_supervisor = Some(supervisor);
+    }
+
+    /// Share file history retention limits with every workspace.
+    pub fn set_history_limits(&mut self, limits: SharedHistoryLimits) {
+        self.history_limits = limits;
+    }
+
+    /// Share change journal retention and size limits with every workspace.
+    pub fn set_journal_limits(&mut self, limits: SharedJournalLimits) {
+        self.journal_limits = limits;
+    }
+
+    /// Set the file watcher backend for workspaces opened from now on.
+    pub fn set_watch_settings(&mut self, settings: WatchSettings) {
+        self.watch_settings = settings;
+    }
+
+    /// Share default language server configurations with every workspace.
+    pub fn set_lsp_servers(&mut self, servers: SharedServerConfigs) {
+        self.lsp_servers = servers;
+    }
+
     /// Open a workspace for a client connection. Returns (workspace_id, notification receiver).
     ///
     /// If the workspace path is already open, reuses the existing instance and
@@ -117,7 +203,7 @@ impl WorkspaceRegistry {
         &self,
         path: &Path,
         client_id: &str,
-    ) -> Result<(String, broadcast::Receiver<String>), ECPError> {
+    ) -> Result<(String, broadcast::Receiver<AddressedNotification>), ECPError> {
         let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
 
         // Check if this client already has a workspace open
@@ -196,6 +282,31 @@ impl WorkspaceRegistry {
         self.workspaces.read().get(workspace_id).map(|e| e.services.clone())
     }
 
+    /// Workspace the given client is scoped to, if any.
+    pub fn client_workspace(&self, client_id: &str) -> Option<String> {
+        self.client_workspaces.read().get(client_id).cloned()
+    }
+
+    /// Summaries of all open workspaces, for `workspace/list`.
+    pub fn list(&self) -> Vec<Value> {
+        let client_workspaces = self.client_workspaces.read();
+        let mut list: Vec<Value> = self.workspaces.read().iter().map(|(id, entry)| {
+            let mut clients: Vec<&String> = client_workspaces.iter()
+                .filter(|(_, ws_id)| *ws_id == id)
+                .map(|(client_id, _)| client_id)
+                .collect();
+            clients.sort();
+            json!({
+                "workspaceId": id,
+                "path": entry.services.path.to_string_lossy(),
+                "refcount": entry.refcount,
+                "clients": clients,
+            })
+        }).collect();
+        list.sort_by(|a, b| a["path"].as_str().cmp(&b["path"].as_str()));
+        list
+    }
+
     /// Shutdown all workspaces (called during server shutdown).
     pub async fn shutdown_all(&self) {
         // Drain all entries while holding the lock briefly
@@ -211,6 +322,12 @@ impl WorkspaceRegistry {
             info!("Shutting down workspace: {}", id);
             entry.services.shutdown().await;
         }
+
+        // Detached terminals go down with the server, except those a
+        // supervisor hosts
+        for (_, sessions) in self.terminal_sessions.lock().drain() {
+            sessions.hang_up_local();
+        }
     }
 
     // ── Internal ──────────────────────────────────────────────────────────
@@ -243,40 +360,87 @@ impl WorkspaceRegistry {
         }
     }
 
+    /// The terminal store for `path`, reusing the one its last instance left.
+    fn terminal_sessions_for(&self, path: &Path) -> SharedTerminalSessions {
+        let mut stores = self.terminal_sessions.lock();
+        // Forget stores no workspace uses that have no terminals left
+        stores.retain(|_, sessions| Arc::strong_count(sessions) > 1 || !sessions.is_empty());
+        stores.entry(path.to_path_buf()).or_default().clone()
+    }
+
     fn create_workspace_services(&self, id: &str, path: &Path) -> WorkspaceServices {
-        let (notification_tx, _) = broadcast::channel::<String>(256);
+        let (notification_tx, _) = broadcast::channel::<AddressedNotification>(self.notification_buffer);
 
-        // Build a notification callback for workspace-scoped services
+        // Build a notification callback for workspace-scoped services. A
+        // `_clients` array in the params addresses it to just those clients.
         let ws_notify_tx = notification_tx.clone();
-        let notify_sender: Arc<dyn Fn(&str, serde_json::Value) + Send + Sync> =
-            Arc::new(move |method, params| {
+        let notify_sender: NotifySender =
+            Arc::new(move |method, mut params| {
+                let clients = params.as_object_mut()
+                    .and_then(|obj| obj.remove("_clients"))
+                    .and_then(|clients| serde_json::from_value::<Vec<String>>(clients).ok());
                 let notification = ECPNotification::new(method, Some(params));
                 if let Ok(json) = serde_json::to_string(&notification) {
-                    let _ = ws_notify_tx.send(json);
+                    let _ = ws_notify_tx.send(AddressedNotification { json, clients: clients.map(Into::into) });
                 }
             });
 
         let watch_service = WatchService::new(path.to_path_buf());
         watch_service.set_notify_sender(notify_sender.clone());
+        watch_service.set_settings(self.watch_settings);
 
         let chat_service = ChatService::new_with_global_db(path, self.global_chat_db.clone());
-        chat_service.set_notify_sender(notify_sender);
-
-        let mut services: Vec<Box<dyn ServiceDyn>> = Vec::new();
-        services.push(Box::new(FileService::new(path.to_path_buf())));
-        services.push(Box::new(GitService::new(path.to_path_buf())));
-        services.push(Box::new(TerminalService::new(path.to_path_buf())));
-        services.push(Box::new(SessionService::new(path.to_path_buf())));
-        services.push(Box::new(chat_service));
-        services.push(Box::new(DatabaseService::new(path.to_path_buf())));
-        services.push(Box::new(LSPService::new(path.to_path_buf())));
-        services.push(Box::new(watch_service));
+        chat_service.set_notify_sender(notify_sender.clone());
+
+        // Change journal shared by the file service (writer) and journal/* (reader)
+        let mut journal = Journal::new(path.to_path_buf());
+        journal.set_limits(self.journal_limits.clone());
+        let journal = Arc::new(journal);
+        let file_service = FileService::new(path.to_path_buf());
+        file_service.set_journal(journal.clone());
+        file_service.set_notify_sender(notify_sender.clone());
+
+        // Search index: kept current by the watcher, ranked by session recency
+        let file_index = file_service.file_index();
+        watch_service.add_event_listener(Arc::new(move |event| file_index.apply_event(event)));
+        let session_service = SessionService::new(path.to_path_buf());
+        session_service.set_file_index(file_service.file_index());
+
+        // Local history: snapshots ECP writes and external changes
+        let mut history = FileHistory::new(path.to_path_buf());
+        history.set_limits(self.history_limits.clone());
+        let history = Arc::new(history);
+        file_service.set_history(history.clone());
+        watch_service.add_event_listener(Arc::new(move |event| history.record_event(event)));
+
+        let mut terminal_service = TerminalService::new(path.to_path_buf());
+        terminal_service.set_limits(self.terminal_limits.clone());
+        terminal_service.set_sessions(self.terminal_sessions_for(path));
+        if let Some(ref supervisor) = self.terminal_supervisor {
+            terminal_service.set_supervisor(supervisor.clone());
+        }
+        terminal_service.set_notify_sender(notify_sender.clone());
+        let mut lsp_service = LSPService::new(path.to_path_buf());
+        lsp_service.set_default_configs(self.lsp_servers.clone());
+
+        let services: Vec<Box<dyn ServiceDyn>> = vec![
+            Box::new(file_service),
+            Box::new(GitService::new(path.to_path_buf())),
+            Box::new(terminal_service),
+            Box::new(session_service),
+            Box::new(chat_service),
+            Box::new(DatabaseService::new(path.to_path_buf())),
+            Box::new(lsp_service),
+            Box::new(watch_service),
+            Box::new(JournalService::new(journal)),
+        ];
 
         WorkspaceServices {
             id: id.to_string(),
             path: path.to_path_buf(),
             services,
             notification_tx,
+            usage: ResourceUsage::default(),
         }
     }
 }
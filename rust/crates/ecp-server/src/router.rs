@@ -3,24 +3,39 @@
 //! The [`ECPServer`] owns global services and a [`WorkspaceRegistry`]. Request
 //! routing works in three phases:
 //!
-//! 1. **Workspace lifecycle** — `workspace/open` and `workspace/close` are
-//!    handled inline by the router.
+//! 1. **Workspace lifecycle** — `workspace/open`, `workspace/close`,
+//!    `workspace/list`, `workspace/resources`, `server/clients` and
+//!    `server/shutdown` are handled inline by the router.
 //! 2. **Global services** — matched by namespace, then fallback try-all.
 //!    Bridge-delegated services have `_workspaceId` injected into params.
 //! 3. **Workspace services** — resolved via `context.workspace_id` (or the
 //!    default workspace from `--workspace`). Returns `-32020` if no workspace
-//!    is open.
+//!    is open. Resource-creating calls are checked against the
+//!    [quotas](crate::quota) first.
+//!
+//! Before phases 2 and 3, requests that carry paths are checked against the
+//! [workspace sandbox](crate::sandbox) when it is enabled.
 
+use std::collections::HashMap;
 use std::path::PathBuf;
-
-use ecp_protocol::{ECPError, ECPErrorCode, ECPNotification, HandlerResult, RequestContext};
-use ecp_services::{Service, ServiceScope};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ecp_protocol::auth::HandshakeClientInfo;
+use ecp_protocol::{
+    AddressedNotification, ECPError, ECPErrorCode, ECPNotification, HandlerResult, RequestContext,
+};
+use ecp_services::{HeldResource, ResourceKind, Service, ServiceScope};
 use ecp_transport::server::RequestHandler;
+use parking_lot::RwLock;
 use serde_json::{json, Value};
-use tokio::sync::broadcast;
-use tracing::info;
+use tokio::sync::{broadcast, Notify};
+use tracing::{info, warn};
 
 use crate::middleware::MiddlewareChain;
+use crate::quota::{self, Claim, Resource, SharedQuotas};
+use crate::sandbox::SharedSandbox;
 use crate::registry::WorkspaceRegistry;
 
 /// The ECP Server — owns global services and a workspace registry.
@@ -37,6 +52,34 @@ pub struct ECPServer {
     global_notification_tx: Option<broadcast::Sender<String>>,
     /// Default workspace ID — auto-opened via --workspace flag for backward compat
     default_workspace: Option<String>,
+    /// Connected clients, for `server/clients`
+    clients: RwLock<HashMap<String, ConnectedClient>>,
+    /// Signalled by `server/shutdown`; the process owner waits on it
+    shutdown_requested: Arc<Notify>,
+    /// Set once draining starts — new requests are rejected
+    draining: AtomicBool,
+    /// Requests currently being handled
+    in_flight: AtomicUsize,
+    /// Per-workspace and per-client resource limits
+    quotas: SharedQuotas,
+    /// Filesystem confinement for path-bearing requests
+    sandbox: SharedSandbox,
+}
+
+/// Decrements the in-flight counter when a request finishes.
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A connected client as reported by `server/clients`.
+struct ConnectedClient {
+    info: Option<HandshakeClientInfo>,
+    /// Milliseconds since the Unix epoch
+    connected_at: u64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -46,9 +89,15 @@ enum ServerState {
     Shutdown,
 }
 
+/// Boxed future returned by [`ServiceDyn::init_dyn`].
+type InitFuture<'a> = std::pin::Pin<
+    Box<dyn std::future::Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>,
+>;
+
 /// Object-safe wrapper for the Service trait.
 pub(crate) trait ServiceDyn: Send + Sync {
     fn namespace_dyn(&self) -> &str;
+    #[allow(dead_code)]
     fn scope_dyn(&self) -> ServiceScope;
     fn is_bridge_delegated_dyn(&self) -> bool;
     fn handle_dyn<'a>(
@@ -56,12 +105,15 @@ pub(crate) trait ServiceDyn: Send + Sync {
         method: &'a str,
         params: Option<Value>,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = HandlerResult> + Send + 'a>>;
-    fn init_dyn(
-        &self,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + '_>>;
+    fn init_dyn(&self) -> InitFuture<'_>;
     fn shutdown_dyn(
         &self,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + '_>>;
+    fn client_disconnected_dyn(&self, client_id: &str);
+    fn held_resources_dyn(
+        &self,
+        kind: ResourceKind,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Vec<HeldResource>> + Send + '_>>;
 }
 
 impl<T: Service> ServiceDyn for T {
@@ -81,9 +133,7 @@ impl<T: Service> ServiceDyn for T {
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = HandlerResult> + Send + 'a>> {
         Box::pin(self.handle(method, params))
     }
-    fn init_dyn(
-        &self,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + '_>> {
+    fn init_dyn(&self) -> InitFuture<'_> {
         Box::pin(self.init())
     }
     fn shutdown_dyn(
@@ -91,6 +141,15 @@ impl<T: Service> ServiceDyn for T {
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + '_>> {
         Box::pin(self.shutdown())
     }
+    fn client_disconnected_dyn(&self, client_id: &str) {
+        self.client_disconnected(client_id)
+    }
+    fn held_resources_dyn(
+        &self,
+        kind: ResourceKind,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Vec<HeldResource>> + Send + '_>> {
+        Box::pin(self.held_resources(kind))
+    }
 }
 
 impl ECPServer {
@@ -102,6 +161,12 @@ impl ECPServer {
             state: ServerState::Uninitialized,
             global_notification_tx: None,
             default_workspace: None,
+            clients: RwLock::new(HashMap::new()),
+            shutdown_requested: Arc::new(Notify::new()),
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            quotas: SharedQuotas::default(),
+            sandbox: SharedSandbox::default(),
         }
     }
 
@@ -121,6 +186,16 @@ impl ECPServer {
         self.default_workspace = Some(ws_id);
     }
 
+    /// Share resource quotas (so they can be changed at runtime).
+    pub fn set_quotas(&mut self, quotas: SharedQuotas) {
+        self.quotas = quotas;
+    }
+
+    /// Share sandbox settings (so they can be changed at runtime).
+    pub fn set_sandbox(&mut self, sandbox: SharedSandbox) {
+        self.sandbox = sandbox;
+    }
+
     /// Get a reference to the workspace registry.
     pub fn workspace_registry(&self) -> &WorkspaceRegistry {
         &self.workspace_registry
@@ -157,6 +232,32 @@ impl ECPServer {
         info!("ECP server shutdown complete");
     }
 
+    /// Notified when a client calls `server/shutdown`.
+    pub fn shutdown_requested(&self) -> Arc<Notify> {
+        self.shutdown_requested.clone()
+    }
+
+    /// Stop accepting requests, wait (up to `timeout`) for in-flight ones to
+    /// finish, then shut down all workspaces. Returns false on timeout.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.draining.store(true, Ordering::SeqCst);
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let drained = loop {
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                break true;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                warn!("{} requests still in flight after {timeout:?}", self.in_flight.load(Ordering::SeqCst));
+                break false;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+
+        self.workspace_registry.shutdown_all().await;
+        drained
+    }
+
     /// Broadcast a notification to all connected clients (global channel).
     pub fn emit_notification(&self, method: &str, params: Option<Value>) {
         if let Some(tx) = &self.global_notification_tx {
@@ -184,9 +285,52 @@ impl ECPServer {
             return self.handle_workspace_close(context).await;
         }
 
+        // 3. Server status — answered from router state
+        if method == "workspace/list" {
+            return Ok(json!({
+                "workspaces": self.workspace_registry.list(),
+                "defaultWorkspaceId": self.default_workspace,
+            }));
+        }
+        if method == "workspace/resources" {
+            let ws_id = context.workspace_id.as_deref()
+                .or(self.default_workspace.as_deref())
+                .ok_or_else(ECPError::no_workspace)?;
+            let ws = self.workspace_registry.get(ws_id)
+                .ok_or_else(|| ECPError::workspace_not_found(ws_id))?;
+            for resource in Resource::LIVE {
+                ws.sync_usage(resource).await;
+            }
+            let mut usage = ws.usage.snapshot(&self.quotas.read());
+            usage["workspaceId"] = json!(ws_id);
+            return Ok(usage);
+        }
+        if method == "server/clients" {
+            return Ok(json!({ "clients": self.list_clients() }));
+        }
+        if method == "server/shutdown" {
+            info!("Shutdown requested by client {}", context.client_id);
+            self.shutdown_requested.notify_one();
+            return Ok(json!({ "shuttingDown": true }));
+        }
+
+        let effective_workspace_id = context.workspace_id.as_deref()
+            .or(self.default_workspace.as_deref());
+
+        // Sandbox — reject paths outside the workspace and allowlist
+        {
+            let sandbox = self.sandbox.read();
+            if sandbox.enabled {
+                let root = effective_workspace_id
+                    .and_then(|id| self.workspace_registry.get(id))
+                    .map(|ws| ws.path.clone());
+                sandbox.check(method, params.as_ref(), root.as_deref(), context)?;
+            }
+        }
+
         let namespace = method.split('/').next().unwrap_or("");
 
-        // 3. Try global services first — exact namespace match
+        // 4. Try global services first — exact namespace match
         for service in &self.global_services {
             if service.namespace_dyn() == namespace {
                 // For bridge-delegated services, inject _workspaceId if available
@@ -199,7 +343,7 @@ impl ECPServer {
             }
         }
 
-        // 4. Try global services — fallback try-all (for multi-namespace like SessionService)
+        // 5. Try global services — fallback try-all (for multi-namespace like SessionService)
         // Note: SessionService is workspace-scoped but handles config/*, theme/* etc.
         // We'll check global services first in fallback too.
         for service in &self.global_services {
@@ -214,10 +358,7 @@ impl ECPServer {
             }
         }
 
-        // 5. Resolve workspace for workspace-scoped services
-        let effective_workspace_id = context.workspace_id.as_deref()
-            .or(self.default_workspace.as_deref());
-
+        // 6. Resolve workspace for workspace-scoped services
         let ws_id = match effective_workspace_id {
             Some(id) => id,
             None => return Err(ECPError::no_workspace()),
@@ -226,8 +367,38 @@ impl ECPServer {
         let ws = self.workspace_registry.get(ws_id)
             .ok_or_else(|| ECPError::workspace_not_found(ws_id))?;
 
-        // 6. Route through workspace services
-        ws.route(method, params).await
+        // 7. Route through workspace services, holding a quota slot if the
+        // request creates a resource
+        let claim = quota::classify(method, params.as_ref());
+        if let Some(Claim::Acquire { resource, .. } | Claim::Hold(resource)) = &claim {
+            ws.sync_usage(*resource).await;
+        }
+        let reservation = match &claim {
+            Some(Claim::Acquire { resource, key, .. }) => {
+                let quotas = *self.quotas.read();
+                ws.usage.reserve(*resource, key.as_deref(), &context.client_id, &quotas)?
+            }
+            Some(Claim::Hold(resource)) => {
+                let quotas = *self.quotas.read();
+                ws.usage.reserve(*resource, None, &context.client_id, &quotas)?
+            }
+            _ => None,
+        };
+
+        let result = ws.route(method, inject_client_id(params, context)).await;
+
+        match (claim, &result) {
+            (Some(Claim::Acquire { result_key, .. }), Ok(value)) => {
+                if let Some(reservation) = reservation {
+                    reservation.commit(result_key.and_then(|k| value.get(k)).and_then(Value::as_str));
+                }
+            }
+            (Some(Claim::Release { resource, key }), Ok(_)) => ws.usage.release(resource, &key),
+            (Some(Claim::ReleaseAll(resource)), Ok(_)) => ws.usage.release_all(resource),
+            // Hold slots and failed acquisitions are released on drop
+            _ => {}
+        }
+        result
     }
 
     /// Handle workspace/open — delegates to registry.
@@ -259,10 +430,39 @@ impl ECPServer {
         &self,
         context: &RequestContext,
     ) -> HandlerResult {
+        self.release_client(&context.client_id);
         self.workspace_registry.close(&context.client_id).await?;
         Ok(json!({ "workspaceClosed": true }))
     }
 
+    /// Let the client's workspace services drop what it held.
+    fn release_client(&self, client_id: &str) {
+        let ws = self.workspace_registry.client_workspace(client_id)
+            .or_else(|| self.default_workspace.clone())
+            .and_then(|id| self.workspace_registry.get(&id));
+        if let Some(ws) = ws {
+            ws.client_disconnected(client_id);
+            ws.usage.release_client(client_id);
+        }
+    }
+
+    /// Connected clients with their workspace, oldest connection first.
+    fn list_clients(&self) -> Vec<Value> {
+        let clients = self.clients.read();
+        let mut list: Vec<(&String, &ConnectedClient)> = clients.iter().collect();
+        list.sort_by_key(|(_, c)| c.connected_at);
+        list.into_iter().map(|(id, client)| {
+            json!({
+                "clientId": id,
+                "name": client.info.as_ref().map(|i| i.name.as_str()),
+                "version": client.info.as_ref().and_then(|i| i.version.as_deref()),
+                "connectedAt": client.connected_at,
+                "workspaceId": self.workspace_registry.client_workspace(id)
+                    .or_else(|| self.default_workspace.clone()),
+            })
+        }).collect()
+    }
+
     /// Inject _workspaceId and _workspacePath into params for bridge-delegated services.
     fn inject_workspace_id(
         &self,
@@ -303,6 +503,11 @@ impl RequestHandler for ECPServer {
             ServerState::Uninitialized => return Err(ECPError::not_initialized()),
             ServerState::Running => {}
         }
+        if self.draining.load(Ordering::SeqCst) {
+            return Err(ECPError::shutting_down());
+        }
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let _in_flight = InFlightGuard(&self.in_flight);
 
         // Run middleware before-chain
         let mw_result = self.middleware.run_before(method, params).await;
@@ -329,14 +534,24 @@ impl RequestHandler for ECPServer {
         result
     }
 
+    fn on_client_connected(&self, client_id: &str, client: Option<HandshakeClientInfo>) {
+        let connected_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.clients.write().insert(client_id.to_string(), ConnectedClient { info: client, connected_at });
+    }
+
     async fn on_client_disconnected(&self, client_id: &str) {
+        self.clients.write().remove(client_id);
+        self.release_client(client_id);
         self.workspace_registry.client_disconnected(client_id).await;
     }
 
     fn workspace_notification_rx(
         &self,
         workspace_id: &str,
-    ) -> Option<broadcast::Receiver<String>> {
+    ) -> Option<broadcast::Receiver<AddressedNotification>> {
         self.workspace_registry.get(workspace_id)
             .map(|ws| ws.notification_tx.subscribe())
     }
@@ -345,3 +560,15 @@ impl RequestHandler for ECPServer {
         self.default_workspace.clone()
     }
 }
+
+/// Tell workspace services who is asking, as `_clientId`, for requests that
+/// carry a params object.
+fn inject_client_id(params: Option<Value>, context: &RequestContext) -> Option<Value> {
+    match params {
+        Some(Value::Object(mut obj)) => {
+            obj.insert("_clientId".into(), json!(context.client_id));
+            Some(Value::Object(obj))
+        }
+        other => other,
+    }
+}
@@ -0,0 +1,229 @@
+//! Workspace sandbox — confines filesystem access to the workspace root plus
+//! an allowlist of directories.
+//!
+//! Like quotas, this is enforced in the router: bridge callbacks from AI
+//! agents reach services the same way client requests do. [`paths`] picks the
+//! path-bearing params of a request, and each one is resolved against the
+//! workspace root with symlinks followed before it is compared against the
+//! allowed roots. The server's own configuration, credentials and control
+//! files stay out of reach even inside an allowed root. Violations fail with
+//! `-32032` and are appended to the audit log.
+
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use ecp_protocol::{ECPError, RequestContext};
+use ecp_services::file_tree;
+use parking_lot::RwLock;
+use serde_json::{json, Value};
+use tracing::warn;
+
+/// Sandbox settings. Disabled by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SandboxConfig {
+    pub enabled: bool,
+    /// Directories outside the workspace that stay accessible
+    pub allow: Vec<PathBuf>,
+    /// Files and directories never accessible, even inside an allowed one:
+    /// the server's own configuration, credentials and control files
+    pub deny: Vec<PathBuf>,
+    /// JSON-lines log of rejected requests
+    pub audit_log: Option<PathBuf>,
+}
+
+/// Sandbox settings shared by the server and whoever reloads configuration.
+pub type SharedSandbox = Arc<RwLock<SandboxConfig>>;
+
+/// Methods that take paths but never touch the filesystem.
+const PURE_PATH_METHODS: &[&str] = &[
+    "file/getParent",
+    "file/getBasename",
+    "file/join",
+    "file/pathToUri",
+    "file/uriToPath",
+];
+
+const FILE_PATH_KEYS: &[&str] = &[
+    "path", "uri", "from", "to", "oldUri", "newUri", "sourceUri", "targetUri", "baseUri",
+];
+
+/// Path-bearing params of a request, as `(param name, raw value)`.
+pub(crate) fn paths(method: &str, params: Option<&Value>) -> Vec<(&'static str, String)> {
+    let Some(params) = params else { return Vec::new() };
+    let keys: &[&'static str] = match method {
+        m if PURE_PATH_METHODS.contains(&m) => &[],
+        "file/glob" => return glob_path(params).into_iter().collect(),
+        "file/tree" if params.get("expandToken").is_some() => {
+            // The token names the directory to list
+            return params.get("expandToken").and_then(Value::as_str)
+                .and_then(file_tree::parse_token)
+                .map(|(path, _)| vec![("expandToken", path.to_string_lossy().to_string())])
+                .unwrap_or_default();
+        }
+        m if m.starts_with("file/") || m.starts_with("watch/") => FILE_PATH_KEYS,
+        "terminal/create" | "terminal/spawn" | "terminal/execute" => &["cwd"],
+        m if m.starts_with("git/") => &["path", "paths"],
+        "document/open" => {
+            // Only file URIs are read from disk
+            return params.get("uri").and_then(Value::as_str)
+                .filter(|uri| uri.starts_with("file://"))
+                .map(|uri| vec![("uri", uri.to_string())])
+                .unwrap_or_default();
+        }
+        _ => &[],
+    };
+
+    let mut found = Vec::new();
+    for key in keys {
+        match params.get(*key) {
+            Some(Value::String(s)) => found.push((*key, s.clone())),
+            Some(Value::Array(items)) => found.extend(
+                items.iter().filter_map(Value::as_str).map(|s| (*key, s.to_string())),
+            ),
+            _ => {}
+        }
+    }
+    found
+}
+
+/// The directory a glob can reach: the base joined with the pattern, cut at
+/// the first component containing a wildcard.
+fn glob_path(params: &Value) -> Option<(&'static str, String)> {
+    let pattern = params.get("pattern").and_then(Value::as_str)?;
+    let base = params.get("baseUri").and_then(Value::as_str).unwrap_or("");
+    let base = base.strip_prefix("file://").unwrap_or(base);
+    let joined = normalize(&Path::new(base).join(pattern));
+    let literal: PathBuf = joined.components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[', '{']))
+        .collect();
+    Some(("pattern", literal.to_string_lossy().to_string()))
+}
+
+impl SandboxConfig {
+    /// Whether `self` reaches anything `current` keeps out: it is off while
+    /// `current` is on, allows a directory `current` doesn't, or denies less.
+    pub fn loosens(&self, current: &SandboxConfig) -> bool {
+        if !current.enabled {
+            return false;
+        }
+        !self.enabled
+            || self.allow.iter().any(|dir| !current.allow.iter().any(|allowed| dir.starts_with(allowed)))
+            || current.deny.iter().any(|denied| !self.deny.contains(denied))
+    }
+
+    /// Reject the request if any of its paths leaves the workspace root and
+    /// the allowlist. Relative paths resolve against `root`; without a
+    /// workspace they are left for the service to refuse.
+    pub(crate) fn check(
+        &self,
+        method: &str,
+        params: Option<&Value>,
+        root: Option<&Path>,
+        context: &RequestContext,
+    ) -> Result<(), ECPError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        for (param, raw) in paths(method, params) {
+            let stripped = raw.strip_prefix("file://").unwrap_or(&raw);
+            let path = Path::new(stripped);
+            let absolute = match root {
+                _ if path.is_absolute() => path.to_path_buf(),
+                Some(root) => root.join(path),
+                None => continue,
+            };
+            let resolved = resolve(&absolute);
+            let allowed = root.into_iter().map(resolve)
+                .chain(self.allow.iter().map(|dir| resolve(dir)))
+                .any(|dir| resolved.starts_with(&dir))
+                && !self.deny.iter().any(|denied| resolved.starts_with(resolve(denied)));
+            if !allowed {
+                self.audit(method, param, &raw, &resolved, context);
+                return Err(ECPError::access_denied(&raw, &resolved.to_string_lossy()));
+            }
+        }
+        Ok(())
+    }
+
+    fn audit(&self, method: &str, param: &str, raw: &str, resolved: &Path, context: &RequestContext) {
+        warn!(
+            "Sandbox violation: {method} {param}={raw} resolves to {} (client {})",
+            resolved.display(), context.client_id,
+        );
+        let Some(ref log) = self.audit_log else { return };
+        let entry = json!({
+            "timestamp": now_ms(),
+            "clientId": context.client_id,
+            "workspaceId": context.workspace_id,
+            "method": method,
+            "param": param,
+            "path": raw,
+            "resolved": resolved.to_string_lossy(),
+        });
+        let written = log.parent().map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::OpenOptions::new().create(true).append(true).open(log))
+            .and_then(|mut file| writeln!(file, "{entry}"));
+        if let Err(e) = written {
+            warn!("Failed to write sandbox audit log {}: {e}", log.display());
+        }
+    }
+}
+
+/// Resolve `.`/`..` and symlinks. Paths that don't exist yet resolve through
+/// their longest existing ancestor, and dangling symlinks through their target,
+/// so a write can't escape via a link created beforehand.
+fn resolve(path: &Path) -> PathBuf {
+    resolve_at_depth(&normalize(path), 0)
+}
+
+fn resolve_at_depth(path: &Path, depth: usize) -> PathBuf {
+    let mut existing = path.to_path_buf();
+    let mut rest: Vec<OsString> = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return rest.iter().rev().fold(canonical, |p, name| p.join(name));
+        }
+        if depth < 32
+            && let Ok(target) = std::fs::read_link(&existing)
+            && let Some(parent) = existing.parent()
+        {
+            let target = resolve_at_depth(&normalize(&parent.join(target)), depth + 1);
+            return rest.iter().rev().fold(target, |p, name| p.join(name));
+        }
+        match (existing.file_name(), existing.parent()) {
+            (Some(name), Some(parent)) => {
+                rest.push(name.to_os_string());
+                existing = parent.to_path_buf();
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Normalize a path by resolving `.` and `..` without touching the
+/// filesystem. `..` never climbs above the root, and leading `..` of a
+/// relative path are kept so joining it later still escapes correctly.
+fn normalize(path: &Path) -> PathBuf {
+    let mut components: Vec<Component> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => match components.last() {
+                Some(Component::Normal(_)) => { components.pop(); }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => components.push(component),
+            },
+            Component::CurDir => {}
+            c => components.push(c),
+        }
+    }
+    components.iter().collect()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
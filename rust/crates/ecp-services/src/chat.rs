@@ -3,6 +3,9 @@
 //! Schema matches the TypeScript ECP unified schema (migration 005) exactly,
 //! ensuring 1:1 compatibility with existing `.ultra/chat.db` databases.
 
+// ChatDb methods take one argument per column, mirroring the SQL rows.
+#![allow(clippy::too_many_arguments)]
+
 use std::sync::Arc;
 
 use ecp_protocol::{ECPError, HandlerResult};
@@ -848,12 +851,11 @@ impl ChatDb {
         add_set!(cli_session_id, "cli_session_id");
 
         // If status is a terminal state, set completed_at
-        if let Some(s) = status {
-            if matches!(s, "completed" | "failed" | "cancelled") {
-                sets.push(format!("completed_at = ?{}", idx));
-                params_vec.push(Box::new(now));
-                idx += 1;
-            }
+        if let Some(s) = status
+            && matches!(s, "completed" | "failed" | "cancelled") {
+            sets.push(format!("completed_at = ?{}", idx));
+            params_vec.push(Box::new(now));
+            idx += 1;
         }
 
         let sql = format!("UPDATE sessions SET {} WHERE id = ?{}", sets.join(", "), idx);
@@ -1006,7 +1008,7 @@ impl ChatDb {
                  FROM messages WHERE session_id = ?1 AND is_active = 1 AND content LIKE ?2
                  ORDER BY created_at DESC LIMIT ?3"
             )?;
-            let rows = stmt.query_map(rusqlite::params![sid, pattern, limit], |row| message_row_to_json(row))?;
+            let rows = stmt.query_map(rusqlite::params![sid, pattern, limit], message_row_to_json)?;
             rows.collect()
         } else {
             let mut stmt = self.conn.prepare(
@@ -1017,7 +1019,7 @@ impl ChatDb {
                  FROM messages WHERE is_active = 1 AND content LIKE ?1
                  ORDER BY created_at DESC LIMIT ?2"
             )?;
-            let rows = stmt.query_map(rusqlite::params![pattern, limit], |row| message_row_to_json(row))?;
+            let rows = stmt.query_map(rusqlite::params![pattern, limit], message_row_to_json)?;
             rows.collect()
         }
     }
@@ -1030,7 +1032,7 @@ impl ChatDb {
                     is_active, compacted_into_id, is_complete, iteration_number, blocks_json, created_at
              FROM messages WHERE is_active = 1 ORDER BY created_at DESC LIMIT ?1"
         )?;
-        let rows = stmt.query_map(rusqlite::params![limit], |row| message_row_to_json(row))?;
+        let rows = stmt.query_map(rusqlite::params![limit], message_row_to_json)?;
         rows.collect()
     }
 
@@ -1160,7 +1162,7 @@ impl ChatDb {
 
         let mut stmt = self.conn.prepare(&sql)?;
         let param_refs: Vec<&dyn rusqlite::types::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-        let rows = stmt.query_map(param_refs.as_slice(), |row| todo_row_to_json(row))?;
+        let rows = stmt.query_map(param_refs.as_slice(), todo_row_to_json)?;
         rows.collect()
     }
 
@@ -1234,14 +1236,14 @@ impl ChatDb {
                 "SELECT id, session_id, workflow_id, tool_name, scope, pattern, decision, granted_at, expires_at
                  FROM permissions WHERE session_id = ?1 ORDER BY granted_at DESC"
             )?;
-            let rows = stmt.query_map(rusqlite::params![sid], |row| permission_row_to_json(row))?;
+            let rows = stmt.query_map(rusqlite::params![sid], permission_row_to_json)?;
             rows.collect()
         } else {
             let mut stmt = self.conn.prepare(
                 "SELECT id, session_id, workflow_id, tool_name, scope, pattern, decision, granted_at, expires_at
                  FROM permissions ORDER BY granted_at DESC"
             )?;
-            let rows = stmt.query_map([], |row| permission_row_to_json(row))?;
+            let rows = stmt.query_map([], permission_row_to_json)?;
             rows.collect()
         }
     }
@@ -1269,7 +1271,7 @@ impl ChatDb {
                     messages_compacted, original_token_count, compressed_token_count, created_at
              FROM compactions WHERE session_id = ?1 ORDER BY created_at ASC"
         )?;
-        let rows = stmt.query_map(rusqlite::params![session_id], |row| compaction_row_to_json(row))?;
+        let rows = stmt.query_map(rusqlite::params![session_id], compaction_row_to_json)?;
         rows.collect()
     }
 
@@ -1375,7 +1377,7 @@ impl ChatDb {
 
         let mut stmt = self.conn.prepare(&sql)?;
         let param_refs: Vec<&dyn rusqlite::types::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-        let rows = stmt.query_map(param_refs.as_slice(), |row| document_row_to_json(row))?;
+        let rows = stmt.query_map(param_refs.as_slice(), document_row_to_json)?;
         rows.collect()
     }
 
@@ -1448,14 +1450,14 @@ impl ChatDb {
                 "SELECT {cols} FROM documents WHERE (title LIKE ?1 OR content LIKE ?1) AND doc_type = ?2
                  ORDER BY created_at DESC LIMIT ?3"
             ))?;
-            let rows = stmt.query_map(rusqlite::params![pattern, dt, limit], |row| document_row_to_json(row))?;
+            let rows = stmt.query_map(rusqlite::params![pattern, dt, limit], document_row_to_json)?;
             rows.collect()
         } else {
             let mut stmt = self.conn.prepare(&format!(
                 "SELECT {cols} FROM documents WHERE title LIKE ?1 OR content LIKE ?1
                  ORDER BY created_at DESC LIMIT ?2"
             ))?;
-            let rows = stmt.query_map(rusqlite::params![pattern, limit], |row| document_row_to_json(row))?;
+            let rows = stmt.query_map(rusqlite::params![pattern, limit], document_row_to_json)?;
             rows.collect()
         }
     }
@@ -1477,7 +1479,7 @@ impl ChatDb {
             let mut stmt = conn.prepare(&format!(
                 "SELECT {cols} FROM documents WHERE parent_id = ?1 ORDER BY created_at ASC"
             ))?;
-            let rows = stmt.query_map(rusqlite::params![parent_id], |row| document_row_to_json(row))?;
+            let rows = stmt.query_map(rusqlite::params![parent_id], document_row_to_json)?;
             let mut children = Vec::new();
             for row in rows {
                 let mut child = row?;
@@ -1514,7 +1516,7 @@ impl ChatDb {
                  AND status NOT IN ('archived', 'completed') AND session_id = ?1
                  ORDER BY {severity_order}, created_at DESC"
             ))?;
-            let rows = stmt.query_map(rusqlite::params![sid], |row| document_row_to_json(row))?;
+            let rows = stmt.query_map(rusqlite::params![sid], document_row_to_json)?;
             rows.collect()
         } else {
             let mut stmt = self.conn.prepare(&format!(
@@ -1522,7 +1524,7 @@ impl ChatDb {
                  AND status NOT IN ('archived', 'completed')
                  ORDER BY {severity_order}, created_at DESC"
             ))?;
-            let rows = stmt.query_map([], |row| document_row_to_json(row))?;
+            let rows = stmt.query_map([], document_row_to_json)?;
             rows.collect()
         }
     }
@@ -1536,7 +1538,7 @@ impl ChatDb {
             "SELECT {cols} FROM documents WHERE review_status = 'pending'
              ORDER BY priority DESC, created_at ASC"
         ))?;
-        let rows = stmt.query_map([], |row| document_row_to_json(row))?;
+        let rows = stmt.query_map([], document_row_to_json)?;
         rows.collect()
     }
 
@@ -1965,7 +1967,7 @@ impl ChatDb {
         }
 
         let mut file_activity_vec: Vec<(&String, &FileStats)> = file_activity_map.iter().collect();
-        file_activity_vec.sort_by(|a, b| b.1.total_ops.cmp(&a.1.total_ops));
+        file_activity_vec.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.total_ops));
         let file_activity: Vec<Value> = file_activity_vec.iter().take(50).map(|(path, stats)| {
             json!({
                 "filePath": path,
@@ -2030,7 +2032,7 @@ impl ChatDb {
 
         // Ops per minute (last 60s)
         let ops_last_60s = tool_calls.iter()
-            .filter(|tc| tc.started_at.map_or(false, |t| t >= now - 60_000))
+            .filter(|tc| tc.started_at.is_some_and(|t| t >= now - 60_000))
             .count() as f64;
 
         // Average latency by tool
@@ -2055,7 +2057,7 @@ impl ChatDb {
                 } else { None }
             })
             .collect();
-        completed_ops.sort_by(|a, b| b.1.cmp(&a.1));
+        completed_ops.sort_by_key(|(_, dur)| std::cmp::Reverse(*dur));
         let slowest: Vec<Value> = completed_ops.iter().take(5).map(|(tc, dur)| {
             json!({
                 "id": tc.id,
@@ -2069,11 +2071,10 @@ impl ChatDb {
         let sparkline_start = now - 600_000;
         let mut sparkline = vec![0i64; 10];
         for tc in &tool_calls {
-            if let Some(t) = tc.started_at {
-                if t >= sparkline_start {
-                    let bucket = ((t - sparkline_start) / 60_000).min(9) as usize;
-                    sparkline[bucket] += 1;
-                }
+            if let Some(t) = tc.started_at
+                && t >= sparkline_start {
+                let bucket = ((t - sparkline_start) / 60_000).min(9) as usize;
+                sparkline[bucket] += 1;
             }
         }
 
@@ -2304,7 +2305,7 @@ impl ChatDb {
 
         let mut stmt = self.conn.prepare(&sql)?;
         let param_refs: Vec<&dyn rusqlite::types::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-        let rows = stmt.query_map(param_refs.as_slice(), |row| persona_row_to_json(row))?;
+        let rows = stmt.query_map(param_refs.as_slice(), persona_row_to_json)?;
         rows.collect()
     }
 
@@ -2449,7 +2450,7 @@ impl ChatDb {
 
         let mut stmt = self.conn.prepare(&sql)?;
         let param_refs: Vec<&dyn rusqlite::types::ToSql> = params.iter().map(|p| p.as_ref()).collect();
-        let rows = stmt.query_map(param_refs.as_slice(), |row| chat_agent_row_to_json(row))?;
+        let rows = stmt.query_map(param_refs.as_slice(), chat_agent_row_to_json)?;
         rows.collect()
     }
 
@@ -3412,8 +3413,8 @@ impl Service for ChatService {
                 let id = p.id.clone();
                 // Project first, fall back to global
                 let persona = self.with_db(move |db| db.get_persona(&p.id)).await?;
-                if persona.is_some() {
-                    return Ok(persona.unwrap());
+                if let Some(persona) = persona {
+                    return Ok(persona);
                 }
                 let persona = self.with_global_db(move |db| db.get_persona(&id)).await?;
                 Ok(persona.unwrap_or(Value::Null))
@@ -3441,10 +3442,9 @@ impl Service for ChatService {
                     merged.push(p);
                 }
                 for p in global {
-                    if let Some(id) = p.get("id").and_then(|v| v.as_str()) {
-                        if !seen.contains(id) {
-                            merged.push(p);
-                        }
+                    if let Some(id) = p.get("id").and_then(|v| v.as_str())
+                        && !seen.contains(id) {
+                        merged.push(p);
                     }
                 }
                 Ok(json!({ "personas": merged }))
@@ -3539,8 +3539,8 @@ impl Service for ChatService {
                 let p: IdParam = parse_params(params)?;
                 let id = p.id.clone();
                 let agent = self.with_db(move |db| db.get_chat_agent(&p.id)).await?;
-                if agent.is_some() {
-                    return Ok(agent.unwrap());
+                if let Some(agent) = agent {
+                    return Ok(agent);
                 }
                 let agent = self.with_global_db(move |db| db.get_chat_agent(&id)).await?;
                 Ok(agent.unwrap_or(Value::Null))
@@ -3550,8 +3550,8 @@ impl Service for ChatService {
                 let p: NameParam = parse_params(params)?;
                 let name = p.name.clone();
                 let agent = self.with_db(move |db| db.get_chat_agent_by_name(&p.name)).await?;
-                if agent.is_some() {
-                    return Ok(agent.unwrap());
+                if let Some(agent) = agent {
+                    return Ok(agent);
                 }
                 let agent = self.with_global_db(move |db| db.get_chat_agent_by_name(&name)).await?;
                 Ok(agent.unwrap_or(Value::Null))
@@ -3579,10 +3579,9 @@ impl Service for ChatService {
                     merged.push(a);
                 }
                 for a in global {
-                    if let Some(id) = a.get("id").and_then(|v| v.as_str()) {
-                        if !seen.contains(id) {
-                            merged.push(a);
-                        }
+                    if let Some(id) = a.get("id").and_then(|v| v.as_str())
+                        && !seen.contains(id) {
+                        merged.push(a);
                     }
                 }
                 Ok(json!({ "agents": merged }))
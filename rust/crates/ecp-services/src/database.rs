@@ -118,11 +118,10 @@ impl DatabaseService {
 
         let mut configs = self.configs.write();
         for path in [global_path, project_path] {
-            if let Ok(data) = std::fs::read_to_string(&path) {
-                if let Ok(list) = serde_json::from_str::<Vec<ConnectionConfig>>(&data) {
-                    for c in list {
-                        configs.insert(c.id.clone(), c);
-                    }
+            if let Ok(data) = std::fs::read_to_string(&path)
+                && let Ok(list) = serde_json::from_str::<Vec<ConnectionConfig>>(&data) {
+                for c in list {
+                    configs.insert(c.id.clone(), c);
                 }
             }
         }
@@ -343,6 +343,12 @@ pub struct DocumentService {
     uri_to_id: RwLock<HashMap<String, String>>,
 }
 
+impl Default for DocumentService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DocumentService {
     pub fn new() -> Self {
         Self {
@@ -706,12 +712,12 @@ impl DocumentService {
                     .ok_or_else(|| doc_not_found(&p.document_id))?;
                 let selections: Vec<String> = doc.cursors.iter()
                     .filter(|c| c.anchor.is_some() && c.head.is_some())
-                    .filter_map(|c| {
+                    .map(|c| {
                         let range = Range {
                             start: c.anchor.clone().unwrap(),
                             end: c.head.clone().unwrap(),
                         };
-                        Some(doc.get_text_in_range(&range))
+                        doc.get_text_in_range(&range)
                     })
                     .collect();
                 Ok(json!({ "selections": selections }))
@@ -0,0 +1,343 @@
+//! Structured edits for `file/edit`.
+//!
+//! A request carries a list of [`Edit`]s: string replacements checked against
+//! how often the anchor occurs, line/column range replacements, or unified
+//! diff patches. Edits apply in order, each to the result of the one before,
+//! and all-or-nothing — the first missing or ambiguous anchor fails the whole
+//! request with [`ECPError::edit_failed`] and the file is left untouched.
+
+use ecp_protocol::ECPError;
+use serde::Deserialize;
+use serde_json::json;
+
+/// One edit in a `file/edit` request.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Edit {
+    /// Replace `oldString`. Without `replaceAll` or `expectedOccurrences` it
+    /// must occur exactly once.
+    Replace {
+        #[serde(rename = "oldString")]
+        old_string: String,
+        #[serde(rename = "newString")]
+        new_string: String,
+        #[serde(default, rename = "replaceAll")]
+        replace_all: bool,
+        /// Fail unless `oldString` occurs exactly this many times; all of
+        /// them are replaced.
+        #[serde(rename = "expectedOccurrences")]
+        expected_occurrences: Option<usize>,
+    },
+    /// Replace a range. Lines and columns are 0-based; columns count
+    /// characters.
+    Range {
+        range: Range,
+        #[serde(rename = "newText", alias = "newString")]
+        new_text: String,
+    },
+    /// Apply the hunks of a single-file unified diff.
+    Patch { patch: String },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// Apply `edits` to `content` in order, returning the new content.
+pub fn apply_edits(content: &str, edits: &[Edit]) -> Result<String, ECPError> {
+    let mut content = content.to_string();
+    for (index, edit) in edits.iter().enumerate() {
+        content = match edit {
+            Edit::Replace { old_string, new_string, replace_all, expected_occurrences } => {
+                replace(&content, old_string, new_string, *replace_all, *expected_occurrences)
+            }
+            Edit::Range { range, new_text } => replace_range(&content, range, new_text),
+            Edit::Patch { patch } => apply_patch(&content, patch),
+        }
+        .map_err(|e| e.into_error(index))?;
+    }
+    Ok(content)
+}
+
+/// Why an edit failed, before it is tied to its index in the request.
+struct Failure {
+    reason: &'static str,
+    message: String,
+    data: serde_json::Value,
+}
+
+impl Failure {
+    fn new(reason: &'static str, message: String) -> Self {
+        Self { reason, message, data: json!({}) }
+    }
+
+    fn with(mut self, key: &str, value: serde_json::Value) -> Self {
+        self.data[key] = value;
+        self
+    }
+
+    fn into_error(self, index: usize) -> ECPError {
+        let mut data = self.data;
+        data["editIndex"] = json!(index);
+        data["reason"] = json!(self.reason);
+        ECPError::edit_failed(format!("Edit {index}: {}", self.message)).with_data(data)
+    }
+}
+
+fn replace(
+    content: &str,
+    old: &str,
+    new: &str,
+    replace_all: bool,
+    expected: Option<usize>,
+) -> Result<String, Failure> {
+    if old.is_empty() {
+        return Err(Failure::new("emptyAnchor", "oldString is empty".into()));
+    }
+    let offsets: Vec<usize> = content.match_indices(old).map(|(i, _)| i).collect();
+    // 1-based, for messages aimed at whoever picks the anchor
+    let lines = || json!(offsets.iter().map(|&i| line_of(content, i) + 1).collect::<Vec<_>>());
+
+    if offsets.is_empty() {
+        return Err(Failure::new("notFound", "oldString not found".into()));
+    }
+    if let Some(expected) = expected
+        && offsets.len() != expected
+    {
+        return Err(Failure::new(
+            "occurrenceMismatch",
+            format!("expected {expected} occurrences of oldString, found {}", offsets.len()),
+        )
+        .with("occurrences", json!(offsets.len()))
+        .with("lines", lines()));
+    }
+    if expected.is_none() && !replace_all && offsets.len() > 1 {
+        return Err(Failure::new(
+            "ambiguous",
+            format!(
+                "oldString occurs {} times; add surrounding context or set replaceAll",
+                offsets.len()
+            ),
+        )
+        .with("occurrences", json!(offsets.len()))
+        .with("lines", lines()));
+    }
+    Ok(content.replace(old, new))
+}
+
+fn replace_range(content: &str, range: &Range, new: &str) -> Result<String, Failure> {
+    let (start, end) = (range.start, range.end);
+    if (start.line, start.column) > (end.line, end.column) {
+        return Err(Failure::new("invalidRange", "range start is after its end".into()));
+    }
+    let start = byte_offset(content, start)?;
+    let end = byte_offset(content, end)?;
+    Ok(format!("{}{new}{}", &content[..start], &content[end..]))
+}
+
+/// Byte offset of a 0-based line/character position.
+fn byte_offset(content: &str, pos: Position) -> Result<usize, Failure> {
+    let out_of_bounds = |detail: String| {
+        Failure::new("outOfBounds", detail)
+            .with("line", json!(pos.line))
+            .with("column", json!(pos.column))
+    };
+
+    let mut line_start = 0;
+    for _ in 0..pos.line {
+        match content[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => {
+                return Err(out_of_bounds(format!(
+                    "line {} is past the end of the file ({} lines)",
+                    pos.line,
+                    content.lines().count()
+                )));
+            }
+        }
+    }
+    let line = content[line_start..].split('\n').next().unwrap_or("");
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    if pos.column == 0 {
+        return Ok(line_start);
+    }
+    match line.char_indices().map(|(i, _)| i).chain([line.len()]).nth(pos.column) {
+        Some(i) => Ok(line_start + i),
+        None => Err(out_of_bounds(format!(
+            "column {} is past the end of line {} ({} characters)",
+            pos.column,
+            pos.line,
+            line.chars().count()
+        ))),
+    }
+}
+
+/// 0-based line containing byte offset `i`.
+fn line_of(content: &str, i: usize) -> usize {
+    content[..i].matches('\n').count()
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Unified diff patches
+// ─────────────────────────────────────────────────────────────────────────────
+
+struct Hunk {
+    /// 1-based; for a pure insertion, the line the new lines follow
+    old_start: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+    /// `\ No newline at end of file` follows the new side
+    new_no_eol: bool,
+}
+
+fn parse_patch(patch: &str) -> Result<Vec<Hunk>, Failure> {
+    let mut hunks = Vec::new();
+    let mut lines = patch.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        // File headers and anything else outside a hunk are ignored
+        let Some(header) = line.strip_prefix("@@ ") else { continue };
+        let (old_start, old_count, new_count) = parse_hunk_header(header)
+            .ok_or_else(|| Failure::new("invalidPatch", format!("malformed hunk header: {line}")))?;
+
+        let mut hunk = Hunk { old_start, old: Vec::new(), new: Vec::new(), new_no_eol: false };
+        let mut last_side_new = false;
+        while hunk.old.len() < old_count || hunk.new.len() < new_count
+            || lines.peek().is_some_and(|l| l.starts_with('\\'))
+        {
+            let Some(line) = lines.next() else {
+                return Err(Failure::new("invalidPatch", format!("hunk ends early: @@ {header}")));
+            };
+            if line.starts_with('\\') {
+                // Only the new side's newline matters for the result
+                hunk.new_no_eol |= last_side_new;
+                continue;
+            }
+            // Some tools strip the leading space from empty context lines
+            let mut chars = line.chars();
+            let tag = chars.next().unwrap_or(' ');
+            let text = chars.as_str();
+            match tag {
+                ' ' => {
+                    hunk.old.push(text.to_string());
+                    hunk.new.push(text.to_string());
+                    last_side_new = true;
+                }
+                '-' => {
+                    hunk.old.push(text.to_string());
+                    last_side_new = false;
+                }
+                '+' => {
+                    hunk.new.push(text.to_string());
+                    last_side_new = true;
+                }
+                _ => {
+                    return Err(Failure::new("invalidPatch", format!("unexpected line in hunk: {line}")));
+                }
+            }
+        }
+        if hunk.old.len() != old_count || hunk.new.len() != new_count {
+            return Err(Failure::new("invalidPatch", format!("hunk line counts don't match: @@ {header}")));
+        }
+        hunks.push(hunk);
+    }
+
+    if hunks.is_empty() {
+        return Err(Failure::new("invalidPatch", "patch has no hunks".into()));
+    }
+    Ok(hunks)
+}
+
+/// Parse `-a,b +c,d @@ ...` into `(a, b, d)`; counts default to 1.
+fn parse_hunk_header(header: &str) -> Option<(usize, usize, usize)> {
+    let mut parts = header.split_whitespace();
+    let range = |part: Option<&str>, sign: char| -> Option<(usize, usize)> {
+        let part = part?.strip_prefix(sign)?;
+        match part.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((part.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(parts.next(), '-')?;
+    let (_, new_count) = range(parts.next(), '+')?;
+    Some((old_start, old_count, new_count))
+}
+
+fn apply_patch(content: &str, patch: &str) -> Result<String, Failure> {
+    let hunks = parse_patch(patch)?;
+    let eol = if content.contains("\r\n") { "\r\n" } else { "\n" };
+    // Lines keep their terminators so untouched lines round-trip exactly
+    let mut lines: Vec<String> = content.split_inclusive('\n').map(String::from).collect();
+    let text = |line: &str| -> String {
+        line.strip_suffix('\n').map(|l| l.strip_suffix('\r').unwrap_or(l)).unwrap_or(line).to_string()
+    };
+
+    // Line delta from hunks already applied, and the first line a later
+    // hunk may touch
+    let mut offset: isize = 0;
+    let mut min_pos = 0;
+    for (n, hunk) in hunks.iter().enumerate() {
+        let hint = if hunk.old.is_empty() { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+        let hint = (hint as isize + offset).max(0) as usize;
+        let matches_at = |pos: usize| {
+            pos + hunk.old.len() <= lines.len()
+                && lines[pos..pos + hunk.old.len()].iter().zip(&hunk.old).all(|(l, o)| text(l) == *o)
+        };
+
+        let pos = if hunk.old.is_empty() {
+            if hint > lines.len() {
+                return Err(Failure::new("outOfBounds", format!("hunk {n} inserts past the end of the file"))
+                    .with("hunk", json!(n)));
+            }
+            hint
+        } else if hint >= min_pos && matches_at(hint) {
+            hint
+        } else {
+            // Content moved: accept it only if the hunk matches in exactly one place
+            let candidates: Vec<usize> = (min_pos..lines.len()).filter(|&p| matches_at(p)).collect();
+            match candidates.as_slice() {
+                [pos] => *pos,
+                [] => {
+                    return Err(Failure::new("notFound", format!(
+                        "hunk {n} (@@ -{}) does not match the file", hunk.old_start
+                    )).with("hunk", json!(n)));
+                }
+                _ => {
+                    return Err(Failure::new("ambiguous", format!(
+                        "hunk {n} (@@ -{}) does not match at its line and matches {} other places",
+                        hunk.old_start,
+                        candidates.len()
+                    ))
+                    .with("hunk", json!(n))
+                    .with("lines", json!(candidates.iter().map(|p| p + 1).collect::<Vec<_>>())));
+                }
+            }
+        };
+
+        let end = pos + hunk.old.len();
+        let at_eof = end == lines.len();
+        let replacement: Vec<String> = hunk.new.iter().map(|l| format!("{l}{eol}")).collect();
+        lines.splice(pos..end, replacement);
+        let last_new = pos + hunk.new.len();
+        if at_eof && hunk.new_no_eol && last_new > 0 {
+            let last = &mut lines[last_new - 1];
+            *last = text(last);
+        }
+        // A line that used to be last may now be followed by inserted ones
+        if pos > 0 && !lines[pos - 1].ends_with('\n') && pos < lines.len() {
+            lines[pos - 1].push_str(eol);
+        }
+
+        offset += hunk.new.len() as isize - hunk.old.len() as isize;
+        min_pos = last_new;
+    }
+    Ok(lines.concat())
+}
@@ -1,31 +1,339 @@
 //! File service — file I/O, directory operations, search, and file watching.
 
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use ecp_protocol::{ECPError, HandlerResult};
-use parking_lot::RwLock;
+use ecp_protocol::{ECPError, HandlerResult, Notifications};
+use parking_lot::{Condvar, Mutex, RwLock};
 use serde::Deserialize;
 use serde_json::json;
 use tracing::debug;
 
+use crate::edit::{apply_edits, Edit};
+use crate::file_content::{self, ChunkDecoder, Decoded, Encoding};
+use crate::file_index::{FileIndex, FileQuery};
+use crate::file_tree::{self, TreeOptions};
+use crate::git;
+use crate::grep::{Grep, GrepOptions};
+use crate::history::{FileHistory, HistoryEntry};
+use crate::journal::{hash_bytes, Journal, PendingEntry};
+use crate::trash::{Trash, TrashEntry};
+use crate::watch::NotifySender;
 use crate::Service;
 
+/// Chunk size for streamed reads, and the bounds a client may pick within.
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+const MIN_CHUNK_SIZE: usize = 4 * 1024;
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Chunks of a streamed read sent ahead of the client's acknowledgements.
+const STREAM_WINDOW: u64 = 4;
+
+/// How long a streamed read waits for an acknowledgement before giving up.
+const STREAM_ACK_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// File service implementation.
 pub struct FileService {
     workspace_root: RwLock<PathBuf>,
+    /// Change journal for mutating operations (set by the workspace registry).
+    journal: RwLock<Option<Arc<Journal>>>,
+    /// Sends `file/grep/result` notifications for streaming searches.
+    notify_tx: RwLock<Option<NotifySender>>,
+    /// Warm index behind `file/search`
+    index: Arc<FileIndex>,
+    /// Serializes precondition checks and writes in `file/write`/`file/edit`
+    write_lock: tokio::sync::Mutex<()>,
+    /// Local version history (set by the workspace registry).
+    history: RwLock<Option<Arc<FileHistory>>>,
+    /// Where `file/delete` and `file/deleteDir` move things
+    trash: RwLock<Arc<Trash>>,
+    /// Streamed reads in progress, by stream id
+    read_streams: Arc<RwLock<HashMap<String, Arc<ReadStream>>>>,
+}
+
+/// Flow control for one streamed read: chunks go out only while fewer than
+/// [`STREAM_WINDOW`] are unacknowledged.
+struct ReadStream {
+    /// Client that started it, which its chunks go to
+    client_id: Option<String>,
+    /// Chunks acknowledged so far
+    acked: Mutex<u64>,
+    ack_received: Condvar,
+    cancelled: AtomicBool,
+}
+
+impl ReadStream {
+    /// Wait until chunk `index` may be sent. Fails if the stream was
+    /// cancelled or the client stopped acknowledging.
+    fn wait_for_window(&self, index: u64) -> Result<(), ECPError> {
+        let mut acked = self.acked.lock();
+        while index >= *acked + STREAM_WINDOW && !self.cancelled.load(Ordering::Relaxed) {
+            if self.ack_received.wait_for(&mut acked, STREAM_ACK_TIMEOUT).timed_out() {
+                return Err(ECPError::server_error("Stream stalled: no chunk acknowledged"));
+            }
+        }
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(ECPError::server_error("Stream cancelled"));
+        }
+        Ok(())
+    }
+
+    fn ack(&self, index: u64) {
+        let mut acked = self.acked.lock();
+        *acked = (*acked).max(index + 1);
+        self.ack_received.notify_all();
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        let _acked = self.acked.lock();
+        self.ack_received.notify_all();
+    }
 }
 
 impl FileService {
     pub fn new(workspace_root: PathBuf) -> Self {
         Self {
+            index: Arc::new(FileIndex::new(workspace_root.clone())),
+            journal: RwLock::new(None),
+            notify_tx: RwLock::new(None),
+            write_lock: tokio::sync::Mutex::new(()),
+            history: RwLock::new(None),
+            trash: RwLock::new(Arc::new(Trash::new(workspace_root.clone()))),
+            read_streams: Arc::new(RwLock::new(HashMap::new())),
             workspace_root: RwLock::new(workspace_root),
         }
     }
 
     pub fn set_workspace_root(&self, root: PathBuf) {
+        *self.trash.write() = Arc::new(Trash::new(root.clone()));
         *self.workspace_root.write() = root;
     }
 
+    /// Record mutating operations in the given change journal.
+    pub fn set_journal(&self, journal: Arc<Journal>) {
+        *self.journal.write() = Some(journal);
+    }
+
+    /// Snapshot saved and externally changed versions into file history.
+    pub fn set_history(&self, history: Arc<FileHistory>) {
+        *self.history.write() = Some(history);
+    }
+
+    /// The file index, for wiring to watcher events and session recency.
+    pub fn file_index(&self) -> Arc<FileIndex> {
+        self.index.clone()
+    }
+
+    /// Set the notification callback for streaming search results.
+    pub fn set_notify_sender(&self, sender: NotifySender) {
+        *self.notify_tx.write() = Some(sender);
+    }
+
+    /// Snapshot `paths` ahead of a mutation, on the blocking pool: a
+    /// directory means reading every file in it.
+    async fn journal_begin(&self, paths: &[&Path]) -> Option<PendingEntry> {
+        let journal = self.journal.read().clone()?;
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.to_path_buf()).collect();
+        tokio::task::spawn_blocking(move || {
+            let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+            journal.begin(&paths)
+        })
+        .await
+        .ok()
+    }
+
+    /// Snapshot `paths` into file history, if enabled, on the blocking pool.
+    /// Called with `external` before a mutation (so a version never seen
+    /// before is kept) and with `save` after it.
+    async fn history_record(&self, paths: &[&Path], source: &'static str) {
+        let Some(history) = self.history.read().clone() else { return };
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.to_path_buf()).collect();
+        let _ = tokio::task::spawn_blocking(move || {
+            for path in paths {
+                history.record(&path, source);
+            }
+        })
+        .await;
+    }
+
+    fn history(&self) -> Result<Arc<FileHistory>, ECPError> {
+        self.history.read().clone()
+            .ok_or_else(|| ECPError::server_error("File history is not enabled for this workspace"))
+    }
+
+    /// Move `path` to the trash, or remove it for good when `permanent` (or
+    /// when it is already in the trash).
+    async fn delete(
+        &self,
+        path: PathBuf,
+        permanent: bool,
+        method: &str,
+        execution_id: Option<&str>,
+    ) -> HandlerResult {
+        let is_dir = method == "file/deleteDir";
+        let meta = tokio::fs::symlink_metadata(&path).await
+            .map_err(|e| ECPError::server_error(format!("Failed to delete {}: {e}", path.display())))?;
+        if !is_dir && meta.is_dir() {
+            return Err(ECPError::server_error(format!(
+                "Failed to delete {}: is a directory; use file/deleteDir", path.display()
+            )));
+        }
+        let trash = self.trash.read().clone();
+        let permanent = permanent || trash.contains(&path);
+
+        self.history_record(&[&path], "external").await;
+        let pending = self.journal_begin(&[&path]).await;
+        let trashed = if permanent {
+            let removed = if is_dir {
+                tokio::fs::remove_dir_all(&path).await
+            } else {
+                tokio::fs::remove_file(&path).await
+            };
+            removed.map(|()| None)
+        } else {
+            let target = path.clone();
+            tokio::task::spawn_blocking(move || trash.put(&target)).await
+                .map_err(|e| ECPError::server_error(format!("Trash task failed: {e}")))?
+                .map(Some)
+        };
+        match trashed {
+            Ok(entry) => {
+                let entry_id = self.journal_commit(pending, method, execution_id).await;
+                Ok(json!({
+                    "success": true,
+                    "journalEntryId": entry_id,
+                    "trashId": entry.map(|e| e.id),
+                }))
+            }
+            Err(e) if is_dir => Err(ECPError::server_error(format!("Failed to delete directory: {e}"))),
+            Err(e) => Err(ECPError::server_error(format!("Failed to delete {}: {e}", path.display()))),
+        }
+    }
+
+    /// Complete a journal entry; returns its id if anything changed.
+    async fn journal_commit(
+        &self,
+        pending: Option<PendingEntry>,
+        method: &str,
+        execution_id: Option<&str>,
+    ) -> Option<String> {
+        let journal = self.journal.read().clone()?;
+        let pending = pending?;
+        let method = method.to_string();
+        let execution_id = execution_id.map(String::from);
+        tokio::task::spawn_blocking(move || {
+            journal.commit(pending, &method, execution_id.as_deref()).map(|e| e.id)
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// Journal an append of `bytes` to a file that was `appended_at` bytes
+    /// long, if journaling is enabled.
+    async fn journal_append(
+        &self,
+        path: &Path,
+        appended_at: u64,
+        bytes: Vec<u8>,
+        method: &str,
+        execution_id: Option<&str>,
+    ) -> Option<String> {
+        let journal = self.journal.read().clone()?;
+        let path = path.to_path_buf();
+        let method = method.to_string();
+        let execution_id = execution_id.map(String::from);
+        tokio::task::spawn_blocking(move || {
+            journal.record_append(&path, appended_at, &bytes, &method, execution_id.as_deref()).map(|e| e.id)
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// Stream `length` bytes from `offset` (to the end if `None`) as
+    /// `file/read/chunk` notifications to the requesting client, the last one
+    /// marked `done`. Each chunk carries its index; the client acknowledges
+    /// them with `file/read/ack` to let more through.
+    fn stream_read(
+        &self,
+        path: PathBuf,
+        offset: u64,
+        length: Option<u64>,
+        encoding: Encoding,
+        chunk_size: Option<usize>,
+        client_id: Option<String>,
+    ) -> String {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let stream_id = format!("read-{}", uuid::Uuid::new_v4());
+        let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE).clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        let notify_tx = self.notify_tx.read().clone();
+        let stream = Arc::new(ReadStream {
+            client_id,
+            acked: Mutex::new(0),
+            ack_received: Condvar::new(),
+            cancelled: AtomicBool::new(false),
+        });
+        self.read_streams.write().insert(stream_id.clone(), stream.clone());
+        let streams = self.read_streams.clone();
+        let id = stream_id.clone();
+        tokio::task::spawn_blocking(move || {
+            let send = |mut params: serde_json::Value| {
+                if let Some(ref tx) = notify_tx {
+                    if let Some(ref client_id) = stream.client_id {
+                        params["_clients"] = json!([client_id]);
+                    }
+                    tx(Notifications::FILE_READ_CHUNK, params);
+                }
+            };
+            let run = || -> Result<u64, ECPError> {
+                let io_error = |e: std::io::Error| ECPError::server_error(format!("Failed to read {}: {e}", path.display()));
+                let mut file = std::fs::File::open(&path).map_err(io_error)?;
+                file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+                let mut reader = file.take(length.unwrap_or(u64::MAX));
+                let mut buf = vec![0u8; chunk_size];
+                let mut decoder: Option<ChunkDecoder> = None;
+                let mut position = offset;
+                let mut index = 0;
+                loop {
+                    let n = reader.read(&mut buf).map_err(io_error)?;
+                    let chunk = &buf[..n];
+                    let decoder = decoder.get_or_insert_with(|| {
+                        ChunkDecoder::new(file_content::detect(chunk, encoding, n == 0), offset == 0)
+                    });
+                    let content = decoder.decode(chunk, n == 0)?;
+                    if !content.is_empty() {
+                        stream.wait_for_window(index)?;
+                        send(json!({
+                            "streamId": id,
+                            "chunk": index,
+                            "offset": position,
+                            "content": content,
+                            "encoding": decoder.encoding().as_str(),
+                        }));
+                        index += 1;
+                    }
+                    position += n as u64;
+                    if n == 0 {
+                        return Ok(position - offset);
+                    }
+                }
+            };
+            let result = run();
+            streams.write().remove(&id);
+            match result {
+                Ok(bytes_read) => send(json!({ "streamId": id, "done": true, "bytesRead": bytes_read })),
+                Err(e) => send(json!({ "streamId": id, "done": true, "error": e.message })),
+            }
+        });
+        stream_id
+    }
+
     /// Resolve a path relative to the workspace root.
     /// Security: rejects paths that escape the workspace via traversal.
     fn resolve_path(&self, path: &str) -> Result<PathBuf, ECPError> {
@@ -43,8 +351,8 @@ impl FileService {
         // Instead, normalize and check prefix.
         let normalized = normalize_path(&resolved);
 
-        // For absolute paths outside workspace, allow them (the ECP is trusted).
-        // But log for audit.
+        // Absolute paths outside the workspace are allowed here; when sandbox
+        // mode is on the router rejects them before they reach this service.
         if !normalized.starts_with(&root) {
             debug!("File access outside workspace: {}", normalized.display());
         }
@@ -58,28 +366,122 @@ impl Service for FileService {
         "file"
     }
 
+    async fn init(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Build the search index in the background so opening stays fast
+        let index = self.index.clone();
+        tokio::spawn(async move { index.ready().await });
+        Ok(())
+    }
+
     async fn handle(&self, method: &str, params: Option<serde_json::Value>) -> HandlerResult {
+        // Agent tool calls carry the id of the AI execution that issued them
+        let execution_id = params.as_ref()
+            .and_then(|p| p.get("_executionId"))
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        let execution_id = execution_id.as_deref();
+
         match method {
             "file/read" => {
                 let p: FileReadParams = parse_params(params)?;
                 let path = self.resolve_path(&p.path)?;
+                let read_error = |message: String| {
+                    ECPError::server_error(format!("Failed to read {}: {message}", path.display()))
+                };
+                let meta = tokio::fs::metadata(&path).await.map_err(|e| read_error(e.to_string()))?;
+                let size = meta.len();
+                let mod_time = file_mod_time(&meta);
+
+                let byte_range = p.offset.is_some() || p.length.is_some();
+                let line_range = p.start_line.is_some() || p.end_line.is_some();
+                if byte_range && line_range {
+                    return Err(ECPError::invalid_params(
+                        "Use either offset/length or startLine/endLine, not both",
+                    ));
+                }
 
-                match tokio::fs::read_to_string(&path).await {
-                    Ok(content) => {
-                        let meta = tokio::fs::metadata(&path).await.ok();
-                        let mod_time = meta.as_ref().and_then(|m| file_mod_time(m));
-                        let size = meta.as_ref().map(|m| m.len()).unwrap_or(content.len() as u64);
-                        Ok(json!({
-                            "content": content,
-                            "encoding": "utf-8",
-                            "modTime": mod_time,
-                            "size": size,
-                        }))
+                if p.stream {
+                    if line_range {
+                        return Err(ECPError::invalid_params("Streaming reads take offset/length, not lines"));
                     }
-                    Err(e) => Err(ECPError::server_error(format!(
-                        "Failed to read {}: {e}", path.display()
-                    ))),
+                    let stream_id = self.stream_read(
+                        path, p.offset.unwrap_or(0), p.length, p.encoding, p.chunk_size, p.client_id,
+                    );
+                    return Ok(json!({ "streamId": stream_id, "size": size, "modTime": mod_time }));
+                }
+
+                if line_range {
+                    let start = p.start_line.unwrap_or(1).max(1);
+                    let (file, end) = (path.clone(), p.end_line);
+                    let range = tokio::task::spawn_blocking(move || file_content::read_lines(&file, start, end))
+                        .await
+                        .map_err(|e| read_error(e.to_string()))?
+                        .map_err(|e| read_error(e.to_string()))?;
+                    let (content, encoding) = file_content::decode(&range.bytes, p.encoding, range.offset == 0)
+                        .map_err(|e| read_error(e.message))?;
+                    return Ok(json!({
+                        "content": content,
+                        "encoding": encoding.as_str(),
+                        "modTime": mod_time,
+                        "size": size,
+                        "offset": range.offset,
+                        "length": range.bytes.len(),
+                        "startLine": start,
+                        "endLine": range.end,
+                        "totalLines": range.total_lines,
+                        "eof": range.end >= range.total_lines,
+                    }));
+                }
+
+                if byte_range {
+                    let offset = p.offset.unwrap_or(0);
+                    let (file, length) = (path.clone(), p.length);
+                    let bytes = tokio::task::spawn_blocking(move || file_content::read_bytes(&file, offset, length))
+                        .await
+                        .map_err(|e| read_error(e.to_string()))?
+                        .map_err(|e| read_error(e.to_string()))?;
+                    // Text is cut to whole characters; the reported range says
+                    // which bytes were actually returned
+                    let mut kept = 0..bytes.len();
+                    if p.encoding != Encoding::Base64 {
+                        let aligned = file_content::utf8_aligned(&bytes, offset == 0);
+                        if file_content::detect(&bytes[aligned.clone()], p.encoding, true) == Decoded::Utf8 {
+                            kept = aligned;
+                        }
+                    }
+                    let offset = offset + kept.start as u64;
+                    let (content, encoding) = file_content::decode(&bytes[kept.clone()], p.encoding, offset == 0)
+                        .map_err(|e| read_error(e.message))?;
+                    return Ok(json!({
+                        "content": content,
+                        "encoding": encoding.as_str(),
+                        "modTime": mod_time,
+                        "size": size,
+                        "offset": offset,
+                        "length": kept.len(),
+                        "eof": offset + kept.len() as u64 >= size,
+                    }));
                 }
+
+                let bytes = tokio::fs::read(&path).await.map_err(|e| read_error(e.to_string()))?;
+                let (content, encoding) = file_content::decode(&bytes, p.encoding, true)
+                    .map_err(|e| read_error(e.message))?;
+                Ok(json!({
+                    "content": content,
+                    "encoding": encoding.as_str(),
+                    "modTime": mod_time,
+                    "size": size,
+                    "hash": hash_bytes(&bytes),
+                }))
+            }
+
+            "file/read/ack" => {
+                let p: FileReadAckParams = parse_params(params)?;
+                let stream = self.read_streams.read().get(&p.stream_id).cloned();
+                if let Some(ref stream) = stream {
+                    stream.ack(p.chunk);
+                }
+                Ok(json!({ "success": stream.is_some() }))
             }
 
             "file/write" => {
@@ -87,23 +489,57 @@ impl Service for FileService {
                 let path = self.resolve_path(&p.path)?;
 
                 // Ensure parent directory exists
-                if let Some(parent) = path.parent() {
-                    if let Err(e) = tokio::fs::create_dir_all(parent).await {
-                        return Err(ECPError::server_error(format!(
-                            "Failed to create directory: {e}"
-                        )));
-                    }
+                if let Some(parent) = path.parent()
+                    && let Err(e) = tokio::fs::create_dir_all(parent).await {
+                    return Err(ECPError::server_error(format!(
+                        "Failed to create directory: {e}"
+                    )));
                 }
 
-                let bytes_written = p.content.len() as u64;
-                match tokio::fs::write(&path, &p.content).await {
+                let _guard = self.write_lock.lock().await;
+                check_expected(&path, p.expected_mtime, p.expected_hash.as_deref()).await?;
+
+                let bytes = file_content::encode(&p.content, p.encoding)?;
+                let bytes_written = bytes.len() as u64;
+                // Appends to an existing file (logs, mostly) never read it:
+                // the journal keeps the added bytes, history skips them, and
+                // no hash of the whole file is returned
+                let appended_at = if p.append {
+                    tokio::fs::metadata(&path).await.ok().filter(|m| m.is_file()).map(|m| m.len())
+                } else {
+                    None
+                };
+                let pending = match appended_at {
+                    Some(_) => None,
+                    None => {
+                        self.history_record(&[&path], "external").await;
+                        self.journal_begin(&[&path]).await
+                    }
+                };
+                let written = if p.append {
+                    append_file(&path, &bytes).await
+                } else {
+                    write_atomic(&path, &bytes).await
+                };
+                match written {
                     Ok(()) => {
+                        let hash = appended_at.is_none().then(|| hash_bytes(&bytes));
+                        let entry_id = match appended_at {
+                            Some(at) => self.journal_append(&path, at, bytes, method, execution_id).await,
+                            None => {
+                                let entry_id = self.journal_commit(pending, method, execution_id).await;
+                                self.history_record(&[&path], "save").await;
+                                entry_id
+                            }
+                        };
                         let mod_time = tokio::fs::metadata(&path).await.ok()
                             .and_then(|m| file_mod_time(&m));
                         Ok(json!({
                             "success": true,
                             "modTime": mod_time,
+                            "hash": hash,
                             "bytesWritten": bytes_written,
+                            "journalEntryId": entry_id,
                         }))
                     }
                     Err(e) => Err(ECPError::server_error(format!(
@@ -150,16 +586,10 @@ impl Service for FileService {
                 }
             }
 
-            "file/delete" => {
-                let p: FilePathParam = parse_params(params)?;
+            "file/delete" | "file/deleteDir" => {
+                let p: FileDeleteParams = parse_params(params)?;
                 let path = self.resolve_path(&p.path)?;
-
-                match tokio::fs::remove_file(&path).await {
-                    Ok(()) => Ok(json!({ "success": true })),
-                    Err(e) => Err(ECPError::server_error(format!(
-                        "Failed to delete {}: {e}", path.display()
-                    ))),
-                }
+                self.delete(path, p.permanent, method, execution_id).await
             }
 
             "file/rename" => {
@@ -167,8 +597,12 @@ impl Service for FileService {
                 let from = self.resolve_path(&p.from)?;
                 let to = self.resolve_path(&p.to)?;
 
+                let pending = self.journal_begin(&[&from, &to]).await;
                 match tokio::fs::rename(&from, &to).await {
-                    Ok(()) => Ok(json!({ "success": true })),
+                    Ok(()) => {
+                        let entry_id = self.journal_commit(pending, method, execution_id).await;
+                        Ok(json!({ "success": true, "journalEntryId": entry_id }))
+                    }
                     Err(e) => Err(ECPError::server_error(format!("Failed to rename: {e}"))),
                 }
             }
@@ -178,8 +612,12 @@ impl Service for FileService {
                 let from = self.resolve_path(&p.from)?;
                 let to = self.resolve_path(&p.to)?;
 
+                let pending = self.journal_begin(&[&to]).await;
                 match tokio::fs::copy(&from, &to).await {
-                    Ok(_) => Ok(json!({ "success": true })),
+                    Ok(_) => {
+                        let entry_id = self.journal_commit(pending, method, execution_id).await;
+                        Ok(json!({ "success": true, "journalEntryId": entry_id }))
+                    }
                     Err(e) => Err(ECPError::server_error(format!("Failed to copy: {e}"))),
                 }
             }
@@ -207,7 +645,7 @@ impl Service for FileService {
                                 "uri": file_uri(&entry_path),
                                 "type": file_type,
                                 "size": meta.as_ref().map(|m| m.len()),
-                                "modTime": meta.as_ref().and_then(|m| file_mod_time(m)),
+                                "modTime": meta.as_ref().and_then(file_mod_time),
                             }));
                         }
                         Ok(json!({ "entries": items }))
@@ -230,18 +668,6 @@ impl Service for FileService {
                 }
             }
 
-            "file/deleteDir" => {
-                let p: FilePathParam = parse_params(params)?;
-                let path = self.resolve_path(&p.path)?;
-
-                match tokio::fs::remove_dir_all(&path).await {
-                    Ok(()) => Ok(json!({ "success": true })),
-                    Err(e) => Err(ECPError::server_error(format!(
-                        "Failed to delete directory: {e}"
-                    ))),
-                }
-            }
-
             "file/getParent" => {
                 let p: FilePathParam = parse_params(params)?;
                 let path = PathBuf::from(&p.path);
@@ -284,19 +710,39 @@ impl Service for FileService {
             "file/edit" => {
                 let p: FileEditParams = parse_params(params)?;
                 let path = self.resolve_path(&p.uri)?;
+                let edits = p.edits()?;
+                let _guard = self.write_lock.lock().await;
+                check_expected(&path, p.expected_mtime, p.expected_hash.as_deref()).await?;
                 let content = tokio::fs::read_to_string(&path).await
                     .map_err(|e| ECPError::server_error(format!("Failed to read {}: {e}", path.display())))?;
 
-                let new_content = if p.replace_all {
-                    content.replace(&p.old_string, &p.new_string)
-                } else {
-                    content.replacen(&p.old_string, &p.new_string, 1)
-                };
-
-                tokio::fs::write(&path, &new_content).await
+                let new_content = apply_edits(&content, &edits)?;
+                let display = path.strip_prefix(&*self.workspace_root.read())
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .to_string();
+                let diff = similar::TextDiff::from_lines(&content, &new_content)
+                    .unified_diff()
+                    .header(&format!("a/{display}"), &format!("b/{display}"))
+                    .to_string();
+
+                self.history_record(&[&path], "external").await;
+                let pending = self.journal_begin(&[&path]).await;
+                write_atomic(&path, new_content.as_bytes()).await
                     .map_err(|e| ECPError::server_error(format!("Failed to write {}: {e}", path.display())))?;
-
-                Ok(json!({ "success": true }))
+                let entry_id = self.journal_commit(pending, method, execution_id).await;
+                self.history_record(&[&path], "save").await;
+                let mod_time = tokio::fs::metadata(&path).await.ok()
+                    .and_then(|m| file_mod_time(&m));
+
+                Ok(json!({
+                    "success": true,
+                    "modTime": mod_time,
+                    "hash": hash_bytes(new_content.as_bytes()),
+                    "editsApplied": edits.len(),
+                    "diff": diff,
+                    "journalEntryId": entry_id,
+                }))
             }
 
             "file/browseDir" => {
@@ -346,50 +792,59 @@ impl Service for FileService {
                 }
             }
 
-            "file/search" => {
-                let p: FileSearchParams = parse_params(params)?;
-                let root = self.workspace_root.read().clone();
-                let max = p.max_results.unwrap_or(100) as usize;
-                let case_sensitive = p.case_sensitive.unwrap_or(false);
-                let pattern = if case_sensitive { p.pattern.clone() } else { p.pattern.to_lowercase() };
-                let mut results = Vec::new();
-
-                fn walk_search(
-                    dir: &Path,
-                    pattern: &str,
-                    case_sensitive: bool,
-                    max: usize,
-                    results: &mut Vec<serde_json::Value>,
-                ) {
-                    if results.len() >= max { return; }
-                    if let Ok(entries) = std::fs::read_dir(dir) {
-                        for entry in entries.flatten() {
-                            if results.len() >= max { return; }
-                            let name = entry.file_name().to_string_lossy().to_string();
-                            let path = entry.path();
-                            let is_dir = path.is_dir();
-
-                            // Skip hidden dirs
-                            if name.starts_with('.') { continue; }
-
-                            let test_name = if case_sensitive { name.clone() } else { name.to_lowercase() };
-                            if test_name.contains(pattern) {
-                                results.push(serde_json::json!({
-                                    "uri": format!("file://{}", path.display()),
-                                    "name": name,
-                                    "score": 1.0,
-                                }));
-                            }
-
-                            if is_dir {
-                                walk_search(&path, pattern, case_sensitive, max, results);
-                            }
+            "file/tree" => {
+                let p: FileTreeParams = parse_params(params.or(Some(json!({}))))?;
+                let (path, options) = match p.expand_token {
+                    Some(ref token) => {
+                        let (path, options) = file_tree::parse_token(token)
+                            .ok_or_else(|| ECPError::invalid_params("Invalid expandToken"))?;
+                        // Tokens are client-controlled, so they only reach into the
+                        // workspace, with symlinks followed
+                        let path = normalize_path(&path);
+                        let root = self.workspace_root.read().clone();
+                        let inside = path.canonicalize().ok()
+                            .zip(root.canonicalize().ok())
+                            .is_some_and(|(path, root)| path.starts_with(root));
+                        if !inside {
+                            return Err(ECPError::invalid_params(
+                                "expandToken is outside the workspace; request the path directly",
+                            ));
                         }
+                        (path, options)
                     }
-                }
+                    None => {
+                        let root = self.workspace_root.read().clone();
+                        let path = match p.path {
+                            Some(ref path) => self.resolve_path(path)?,
+                            None => root,
+                        };
+                        (path, p.options)
+                    }
+                };
 
-                walk_search(&root, &pattern, case_sensitive, max, &mut results);
-                Ok(json!({ "results": results }))
+                let statuses = if options.git_status {
+                    git::path_statuses(&path).await
+                } else {
+                    Default::default()
+                };
+                let dir = path.clone();
+                tokio::task::spawn_blocking(move || file_tree::build(&dir, &options, statuses)).await
+                    .map_err(|e| ECPError::server_error(format!("Tree listing failed: {e}")))?
+                    .map_err(|e| ECPError::server_error(format!("Failed to list {}: {e}", path.display())))
+            }
+
+            "file/search" => {
+                let p: FileSearchParams = parse_params(params)?;
+                self.index.ready().await;
+                let index = self.index.clone();
+                let query = FileQuery {
+                    pattern: p.pattern,
+                    max_results: p.max_results.unwrap_or(100) as usize,
+                    case_sensitive: p.case_sensitive,
+                };
+                let results = tokio::task::spawn_blocking(move || index.search(&query)).await
+                    .map_err(|e| ECPError::server_error(format!("Search failed: {e}")))?;
+                Ok(json!({ "results": results, "indexedFiles": self.index.len() }))
             }
 
             "file/glob" => {
@@ -420,48 +875,355 @@ impl Service for FileService {
                 } else {
                     self.workspace_root.read().clone()
                 };
-                let max = p.max_results.unwrap_or(200) as usize;
-                let case_sensitive = p.case_sensitive.unwrap_or(true);
+                let context = p.context_lines.unwrap_or(0);
+                let grep = Grep::new(GrepOptions {
+                    pattern: p.pattern,
+                    literal: p.literal,
+                    case_sensitive: p.case_sensitive.unwrap_or(true),
+                    whole_word: p.whole_word,
+                    multiline: p.multiline,
+                    include: p.glob.into_iter().chain(p.include).collect(),
+                    exclude: p.exclude,
+                    context_before: p.before_context.unwrap_or(context),
+                    context_after: p.after_context.unwrap_or(context),
+                    max_results: p.max_results.unwrap_or(200) as usize,
+                    include_hidden: p.include_hidden,
+                    respect_ignore: p.respect_ignore.unwrap_or(true),
+                })?;
+
+                if !p.stream {
+                    let (matches, summary) = tokio::task::spawn_blocking(move || {
+                        let mut matches = Vec::new();
+                        let summary = grep.run(&search_path, |_, results| matches.extend(results));
+                        (matches, summary)
+                    }).await.map_err(|e| ECPError::server_error(format!("Grep failed: {e}")))?;
+
+                    let mut result = summary.to_json();
+                    result["matches"] = json!(matches);
+                    return Ok(result);
+                }
 
-                let mut args = vec!["-rn".to_string()];
-                if !case_sensitive {
-                    args.push("-i".to_string());
+                // Streaming: results arrive as notifications, the last one
+                // carrying the summary
+                let search_id = format!("grep-{}", uuid::Uuid::new_v4());
+                let notify_tx = self.notify_tx.read().clone();
+                let id = search_id.clone();
+                let client_id = p.client_id;
+                tokio::task::spawn_blocking(move || {
+                    let send = |mut params: serde_json::Value| {
+                        if let Some(ref tx) = notify_tx {
+                            if let Some(ref client_id) = client_id {
+                                params["_clients"] = json!([client_id]);
+                            }
+                            tx(Notifications::FILE_GREP_RESULT, params);
+                        }
+                    };
+                    let summary = grep.run(&search_path, |path, results| send(json!({
+                        "searchId": id,
+                        "file": path.to_string_lossy(),
+                        "uri": file_uri(path),
+                        "matches": results,
+                    })));
+                    let mut done = summary.to_json();
+                    done["searchId"] = json!(id);
+                    done["done"] = json!(true);
+                    send(done);
+                });
+                Ok(json!({ "searchId": search_id }))
+            }
+
+            "file/replaceAll" => {
+                let p: FileReplaceAllParams = parse_params(params)?;
+                let root = self.workspace_root.read().clone();
+                let search_path = match p.path {
+                    Some(ref path) => self.resolve_path(path)?,
+                    None => root.clone(),
+                };
+                let grep = Grep::new(GrepOptions {
+                    pattern: p.pattern,
+                    literal: p.literal,
+                    case_sensitive: p.case_sensitive.unwrap_or(true),
+                    whole_word: p.whole_word,
+                    multiline: true,
+                    include: p.include,
+                    exclude: p.exclude,
+                    context_before: 0,
+                    context_after: 0,
+                    max_results: usize::MAX,
+                    include_hidden: p.include_hidden,
+                    respect_ignore: p.respect_ignore.unwrap_or(true),
+                })?;
+
+                // Hold the write lock from reading to writing so the preview
+                // is exactly what gets applied
+                let _guard = if p.dry_run { None } else { Some(self.write_lock.lock().await) };
+                let replacement = p.replacement;
+                let (changes, files_searched) = tokio::task::spawn_blocking(move || {
+                    let mut changes: Vec<Replacement> = Vec::new();
+                    let searched = grep.for_each_file(&search_path, |path, bytes| {
+                        // Rewriting lossily decoded content would corrupt the file
+                        let Ok(content) = std::str::from_utf8(bytes) else { return true };
+                        let (new_content, count) = grep.replace(content, &replacement);
+                        if count > 0 && new_content != content {
+                            changes.push(Replacement {
+                                path: path.to_path_buf(),
+                                old: content.to_string(),
+                                new: new_content.into_owned(),
+                                count,
+                            });
+                        }
+                        true
+                    });
+                    (changes, searched)
+                }).await.map_err(|e| ECPError::server_error(format!("Replace failed: {e}")))?;
+
+                let relative = |path: &Path| path.strip_prefix(&root).unwrap_or(path).to_string_lossy().to_string();
+                let stale: Vec<serde_json::Value> = changes.iter()
+                    .filter_map(|c| {
+                        let expected = p.expected_hashes.get(&relative(&c.path))?;
+                        let hash = hash_bytes(c.old.as_bytes());
+                        (*expected != hash).then(|| json!({ "path": relative(&c.path), "hash": hash }))
+                    })
+                    .collect();
+                if !stale.is_empty() {
+                    return Err(ECPError::conflict(format!("{} file(s) changed since the preview", stale.len()))
+                        .with_data(json!({ "files": stale })));
                 }
-                if let Some(ref glob_pat) = p.glob {
-                    args.push("--include".to_string());
-                    args.push(glob_pat.clone());
+
+                let files: Vec<serde_json::Value> = changes.iter().map(|c| {
+                    let path = relative(&c.path);
+                    let diff = similar::TextDiff::from_lines(&c.old, &c.new);
+                    let mut file = json!({
+                        "path": path,
+                        "uri": file_uri(&c.path),
+                        "replacements": c.count,
+                        "hash": hash_bytes(c.old.as_bytes()),
+                        "diff": diff.unified_diff()
+                            .header(&format!("a/{path}"), &format!("b/{path}"))
+                            .to_string(),
+                    });
+                    if p.dry_run {
+                        file["hunks"] = json!(diff_hunks(&diff));
+                    }
+                    file
+                }).collect();
+                let replacement_count: usize = changes.iter().map(|c| c.count).sum();
+                let mut result = json!({
+                    "dryRun": p.dry_run,
+                    "files": files,
+                    "fileCount": changes.len(),
+                    "replacementCount": replacement_count,
+                    "filesSearched": files_searched,
+                });
+                if p.dry_run || changes.is_empty() {
+                    return Ok(result);
                 }
-                args.push(p.pattern.clone());
-                args.push(search_path.to_string_lossy().to_string());
-
-                let output = tokio::process::Command::new("grep")
-                    .args(&args)
-                    .output()
-                    .await
-                    .map_err(|e| ECPError::server_error(format!("Grep failed: {e}")))?;
-
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                let mut matches = Vec::new();
-                for line in stdout.lines().take(max) {
-                    // Format: file:line:text
-                    if let Some((file_and_line, text)) = line.split_once(':') {
-                        if let Some((file, line_no)) = file_and_line.rsplit_once(':') {
-                            matches.push(json!({
-                                "file": file,
-                                "line": line_no.parse::<u64>().unwrap_or(0),
-                                "column": 0,
-                                "text": text,
-                            }));
+
+                // Apply: every file or none
+                let paths: Vec<&Path> = changes.iter().map(|c| c.path.as_path()).collect();
+                self.history_record(&paths, "external").await;
+                let pending = self.journal_begin(&paths).await;
+                for (i, change) in changes.iter().enumerate() {
+                    if let Err(e) = write_atomic(&change.path, change.new.as_bytes()).await {
+                        for done in &changes[..i] {
+                            let _ = write_atomic(&done.path, done.old.as_bytes()).await;
                         }
+                        return Err(ECPError::server_error(format!(
+                            "Failed to write {}: {e}; no files were changed", change.path.display()
+                        )));
+                    }
+                }
+                let entry_id = self.journal_commit(pending, method, execution_id).await;
+                self.history_record(&paths, "save").await;
+
+                if let Some(ref tx) = *self.notify_tx.read() {
+                    for change in &changes {
+                        tx(Notifications::FILE_DID_CHANGE, json!({
+                            "uri": file_uri(&change.path),
+                            "type": "changed",
+                            "timestamp": now_ms(),
+                        }));
                     }
                 }
+                result["undoToken"] = json!(entry_id);
+                result["journalEntryId"] = json!(entry_id);
+                Ok(result)
+            }
+
+            "file/history/list" => {
+                let p: FileHistoryListParams = parse_params(params.or(Some(json!({}))))?;
+                let history = self.history()?;
+                let path = p.path.as_deref().map(|path| self.resolve_path(path)).transpose()?;
+                let entries: Vec<serde_json::Value> = history.list(path.as_deref(), p.limit.unwrap_or(100) as usize)
+                    .into_iter()
+                    .map(|entry| {
+                        let mut value = json!(entry);
+                        value["uri"] = json!(file_uri(&history.absolute(&entry)));
+                        value
+                    })
+                    .collect();
+                Ok(json!({ "entries": entries }))
+            }
+
+            "file/history/get" => {
+                let p: FileHistoryIdParams = parse_params(params)?;
+                let history = self.history()?;
+                let (entry, bytes) = history_version(&history, &p.id)?;
+                let (content, encoding) = file_content::decode(&bytes, p.encoding, true)?;
+                Ok(json!({
+                    "entry": entry,
+                    "content": content,
+                    "encoding": encoding.as_str(),
+                }))
+            }
+
+            "file/history/diff" => {
+                let p: FileHistoryDiffParams = parse_params(params)?;
+                let history = self.history()?;
+                let (entry, old) = history_version(&history, &p.id)?;
+                // Against another version, or the file as it is now
+                let new = match p.against {
+                    Some(ref against) => history_version(&history, against)?.1,
+                    None => tokio::fs::read(history.absolute(&entry)).await.unwrap_or_default(),
+                };
+                match (std::str::from_utf8(&old), std::str::from_utf8(&new)) {
+                    (Ok(old), Ok(new)) => {
+                        let diff = similar::TextDiff::from_lines(old, new)
+                            .unified_diff()
+                            .header(&format!("a/{}", entry.path), &format!("b/{}", entry.path))
+                            .to_string();
+                        Ok(json!({ "entry": entry, "binary": false, "diff": diff }))
+                    }
+                    _ => Ok(json!({ "entry": entry, "binary": true, "diff": null })),
+                }
+            }
+
+            "file/history/restore" => {
+                let p: FileHistoryRestoreParams = parse_params(params)?;
+                let history = self.history()?;
+                let (entry, bytes) = history_version(&history, &p.id)?;
+                let path = history.absolute(&entry);
+
+                let _guard = self.write_lock.lock().await;
+                check_expected(&path, p.expected_mtime, p.expected_hash.as_deref()).await?;
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await
+                        .map_err(|e| ECPError::server_error(format!("Failed to create directory: {e}")))?;
+                }
+                self.history_record(&[&path], "external").await;
+                let pending = self.journal_begin(&[&path]).await;
+                write_atomic(&path, &bytes).await
+                    .map_err(|e| ECPError::server_error(format!("Failed to write {}: {e}", path.display())))?;
+                let entry_id = self.journal_commit(pending, method, execution_id).await;
+                self.history_record(&[&path], "restore").await;
+                let mod_time = tokio::fs::metadata(&path).await.ok()
+                    .and_then(|m| file_mod_time(&m));
+
+                Ok(json!({
+                    "success": true,
+                    "uri": file_uri(&path),
+                    "modTime": mod_time,
+                    "hash": entry.hash,
+                    "journalEntryId": entry_id,
+                }))
+            }
+
+            "file/trash/list" => {
+                let trash = self.trash.read().clone();
+                let items: Vec<serde_json::Value> = trash.list()
+                    .into_iter()
+                    .map(|entry| {
+                        let mut value = json!(entry);
+                        value["uri"] = json!(file_uri(&trash.original(&entry)));
+                        value
+                    })
+                    .collect();
+                Ok(json!({ "items": items }))
+            }
 
-                Ok(json!({ "matches": matches }))
+            "file/trash/restore" => {
+                let p: FileTrashRestoreParams = parse_params(params)?;
+                let trash = self.trash.read().clone();
+                let entry = trash_entry(&trash, &p.id)?;
+                let to = match p.path {
+                    Some(ref path) => self.resolve_path(path)?,
+                    None => trash.original(&entry),
+                };
+                if tokio::fs::symlink_metadata(&to).await.is_ok() {
+                    return Err(ECPError::conflict(format!(
+                        "Cannot restore {}: {} already exists", entry.original_path, to.display()
+                    ))
+                    .with_data(json!({ "uri": file_uri(&to) })));
+                }
+
+                let pending = self.journal_begin(&[&to]).await;
+                let target = to.clone();
+                let restore_entry = entry.clone();
+                tokio::task::spawn_blocking(move || trash.restore(&restore_entry, &target)).await
+                    .map_err(|e| ECPError::server_error(format!("Trash task failed: {e}")))?
+                    .map_err(|e| ECPError::server_error(format!("Failed to restore {}: {e}", entry.original_path)))?;
+                let entry_id = self.journal_commit(pending, method, execution_id).await;
+                Ok(json!({ "success": true, "uri": file_uri(&to), "journalEntryId": entry_id }))
+            }
+
+            "file/trash/empty" => {
+                let p: FileTrashEmptyParams = parse_params(params.or(Some(json!({}))))?;
+                let trash = self.trash.read().clone();
+                let entries = match p.ids {
+                    Some(ref ids) => ids.iter().map(|id| trash_entry(&trash, id)).collect::<Result<Vec<_>, _>>()?,
+                    None => trash.list(),
+                };
+                let count = entries.len();
+                // Each item may be a whole directory tree
+                let removed = tokio::task::spawn_blocking(move || {
+                    entries.iter()
+                        .filter(|entry| match trash.remove(entry) {
+                            Ok(()) => true,
+                            Err(e) => {
+                                debug!("Failed to empty trash item {}: {e}", entry.id);
+                                false
+                            }
+                        })
+                        .count()
+                })
+                .await
+                .map_err(|e| ECPError::server_error(format!("Trash task failed: {e}")))?;
+                Ok(json!({ "success": removed == count, "removed": removed }))
             }
 
             _ => Err(ECPError::method_not_found(method)),
         }
     }
+
+    fn client_disconnected(&self, client_id: &str) {
+        // Nobody is left to acknowledge their chunks
+        for stream in self.read_streams.read().values() {
+            if stream.client_id.as_deref() == Some(client_id) {
+                stream.cancel();
+            }
+        }
+    }
+}
+
+fn trash_entry(trash: &Trash, id: &str) -> Result<TrashEntry, ECPError> {
+    trash.get(id).ok_or_else(|| ECPError::invalid_params(format!("Trash item not found: {id}")))
+}
+
+/// A history entry and its content, or an error naming the missing piece.
+fn history_version(history: &FileHistory, id: &str) -> Result<(HistoryEntry, Vec<u8>), ECPError> {
+    let entry = history.get(id)
+        .ok_or_else(|| ECPError::invalid_params(format!("History entry not found: {id}")))?;
+    let content = history.read(&entry)
+        .ok_or_else(|| ECPError::server_error(format!("History content missing for {id}")))?;
+    Ok((entry, content))
+}
+
+/// One file's pending `file/replaceAll` change.
+struct Replacement {
+    path: PathBuf,
+    old: String,
+    new: String,
+    count: usize,
 }
 
 // ─────────────────────────────────────────────────────────────────────────────
@@ -483,6 +1245,32 @@ struct FileUriParam {
 struct FileReadParams {
     #[serde(alias = "uri")]
     path: String,
+    #[serde(default)]
+    encoding: Encoding,
+    /// Byte range
+    offset: Option<u64>,
+    length: Option<u64>,
+    /// 1-based inclusive line range
+    #[serde(rename = "startLine")]
+    start_line: Option<usize>,
+    #[serde(rename = "endLine")]
+    end_line: Option<usize>,
+    /// Send content as `file/read/chunk` notifications
+    #[serde(default)]
+    stream: bool,
+    #[serde(rename = "chunkSize")]
+    chunk_size: Option<usize>,
+    /// Streamed chunks go only to the requester
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FileReadAckParams {
+    #[serde(rename = "streamId")]
+    stream_id: String,
+    /// Index of the last chunk processed
+    chunk: u64,
 }
 
 #[derive(Deserialize)]
@@ -490,6 +1278,15 @@ struct FileWriteParams {
     #[serde(alias = "uri")]
     path: String,
     content: String,
+    /// `utf8` (default) or `base64`
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    append: bool,
+    #[serde(rename = "expectedMtime")]
+    expected_mtime: Option<u64>,
+    #[serde(rename = "expectedHash")]
+    expected_hash: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -518,13 +1315,48 @@ struct FileJoinParams {
 
 #[derive(Deserialize)]
 struct FileEditParams {
+    #[serde(alias = "path")]
     uri: String,
+    /// Single replacement (the original form of `file/edit`)
     #[serde(rename = "oldString")]
-    old_string: String,
+    old_string: Option<String>,
     #[serde(rename = "newString")]
-    new_string: String,
+    new_string: Option<String>,
     #[serde(default, rename = "replaceAll")]
     replace_all: bool,
+    #[serde(default)]
+    edits: Vec<Edit>,
+    /// Shorthand for a single patch edit
+    patch: Option<String>,
+    #[serde(rename = "expectedMtime")]
+    expected_mtime: Option<u64>,
+    #[serde(rename = "expectedHash")]
+    expected_hash: Option<String>,
+}
+
+impl FileEditParams {
+    /// All requested edits, in application order.
+    fn edits(&self) -> Result<Vec<Edit>, ECPError> {
+        let mut edits = Vec::new();
+        if let Some(ref old_string) = self.old_string {
+            let new_string = self.new_string.clone()
+                .ok_or_else(|| ECPError::invalid_params("oldString requires newString"))?;
+            edits.push(Edit::Replace {
+                old_string: old_string.clone(),
+                new_string,
+                replace_all: self.replace_all,
+                expected_occurrences: None,
+            });
+        }
+        if let Some(ref patch) = self.patch {
+            edits.push(Edit::Patch { patch: patch.clone() });
+        }
+        edits.extend(self.edits.iter().cloned());
+        if edits.is_empty() {
+            return Err(ECPError::invalid_params("No edits given: pass oldString/newString, edits or patch"));
+        }
+        Ok(edits)
+    }
 }
 
 #[derive(Deserialize)]
@@ -536,6 +1368,19 @@ struct FileBrowseDirParams {
     directories_only: Option<bool>,
 }
 
+#[derive(Deserialize)]
+struct FileTreeParams {
+    /// Defaults to the workspace root
+    #[serde(alias = "uri")]
+    path: Option<String>,
+    /// From an unexpanded directory in an earlier `file/tree` result;
+    /// replaces `path` and the options
+    #[serde(rename = "expandToken")]
+    expand_token: Option<String>,
+    #[serde(flatten)]
+    options: TreeOptions,
+}
+
 #[derive(Deserialize)]
 struct FileSearchParams {
     pattern: String,
@@ -558,11 +1403,117 @@ struct FileGlobParams {
 struct FileGrepParams {
     pattern: String,
     path: Option<String>,
+    /// Legacy single include glob
     glob: Option<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
     #[serde(rename = "caseSensitive")]
     case_sensitive: Option<bool>,
+    #[serde(default)]
+    literal: bool,
+    #[serde(default, rename = "wholeWord")]
+    whole_word: bool,
+    #[serde(default)]
+    multiline: bool,
+    #[serde(rename = "contextLines")]
+    context_lines: Option<usize>,
+    #[serde(rename = "beforeContext")]
+    before_context: Option<usize>,
+    #[serde(rename = "afterContext")]
+    after_context: Option<usize>,
     #[serde(rename = "maxResults")]
     max_results: Option<u32>,
+    #[serde(default, rename = "includeHidden")]
+    include_hidden: bool,
+    #[serde(rename = "respectIgnore")]
+    respect_ignore: Option<bool>,
+    #[serde(default)]
+    stream: bool,
+    /// Streamed results go only to the requester
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FileReplaceAllParams {
+    pattern: String,
+    replacement: String,
+    path: Option<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(rename = "caseSensitive")]
+    case_sensitive: Option<bool>,
+    #[serde(default)]
+    literal: bool,
+    #[serde(default, rename = "wholeWord")]
+    whole_word: bool,
+    #[serde(default, rename = "includeHidden")]
+    include_hidden: bool,
+    #[serde(rename = "respectIgnore")]
+    respect_ignore: Option<bool>,
+    #[serde(default, rename = "dryRun")]
+    dry_run: bool,
+    /// Workspace-relative path → hash from a dry run; applying fails if
+    /// any of these files changed since
+    #[serde(default, rename = "expectedHashes")]
+    expected_hashes: std::collections::HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct FileDeleteParams {
+    #[serde(alias = "uri")]
+    path: String,
+    /// Remove for good instead of moving to the trash
+    #[serde(default)]
+    permanent: bool,
+}
+
+#[derive(Deserialize)]
+struct FileTrashRestoreParams {
+    id: String,
+    /// Restore somewhere other than the original path
+    #[serde(alias = "uri")]
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FileTrashEmptyParams {
+    /// Items to remove; all of them if omitted
+    ids: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct FileHistoryListParams {
+    #[serde(alias = "uri")]
+    path: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct FileHistoryIdParams {
+    id: String,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+#[derive(Deserialize)]
+struct FileHistoryDiffParams {
+    id: String,
+    /// Another entry to compare with, instead of the current file
+    against: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FileHistoryRestoreParams {
+    id: String,
+    #[serde(rename = "expectedMtime")]
+    expected_mtime: Option<u64>,
+    #[serde(rename = "expectedHash")]
+    expected_hash: Option<String>,
 }
 
 // ─────────────────────────────────────────────────────────────────────────────
@@ -582,6 +1533,46 @@ fn file_uri(path: &Path) -> String {
     format!("file://{}", path.display())
 }
 
+/// Structured hunks of a diff, in the shape `git/diff` uses.
+fn diff_hunks<'a>(diff: &similar::TextDiff<'a, 'a, '_, str>) -> Vec<serde_json::Value> {
+    diff.unified_diff().iter_hunks().map(|hunk| {
+        let ops = hunk.ops();
+        let (first, last) = (&ops[0], &ops[ops.len() - 1]);
+        let old = first.old_range().start..last.old_range().end;
+        let new = first.new_range().start..last.new_range().end;
+        let lines: Vec<serde_json::Value> = hunk.iter_changes().map(|change| {
+            let content = change.value().trim_end_matches(['\n', '\r']);
+            match change.tag() {
+                similar::ChangeTag::Delete => json!({
+                    "type": "-", "content": content, "oldLineNum": change.old_index().map(|i| i + 1),
+                }),
+                similar::ChangeTag::Insert => json!({
+                    "type": "+", "content": content, "newLineNum": change.new_index().map(|i| i + 1),
+                }),
+                similar::ChangeTag::Equal => json!({
+                    "type": " ", "content": content,
+                    "oldLineNum": change.old_index().map(|i| i + 1),
+                    "newLineNum": change.new_index().map(|i| i + 1),
+                }),
+            }
+        }).collect();
+        json!({
+            "oldStart": old.start + 1,
+            "oldCount": old.len(),
+            "newStart": new.start + 1,
+            "newCount": new.len(),
+            "lines": lines,
+        })
+    }).collect()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 /// Extract modification time as milliseconds since epoch.
 fn file_mod_time(meta: &std::fs::Metadata) -> Option<u64> {
     meta.modified().ok().and_then(|t|
@@ -596,6 +1587,76 @@ fn file_create_time(meta: &std::fs::Metadata) -> Option<u64> {
     )
 }
 
+/// Fail with a conflict if the file no longer matches the `modTime`/`hash`
+/// the client last saw. The error data carries the current metadata so the
+/// client can re-read and retry.
+async fn check_expected(
+    path: &Path,
+    expected_mtime: Option<u64>,
+    expected_hash: Option<&str>,
+) -> Result<(), ECPError> {
+    if expected_mtime.is_none() && expected_hash.is_none() {
+        return Ok(());
+    }
+    let meta = tokio::fs::metadata(path).await.ok();
+    let mod_time = meta.as_ref().and_then(file_mod_time);
+    let hash = tokio::fs::read(path).await.ok().map(|c| hash_bytes(&c));
+
+    let mtime_stale = expected_mtime.is_some_and(|m| mod_time != Some(m));
+    let hash_stale = expected_hash.is_some_and(|h| hash.as_deref() != Some(h));
+    if !mtime_stale && !hash_stale {
+        return Ok(());
+    }
+    Err(ECPError::conflict(format!("{} has changed on disk", path.display()))
+        .with_data(json!({
+            "uri": file_uri(path),
+            "exists": meta.is_some(),
+            "modTime": mod_time,
+            "hash": hash,
+            "size": meta.as_ref().map(|m| m.len()),
+            "expectedMtime": expected_mtime,
+            "expectedHash": expected_hash,
+        })))
+}
+
+/// Replace a file's content atomically: write a temp file next to it, then
+/// rename it over the original. Keeps the original's permissions, and writes
+/// through a symlink to its target rather than replacing the link.
+async fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let target = tokio::fs::canonicalize(path).await.unwrap_or_else(|_| path.to_path_buf());
+    let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+        return tokio::fs::write(&target, content).await;
+    };
+    let tmp = dir.join(format!(".{}.{}.tmp", name.to_string_lossy(), uuid::Uuid::new_v4().simple()));
+    let permissions = tokio::fs::metadata(&target).await.ok().map(|m| m.permissions());
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        drop(file);
+        if let Some(permissions) = permissions {
+            tokio::fs::set_permissions(&tmp, permissions).await?;
+        }
+        tokio::fs::rename(&tmp, &target).await
+    }.await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    result
+}
+
+/// Append to a file, creating it if needed.
+async fn append_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(content).await?;
+    file.flush().await
+}
+
 /// Normalize a path by resolving `.` and `..` without touching the filesystem.
 fn normalize_path(path: &Path) -> PathBuf {
     let mut components = Vec::new();
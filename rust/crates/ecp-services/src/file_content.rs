@@ -0,0 +1,242 @@
+//! Encodings and ranged reads for `file/read` and `file/write`.
+//!
+//! Content travels as text when it decodes as text and as base64 otherwise.
+//! `auto` detection honours UTF-8 and UTF-16 byte order marks, accepts valid
+//! UTF-8, sends files that look binary as base64 and falls back to latin-1 for
+//! anything else. Reads can be limited to a byte range or a line range, and
+//! large files can be streamed in chunks through a [`ChunkDecoder`].
+
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use base64::Engine;
+use ecp_protocol::ECPError;
+use serde::Deserialize;
+
+/// Bytes inspected for a NUL when deciding whether content is binary.
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// Encoding requested by the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "utf8", alias = "utf-8")]
+    Utf8,
+    #[serde(rename = "base64")]
+    Base64,
+}
+
+/// Encoding content is actually sent in, as reported to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoded {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Base64,
+}
+
+impl Decoded {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Latin1 => "latin1",
+            Self::Base64 => "base64",
+        }
+    }
+}
+
+/// Pick the encoding for `bytes`. Unless `complete`, `bytes` is the first
+/// chunk of a stream and a UTF-8 sequence cut off at its end is allowed.
+pub fn detect(bytes: &[u8], requested: Encoding, complete: bool) -> Decoded {
+    match requested {
+        Encoding::Utf8 => Decoded::Utf8,
+        Encoding::Base64 => Decoded::Base64,
+        Encoding::Auto => {
+            if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+                Decoded::Utf8
+            } else if bytes.starts_with(&[0xFF, 0xFE]) {
+                Decoded::Utf16Le
+            } else if bytes.starts_with(&[0xFE, 0xFF]) {
+                Decoded::Utf16Be
+            } else if bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+                Decoded::Base64
+            } else {
+                match std::str::from_utf8(bytes) {
+                    Ok(_) => Decoded::Utf8,
+                    Err(e) if e.error_len().is_none() && !complete => Decoded::Utf8,
+                    Err(_) => Decoded::Latin1,
+                }
+            }
+        }
+    }
+}
+
+/// Trim a byte range of UTF-8 text to whole characters: continuation bytes
+/// at the start (unless it is the start of the file) and an incomplete
+/// sequence at the end are dropped. Returns the range kept.
+pub fn utf8_aligned(bytes: &[u8], at_file_start: bool) -> std::ops::Range<usize> {
+    let is_continuation = |b: u8| b & 0xC0 == 0x80;
+    let start = if at_file_start {
+        0
+    } else {
+        bytes.iter().take(3).take_while(|&&b| is_continuation(b)).count()
+    };
+    let mut end = bytes.len();
+    for back in 1..=(bytes.len() - start).min(3) {
+        let b = bytes[bytes.len() - back];
+        if !is_continuation(b) {
+            let width = match b {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            if width > back {
+                end = bytes.len() - back;
+            }
+            break;
+        }
+    }
+    start..end
+}
+
+/// Decodes a byte stream chunk by chunk, carrying multi-byte sequences that
+/// straddle chunk boundaries over to the next chunk.
+pub struct ChunkDecoder {
+    encoding: Decoded,
+    carry: Vec<u8>,
+    /// Whether the next chunk is the start of the file (BOMs are stripped)
+    at_start: bool,
+}
+
+impl ChunkDecoder {
+    pub fn new(encoding: Decoded, at_start: bool) -> Self {
+        Self { encoding, carry: Vec::new(), at_start }
+    }
+
+    pub fn encoding(&self) -> Decoded {
+        self.encoding
+    }
+
+    /// Decode the next chunk. Unless `last`, a trailing partial sequence is
+    /// held back for the next chunk.
+    pub fn decode(&mut self, chunk: &[u8], last: bool) -> Result<String, ECPError> {
+        let mut bytes = std::mem::take(&mut self.carry);
+        bytes.extend_from_slice(chunk);
+        let mut data = bytes.as_slice();
+        if std::mem::take(&mut self.at_start) {
+            let bom: &[u8] = match self.encoding {
+                Decoded::Utf8 => &[0xEF, 0xBB, 0xBF],
+                Decoded::Utf16Le => &[0xFF, 0xFE],
+                Decoded::Utf16Be => &[0xFE, 0xFF],
+                _ => &[],
+            };
+            data = data.strip_prefix(bom).unwrap_or(data);
+        }
+
+        match self.encoding {
+            Decoded::Base64 => Ok(base64::engine::general_purpose::STANDARD.encode(data)),
+            Decoded::Latin1 => Ok(data.iter().map(|&b| b as char).collect()),
+            Decoded::Utf8 => match std::str::from_utf8(data) {
+                Ok(text) => Ok(text.to_string()),
+                Err(e) if e.error_len().is_none() && !last => {
+                    let (text, rest) = data.split_at(e.valid_up_to());
+                    self.carry = rest.to_vec();
+                    Ok(String::from_utf8_lossy(text).into_owned())
+                }
+                Err(e) => Err(ECPError::server_error(format!(
+                    "Content is not valid UTF-8 (at byte {}); read with encoding \"base64\" or \"auto\"",
+                    e.valid_up_to()
+                ))),
+            },
+            Decoded::Utf16Le | Decoded::Utf16Be => {
+                let even = data.len() - data.len() % 2;
+                let (units, rest) = data.split_at(even);
+                if !last {
+                    self.carry = rest.to_vec();
+                }
+                let units: Vec<u16> = units.chunks_exact(2)
+                    .map(|pair| match self.encoding {
+                        Decoded::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                        _ => u16::from_be_bytes([pair[0], pair[1]]),
+                    })
+                    .collect();
+                Ok(String::from_utf16_lossy(&units))
+            }
+        }
+    }
+}
+
+/// Decode a whole buffer (or a range of one) in one go.
+pub fn decode(bytes: &[u8], requested: Encoding, at_start: bool) -> Result<(String, Decoded), ECPError> {
+    let encoding = detect(bytes, requested, true);
+    let content = ChunkDecoder::new(encoding, at_start).decode(bytes, true)?;
+    Ok((content, encoding))
+}
+
+/// Turn `file/write` content into bytes.
+pub fn encode(content: &str, encoding: Encoding) -> Result<Vec<u8>, ECPError> {
+    match encoding {
+        Encoding::Base64 => base64::engine::general_purpose::STANDARD.decode(content.trim())
+            .map_err(|e| ECPError::invalid_params(format!("Invalid base64 content: {e}"))),
+        Encoding::Utf8 | Encoding::Auto => Ok(content.as_bytes().to_vec()),
+    }
+}
+
+/// Up to `length` bytes starting at `offset`.
+pub fn read_bytes(path: &Path, offset: u64, length: Option<u64>) -> std::io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    match length {
+        Some(length) => file.take(length).read_to_end(&mut bytes)?,
+        None => file.read_to_end(&mut bytes)?,
+    };
+    Ok(bytes)
+}
+
+/// Lines `start..=end` (1-based, `end` clamped to the file).
+pub struct LineRange {
+    pub bytes: Vec<u8>,
+    /// Byte offset of the first returned line
+    pub offset: u64,
+    /// Last line actually returned (`start - 1` if none were)
+    pub end: usize,
+    pub total_lines: usize,
+}
+
+/// Read a 1-based inclusive line range, counting the file's total lines on
+/// the way without holding more than the requested lines in memory.
+pub fn read_lines(path: &Path, start: usize, end: Option<usize>) -> std::io::Result<LineRange> {
+    let start = start.max(1);
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut range = LineRange { bytes: Vec::new(), offset: 0, end: start - 1, total_lines: 0 };
+    let mut position = 0u64;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        range.total_lines += 1;
+        let n = range.total_lines;
+        if n == start {
+            range.offset = position;
+        }
+        if n >= start && end.is_none_or(|end| n <= end) {
+            range.bytes.extend_from_slice(&line);
+            range.end = n;
+        }
+        position += read as u64;
+    }
+    if range.end < start {
+        range.offset = position;
+    }
+    Ok(range)
+}
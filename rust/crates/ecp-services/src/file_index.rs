@@ -0,0 +1,379 @@
+//! Warm file index for `file/search`.
+//!
+//! Every file in the workspace (gitignore-aware, hidden files included, `.git`,
+//! `node_modules` and `.ultra` skipped) is listed once in the background when
+//! the workspace opens and then kept current from [`WatchService`] events, so
+//! queries never touch the filesystem. Matching is fzf-style via
+//! [`nucleo_matcher`] with path-aware bonuses; files open in the session get a
+//! recency boost.
+//!
+//! [`WatchService`]: crate::watch::WatchService
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use notify::{Event, EventKind};
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config, Matcher, Utf32String};
+use parking_lot::{Mutex, RwLock};
+use serde_json::{json, Value};
+use tokio::sync::OnceCell;
+use tracing::{debug, info};
+
+/// Directory names never indexed, wherever they appear.
+const SKIPPED_DIRS: &[&str] = &[".git", "node_modules", ".ultra"];
+
+/// Files remembered for recency boosting.
+const MAX_RECENT: usize = 50;
+
+/// Files per thread below which queries are scored on one thread.
+const PARALLEL_CHUNK_MIN: usize = 20_000;
+
+/// Score added to the most recently opened file, tapering off for older ones.
+const RECENT_BONUS: u32 = 64;
+
+/// A fuzzy query against the index.
+#[derive(Debug, Clone)]
+pub struct FileQuery {
+    pub pattern: String,
+    pub max_results: usize,
+    /// `None` is smart case: sensitive only if the pattern has uppercase
+    pub case_sensitive: Option<bool>,
+}
+
+pub struct FileIndex {
+    root: PathBuf,
+    /// Paths relative to the root, sorted
+    files: RwLock<Vec<Utf32String>>,
+    /// Root-level ignore rules, for files added after the initial walk
+    ignore: RwLock<Gitignore>,
+    /// Recently opened files (relative), most recent first
+    recent: RwLock<Vec<String>>,
+    built: OnceCell<()>,
+    /// Changed paths waiting to be re-checked
+    queue: Mutex<EventQueue>,
+}
+
+#[derive(Default)]
+struct EventQueue {
+    paths: Vec<PathBuf>,
+    /// Whether a blocking task is working through `paths`
+    draining: bool,
+}
+
+impl FileIndex {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            files: RwLock::new(Vec::new()),
+            ignore: RwLock::new(Gitignore::empty()),
+            recent: RwLock::new(Vec::new()),
+            built: OnceCell::new(),
+            queue: Mutex::new(EventQueue::default()),
+        }
+    }
+
+    /// Wait until the initial walk has finished, starting it if needed.
+    pub async fn ready(self: &Arc<Self>) {
+        self.built.get_or_init(|| async {
+            let index = self.clone();
+            let _ = tokio::task::spawn_blocking(move || index.rebuild()).await;
+        }).await;
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.built.initialized()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.read().is_empty()
+    }
+
+    /// Walk the workspace and replace the index contents.
+    fn rebuild(&self) {
+        let started = std::time::Instant::now();
+        let mut files: Vec<Utf32String> = walk(&self.root)
+            .filter_map(|path| self.relative(&path))
+            .map(Utf32String::from)
+            .collect();
+        files.sort_unstable();
+        let count = files.len();
+        *self.files.write() = files;
+        *self.ignore.write() = root_ignore(&self.root);
+        info!("Indexed {count} files in {:?}", started.elapsed());
+    }
+
+    /// Set the recently opened files, most recent first. Accepts absolute
+    /// paths, `file://` URIs or workspace-relative paths.
+    pub fn set_recent<'a>(&self, paths: impl IntoIterator<Item = &'a str>) {
+        let mut recent: Vec<String> = Vec::new();
+        for path in paths {
+            let path = path.strip_prefix("file://").unwrap_or(path);
+            let relative = if Path::new(path).is_absolute() {
+                self.relative(Path::new(path))
+            } else {
+                Some(path.to_string())
+            };
+            if let Some(relative) = relative
+                && !recent.contains(&relative)
+                && recent.len() < MAX_RECENT
+            {
+                recent.push(relative);
+            }
+        }
+        *self.recent.write() = recent;
+    }
+
+    /// Apply a watcher event: changed paths are queued and re-checked
+    /// against the disk on the blocking pool, one batch at a time.
+    pub fn apply_event(self: &Arc<Self>, event: &Event) {
+        if !self.is_ready() || matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        let mut queue = self.queue.lock();
+        queue.paths.extend(event.paths.iter().cloned());
+        if !queue.draining {
+            queue.draining = true;
+            let index = self.clone();
+            tokio::task::spawn_blocking(move || index.drain());
+        }
+    }
+
+    /// Apply queued paths until none are left.
+    fn drain(&self) {
+        loop {
+            let mut paths = {
+                let mut queue = self.queue.lock();
+                if queue.paths.is_empty() {
+                    queue.draining = false;
+                    return;
+                }
+                std::mem::take(&mut queue.paths)
+            };
+            paths.sort_unstable();
+            paths.dedup();
+            for path in &paths {
+                if !self.apply_path(path) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Bring one path up to date. Returns false after a full rebuild, which
+    /// covers the rest of the batch.
+    fn apply_path(&self, path: &Path) -> bool {
+        let Some(relative) = self.relative(path) else { return true };
+        if skipped(&relative) {
+            return true;
+        }
+
+        // New ignore rules change what belongs in the index
+        let name = path.file_name().and_then(|n| n.to_str());
+        if matches!(name, Some(".gitignore" | ".ignore")) {
+            self.rebuild();
+            return false;
+        }
+
+        match std::fs::metadata(path) {
+            Ok(meta) if meta.is_file() => {
+                if !self.ignore.read().matched_path_or_any_parents(&relative, false).is_ignore() {
+                    self.insert(relative);
+                }
+            }
+            Ok(meta) if meta.is_dir() => {
+                for file in walk(path) {
+                    if let Some(relative) = self.relative(&file) {
+                        self.insert(relative);
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(_) => self.remove(&relative),
+        }
+        true
+    }
+
+    fn insert(&self, relative: String) {
+        let entry = Utf32String::from(relative);
+        let mut files = self.files.write();
+        if let Err(pos) = files.binary_search(&entry) {
+            debug!("Indexed {entry}");
+            files.insert(pos, entry);
+        }
+    }
+
+    /// Remove a file, or everything under a removed directory.
+    fn remove(&self, relative: &str) {
+        let entry = Utf32String::from(relative);
+        let mut files = self.files.write();
+        match files.binary_search(&entry) {
+            Ok(pos) => { files.remove(pos); }
+            Err(_) => {
+                let prefix = Utf32String::from(format!("{relative}/"));
+                let prefix = prefix.slice(..);
+                // Chars rather than slices: an ASCII prefix of a non-ASCII
+                // path is stored as Unicode
+                files.retain(|f| f.len() < prefix.len() || !f.slice(..prefix.len()).chars().eq(prefix.chars()));
+            }
+        }
+    }
+
+    /// Best matches for the query, highest score first.
+    pub fn search(&self, query: &FileQuery) -> Vec<Value> {
+        let files = self.files.read();
+        let recent = self.recent.read();
+
+        if query.pattern.trim().is_empty() {
+            // Nothing typed yet — offer recently opened files
+            return recent.iter()
+                .filter(|r| files.binary_search(&Utf32String::from(r.as_str())).is_ok())
+                .take(query.max_results)
+                .map(|r| self.result(r, 0, Vec::new(), true))
+                .collect();
+        }
+
+        let case = match query.case_sensitive {
+            Some(true) => CaseMatching::Respect,
+            Some(false) => CaseMatching::Ignore,
+            None => CaseMatching::Smart,
+        };
+        let pattern = Pattern::parse(&query.pattern, case, Normalization::Smart);
+        let mut matcher = Matcher::new(Config::DEFAULT.match_paths());
+
+        let boosts: HashMap<usize, u32> = recent.iter().enumerate()
+            .filter_map(|(rank, r)| {
+                let idx = files.binary_search(&Utf32String::from(r.as_str())).ok()?;
+                let bonus = RECENT_BONUS * (MAX_RECENT - rank) as u32 / MAX_RECENT as u32;
+                Some((idx, bonus))
+            })
+            .collect();
+
+        // Score in parallel chunks once the index is large enough to benefit
+        let score_chunk = |offset: usize, chunk: &[Utf32String]| {
+            let mut matcher = Matcher::new(Config::DEFAULT.match_paths());
+            chunk.iter().enumerate()
+                .filter_map(|(i, file)| {
+                    let idx = offset + i;
+                    let score = pattern.score(file.slice(..), &mut matcher)?;
+                    Some((score + boosts.get(&idx).copied().unwrap_or(0), idx))
+                })
+                .collect::<Vec<_>>()
+        };
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get())
+            .min(files.len() / PARALLEL_CHUNK_MIN)
+            .max(1);
+        let mut scored: Vec<(u32, usize)> = if threads == 1 {
+            score_chunk(0, &files)
+        } else {
+            let chunk_len = files.len().div_ceil(threads);
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = files.chunks(chunk_len).enumerate()
+                    .map(|(n, chunk)| scope.spawn(move || score_chunk(n * chunk_len, chunk)))
+                    .collect();
+                handles.into_iter().flat_map(|h| h.join().unwrap_or_default()).collect()
+            })
+        };
+
+        // Highest score, then shortest path, then alphabetical
+        let order = |a: &(u32, usize), b: &(u32, usize)| {
+            b.0.cmp(&a.0)
+                .then_with(|| files[a.1].len().cmp(&files[b.1].len()))
+                .then_with(|| a.1.cmp(&b.1))
+        };
+        if scored.len() > query.max_results && query.max_results > 0 {
+            scored.select_nth_unstable_by(query.max_results - 1, order);
+            scored.truncate(query.max_results);
+        }
+        scored.sort_unstable_by(order);
+
+        let mut indices = Vec::new();
+        scored.into_iter().map(|(score, idx)| {
+            let file = &files[idx];
+            indices.clear();
+            pattern.indices(file.slice(..), &mut matcher, &mut indices);
+            indices.sort_unstable();
+            indices.dedup();
+            self.result(&file.to_string(), score, ranges(&indices), boosts.contains_key(&idx))
+        }).collect()
+    }
+
+    fn result(&self, relative: &str, score: u32, ranges: Vec<Value>, recent: bool) -> Value {
+        let path = self.root.join(relative);
+        json!({
+            "uri": format!("file://{}", path.display()),
+            "path": relative,
+            "name": path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(),
+            "score": score,
+            "ranges": ranges,
+            "recent": recent,
+        })
+    }
+
+    fn relative(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+        Some(relative.to_string_lossy().to_string())
+    }
+}
+
+/// Collapse sorted character indices into `[start, end)` ranges.
+fn ranges(indices: &[u32]) -> Vec<Value> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &i in indices {
+        match ranges.last_mut() {
+            Some((_, end)) if *end == i => *end += 1,
+            _ => ranges.push((i, i + 1)),
+        }
+    }
+    ranges.into_iter().map(|(start, end)| json!({ "start": start, "end": end })).collect()
+}
+
+pub(crate) fn skipped(relative: &str) -> bool {
+    relative.split('/').any(|part| SKIPPED_DIRS.contains(&part))
+}
+
+/// Entries under `dir`, honouring ignore files and skipping [`SKIPPED_DIRS`].
+fn walker(dir: &Path) -> impl Iterator<Item = ignore::DirEntry> {
+    WalkBuilder::new(dir)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| {
+            !(entry.depth() > 0
+                && entry.file_type().is_some_and(|t| t.is_dir())
+                && entry.file_name().to_str().is_some_and(|n| SKIPPED_DIRS.contains(&n)))
+        })
+        .build()
+        .flatten()
+}
+
+/// Files under `dir`, honouring ignore files and skipping [`SKIPPED_DIRS`].
+fn walk(dir: &Path) -> impl Iterator<Item = PathBuf> {
+    walker(dir)
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.into_path())
+}
+
+/// `dir` and the directories under it that [`walk`] descends into.
+pub(crate) fn walk_dirs(dir: &Path) -> impl Iterator<Item = PathBuf> {
+    walker(dir)
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_dir()))
+        .map(|entry| entry.into_path())
+}
+
+pub(crate) fn root_ignore(root: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(root);
+    for file in [".gitignore", ".ignore", ".git/info/exclude"] {
+        let _ = builder.add(root.join(file));
+    }
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
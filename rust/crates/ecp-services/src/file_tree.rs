@@ -0,0 +1,249 @@
+//! Directory trees for `file/tree`.
+//!
+//! One request lists a directory and its descendants down to `depth` levels,
+//! with sizes, mtimes, ignore status and git decorations, replacing a
+//! `readDir` plus `stat` per entry. Directories below the depth limit, ignored
+//! directories and listings cut short by `maxEntries` come back with an
+//! `expandToken`; passing it to `file/tree` fetches that directory with the
+//! same options.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Deepest tree a single request may ask for.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Extension,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TreeOptions {
+    /// Levels listed below the requested directory (1 = its children only)
+    pub depth: usize,
+    /// Include gitignored entries, marked `ignored` and never expanded
+    pub show_ignored: bool,
+    pub show_hidden: bool,
+    pub git_status: bool,
+    pub sort_by: SortBy,
+    pub descending: bool,
+    pub directories_first: bool,
+    /// Entries returned across the whole tree
+    pub max_entries: usize,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            show_ignored: false,
+            show_hidden: true,
+            git_status: true,
+            sort_by: SortBy::Name,
+            descending: false,
+            directories_first: true,
+            max_entries: 5000,
+        }
+    }
+}
+
+/// What an expand token carries: the directory and the options it was
+/// listed with.
+#[derive(Serialize, Deserialize)]
+struct Expansion {
+    path: PathBuf,
+    options: TreeOptions,
+}
+
+pub fn expand_token(path: &Path, options: &TreeOptions) -> String {
+    let expansion = Expansion { path: path.to_path_buf(), options: options.clone() };
+    let json = serde_json::to_vec(&expansion).unwrap_or_default();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json)
+}
+
+/// The directory and options behind an expand token.
+pub fn parse_token(token: &str) -> Option<(PathBuf, TreeOptions)> {
+    let json = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(token).ok()?;
+    let expansion: Expansion = serde_json::from_slice(&json).ok()?;
+    Some((expansion.path, expansion.options))
+}
+
+/// List `dir` as a tree. `statuses` maps changed paths to their git status;
+/// directories take the status of what changed inside them.
+pub fn build(dir: &Path, options: &TreeOptions, statuses: HashMap<PathBuf, &'static str>) -> std::io::Result<Value> {
+    let mut options = options.clone();
+    options.depth = options.depth.clamp(1, MAX_DEPTH);
+
+    let mut dir_statuses: HashMap<PathBuf, &'static str> = HashMap::new();
+    for (path, status) in &statuses {
+        for ancestor in path.ancestors().skip(1).take_while(|a| a.starts_with(dir) && *a != dir) {
+            dir_statuses.entry(ancestor.to_path_buf())
+                .and_modify(|s| if s != status { *s = "modified" })
+                .or_insert(status);
+        }
+    }
+
+    let mut tree = Tree { options, statuses, dir_statuses, remaining: 0, truncated: false };
+    tree.remaining = tree.options.max_entries.max(1);
+    let depth = tree.options.depth;
+    let (entries, complete) = tree.list(dir, depth)?;
+    Ok(json!({
+        "path": dir.to_string_lossy(),
+        "uri": format!("file://{}", dir.display()),
+        "entries": entries,
+        "complete": complete,
+        "expandToken": (!complete).then(|| expand_token(dir, &tree.options)),
+        "truncated": tree.truncated,
+    }))
+}
+
+struct Tree {
+    options: TreeOptions,
+    statuses: HashMap<PathBuf, &'static str>,
+    dir_statuses: HashMap<PathBuf, &'static str>,
+    /// Entries left before the listing is cut short
+    remaining: usize,
+    truncated: bool,
+}
+
+struct Item {
+    name: String,
+    path: PathBuf,
+    meta: Option<std::fs::Metadata>,
+    ignored: bool,
+}
+
+impl Item {
+    fn is_dir(&self) -> bool {
+        self.meta.as_ref().is_some_and(|m| m.is_dir())
+    }
+
+    /// Directories sort as empty
+    fn size(&self) -> u64 {
+        self.meta.as_ref().filter(|m| !m.is_dir()).map_or(0, |m| m.len())
+    }
+
+    fn modified(&self) -> Option<u64> {
+        self.meta.as_ref()?.modified().ok()?
+            .duration_since(std::time::UNIX_EPOCH).ok()
+            .map(|d| d.as_millis() as u64)
+    }
+
+    fn extension(&self) -> &str {
+        self.name.rsplit_once('.').filter(|(stem, _)| !stem.is_empty()).map_or("", |(_, ext)| ext)
+    }
+}
+
+impl Tree {
+    /// Entries of `dir`, expanded `depth - 1` more levels, and whether the
+    /// listing is complete.
+    fn list(&mut self, dir: &Path, depth: usize) -> std::io::Result<(Vec<Value>, bool)> {
+        let visible = not_ignored(dir);
+        let mut items: Vec<Item> = std::fs::read_dir(dir)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name == ".git" || (!self.options.show_hidden && name.starts_with('.')) {
+                    return None;
+                }
+                let path = entry.path();
+                let ignored = !visible.contains(&path);
+                if ignored && !self.options.show_ignored {
+                    return None;
+                }
+                let meta = std::fs::symlink_metadata(&path).ok();
+                Some(Item { name, path, meta, ignored })
+            })
+            .collect();
+        self.sort(&mut items);
+
+        let mut entries = Vec::with_capacity(items.len().min(self.remaining));
+        for item in items {
+            if self.remaining == 0 {
+                self.truncated = true;
+                return Ok((entries, false));
+            }
+            self.remaining -= 1;
+            entries.push(self.node(item, depth)?);
+        }
+        Ok((entries, true))
+    }
+
+    fn node(&mut self, item: Item, depth: usize) -> std::io::Result<Value> {
+        let is_symlink = item.meta.as_ref().is_some_and(|m| m.is_symlink());
+        let is_dir = item.is_dir();
+        let mut node = json!({
+            "name": item.name,
+            "path": item.path.to_string_lossy(),
+            "uri": format!("file://{}", item.path.display()),
+            "type": if is_symlink { "symlink" } else if is_dir { "directory" } else { "file" },
+            "size": (!is_dir).then(|| item.size()),
+            "modTime": item.modified(),
+            "ignored": item.ignored,
+        });
+        if self.options.git_status {
+            let status = self.statuses.get(&item.path).or_else(|| self.dir_statuses.get(&item.path));
+            node["gitStatus"] = json!(status);
+        }
+        if is_dir {
+            if depth > 1 && !item.ignored {
+                // A directory that vanished mid-listing is shown empty
+                let (children, complete) = self.list(&item.path, depth - 1).unwrap_or((Vec::new(), true));
+                node["children"] = json!(children);
+                if !complete {
+                    node["expandToken"] = json!(expand_token(&item.path, &self.options));
+                }
+            } else {
+                node["expandToken"] = json!(expand_token(&item.path, &self.options));
+            }
+        }
+        Ok(node)
+    }
+
+    fn sort(&self, items: &mut [Item]) {
+        let options = &self.options;
+        items.sort_by(|a, b| {
+            if options.directories_first && a.is_dir() != b.is_dir() {
+                return b.is_dir().cmp(&a.is_dir());
+            }
+            let by_name = || {
+                a.name.to_lowercase().cmp(&b.name.to_lowercase()).then_with(|| a.name.cmp(&b.name))
+            };
+            let order = match options.sort_by {
+                SortBy::Name => Ordering::Equal,
+                SortBy::Size => a.size().cmp(&b.size()),
+                SortBy::Modified => a.modified().cmp(&b.modified()),
+                SortBy::Extension => a.extension().to_lowercase().cmp(&b.extension().to_lowercase()),
+            }
+            .then_with(by_name);
+            if options.descending { order.reverse() } else { order }
+        });
+    }
+}
+
+/// Children of `dir` that ignore files (including those in parent
+/// directories) leave visible.
+fn not_ignored(dir: &Path) -> HashSet<PathBuf> {
+    WalkBuilder::new(dir)
+        .hidden(false)
+        .require_git(false)
+        .max_depth(Some(1))
+        .build()
+        .flatten()
+        .filter(|entry| entry.depth() == 1)
+        .map(|entry| entry.into_path())
+        .collect()
+}
@@ -1,6 +1,7 @@
 //! Git service — wraps the git CLI for repository operations.
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
 use ecp_protocol::{ECPError, HandlerResult};
@@ -208,31 +209,30 @@ impl Service for GitService {
 
                 // For untracked files, `git diff` returns empty output.
                 // Synthesize a diff showing the entire file as added lines.
-                if hunks.is_empty() {
-                    if let Some(ref path) = p.path {
-                        let cwd = self.workspace_root.read().clone();
-                        let file_path = cwd.join(path);
-                        if let Ok(content) = tokio::fs::read_to_string(&file_path).await {
-                            let file_lines: Vec<&str> = content.lines().collect();
-                            let count = file_lines.len() as u32;
-                            if count > 0 {
-                                let lines: Vec<serde_json::Value> = file_lines
-                                    .iter()
-                                    .enumerate()
-                                    .map(|(i, l)| json!({
-                                        "type": "+",
-                                        "content": *l,
-                                        "newLineNum": (i as u32) + 1,
-                                    }))
-                                    .collect();
-                                hunks.push(json!({
-                                    "oldStart": 0,
-                                    "oldCount": 0,
-                                    "newStart": 1,
-                                    "newCount": count,
-                                    "lines": lines,
-                                }));
-                            }
+                if hunks.is_empty()
+                    && let Some(ref path) = p.path {
+                    let cwd = self.workspace_root.read().clone();
+                    let file_path = cwd.join(path);
+                    if let Ok(content) = tokio::fs::read_to_string(&file_path).await {
+                        let file_lines: Vec<&str> = content.lines().collect();
+                        let count = file_lines.len() as u32;
+                        if count > 0 {
+                            let lines: Vec<serde_json::Value> = file_lines
+                                .iter()
+                                .enumerate()
+                                .map(|(i, l)| json!({
+                                    "type": "+",
+                                    "content": *l,
+                                    "newLineNum": (i as u32) + 1,
+                                }))
+                                .collect();
+                            hunks.push(json!({
+                                "oldStart": 0,
+                                "oldCount": 0,
+                                "newStart": 1,
+                                "newCount": count,
+                                "lines": lines,
+                            }));
                         }
                     }
                 }
@@ -719,6 +719,57 @@ struct GitMergeParam {
 // Helpers
 // ─────────────────────────────────────────────────────────────────────────────
 
+/// Status of every changed path under `dir`, keyed by absolute path (joined
+/// onto `dir` as given, so symlinked workspaces still match), for decorating
+/// file trees. Empty outside a repository.
+pub async fn path_statuses(dir: &Path) -> HashMap<PathBuf, &'static str> {
+    let run = |args: &'static [&'static str]| {
+        tokio::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+    };
+    let (Ok(prefix), Ok(status)) = (
+        run(&["rev-parse", "--show-prefix"]).await,
+        run(&["status", "--porcelain=v1", "-z", "-uall"]).await,
+    ) else {
+        return HashMap::new();
+    };
+    if !prefix.status.success() || !status.status.success() {
+        return HashMap::new();
+    }
+    // `dir` relative to the repository root, which status paths are relative to
+    let prefix = String::from_utf8_lossy(&prefix.stdout).trim().to_string();
+
+    let mut statuses = HashMap::new();
+    // `XY path\0`, with renames and copies followed by `orig\0`
+    let mut records = status.stdout.split(|&b| b == 0);
+    while let Some(record) = records.next() {
+        if record.len() < 4 {
+            continue;
+        }
+        let (x, y) = (record[0], record[1]);
+        if matches!(x, b'R' | b'C') {
+            records.next();
+        }
+        let status = match (x, y) {
+            (b'?', b'?') => "untracked",
+            (b'U', _) | (_, b'U') | (b'A', b'A') | (b'D', b'D') => "conflicted",
+            (b'A', _) | (b'C', _) => "added",
+            (b'R', _) => "renamed",
+            (b'D', _) | (_, b'D') => "deleted",
+            _ => "modified",
+        };
+        let path = String::from_utf8_lossy(&record[3..]);
+        if let Some(relative) = path.strip_prefix(prefix.as_str()) {
+            statuses.insert(dir.join(relative), status);
+        }
+    }
+    statuses
+}
+
 fn parse_params<T: for<'de> Deserialize<'de>>(params: Option<serde_json::Value>) -> Result<T, ECPError> {
     match params {
         Some(v) => serde_json::from_value(v)
@@ -776,14 +827,14 @@ fn parse_diff(diff_text: &str) -> Vec<serde_json::Value> {
             new_line = new_start;
             in_hunk = true;
         } else if in_hunk {
-            if line.starts_with('+') {
-                current_lines.push(json!({ "type": "+", "content": &line[1..], "newLineNum": new_line }));
+            if let Some(content) = line.strip_prefix('+') {
+                current_lines.push(json!({ "type": "+", "content": content, "newLineNum": new_line }));
                 new_line += 1;
-            } else if line.starts_with('-') {
-                current_lines.push(json!({ "type": "-", "content": &line[1..], "oldLineNum": old_line }));
+            } else if let Some(content) = line.strip_prefix('-') {
+                current_lines.push(json!({ "type": "-", "content": content, "oldLineNum": old_line }));
                 old_line += 1;
-            } else if line.starts_with(' ') {
-                current_lines.push(json!({ "type": " ", "content": &line[1..], "oldLineNum": old_line, "newLineNum": new_line }));
+            } else if let Some(content) = line.strip_prefix(' ') {
+                current_lines.push(json!({ "type": " ", "content": content, "oldLineNum": old_line, "newLineNum": new_line }));
                 old_line += 1;
                 new_line += 1;
             }
@@ -816,12 +867,12 @@ fn parse_diff_lines(diff_text: &str) -> Vec<serde_json::Value> {
         }
         if !in_hunk { continue; }
 
-        if line.starts_with('+') {
-            changes.push(json!({ "type": "+", "line": &line[1..] }));
-        } else if line.starts_with('-') {
-            changes.push(json!({ "type": "-", "line": &line[1..] }));
-        } else if line.starts_with(' ') {
-            changes.push(json!({ "type": " ", "line": &line[1..] }));
+        if let Some(content) = line.strip_prefix('+') {
+            changes.push(json!({ "type": "+", "line": content }));
+        } else if let Some(content) = line.strip_prefix('-') {
+            changes.push(json!({ "type": "-", "line": content }));
+        } else if let Some(content) = line.strip_prefix(' ') {
+            changes.push(json!({ "type": " ", "line": content }));
         }
     }
 
@@ -866,14 +917,14 @@ fn parse_blame_porcelain(output: &str) -> Vec<serde_json::Value> {
     let mut line_number = 0u32;
 
     for line in output.lines() {
-        if line.starts_with('\t') {
+        if let Some(content) = line.strip_prefix('\t') {
             // Content line
             lines.push(json!({
                 "commit": &current_commit[..8.min(current_commit.len())],
                 "author": current_author,
                 "date": current_date,
                 "line": line_number,
-                "content": &line[1..],
+                "content": content,
             }));
         } else if line.len() >= 40 && line.chars().take(40).all(|c| c.is_ascii_hexdigit()) {
             // Commit header: hash orig_line final_line [group_lines]
@@ -0,0 +1,317 @@
+//! In-process content search for `file/grep` and `file/replaceAll`.
+//!
+//! Files are walked with [`ignore`], so `.gitignore`, `.ignore` and the
+//! global git excludes are honoured, then searched with [`regex`]. Each result
+//! is one line (or, in multiline mode, the span of lines a match covers) with
+//! the exact character ranges of every match on it.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use ecp_protocol::ECPError;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use regex::{NoExpand, Regex, RegexBuilder};
+use serde_json::{json, Value};
+
+/// Byte range of one match within a file.
+type Span = (usize, usize);
+
+/// Bytes inspected for a NUL when deciding whether a file is binary.
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// How to search. Built from `file/grep` params.
+#[derive(Debug, Clone)]
+pub struct GrepOptions {
+    pub pattern: String,
+    /// Treat `pattern` as a plain string rather than a regex
+    pub literal: bool,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    /// Let matches span lines (`\n` in the pattern)
+    pub multiline: bool,
+    /// Only search files matching one of these globs
+    pub include: Vec<String>,
+    /// Skip files and directories matching any of these globs
+    pub exclude: Vec<String>,
+    pub context_before: usize,
+    pub context_after: usize,
+    pub max_results: usize,
+    pub include_hidden: bool,
+    /// Honour `.gitignore`, `.ignore` and git excludes
+    pub respect_ignore: bool,
+}
+
+/// Totals for a finished search.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrepSummary {
+    pub match_count: usize,
+    pub file_count: usize,
+    pub files_searched: usize,
+    pub truncated: bool,
+}
+
+impl GrepSummary {
+    pub fn to_json(self) -> Value {
+        json!({
+            "matchCount": self.match_count,
+            "fileCount": self.file_count,
+            "filesSearched": self.files_searched,
+            "truncated": self.truncated,
+        })
+    }
+}
+
+/// A compiled search, ready to run.
+pub struct Grep {
+    regex: Regex,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    options: GrepOptions,
+}
+
+impl Grep {
+    /// Compile the pattern and globs, failing with invalid params if either
+    /// doesn't parse.
+    pub fn new(options: GrepOptions) -> Result<Self, ECPError> {
+        let mut pattern = if options.literal {
+            regex::escape(&options.pattern)
+        } else {
+            options.pattern.clone()
+        };
+        if options.whole_word {
+            pattern = format!(r"\b(?:{pattern})\b");
+        }
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!options.case_sensitive)
+            .multi_line(true)
+            .build()
+            .map_err(|e| ECPError::invalid_params(format!("Invalid pattern: {e}")))?;
+
+        Ok(Self {
+            regex,
+            include: glob_set(&options.include)?,
+            exclude: glob_set(&options.exclude)?,
+            options,
+        })
+    }
+
+    /// Search `root` (a directory or a single file), calling `on_file` with
+    /// each file's results as they are found.
+    pub fn run(&self, root: &Path, mut on_file: impl FnMut(&Path, Vec<Value>)) -> GrepSummary {
+        let mut summary = GrepSummary::default();
+        summary.files_searched = self.for_each_file(root, |path, bytes| {
+            let content = String::from_utf8_lossy(bytes);
+            let remaining = self.options.max_results - summary.match_count;
+            let (results, truncated) = self.search_text(path, &content, remaining);
+            summary.truncated = truncated;
+            if !results.is_empty() {
+                summary.match_count += results.len();
+                summary.file_count += 1;
+                on_file(path, results);
+            }
+            !truncated
+        });
+        summary
+    }
+
+    /// Visit every text file under `root` that passes the ignore rules and
+    /// globs, until `visit` returns `false`. Returns the number visited.
+    pub fn for_each_file(&self, root: &Path, mut visit: impl FnMut(&Path, &[u8]) -> bool) -> usize {
+        let mut visited = 0;
+        let exclude = self.exclude.clone();
+        let walk_root = root.to_path_buf();
+        let walker = WalkBuilder::new(root)
+            .hidden(!self.options.include_hidden)
+            .ignore(self.options.respect_ignore)
+            .git_ignore(self.options.respect_ignore)
+            .git_global(self.options.respect_ignore)
+            .git_exclude(self.options.respect_ignore)
+            .parents(self.options.respect_ignore)
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |entry| match exclude {
+                Some(ref exclude) if entry.depth() > 0 => !matches_glob(exclude, &walk_root, entry.path()),
+                _ => true,
+            })
+            .build();
+
+        for entry in walker.flatten() {
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let path = entry.path();
+            if let Some(ref include) = self.include
+                && !matches_glob(include, root, path)
+            {
+                continue;
+            }
+            let Ok(bytes) = std::fs::read(path) else { continue };
+            if bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+                continue;
+            }
+            visited += 1;
+            if !visit(path, &bytes) {
+                break;
+            }
+        }
+        visited
+    }
+
+    /// Replace every match in `content`, returning the new content and the
+    /// number of replacements. `$1`/`${name}` in `replacement` expand to
+    /// capture groups unless the search is literal.
+    pub fn replace<'a>(&self, content: &'a str, replacement: &str) -> (Cow<'a, str>, usize) {
+        let count = self.regex.find_iter(content).count();
+        if count == 0 {
+            return (Cow::Borrowed(content), 0);
+        }
+        let replaced = if self.options.literal {
+            self.regex.replace_all(content, NoExpand(replacement))
+        } else {
+            self.regex.replace_all(content, replacement)
+        };
+        (replaced, count)
+    }
+
+    /// Results for one file's content, at most `limit` of them, and whether
+    /// any were left out.
+    fn search_text(&self, path: &Path, content: &str, limit: usize) -> (Vec<Value>, bool) {
+        let lines = Lines::new(content);
+
+        // Byte ranges of every match, in order
+        let spans: Vec<Span> = if self.options.multiline {
+            self.regex.find_iter(content).map(|m| (m.start(), m.end())).collect()
+        } else {
+            (0..lines.len())
+                .flat_map(|i| {
+                    let (start, end) = lines.span(i);
+                    self.regex.find_iter(&content[start..end])
+                        .map(move |m| (start + m.start(), start + m.end()))
+                })
+                .collect()
+        };
+
+        // Group matches into results: one per line, or per run of lines a
+        // multiline match covers
+        let mut groups: Vec<(usize, usize, Vec<Span>)> = Vec::new();
+        let mut truncated = false;
+        for (start, end) in spans {
+            let first = lines.index_of(start);
+            let last = lines.index_of(if end > start { end - 1 } else { start });
+            let count = groups.len();
+            match groups.last_mut() {
+                Some((_, group_last, ranges)) if first <= *group_last => {
+                    *group_last = (*group_last).max(last);
+                    ranges.push((start, end));
+                }
+                _ if count >= limit => {
+                    truncated = true;
+                    break;
+                }
+                _ => groups.push((first, last, vec![(start, end)])),
+            }
+        }
+
+        let uri = format!("file://{}", path.display());
+        let results = groups.into_iter().map(|(first, last, ranges)| {
+            let text_start = lines.span(first).0;
+            let text_end = lines.span(last).1;
+            let text = &content[text_start..text_end];
+            let chars = |byte: usize| content[text_start..byte.clamp(text_start, text_end)].chars().count();
+            let ranges: Vec<Value> = ranges.iter()
+                .map(|&(start, end)| json!({ "start": chars(start), "end": chars(end) }))
+                .collect();
+
+            let mut result = json!({
+                "file": path.to_string_lossy(),
+                "uri": uri,
+                "line": first + 1,
+                "endLine": last + 1,
+                "column": ranges[0]["start"].as_u64().unwrap_or(0) + 1,
+                "text": text,
+                "ranges": ranges,
+            });
+            if self.options.context_before > 0 {
+                let from = first.saturating_sub(self.options.context_before);
+                result["before"] = json!((from..first).map(|i| lines.text(content, i)).collect::<Vec<_>>());
+            }
+            if self.options.context_after > 0 {
+                let to = (last + 1 + self.options.context_after).min(lines.len());
+                result["after"] = json!((last + 1..to).map(|i| lines.text(content, i)).collect::<Vec<_>>());
+            }
+            result
+        }).collect();
+        (results, truncated)
+    }
+}
+
+/// Line boundaries of a file's content.
+struct Lines {
+    starts: Vec<usize>,
+    ends: Vec<usize>,
+}
+
+impl Lines {
+    fn new(content: &str) -> Self {
+        let mut starts = vec![0];
+        let mut ends = Vec::new();
+        for (i, _) in content.match_indices('\n') {
+            ends.push(i);
+            starts.push(i + 1);
+        }
+        if starts.last() == Some(&content.len()) && !content.is_empty() {
+            // Trailing newline — no empty last line
+            starts.pop();
+        } else {
+            ends.push(content.len());
+        }
+        // Report lines without their `\r` on CRLF files
+        let bytes = content.as_bytes();
+        for (start, end) in starts.iter().zip(ends.iter_mut()) {
+            if *end > *start && bytes[*end - 1] == b'\r' {
+                *end -= 1;
+            }
+        }
+        Self { starts, ends }
+    }
+
+    fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    fn span(&self, line: usize) -> (usize, usize) {
+        (self.starts[line], self.ends[line])
+    }
+
+    fn index_of(&self, byte: usize) -> usize {
+        self.starts.partition_point(|&start| start <= byte).saturating_sub(1)
+    }
+
+    fn text<'a>(&self, content: &'a str, line: usize) -> &'a str {
+        let (start, end) = self.span(line);
+        &content[start..end]
+    }
+}
+
+pub(crate) fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>, ECPError> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| ECPError::invalid_params(format!("Invalid glob {pattern}: {e}")))?;
+        builder.add(glob);
+    }
+    builder.build()
+        .map(Some)
+        .map_err(|e| ECPError::invalid_params(format!("Invalid globs: {e}")))
+}
+
+/// Globs match either the path relative to the search root or the bare file
+/// name, so `*.rs` and `src/**/*.rs` both work.
+pub(crate) fn matches_glob(set: &GlobSet, root: &Path, path: &Path) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    set.is_match(relative) || path.file_name().is_some_and(|name| set.is_match(name))
+}
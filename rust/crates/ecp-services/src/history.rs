@@ -0,0 +1,359 @@
+//! Local file history — a per-file timeline of versions, independent of git.
+//!
+//! `FileService` snapshots a file before and after every `file/write`,
+//! `file/edit`, `file/replaceAll` and history restore; external changes
+//! reported by [`WatchService`] are snapshotted too, skipping ignored files.
+//! A version identical to the file's latest one is not recorded again.
+//!
+//! Storage lives under `<workspace>/.ultra/history/`: `entries.jsonl` holds one
+//! entry per version and `blobs/` holds contents keyed by SHA-256. Old versions
+//! are pruned per [`HistoryLimits`]; a pruned version gets a tombstone line
+//! appended rather than a rewrite of the log, which is compacted once
+//! tombstoned lines outnumber live ones.
+//!
+//! [`WatchService`]: crate::watch::WatchService
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use ignore::gitignore::Gitignore;
+use notify::event::{EventKind, ModifyKind};
+use notify::Event;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::file_index::{root_ignore, skipped};
+use crate::journal::{create_store_dir, hash_bytes};
+
+/// Retention limits for file history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryLimits {
+    /// Versions kept per file; the oldest are dropped first
+    pub max_entries_per_file: usize,
+    /// Versions older than this are dropped (0 keeps them forever)
+    pub max_age_days: u64,
+    /// Larger files are not snapshotted
+    pub max_file_bytes: u64,
+}
+
+impl Default for HistoryLimits {
+    fn default() -> Self {
+        Self {
+            max_entries_per_file: 50,
+            max_age_days: 30,
+            max_file_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
+
+/// Limits shared across workspaces so they can be changed at runtime.
+pub type SharedHistoryLimits = Arc<RwLock<HistoryLimits>>;
+
+/// One recorded version of a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub id: String,
+    /// Workspace-relative path
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+    pub timestamp: u64,
+    /// `save` (written through ECP), `external` (changed outside ECP, or
+    /// first seen before an ECP write) or `restore`
+    pub source: String,
+}
+
+/// A line of `entries.jsonl`: a version, or the ids of versions pruned since.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Entry(HistoryEntry),
+    Pruned { pruned: Vec<String> },
+}
+
+/// Version store for one workspace.
+pub struct FileHistory {
+    workspace_root: PathBuf,
+    dir: PathBuf,
+    limits: SharedHistoryLimits,
+    /// Loaded lazily from `entries.jsonl` on first access, oldest first.
+    entries: RwLock<Option<Vec<HistoryEntry>>>,
+    /// Lines of `entries.jsonl` that are tombstones or tombstoned versions
+    dead_lines: AtomicUsize,
+    /// Root ignore rules for external changes, rebuilt when they change
+    ignore: RwLock<Option<Gitignore>>,
+}
+
+impl FileHistory {
+    pub fn new(workspace_root: PathBuf) -> Self {
+        let dir = workspace_root.join(".ultra/history");
+        Self {
+            workspace_root,
+            dir,
+            limits: Arc::new(RwLock::new(HistoryLimits::default())),
+            entries: RwLock::new(None),
+            dead_lines: AtomicUsize::new(0),
+            ignore: RwLock::new(None),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: SharedHistoryLimits) {
+        self.limits = limits;
+    }
+
+    /// Snapshot the file's current content unless it matches the latest
+    /// version. Returns the new entry, if one was recorded.
+    pub fn record(&self, path: &Path, source: &str) -> Option<HistoryEntry> {
+        let relative = self.relative(path)?;
+        if skipped(&relative) {
+            return None;
+        }
+        let meta = std::fs::symlink_metadata(path).ok()?;
+        if !meta.is_file() || meta.len() > self.limits.read().max_file_bytes {
+            return None;
+        }
+        let content = std::fs::read(path).ok()?;
+        let hash = hash_bytes(&content);
+
+        // Read, hash and store the blob before taking the lock
+        self.ensure_loaded();
+        if self.latest_hash(&relative).as_ref() == Some(&hash) {
+            return None;
+        }
+        if let Err(e) = self.store_blob(&hash, &content) {
+            warn!("Failed to store history blob for {}: {e}", path.display());
+            return None;
+        }
+
+        let mut guard = self.entries.write();
+        let entries = guard.as_mut()?;
+        if entries.iter().rev().find(|e| e.path == relative).is_some_and(|e| e.hash == hash) {
+            return None;
+        }
+        // Pruning may have removed an identical blob since it was stored
+        if let Err(e) = self.store_blob(&hash, &content) {
+            warn!("Failed to store history blob for {}: {e}", path.display());
+            return None;
+        }
+        let entry = HistoryEntry {
+            id: format!("h-{}", uuid::Uuid::new_v4()),
+            path: relative,
+            hash,
+            size: content.len() as u64,
+            timestamp: now_ms(),
+            source: source.to_string(),
+        };
+        entries.push(entry.clone());
+
+        let pruned = self.prune(entries);
+        let dead = match pruned.len() {
+            0 => 0,
+            n => n + 1,
+        };
+        let result = if self.dead_lines.load(Ordering::Relaxed) + dead > entries.len() {
+            self.rewrite(entries)
+        } else {
+            let mut lines = vec![Line::Entry(entry.clone())];
+            if !pruned.is_empty() {
+                lines.push(Line::Pruned { pruned });
+            }
+            self.dead_lines.fetch_add(dead, Ordering::Relaxed);
+            self.append(&lines)
+        };
+        if let Err(e) = result {
+            warn!("Failed to write history entry: {e}");
+        }
+        debug!("History: {} {} ({})", entry.source, entry.path, entry.id);
+        Some(entry)
+    }
+
+    /// Snapshot files changed outside ECP, as reported by the watcher, on
+    /// the blocking pool. Ignored files are skipped.
+    pub fn record_event(self: &Arc<Self>, event: &Event) {
+        let content_changed = matches!(
+            event.kind,
+            EventKind::Create(_)
+                | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+        );
+        if !content_changed {
+            return;
+        }
+        if event.paths.iter().any(|p| p.file_name().is_some_and(|n| n == ".gitignore" || n == ".ignore")) {
+            *self.ignore.write() = None;
+        }
+        let paths: Vec<PathBuf> = event.paths.iter()
+            .filter(|path| self.relative(path).is_some_and(|r| !skipped(&r)))
+            .cloned()
+            .collect();
+        if paths.is_empty() {
+            return;
+        }
+        let history = self.clone();
+        tokio::task::spawn_blocking(move || {
+            for path in paths {
+                if history.relative(&path).is_some_and(|r| !history.is_ignored(&r)) {
+                    history.record(&path, "external");
+                }
+            }
+        });
+    }
+
+    /// Versions, newest first, optionally for one path.
+    pub fn list(&self, path: Option<&Path>, limit: usize) -> Vec<HistoryEntry> {
+        let relative = path.and_then(|p| self.relative(p));
+        if path.is_some() && relative.is_none() {
+            return Vec::new();
+        }
+        self.ensure_loaded();
+        self.entries.read().iter().flatten().rev()
+            .filter(|e| relative.as_ref().is_none_or(|r| e.path == *r))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<HistoryEntry> {
+        self.ensure_loaded();
+        self.entries.read().iter().flatten().find(|e| e.id == id).cloned()
+    }
+
+    /// Content of a recorded version.
+    pub fn read(&self, entry: &HistoryEntry) -> Option<Vec<u8>> {
+        std::fs::read(self.dir.join("blobs").join(&entry.hash)).ok()
+    }
+
+    pub fn absolute(&self, entry: &HistoryEntry) -> PathBuf {
+        self.workspace_root.join(&entry.path)
+    }
+
+    // ── Internal ──────────────────────────────────────────────────────────
+
+    fn relative(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.workspace_root).ok()?;
+        (!relative.as_os_str().is_empty()).then(|| relative.to_string_lossy().to_string())
+    }
+
+    fn latest_hash(&self, relative: &str) -> Option<String> {
+        self.entries.read().iter().flatten().rev().find(|e| e.path == relative).map(|e| e.hash.clone())
+    }
+
+    fn store_blob(&self, hash: &str, content: &[u8]) -> std::io::Result<()> {
+        let blob = self.dir.join("blobs").join(hash);
+        if blob.exists() {
+            return Ok(());
+        }
+        create_store_dir(&self.dir.join("blobs"))?;
+        std::fs::write(blob, content)
+    }
+
+    fn is_ignored(&self, relative: &str) -> bool {
+        if let Some(ref ignore) = *self.ignore.read() {
+            return ignore.matched_path_or_any_parents(relative, false).is_ignore();
+        }
+        let ignore = root_ignore(&self.workspace_root);
+        let ignored = ignore.matched_path_or_any_parents(relative, false).is_ignore();
+        *self.ignore.write() = Some(ignore);
+        ignored
+    }
+
+    /// Drop versions past the limits — the oldest beyond each file's count,
+    /// and any older than the age limit — and blobs nothing refers to any
+    /// more. Returns the ids of the dropped versions.
+    fn prune(&self, entries: &mut Vec<HistoryEntry>) -> Vec<String> {
+        let limits = *self.limits.read();
+        let cutoff = match limits.max_age_days {
+            0 => 0,
+            days => now_ms().saturating_sub(days * 24 * 60 * 60 * 1000),
+        };
+        let mut excess: HashMap<String, usize> = HashMap::new();
+        for entry in entries.iter() {
+            *excess.entry(entry.path.clone()).or_default() += 1;
+        }
+        for count in excess.values_mut() {
+            *count = count.saturating_sub(limits.max_entries_per_file.max(1));
+        }
+        let mut removed = HashSet::new();
+        let mut ids = Vec::new();
+        entries.retain(|e| {
+            let excess = excess.get_mut(&e.path).expect("counted above");
+            let drop = *excess > 0 || e.timestamp < cutoff;
+            if drop {
+                *excess = excess.saturating_sub(1);
+                removed.insert(e.hash.clone());
+                ids.push(e.id.clone());
+            }
+            !drop
+        });
+        for hash in removed {
+            if !entries.iter().any(|e| e.hash == hash) {
+                let _ = std::fs::remove_file(self.dir.join("blobs").join(hash));
+            }
+        }
+        ids
+    }
+
+    fn append(&self, lines: &[Line]) -> std::io::Result<()> {
+        let mut content = String::new();
+        for line in lines {
+            content.push_str(&serde_json::to_string(line)?);
+            content.push('\n');
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("entries.jsonl"))?;
+        file.write_all(content.as_bytes())
+    }
+
+    fn rewrite(&self, entries: &[HistoryEntry]) -> std::io::Result<()> {
+        let mut content = String::new();
+        for entry in entries {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+        let tmp = self.dir.join("entries.jsonl.tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(tmp, self.dir.join("entries.jsonl"))?;
+        self.dead_lines.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn ensure_loaded(&self) {
+        if self.entries.read().is_some() {
+            return;
+        }
+        let content = std::fs::read_to_string(self.dir.join("entries.jsonl")).unwrap_or_default();
+        let mut entries: Vec<HistoryEntry> = Vec::new();
+        let mut lines = 0;
+        for line in content.lines().filter_map(|line| serde_json::from_str(line).ok()) {
+            lines += 1;
+            match line {
+                Line::Entry(entry) => entries.push(entry),
+                Line::Pruned { pruned } => entries.retain(|e| !pruned.contains(&e.id)),
+            }
+        }
+        let mut guard = self.entries.write();
+        if guard.is_some() {
+            return;
+        }
+        let entries = guard.insert(entries);
+        self.dead_lines.store(lines - entries.len(), Ordering::Relaxed);
+        if !self.prune(entries).is_empty()
+            && let Err(e) = self.rewrite(entries)
+        {
+            warn!("Failed to write history entries: {e}");
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,849 @@
+//! Change journal — per-workspace record of mutating file operations.
+//!
+//! `FileService` captures the content of every path touched by `file/write`,
+//! `file/edit`, `file/rename`, `file/copy`, `file/delete` and `file/deleteDir`
+//! before and after the operation. Each request becomes one [`JournalEntry`];
+//! requests issued by an AI agent carry the `_executionId` of the agent run, so
+//! everything one run did can be reverted together, even in untracked files.
+//!
+//! Storage lives under `<workspace>/.ultra/journal/`: `entries.jsonl` holds one
+//! entry per line and `blobs/` holds file contents keyed by SHA-256. Old
+//! entries are pruned and oversized files and directories are recorded
+//! without their content, per [`JournalLimits`]. Snapshots read whole trees,
+//! so `FileService` takes them on the blocking pool. An append to an existing
+//! file only records the bytes added and where they start; reverting it
+//! truncates the file.
+//!
+//! Exposes `journal/list`, `journal/diff` and `journal/revert`.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ecp_protocol::{ECPError, HandlerResult};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::Service;
+
+/// Retention and size limits for the change journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalLimits {
+    /// Entries kept; the oldest are dropped first
+    pub max_entries: usize,
+    /// Entries older than this are dropped (0 keeps them forever)
+    pub max_age_days: u64,
+    /// Larger files are recorded without their content
+    pub max_file_bytes: u64,
+    /// Directories holding more than this are recorded without their content
+    pub max_tree_bytes: u64,
+}
+
+impl Default for JournalLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            max_age_days: 30,
+            max_file_bytes: 16 * 1024 * 1024,
+            max_tree_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Limits shared across workspaces so they can be changed at runtime.
+pub type SharedJournalLimits = Arc<RwLock<JournalLimits>>;
+
+/// One journaled request: the set of paths it changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub id: String,
+    /// The ECP method that produced this entry (e.g. `file/edit`).
+    pub method: String,
+    /// AI execution id, when the request came from an agent run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution_id: Option<String>,
+    pub timestamp: u64,
+    pub changes: Vec<JournalChange>,
+    /// Ids of the entries this entry reverted (only set by `journal/revert`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reverts: Vec<String>,
+    /// Directories the request created (e.g. the target of a directory
+    /// rename); a revert removes them once it has emptied them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub created_dirs: Vec<String>,
+    /// Paths over the size limits, recorded without content; a revert
+    /// leaves them as they are.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+}
+
+/// Content of a single path before and after a request.
+///
+/// `before`/`after` are blob hashes; `None` means the file did not exist.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalChange {
+    /// Workspace-relative path, or absolute for files outside the workspace.
+    pub path: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Set when the request appended to an existing file: its length
+    /// before. `after` then holds only the appended bytes, and a revert
+    /// truncates the file back to this length.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appended_at: Option<u64>,
+}
+
+/// Snapshot taken by [`Journal::begin`], completed by [`Journal::commit`].
+pub struct PendingEntry {
+    snapshots: Vec<(PathBuf, Option<String>)>,
+    /// The paths passed to `begin`, and whether each existed then
+    roots: Vec<(PathBuf, bool)>,
+    /// Paths over the size limits
+    skipped: Vec<PathBuf>,
+}
+
+/// A path's content as the journal sees it.
+enum Snapshot {
+    /// Not a regular file
+    Missing,
+    /// Stored as the blob with this hash
+    Stored(String),
+    /// Over the size limit; not stored
+    TooLarge,
+}
+
+/// A path's content as a revert's dry pass sees it.
+enum View {
+    /// As on disk
+    Disk,
+    /// The blob with this hash, or missing
+    Stored(Option<String>),
+    /// Cut back by an undone append
+    Truncated(Vec<u8>),
+}
+
+/// What a revert does to one path.
+enum Undo {
+    Restore(Option<String>),
+    Truncate(u64),
+}
+
+/// Append-only change journal for one workspace.
+pub struct Journal {
+    workspace_root: PathBuf,
+    dir: PathBuf,
+    limits: SharedJournalLimits,
+    /// Loaded lazily from `entries.jsonl` on first access.
+    entries: RwLock<Option<Vec<JournalEntry>>>,
+}
+
+impl Journal {
+    pub fn new(workspace_root: PathBuf) -> Self {
+        let dir = workspace_root.join(".ultra/journal");
+        Self {
+            workspace_root,
+            dir,
+            limits: Arc::new(RwLock::new(JournalLimits::default())),
+            entries: RwLock::new(None),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: SharedJournalLimits) {
+        self.limits = limits;
+    }
+
+    /// Snapshot the current content of `paths` ahead of a mutation.
+    /// Directories are expanded to the regular files they contain, both now
+    /// and, for paths that are directories afterwards, at commit.
+    pub fn begin(&self, paths: &[&Path]) -> PendingEntry {
+        let roots = paths.iter()
+            .map(|path| (path.to_path_buf(), std::fs::symlink_metadata(path).is_ok()))
+            .collect();
+        let mut skipped = Vec::new();
+        let mut files = Vec::new();
+        for path in paths {
+            if !path.is_dir() {
+                files.push(path.to_path_buf());
+            } else if !self.collect_tree(path, &mut files) {
+                skipped.push(path.to_path_buf());
+            }
+        }
+
+        let mut snapshots = Vec::new();
+        for path in files {
+            if path.starts_with(&self.dir) {
+                continue;
+            }
+            match self.store_file(&path) {
+                Snapshot::Missing => snapshots.push((path, None)),
+                Snapshot::Stored(hash) => snapshots.push((path, Some(hash))),
+                Snapshot::TooLarge => skipped.push(path),
+            }
+        }
+        PendingEntry { snapshots, roots, skipped }
+    }
+
+    /// Record the outcome of a mutation started with [`begin`](Self::begin).
+    /// Returns `None` when nothing actually changed.
+    pub fn commit(
+        &self,
+        pending: PendingEntry,
+        method: &str,
+        execution_id: Option<&str>,
+    ) -> Option<JournalEntry> {
+        self.commit_entry(pending, method, execution_id, Vec::new())
+    }
+
+    /// Record an append of `bytes` to an existing file that was
+    /// `appended_at` bytes long, without reading the file.
+    pub fn record_append(
+        &self,
+        path: &Path,
+        appended_at: u64,
+        bytes: &[u8],
+        method: &str,
+        execution_id: Option<&str>,
+    ) -> Option<JournalEntry> {
+        if bytes.is_empty() || path.starts_with(&self.dir) {
+            return None;
+        }
+        let mut entry = JournalEntry {
+            id: format!("j-{}", uuid::Uuid::new_v4()),
+            method: method.to_string(),
+            execution_id: execution_id.map(String::from),
+            timestamp: now_ms(),
+            changes: Vec::new(),
+            reverts: Vec::new(),
+            created_dirs: Vec::new(),
+            skipped: Vec::new(),
+        };
+        if bytes.len() as u64 > self.limits.read().max_file_bytes {
+            entry.skipped.push(self.display_path(path));
+        } else {
+            entry.changes.push(JournalChange {
+                path: self.display_path(path),
+                before: None,
+                after: Some(self.store_blob(path, bytes)),
+                appended_at: Some(appended_at),
+            });
+        }
+
+        if let Err(e) = self.append(&entry) {
+            warn!("Failed to write journal entry: {e}");
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// All entries, oldest first.
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.ensure_loaded();
+        self.entries.read().clone().unwrap_or_default()
+    }
+
+    pub fn get(&self, id: &str) -> Option<JournalEntry> {
+        self.entries().into_iter().find(|e| e.id == id)
+    }
+
+    /// Read a stored blob by hash.
+    pub fn read_blob(&self, hash: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.dir.join("blobs").join(hash)).ok()
+    }
+
+    /// Ids of entries already undone by a later `journal/revert`.
+    pub fn reverted_ids(&self) -> HashSet<String> {
+        self.entries()
+            .into_iter()
+            .flat_map(|e| e.reverts)
+            .collect()
+    }
+
+    /// Restore the `before` state of the given entries, newest first.
+    ///
+    /// Fails with a conflict error (and changes nothing) if a path no longer
+    /// holds the content the entry left behind, unless `force` is set. The
+    /// revert is itself journaled, so it can be undone in turn.
+    pub fn revert(&self, ids: &[String], force: bool) -> Result<Option<JournalEntry>, ECPError> {
+        let all = self.entries();
+        // Entries are stored in commit order; undo them newest first.
+        let targets: Vec<&JournalEntry> = all.iter().rev().filter(|e| ids.contains(&e.id)).collect();
+
+        // Dry pass: walk the changes against a virtual view of the disk so that
+        // several entries touching the same path are checked in sequence.
+        let mut current: HashMap<PathBuf, View> = HashMap::new();
+        let mut conflicts = Vec::new();
+        let mut steps: Vec<(PathBuf, Undo)> = Vec::new();
+        for entry in &targets {
+            for change in entry.changes.iter().rev() {
+                let path = self.absolute(&change.path);
+                let view = current.remove(&path).unwrap_or(View::Disk);
+                let (matches, next, undo) = match change.appended_at {
+                    // The appended bytes must still end the file
+                    Some(at) => {
+                        let appended = change.after.as_deref().and_then(|h| self.read_blob(h)).unwrap_or_default();
+                        let content = self.view_content(&path, &view).unwrap_or_default();
+                        let matches = content.len() as u64 == at + appended.len() as u64
+                            && content.ends_with(&appended);
+                        let kept = content[..content.len().min(at as usize)].to_vec();
+                        (matches, View::Truncated(kept), Undo::Truncate(at))
+                    }
+                    None => (
+                        self.view_hash(&path, &view) == change.after,
+                        View::Stored(change.before.clone()),
+                        Undo::Restore(change.before.clone()),
+                    ),
+                };
+                if !matches {
+                    conflicts.push(json!({
+                        "entryId": entry.id,
+                        "path": change.path,
+                        "expected": change.after,
+                        "actual": self.view_hash(&path, &view),
+                    }));
+                }
+                current.insert(path.clone(), next);
+                steps.push((path, undo));
+            }
+        }
+
+        if !conflicts.is_empty() && !force {
+            return Err(ECPError::conflict(
+                "Files changed since the journal entry was recorded; pass force to overwrite",
+            ).with_data(json!({ "conflicts": conflicts })));
+        }
+
+        let paths: Vec<&Path> = steps.iter().map(|(p, _)| p.as_path()).collect();
+        let pending = self.begin(&paths);
+        for (path, undo) in &steps {
+            match undo {
+                Undo::Restore(before) => self.restore(path, before.as_deref())?,
+                Undo::Truncate(len) => truncate(path, *len)?,
+            }
+        }
+
+        for entry in &targets {
+            for dir in entry.created_dirs.iter().rev() {
+                remove_empty_dirs(&self.absolute(dir));
+            }
+        }
+
+        let reverts = targets.iter().map(|e| e.id.clone()).collect();
+        Ok(self.commit_entry(pending, "journal/revert", None, reverts))
+    }
+
+    /// Path as stored in the journal: workspace-relative when possible.
+    pub fn display_path(&self, path: &Path) -> String {
+        path.strip_prefix(&self.workspace_root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    // ── Internal ──────────────────────────────────────────────────────────
+
+    fn absolute(&self, stored: &str) -> PathBuf {
+        let p = Path::new(stored);
+        if p.is_absolute() { p.to_path_buf() } else { self.workspace_root.join(p) }
+    }
+
+    fn commit_entry(
+        &self,
+        pending: PendingEntry,
+        method: &str,
+        execution_id: Option<&str>,
+        reverts: Vec<String>,
+    ) -> Option<JournalEntry> {
+        // Files that appeared under a path that is a directory now, such as
+        // the target of a directory rename, did not exist before
+        let mut snapshots = pending.snapshots;
+        let mut skipped = pending.skipped;
+        let mut created_dirs = Vec::new();
+        for (root, existed) in &pending.roots {
+            if !root.is_dir() || root.starts_with(&self.dir) || skipped.contains(root) {
+                continue;
+            }
+            if !existed {
+                created_dirs.push(self.display_path(root));
+            }
+            let mut files = Vec::new();
+            if !self.collect_tree(root, &mut files) {
+                skipped.push(root.clone());
+                continue;
+            }
+            for file in files {
+                if !snapshots.iter().any(|(path, _)| *path == file) {
+                    snapshots.push((file, None));
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let changes: Vec<JournalChange> = snapshots
+            .into_iter()
+            .filter(|(path, _)| seen.insert(path.clone()))
+            .filter_map(|(path, before)| {
+                let after = match self.store_file(&path) {
+                    Snapshot::Missing => None,
+                    Snapshot::Stored(hash) => Some(hash),
+                    Snapshot::TooLarge => {
+                        skipped.push(path);
+                        return None;
+                    }
+                };
+                (before != after).then(|| JournalChange {
+                    path: self.display_path(&path),
+                    before,
+                    after,
+                    appended_at: None,
+                })
+            })
+            .collect();
+
+        if changes.is_empty() && reverts.is_empty() && skipped.is_empty() {
+            return None;
+        }
+        let mut skipped: Vec<String> = skipped.iter().map(|p| self.display_path(p)).collect();
+        skipped.sort();
+        skipped.dedup();
+
+        let entry = JournalEntry {
+            id: format!("j-{}", uuid::Uuid::new_v4()),
+            method: method.to_string(),
+            execution_id: execution_id.map(String::from),
+            timestamp: now_ms(),
+            changes,
+            reverts,
+            created_dirs,
+            skipped,
+        };
+
+        if let Err(e) = self.append(&entry) {
+            warn!("Failed to write journal entry: {e}");
+            return None;
+        }
+        Some(entry)
+    }
+
+    fn append(&self, entry: &JournalEntry) -> std::io::Result<()> {
+        self.ensure_loaded();
+        create_store_dir(&self.dir)?;
+        let mut guard = self.entries.write();
+        let Some(entries) = guard.as_mut() else { return Ok(()) };
+        entries.push(entry.clone());
+        if self.prune(entries) {
+            return self.rewrite(entries);
+        }
+        let line = serde_json::to_string(entry)?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("entries.jsonl"))?;
+        writeln!(file, "{line}")
+    }
+
+    /// Drop entries past the limits, and blobs nothing refers to any more.
+    /// Returns whether anything was dropped.
+    fn prune(&self, entries: &mut Vec<JournalEntry>) -> bool {
+        let limits = *self.limits.read();
+        let cutoff = match limits.max_age_days {
+            0 => 0,
+            days => now_ms().saturating_sub(days * 24 * 60 * 60 * 1000),
+        };
+        let mut excess = entries.len().saturating_sub(limits.max_entries.max(1));
+        let mut removed = HashSet::new();
+        entries.retain(|e| {
+            let drop = excess > 0 || e.timestamp < cutoff;
+            if drop {
+                excess = excess.saturating_sub(1);
+                removed.extend(e.changes.iter().flat_map(|c| [c.before.clone(), c.after.clone()]).flatten());
+            }
+            !drop
+        });
+        if removed.is_empty() {
+            return false;
+        }
+        let kept: HashSet<&String> = entries.iter()
+            .flat_map(|e| e.changes.iter())
+            .flat_map(|c| c.before.iter().chain(c.after.iter()))
+            .collect();
+        for hash in removed.iter().filter(|hash| !kept.contains(hash)) {
+            let _ = std::fs::remove_file(self.dir.join("blobs").join(hash));
+        }
+        true
+    }
+
+    fn rewrite(&self, entries: &[JournalEntry]) -> std::io::Result<()> {
+        let mut content = String::new();
+        for entry in entries {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+        let tmp = self.dir.join("entries.jsonl.tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(tmp, self.dir.join("entries.jsonl"))
+    }
+
+    fn ensure_loaded(&self) {
+        if self.entries.read().is_some() {
+            return;
+        }
+        let entries = std::fs::read_to_string(self.dir.join("entries.jsonl"))
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut guard = self.entries.write();
+        if guard.is_none() {
+            *guard = Some(entries);
+        }
+    }
+
+    /// Collect the regular files under `dir`, unless together they are over
+    /// the tree size limit.
+    fn collect_tree(&self, dir: &Path, out: &mut Vec<PathBuf>) -> bool {
+        let mut budget = self.limits.read().max_tree_bytes;
+        let mut files = Vec::new();
+        if !collect_files_within(dir, &mut budget, &mut files) {
+            return false;
+        }
+        out.extend(files);
+        true
+    }
+
+    /// Store the file's content as a blob.
+    fn store_file(&self, path: &Path) -> Snapshot {
+        let Ok(meta) = std::fs::symlink_metadata(path) else { return Snapshot::Missing };
+        if !meta.is_file() {
+            return Snapshot::Missing;
+        }
+        if meta.len() > self.limits.read().max_file_bytes {
+            return Snapshot::TooLarge;
+        }
+        let Ok(content) = std::fs::read(path) else { return Snapshot::Missing };
+        Snapshot::Stored(self.store_blob(path, &content))
+    }
+
+    /// Store `content`, read from or written to `path`, as a blob.
+    fn store_blob(&self, path: &Path, content: &[u8]) -> String {
+        let hash = hash_bytes(content);
+        let blobs = self.dir.join("blobs");
+        let blob_path = blobs.join(&hash);
+        if !blob_path.exists()
+            && let Err(e) = create_store_dir(&blobs)
+                .and_then(|_| std::fs::write(&blob_path, content))
+        {
+            warn!("Failed to store journal blob for {}: {e}", path.display());
+        }
+        hash
+    }
+
+    fn view_content(&self, path: &Path, view: &View) -> Option<Vec<u8>> {
+        match view {
+            View::Disk => std::fs::read(path).ok(),
+            View::Stored(hash) => hash.as_deref().and_then(|h| self.read_blob(h)),
+            View::Truncated(content) => Some(content.clone()),
+        }
+    }
+
+    fn view_hash(&self, path: &Path, view: &View) -> Option<String> {
+        match view {
+            View::Disk => hash_file(path),
+            View::Stored(hash) => hash.clone(),
+            View::Truncated(content) => Some(hash_bytes(content)),
+        }
+    }
+
+    fn restore(&self, path: &Path, hash: Option<&str>) -> Result<(), ECPError> {
+        match hash {
+            Some(hash) => {
+                let content = self.read_blob(hash).ok_or_else(|| {
+                    ECPError::server_error(format!("Journal blob missing: {hash}"))
+                })?;
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| {
+                        ECPError::server_error(format!("Failed to create directory: {e}"))
+                    })?;
+                }
+                std::fs::write(path, content).map_err(|e| {
+                    ECPError::server_error(format!("Failed to restore {}: {e}", path.display()))
+                })
+            }
+            None => match std::fs::remove_file(path) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(ECPError::server_error(format!(
+                    "Failed to remove {}: {e}", path.display()
+                ))),
+            },
+        }
+    }
+}
+
+/// Journal service — exposes the workspace change journal over ECP.
+pub struct JournalService {
+    journal: Arc<Journal>,
+}
+
+impl JournalService {
+    pub fn new(journal: Arc<Journal>) -> Self {
+        Self { journal }
+    }
+}
+
+impl Service for JournalService {
+    fn namespace(&self) -> &str {
+        "journal"
+    }
+
+    async fn handle(&self, method: &str, params: Option<Value>) -> HandlerResult {
+        match method {
+            "journal/list" => {
+                let p: JournalListParams = params
+                    .map(serde_json::from_value)
+                    .transpose()
+                    .map_err(|e| ECPError::invalid_params(format!("Invalid parameters: {e}")))?
+                    .unwrap_or_default();
+                let limit = p.limit.unwrap_or(100) as usize;
+                let reverted = self.journal.reverted_ids();
+
+                let entries: Vec<Value> = self.journal.entries()
+                    .into_iter()
+                    .rev()
+                    .filter(|e| p.execution_id.is_none() || e.execution_id == p.execution_id)
+                    .filter(|e| {
+                        p.path.as_ref().is_none_or(|path| e.changes.iter().any(|c| &c.path == path))
+                    })
+                    .take(limit)
+                    .map(|e| {
+                        let paths: Vec<&str> = e.changes.iter().map(|c| c.path.as_str()).collect();
+                        json!({
+                            "id": e.id,
+                            "method": e.method,
+                            "executionId": e.execution_id,
+                            "timestamp": e.timestamp,
+                            "paths": paths,
+                            "reverted": reverted.contains(&e.id),
+                            "reverts": e.reverts,
+                            "skipped": e.skipped,
+                        })
+                    })
+                    .collect();
+
+                Ok(json!({ "entries": entries }))
+            }
+
+            "journal/diff" => {
+                let p: JournalEntryIdParam = parse_params(params)?;
+                let entry = self.journal.get(&p.entry_id)
+                    .ok_or_else(|| ECPError::invalid_params(format!("Journal entry not found: {}", p.entry_id)))?;
+
+                let files: Vec<Value> = entry.changes.iter().map(|c| {
+                    let before = c.before.as_deref().and_then(|h| self.journal.read_blob(h)).unwrap_or_default();
+                    let after = c.after.as_deref().and_then(|h| self.journal.read_blob(h)).unwrap_or_default();
+                    let status = match (&c.before, &c.after) {
+                        _ if c.appended_at.is_some() => "appended",
+                        (None, _) => "added",
+                        (_, None) => "deleted",
+                        _ => "modified",
+                    };
+                    match (std::str::from_utf8(&before), std::str::from_utf8(&after)) {
+                        (Ok(old), Ok(new)) => {
+                            let diff = similar::TextDiff::from_lines(old, new)
+                                .unified_diff()
+                                .header(&format!("a/{}", c.path), &format!("b/{}", c.path))
+                                .to_string();
+                            json!({ "path": c.path, "status": status, "binary": false, "diff": diff })
+                        }
+                        _ => json!({ "path": c.path, "status": status, "binary": true, "diff": null }),
+                    }
+                }).collect();
+
+                Ok(json!({
+                    "entryId": entry.id,
+                    "method": entry.method,
+                    "executionId": entry.execution_id,
+                    "timestamp": entry.timestamp,
+                    "files": files,
+                }))
+            }
+
+            "journal/revert" => {
+                let p: JournalRevertParams = parse_params(params)?;
+                let reverted = self.journal.reverted_ids();
+
+                let ids: Vec<String> = match (&p.entry_id, &p.execution_id) {
+                    (Some(id), _) => {
+                        if self.journal.get(id).is_none() {
+                            return Err(ECPError::invalid_params(format!("Journal entry not found: {id}")));
+                        }
+                        if reverted.contains(id) {
+                            return Err(ECPError::invalid_params(format!("Journal entry already reverted: {id}")));
+                        }
+                        vec![id.clone()]
+                    }
+                    (None, Some(exec_id)) => self.journal.entries()
+                        .into_iter()
+                        .filter(|e| e.execution_id.as_deref() == Some(exec_id.as_str()))
+                        .filter(|e| !reverted.contains(&e.id))
+                        .map(|e| e.id)
+                        .collect(),
+                    (None, None) => {
+                        return Err(ECPError::invalid_params("Either 'entryId' or 'executionId' is required"));
+                    }
+                };
+
+                if ids.is_empty() {
+                    return Err(ECPError::invalid_params("No journal entries to revert"));
+                }
+
+                // Left as they are: recorded without content
+                let skipped: Vec<String> = self.journal.entries()
+                    .into_iter()
+                    .filter(|e| ids.contains(&e.id))
+                    .flat_map(|e| e.skipped)
+                    .collect();
+                let journal = self.journal.clone();
+                let targets = ids.clone();
+                let entry = tokio::task::spawn_blocking(move || journal.revert(&targets, p.force)).await
+                    .map_err(|e| ECPError::server_error(format!("Journal task failed: {e}")))??;
+                Ok(json!({
+                    "success": true,
+                    "reverted": ids,
+                    "entryId": entry.as_ref().map(|e| e.id.clone()),
+                    "changes": entry.map(|e| e.changes.len()).unwrap_or(0),
+                    "skipped": skipped,
+                }))
+            }
+
+            _ => Err(ECPError::method_not_found(method)),
+        }
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Parameter types
+// ─────────────────────────────────────────────────────────────────────────────
+
+#[derive(Deserialize, Default)]
+struct JournalListParams {
+    #[serde(rename = "executionId")]
+    execution_id: Option<String>,
+    path: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct JournalEntryIdParam {
+    #[serde(rename = "entryId", alias = "id")]
+    entry_id: String,
+}
+
+#[derive(Deserialize)]
+struct JournalRevertParams {
+    #[serde(rename = "entryId")]
+    entry_id: Option<String>,
+    #[serde(rename = "executionId")]
+    execution_id: Option<String>,
+    #[serde(default)]
+    force: bool,
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Helpers
+// ─────────────────────────────────────────────────────────────────────────────
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Option<Value>) -> Result<T, ECPError> {
+    match params {
+        Some(v) => serde_json::from_value(v)
+            .map_err(|e| ECPError::invalid_params(format!("Invalid parameters: {e}"))),
+        None => Err(ECPError::invalid_params("Parameters required")),
+    }
+}
+
+/// Create a directory for one of the stores under `<workspace>/.ultra/`,
+/// and keep `.ultra` out of git: the stores hold copies of deleted and
+/// overwritten files, secrets included.
+pub(crate) fn create_store_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    if let Some(ultra) = dir.ancestors().find(|a| a.file_name().is_some_and(|n| n == ".ultra")) {
+        let ignore = ultra.join(".gitignore");
+        if !ignore.exists() {
+            std::fs::write(ignore, "*\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// SHA-256 of `content` as lowercase hex; also the file version hash
+/// reported by `FileService`.
+pub(crate) fn hash_bytes(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    let meta = std::fs::symlink_metadata(path).ok()?;
+    if !meta.is_file() {
+        return None;
+    }
+    std::fs::read(path).ok().map(|c| hash_bytes(&c))
+}
+
+/// Recursively collect regular files under `dir` (symlinks are not
+/// followed), giving up once their sizes add up to more than `budget`.
+fn collect_files_within(dir: &Path, budget: &mut u64, out: &mut Vec<PathBuf>) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else { return true };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(t) if t.is_dir() && !collect_files_within(&path, budget, out) => return false,
+            Ok(t) if t.is_dir() => {}
+            Ok(t) if t.is_file() => {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                match budget.checked_sub(size) {
+                    Some(left) => *budget = left,
+                    None => return false,
+                }
+                out.push(path);
+            }
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Remove `dir` and the directories below it that hold no files.
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                remove_empty_dirs(&entry.path());
+            }
+        }
+    }
+    let _ = std::fs::remove_dir(dir);
+}
+
+/// Cut `path` back to `len` bytes; a missing file is left missing.
+fn truncate(path: &Path, len: u64) -> Result<(), ECPError> {
+    match std::fs::OpenOptions::new().write(true).open(path) {
+        Ok(file) => file.set_len(len),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+    .map_err(|e| ECPError::server_error(format!("Failed to truncate {}: {e}", path.display())))
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -10,20 +10,35 @@
 //! [`DocumentService`](document::DocumentService), and all bridge-delegated
 //! services (AI, Auth, Agent, Workflow, Syntax).
 //!
-//! Workspace services: File, Git, Watch, Terminal, Session, Chat, Database, LSP.
+//! Workspace services: File, Git, Watch, Terminal, Session, Chat, Database, LSP,
+//! Journal.
 
 pub mod bridge_services;
 pub mod chat;
 pub mod database;
 pub mod document;
+pub mod edit;
 pub mod file;
+pub mod file_content;
+pub mod file_index;
+pub mod file_tree;
 pub mod git;
+pub mod grep;
+pub mod history;
+pub mod journal;
 pub mod lsp;
 pub mod models;
 pub mod secret;
 pub mod session;
 pub mod terminal;
+pub mod terminal_execute;
+pub mod terminal_output;
+pub mod terminal_screen;
+pub mod terminal_shell;
+pub mod terminal_supervisor;
+pub mod trash;
 pub mod watch;
+pub mod watch_poll;
 
 use ecp_protocol::HandlerResult;
 
@@ -36,6 +51,25 @@ pub enum ServiceScope {
     Workspace,
 }
 
+/// A kind of long-lived resource that counts against a quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Terminal,
+    LspClient,
+    Watch,
+    /// A `terminal/execute` running in the background
+    Execution,
+}
+
+/// A quota-limited resource a service holds right now.
+#[derive(Debug, Clone)]
+pub struct HeldResource {
+    /// Terminal ID, language ID, watch ID or execution ID
+    pub id: String,
+    /// Client it belongs to, where the service knows
+    pub owner: Option<String>,
+}
+
 /// Trait implemented by all ECP services.
 ///
 /// Each service handles a namespace of methods (e.g., "file/*", "git/*").
@@ -73,4 +107,14 @@ pub trait Service: Send + Sync {
     fn shutdown(&self) -> impl std::future::Future<Output = ()> + Send {
         async {}
     }
+
+    /// Release whatever a client held, when it closes the workspace or
+    /// disconnects. Requests carry the caller's id as `_clientId`.
+    fn client_disconnected(&self, _client_id: &str) {}
+
+    /// Resources of `kind` alive in this service, so quota usage follows
+    /// what still runs rather than what was requested.
+    fn held_resources(&self, _kind: ResourceKind) -> impl std::future::Future<Output = Vec<HeldResource>> + Send {
+        async { Vec::new() }
+    }
 }
@@ -8,15 +8,15 @@ use std::process::Stdio;
 use std::sync::Arc;
 
 use ecp_protocol::{ECPError, HandlerResult};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 use tokio::sync::{mpsc, oneshot, Mutex as TokioMutex};
 use tracing::{debug, info, warn};
 
-use crate::Service;
+use crate::{HeldResource, ResourceKind, Service};
 
 // ─────────────────────────────────────────────────────────────────────────────
 // Server configuration
@@ -55,6 +55,10 @@ pub struct ServerConfig {
     pub env: Option<HashMap<String, String>>,
 }
 
+/// Server-wide default configurations by language ID (from `server.toml`),
+/// shared across workspaces so they can be changed at runtime.
+pub type SharedServerConfigs = Arc<RwLock<HashMap<String, ServerConfig>>>;
+
 // ─────────────────────────────────────────────────────────────────────────────
 // LSP Client — manages a single language server process
 // ─────────────────────────────────────────────────────────────────────────────
@@ -67,6 +71,8 @@ struct LSPClient {
     capabilities: RwLock<Option<Value>>,
     diagnostics: Arc<RwLock<HashMap<String, Vec<Value>>>>,
     status: RwLock<String>,
+    /// The server process, killed when the client is dropped
+    process: Mutex<Child>,
 }
 
 impl LSPClient {
@@ -174,16 +180,14 @@ impl LSPClient {
                     }
                 } else if let Some(method) = msg.get("method").and_then(|v| v.as_str()) {
                     // Server notification
-                    if method == "textDocument/publishDiagnostics" {
-                        if let Some(params) = msg.get("params") {
-                            if let Some(uri) = params.get("uri").and_then(|v| v.as_str()) {
-                                let diags = params.get("diagnostics")
-                                    .and_then(|v| v.as_array())
-                                    .cloned()
-                                    .unwrap_or_default();
-                                diagnostics_clone.write().insert(uri.to_string(), diags);
-                            }
-                        }
+                    if method == "textDocument/publishDiagnostics"
+                        && let Some(params) = msg.get("params")
+                        && let Some(uri) = params.get("uri").and_then(|v| v.as_str()) {
+                        let diags = params.get("diagnostics")
+                            .and_then(|v| v.as_array())
+                            .cloned()
+                            .unwrap_or_default();
+                        diagnostics_clone.write().insert(uri.to_string(), diags);
                     }
                 }
             }
@@ -207,6 +211,7 @@ impl LSPClient {
             capabilities: RwLock::new(None),
             diagnostics,
             status: RwLock::new("starting".into()),
+            process: Mutex::new(child),
         };
 
         // Send initialize request
@@ -269,6 +274,11 @@ impl LSPClient {
             .map_err(|_| ECPError::server_error(format!("LSP response channel closed: {method}")))
     }
 
+    /// Whether the server process is still alive.
+    fn is_running(&self) -> bool {
+        matches!(self.process.lock().try_wait(), Ok(None))
+    }
+
     /// Send a JSON-RPC notification (no response expected).
     async fn send_notification(&self, method: &str, params: Value) -> Result<(), ECPError> {
         let msg = json!({
@@ -299,6 +309,8 @@ pub struct LSPService {
     clients: Arc<TokioMutex<HashMap<String, LSPClient>>>,
     /// Custom server configurations
     server_configs: RwLock<HashMap<String, ServerConfig>>,
+    /// Defaults used when no custom configuration is set for a language
+    default_configs: SharedServerConfigs,
     /// Open documents tracked for synchronization
     open_docs: RwLock<HashMap<String, DocState>>,
 }
@@ -314,18 +326,30 @@ impl LSPService {
             workspace_root: workspace_root.to_string_lossy().to_string(),
             clients: Arc::new(TokioMutex::new(HashMap::new())),
             server_configs: RwLock::new(HashMap::new()),
+            default_configs: Arc::new(RwLock::new(HashMap::new())),
             open_docs: RwLock::new(HashMap::new()),
         }
     }
 
+    pub fn set_default_configs(&mut self, configs: SharedServerConfigs) {
+        self.default_configs = configs;
+    }
+
+    /// Custom configuration for a language, falling back to the server default.
+    fn server_config(&self, language_id: &str) -> Option<ServerConfig> {
+        let custom = self.server_configs.read().get(language_id).cloned();
+        custom.or_else(|| self.default_configs.read().get(language_id).cloned())
+    }
+
     /// Get or start a language server for the given language.
     async fn get_client(&self, language_id: &str) -> Result<(), ECPError> {
         let mut clients = self.clients.lock().await;
-        if clients.contains_key(language_id) {
+        // A server that crashed is started again
+        if clients.get(language_id).is_some_and(LSPClient::is_running) {
             return Ok(());
         }
 
-        let custom_config = self.server_configs.read().get(language_id).cloned();
+        let custom_config = self.server_config(language_id);
         let client = LSPClient::start(language_id, &self.workspace_root, custom_config.as_ref()).await?;
         clients.insert(language_id.to_string(), client);
         Ok(())
@@ -621,7 +645,7 @@ impl Service for LSPService {
 
             "lsp/getServerConfig" => {
                 let p: LanguageIdParam = parse_params(params)?;
-                let config = self.server_configs.read().get(&p.language_id).cloned();
+                let config = self.server_config(&p.language_id);
                 Ok(json!({ "config": config }))
             }
 
@@ -642,6 +666,19 @@ impl Service for LSPService {
         }
     }
 
+    async fn held_resources(&self, kind: ResourceKind) -> Vec<HeldResource> {
+        if kind != ResourceKind::LspClient {
+            return Vec::new();
+        }
+        self.clients.lock().await.iter()
+            .filter(|(_, client)| client.is_running())
+            .map(|(language_id, _)| HeldResource {
+                id: language_id.clone(),
+                owner: None,
+            })
+            .collect()
+    }
+
     async fn shutdown(&self) {
         // Stop all language servers
         let mut clients = self.clients.lock().await;
@@ -734,7 +771,7 @@ fn parse_params<T: for<'de> Deserialize<'de>>(params: Option<Value>) -> Result<T
 }
 
 /// Detect language from file URI or path.
-fn detect_language(uri: &str) -> String {
+pub fn detect_language(uri: &str) -> String {
     let ext = uri.rsplit('.').next().unwrap_or("");
     match ext {
         "ts" => "typescript",
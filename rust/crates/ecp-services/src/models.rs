@@ -74,10 +74,9 @@ impl Service for ModelsService {
         match method {
             "models/list" => {
                 // Prefer bridge (adds agent-sdk models dynamically)
-                if let Some(ref bridge) = self.bridge {
-                    if bridge.is_running() {
-                        return bridge.request("models/list", params).await;
-                    }
+                if let Some(ref bridge) = self.bridge
+                    && bridge.is_running() {
+                    return bridge.request("models/list", params).await;
                 }
                 // Fallback: read directly from file
                 let config = Self::read_models_file().await;
@@ -85,12 +84,11 @@ impl Service for ModelsService {
             }
 
             "models/refresh" => {
-                if let Some(ref bridge) = self.bridge {
-                    if bridge.is_running() {
-                        let result = bridge.request("models/refresh", params).await?;
-                        info!("Models refreshed via bridge");
-                        return Ok(result);
-                    }
+                if let Some(ref bridge) = self.bridge
+                    && bridge.is_running() {
+                    let result = bridge.request("models/refresh", params).await?;
+                    info!("Models refreshed via bridge");
+                    return Ok(result);
                 }
                 Err(ECPError::server_error(
                     "models/refresh requires the AI bridge subprocess".to_string(),
@@ -38,7 +38,7 @@ impl SecretProvider for EnvProvider {
             "gemini-api-key" => "GEMINI_API_KEY",
             _ => {
                 // Try direct env var name (uppercase, dashes/dots to underscores)
-                let upper = key.replace('-', "_").replace('.', "_").to_uppercase();
+                let upper = key.replace(['-', '.'], "_").to_uppercase();
                 return std::env::var(&upper).ok();
             }
         };
@@ -123,6 +123,12 @@ pub struct SecretService {
     providers: Vec<Box<dyn SecretProvider>>,
 }
 
+impl Default for SecretService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SecretService {
     pub fn new() -> Self {
         Self {
@@ -175,7 +181,7 @@ impl Service for SecretService {
                 for provider in &self.providers {
                     if provider.is_writable() {
                         provider.set(&p.key, &p.value)
-                            .map_err(|e| ECPError::server_error(e))?;
+                            .map_err(ECPError::server_error)?;
                         return Ok(json!({ "success": true }));
                     }
                 }
@@ -187,10 +193,9 @@ impl Service for SecretService {
                 let p: SecretKeyParam = parse_params(params)?;
                 let mut deleted = false;
                 for provider in &self.providers {
-                    if provider.is_writable() {
-                        if let Ok(true) = provider.delete(&p.key) {
-                            deleted = true;
-                        }
+                    if provider.is_writable()
+                        && let Ok(true) = provider.delete(&p.key) {
+                        deleted = true;
                     }
                 }
                 Ok(json!({ "deleted": deleted }))
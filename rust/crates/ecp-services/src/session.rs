@@ -2,6 +2,7 @@
 
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use ecp_protocol::{ECPError, HandlerResult};
 use parking_lot::RwLock;
@@ -9,6 +10,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tracing::{debug, info};
 
+use crate::file_index::FileIndex;
 use crate::Service;
 
 /// Serialized session state.
@@ -44,6 +46,8 @@ pub struct SessionService {
     sessions_dir: RwLock<PathBuf>,
     settings: RwLock<HashMap<String, Value>>,
     current_session: RwLock<Option<SessionState>>,
+    /// Told about the session's open files, for search recency
+    file_index: RwLock<Option<Arc<FileIndex>>>,
 }
 
 impl SessionService {
@@ -56,6 +60,7 @@ impl SessionService {
             sessions_dir: RwLock::new(sessions_dir),
             settings: RwLock::new(default_settings()),
             current_session: RwLock::new(None),
+            file_index: RwLock::new(None),
         }
     }
 
@@ -66,9 +71,25 @@ impl SessionService {
             sessions_dir: RwLock::new(sessions_dir),
             settings: RwLock::new(default_settings()),
             current_session: RwLock::new(None),
+            file_index: RwLock::new(None),
         }
     }
 
+    /// Feed the session's open files to the file index as recently used.
+    pub fn set_file_index(&self, index: Arc<FileIndex>) {
+        *self.file_index.write() = Some(index);
+    }
+
+    /// Make `state` current, updating search recency from its open files.
+    fn set_current(&self, state: SessionState) {
+        if let Some(ref index) = *self.file_index.read() {
+            index.set_recent(
+                state.active_file.iter().chain(&state.open_files).map(String::as_str),
+            );
+        }
+        *self.current_session.write() = Some(state);
+    }
+
     /// Load settings from the user config file.
     /// Tries `.jsonc` first (JSONC with comments), then `.json`.
     async fn load_settings(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -189,7 +210,7 @@ impl Service for SessionService {
                 tokio::fs::write(&path, json).await
                     .map_err(|e| ECPError::server_error(format!("Write error: {e}")))?;
 
-                *self.current_session.write() = Some(state);
+                self.set_current(state);
                 Ok(json!({ "sessionId": id }))
             }
 
@@ -211,7 +232,7 @@ impl Service for SessionService {
                     }
                 }
 
-                *self.current_session.write() = Some(state.clone());
+                self.set_current(state.clone());
                 Ok(json!({
                     "session": state,
                 }))
@@ -249,7 +270,7 @@ impl Service for SessionService {
 
             "session/setCurrent" => {
                 let p: SessionSetCurrentParam = parse_params(params)?;
-                *self.current_session.write() = Some(p.state);
+                self.set_current(p.state);
                 Ok(json!({ "success": true }))
             }
 
@@ -287,11 +308,10 @@ impl Service for SessionService {
                 if let Some(ref ws_root) = p.workspace_root {
                     let id = format!("workspace-{:x}", simple_hash(ws_root));
                     let path = sessions_dir.join(format!("{id}.json"));
-                    if let Ok(content) = tokio::fs::read_to_string(&path).await {
-                        if let Ok(state) = serde_json::from_str::<SessionState>(&content) {
-                            *self.current_session.write() = Some(state.clone());
-                            return Ok(serde_json::to_value(&state).unwrap_or(Value::Null));
-                        }
+                    if let Ok(content) = tokio::fs::read_to_string(&path).await
+                        && let Ok(state) = serde_json::from_str::<SessionState>(&content) {
+                        self.set_current(state.clone());
+                        return Ok(serde_json::to_value(&state).unwrap_or(Value::Null));
                     }
                     // No saved state for this workspace — return null
                     return Ok(Value::Null);
@@ -303,16 +323,15 @@ impl Service for SessionService {
                 if let Ok(mut entries) = tokio::fs::read_dir(&sessions_dir).await {
                     while let Ok(Some(entry)) = entries.next_entry().await {
                         let name = entry.file_name().to_string_lossy().to_string();
-                        if name.ends_with(".json") {
-                            if let Ok(meta) = entry.metadata().await {
-                                let mtime = meta.modified().ok()
-                                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-                                    .map(|d| d.as_millis() as u64)
-                                    .unwrap_or(0);
-                                if newest.as_ref().map_or(true, |(t, _)| mtime > *t) {
-                                    let id = name.trim_end_matches(".json").to_string();
-                                    newest = Some((mtime, id));
-                                }
+                        if name.ends_with(".json")
+                            && let Ok(meta) = entry.metadata().await {
+                            let mtime = meta.modified().ok()
+                                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                                .map(|d| d.as_millis() as u64)
+                                .unwrap_or(0);
+                            if newest.as_ref().is_none_or(|(t, _)| mtime > *t) {
+                                let id = name.trim_end_matches(".json").to_string();
+                                newest = Some((mtime, id));
                             }
                         }
                     }
@@ -324,7 +343,7 @@ impl Service for SessionService {
                         .map_err(|e| ECPError::server_error(format!("Load failed: {e}")))?;
                     let state: SessionState = serde_json::from_str(&content)
                         .map_err(|e| ECPError::server_error(format!("Parse error: {e}")))?;
-                    *self.current_session.write() = Some(state.clone());
+                    self.set_current(state.clone());
                     Ok(serde_json::to_value(&state).unwrap_or(Value::Null))
                 } else {
                     Ok(Value::Null)
@@ -606,10 +625,9 @@ fn theme_search_dirs() -> Vec<PathBuf> {
     }
 
     // Exe-relative: Contents/MacOS/ultra-ecp → Contents/Resources/config/themes (app bundle)
-    if let Ok(exe) = std::env::current_exe() {
-        if let Some(exe_dir) = exe.parent() {
-            dirs.push(exe_dir.join("../Resources/config/themes"));
-        }
+    if let Ok(exe) = std::env::current_exe()
+        && let Some(exe_dir) = exe.parent() {
+        dirs.push(exe_dir.join("../Resources/config/themes"));
     }
 
     // CWD-relative (dev: running from project root or rust/ subdir)
@@ -723,11 +741,10 @@ fn strip_jsonc_comments(input: &str) -> String {
                     // Block comment — skip until */
                     chars.next(); // consume *
                     while let Some(c) = chars.next() {
-                        if c == '*' {
-                            if chars.peek() == Some(&'/') {
-                                chars.next();
-                                break;
-                            }
+                        if c == '*'
+                            && chars.peek() == Some(&'/') {
+                            chars.next();
+                            break;
                         }
                     }
                 }
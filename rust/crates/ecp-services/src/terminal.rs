@@ -1,27 +1,175 @@
 //! Terminal service — PTY management for shell sessions.
-
-use std::collections::HashMap;
+//!
+//! `terminal/create` and `terminal/attachTmux` run their process on a real
+//! pseudo-terminal (via `portable-pty`), so prompts, colors, full-screen
+//! programs and job control work, stderr arrives interleaved with stdout, and
+//! `terminal/resize` reaches the program as `SIGWINCH`. Closing a terminal
+//! hangs up its process. Output, title changes and exits are pushed to
+//! clients as they happen (see [`crate::terminal_output`]), and kept on an
+//! emulated screen with bounded scrollback (see [`crate::terminal_screen`]).
+//!
+//! Terminals outlive the clients that use them, like tmux sessions. A client
+//! is attached to the terminals it creates or `terminal/attach`es to until it
+//! detaches or disconnects; with nobody attached a terminal keeps running,
+//! and `terminal/list` shows it as detached along with its last activity.
+//! Once it has exited with nobody attached for [`EXITED_KEPT`], it is dropped.
+//! Given a [`SharedTerminalSessions`] store, terminals also outlive the
+//! workspace instance, and with a supervisor set (see
+//! [`crate::terminal_supervisor`]) they outlive the server too.
+//!
+//! Shells started by `terminal/create` report their prompts, commands and
+//! working directory (see [`crate::terminal_shell`]): `terminal/commands`
+//! lists what ran with exit codes and output lines, `terminal/getInfo`
+//! gives the live `cwd`, and `terminal/commandFinished` is pushed as each
+//! command ends.
+//!
+//! `terminal/execute` runs one-off commands outside any terminal (see
+//! [`crate::terminal_execute`]); with `async` set it returns an execution id
+//! at once, streams `terminal/execute/output` to the caller and ends with
+//! `terminal/execute/exit`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
 use std::path::PathBuf;
-use std::process::Stdio;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use ecp_protocol::{ECPError, HandlerResult, Notifications};
+use parking_lot::{Mutex, RwLock};
+use portable_pty::{native_pty_system, ChildKiller, CommandBuilder, MasterPty, PtySize};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use crate::terminal_execute::{self, Capture, ExecuteCommand, Execution, Pipe};
+use crate::terminal_output::{decode_utf8, now_ms, pump, ExitInfo, NotifySlot, OutputEncoding, OutputStream};
+use crate::terminal_screen::{BufferFormat, TerminalScreen};
+use crate::terminal_shell;
+use crate::terminal_supervisor::{SupervisedTerminal, SupervisorClient, TerminalSpec};
+use crate::watch::NotifySender;
+use crate::{HeldResource, ResourceKind, Service};
+
+/// Resource limits for terminal sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalLimits {
+    /// Rows kept per session once scrolled off screen; older rows are
+    /// dropped first. Applies to sessions created afterwards
+    pub scrollback_lines: usize,
+    /// Output `terminal/execute` captures per stream before truncating
+    pub max_output_bytes: usize,
+}
+
+impl Default for TerminalLimits {
+    fn default() -> Self {
+        Self {
+            scrollback_lines: 5000,
+            max_output_bytes: 1024 * 1024,
+        }
+    }
+}
 
-use ecp_protocol::{ECPError, HandlerResult};
-use parking_lot::RwLock;
-use serde::Deserialize;
-use serde_json::json;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::process::Command;
-use tokio::sync::mpsc;
-use tracing::{debug, info};
+/// Limits shared across workspaces so they can be changed at runtime.
+pub type SharedTerminalLimits = Arc<RwLock<TerminalLimits>>;
 
-use crate::Service;
+/// How long a terminal that exited stays listed with nobody attached.
+pub const EXITED_KEPT: Duration = Duration::from_secs(5 * 60);
+
+/// How often exited terminals are looked for.
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// One workspace's terminals, kept apart from its [`TerminalService`] so
+/// they can outlive it: the registry hands the same store to each instance
+/// of the workspace, and detached terminals are still there when it reopens.
+/// Dropping the store hangs up whatever runs in this process.
+#[derive(Default)]
+pub struct TerminalSessions {
+    sessions: RwLock<HashMap<String, Arc<RwLock<TerminalSessionInfo>>>>,
+    /// Where their notifications go: the workspace instance now open
+    notify: NotifySlot,
+    /// Set once a task drops exited terminals
+    reaping: AtomicBool,
+}
+
+/// A terminal store shared by successive instances of a workspace.
+pub type SharedTerminalSessions = Arc<TerminalSessions>;
+
+impl TerminalSessions {
+    pub fn is_empty(&self) -> bool {
+        self.sessions.read().is_empty()
+    }
+
+    /// Hang up the terminals running in this process. Supervised ones are
+    /// left running in the supervisor.
+    pub fn hang_up_local(&self) {
+        self.sessions.write().retain(|_, session| {
+            let info = session.read();
+            if matches!(info.backend, Backend::Supervised(_)) {
+                return true;
+            }
+            info.hang_up();
+            false
+        });
+    }
+
+    /// Drop terminals that have exited and had nobody attached for `grace`.
+    pub fn reap_exited(&self, grace: Duration) {
+        let now = now_ms();
+        self.sessions.write().retain(|id, session| {
+            let mut info = session.write();
+            if info.running.load(Ordering::Relaxed) || !info.clients.is_empty() {
+                info.exited_detached_at = None;
+                return true;
+            }
+            let since = *info.exited_detached_at.get_or_insert(now);
+            let keep = now.saturating_sub(since) < grace.as_millis() as u64;
+            if !keep {
+                debug!("Dropping exited terminal {id}");
+            }
+            keep
+        });
+    }
+
+    /// Reap exited terminals every [`REAP_INTERVAL`] for as long as the
+    /// store lives, unless that is already being done.
+    fn start_reaping(self: &Arc<Self>) {
+        if self.reaping.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let store = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(REAP_INTERVAL);
+            loop {
+                tick.tick().await;
+                let Some(store) = store.upgrade() else { break };
+                store.reap_exited(EXITED_KEPT);
+            }
+        });
+    }
+}
+
+impl Drop for TerminalSessions {
+    fn drop(&mut self) {
+        self.hang_up_local();
+    }
+}
 
 /// Terminal service — manages multiple shell sessions.
 pub struct TerminalService {
     workspace_root: RwLock<PathBuf>,
-    sessions: RwLock<HashMap<String, Arc<RwLock<TerminalSessionInfo>>>>,
-    /// Notification callback for terminal output/exit events
-    notification_tx: Option<mpsc::UnboundedSender<TerminalNotification>>,
+    store: SharedTerminalSessions,
+    limits: SharedTerminalLimits,
+    /// Hosts new terminals outside this process, when set
+    supervisor: Option<Arc<SupervisorClient>>,
+    /// Async `terminal/execute` commands still running, by execution id
+    executions: Arc<RwLock<HashMap<String, RunningExecution>>>,
+}
+
+struct RunningExecution {
+    /// Client that started it, which its notifications go to
+    client_id: Option<String>,
+    execution: Execution,
 }
 
 /// Lightweight session info (the actual process is managed by spawned tasks).
@@ -32,39 +180,416 @@ struct TerminalSessionInfo {
     cwd: String,
     cols: u16,
     rows: u16,
-    running: bool,
-    buffer: Arc<RwLock<String>>,
+    /// Cleared when the process exits
+    running: Arc<AtomicBool>,
+    screen: Arc<RwLock<TerminalScreen>>,
+    /// Sequence number of the next `terminal/output`
+    seq: Arc<AtomicU64>,
+    /// Last output or input, in milliseconds since the Unix epoch
+    last_activity: Arc<AtomicU64>,
+    /// Clients attached to it; with none it is detached
+    clients: BTreeSet<String>,
+    /// When it was first found exited and detached, in milliseconds since
+    /// the Unix epoch
+    exited_detached_at: Option<u64>,
     scroll_offset: usize,
     input_tx: mpsc::Sender<Vec<u8>>,
+    backend: Backend,
+}
+
+/// What a session's process runs on.
+enum Backend {
+    /// Plain pipes (`terminal/spawn`)
+    Captured,
+    /// A pseudo-terminal owned by this process
+    Pty(Pty),
+    /// A pseudo-terminal in the terminal supervisor
+    Supervised(SupervisedTerminal),
+}
+
+impl TerminalSessionInfo {
+    fn pid(&self) -> Option<u32> {
+        match self.backend {
+            Backend::Captured => None,
+            Backend::Pty(ref pty) => pty.pid,
+            Backend::Supervised(ref terminal) => terminal.pid,
+        }
+    }
+
+    /// Hang up the process (`SIGHUP` on Unix).
+    fn hang_up(&self) {
+        if !self.running.load(Ordering::Relaxed) {
+            return;
+        }
+        match self.backend {
+            Backend::Captured => {}
+            Backend::Pty(ref pty) => {
+                if let Err(e) = pty.hang_up() {
+                    debug!("Failed to hang up terminal {}: {e}", self.id);
+                }
+            }
+            Backend::Supervised(ref terminal) => terminal.hang_up(),
+        }
+    }
+
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), ECPError> {
+        match self.backend {
+            Backend::Captured => Ok(()),
+            Backend::Pty(ref pty) => pty.resize(cols, rows),
+            Backend::Supervised(ref terminal) => {
+                terminal.resize(cols, rows);
+                Ok(())
+            }
+        }
+    }
+
+    /// Working directory as the shell last reported it, or where it started.
+    fn live_cwd(&self) -> String {
+        self.screen.read().commands().cwd().map_or_else(|| self.cwd.clone(), str::to_string)
+    }
+
+    /// Where the client can pick up: the buffer in `format` and the
+    /// sequence number of the first `terminal/output` not in it.
+    fn snapshot(&self, format: BufferFormat) -> Value {
+        // The sequence number is bumped under the screen lock
+        let mut screen = self.screen.write();
+        let seq = self.seq.load(Ordering::Relaxed);
+        match format {
+            BufferFormat::Text => json!({ "buffer": screen.text(), "seq": seq }),
+            BufferFormat::Screen => json!({ "screen": screen.cells(), "seq": seq }),
+        }
+    }
 }
 
-/// Terminal notifications sent to the transport layer.
-#[derive(Debug)]
-pub enum TerminalNotification {
-    Output { terminal_id: String, data: String },
-    Exit { terminal_id: String, code: Option<i32> },
+/// A process running on a pseudo-terminal.
+pub(crate) struct Pty {
+    /// Master side, kept for resizing
+    master: Mutex<Box<dyn MasterPty + Send>>,
+    killer: Mutex<Box<dyn ChildKiller + Send + Sync>>,
+    pub pid: Option<u32>,
+}
+
+impl Pty {
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<(), ECPError> {
+        let size = PtySize { rows, cols, pixel_width: 0, pixel_height: 0 };
+        self.master.lock().resize(size)
+            .map_err(|e| ECPError::server_error(format!("Failed to resize terminal: {e}")))
+    }
+
+    /// Hang up the process (`SIGHUP` on Unix).
+    pub fn hang_up(&self) -> std::io::Result<()> {
+        self.killer.lock().kill()
+    }
+}
+
+/// A process just started on a pseudo-terminal.
+pub(crate) struct PtyProcess {
+    pub pty: Pty,
+    /// Typed into the terminal
+    pub input_tx: mpsc::Sender<Vec<u8>>,
+    /// Output as it is read
+    pub chunks: mpsc::UnboundedReceiver<Vec<u8>>,
+    pub exit: oneshot::Receiver<ExitInfo>,
+}
+
+/// Start `cmd` on a new pseudo-terminal. `running` is cleared when the
+/// process exits.
+pub(crate) fn open_pty(
+    id: &str,
+    mut cmd: CommandBuilder,
+    (cols, rows): (u16, u16),
+    running: &Arc<AtomicBool>,
+) -> Result<PtyProcess, ECPError> {
+    cmd.env("TERM", "xterm-256color");
+    cmd.env("COLORTERM", "truecolor");
+    let pair = native_pty_system()
+        .openpty(PtySize { rows, cols, pixel_width: 0, pixel_height: 0 })
+        .map_err(|e| ECPError::server_error(format!("Failed to open pty: {e}")))?;
+    let mut child = pair.slave.spawn_command(cmd)
+        .map_err(|e| ECPError::server_error(format!("Failed to spawn terminal: {e}")))?;
+    // Only the child holds the slave side now, so reads end when it exits
+    drop(pair.slave);
+    let mut reader = pair.master.try_clone_reader()
+        .map_err(|e| ECPError::server_error(format!("Failed to read pty: {e}")))?;
+    let mut writer = pair.master.take_writer()
+        .map_err(|e| ECPError::server_error(format!("Failed to write pty: {e}")))?;
+
+    // The pty is blocking I/O, so each direction gets a thread
+    let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
+    let input_id = id.to_string();
+    std::thread::spawn(move || {
+        while let Some(data) = input_rx.blocking_recv() {
+            if writer.write_all(&data).and_then(|_| writer.flush()).is_err() {
+                break;
+            }
+        }
+        debug!("Input task ended for {input_id}");
+    });
+
+    let (chunk_tx, chunks) = mpsc::unbounded_channel::<Vec<u8>>();
+    let output_id = id.to_string();
+    std::thread::spawn(move || {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if chunk_tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                // EIO once the child side closes
+                Err(_) => break,
+            }
+        }
+        debug!("Output task ended for {output_id}");
+    });
+
+    let pid = child.process_id();
+    let killer = child.clone_killer();
+    let exit_running = running.clone();
+    let exit_id = id.to_string();
+    let (exit_tx, exit) = oneshot::channel();
+    std::thread::spawn(move || {
+        let exit = match child.wait() {
+            Ok(status) => ExitInfo::from(status),
+            Err(e) => {
+                warn!("Failed to wait for terminal {exit_id}: {e}");
+                ExitInfo::default()
+            }
+        };
+        debug!("Terminal {exit_id} exited: {exit:?}");
+        exit_running.store(false, Ordering::Relaxed);
+        let _ = exit_tx.send(exit);
+    });
+
+    let pty = Pty { master: Mutex::new(pair.master), killer: Mutex::new(killer), pid };
+    Ok(PtyProcess { pty, input_tx, chunks, exit })
+}
+
+/// What the supervisor keeps with a terminal so a later server can rebuild
+/// its session.
+#[derive(Default, Serialize, Deserialize)]
+struct SupervisedMeta {
+    name: String,
+    shell: String,
+    cwd: String,
+    #[serde(default)]
+    encoding: OutputEncoding,
+}
+
+/// A new session's output stream with the screen and counters it updates.
+struct SessionOutput {
+    stream: OutputStream,
+    screen: Arc<RwLock<TerminalScreen>>,
+    seq: Arc<AtomicU64>,
+    last_activity: Arc<AtomicU64>,
 }
 
 impl TerminalService {
     pub fn new(workspace_root: PathBuf) -> Self {
         Self {
             workspace_root: RwLock::new(workspace_root),
-            sessions: RwLock::new(HashMap::new()),
-            notification_tx: None,
+            store: SharedTerminalSessions::default(),
+            limits: Arc::new(RwLock::new(TerminalLimits::default())),
+            supervisor: None,
+            executions: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    pub fn set_limits(&mut self, limits: SharedTerminalLimits) {
+        self.limits = limits;
+    }
+
+    /// Keep terminals in `store`, shared with other instances of the same
+    /// workspace, so they outlive this one.
+    pub fn set_sessions(&mut self, store: SharedTerminalSessions) {
+        if let Some(sender) = self.store.notify.read().clone() {
+            *store.notify.write() = Some(sender);
+        }
+        self.store = store;
+    }
+
+    /// Host new terminals in the supervisor behind `supervisor`, and adopt
+    /// this workspace's terminals already there on init.
+    pub fn set_supervisor(&mut self, supervisor: Arc<SupervisorClient>) {
+        self.supervisor = Some(supervisor);
+    }
+
     pub fn set_workspace_root(&self, root: PathBuf) {
         *self.workspace_root.write() = root;
     }
 
-    pub fn set_notification_sender(&mut self, tx: mpsc::UnboundedSender<TerminalNotification>) {
-        self.notification_tx = Some(tx);
+    /// Set the notification callback for terminal output, title and exit
+    /// events.
+    pub fn set_notify_sender(&self, sender: NotifySender) {
+        *self.store.notify.write() = Some(sender);
     }
 
     fn shell_name(shell: &str) -> String {
         shell.rsplit('/').next().unwrap_or(shell).to_string()
     }
+
+    fn session(&self, id: &str) -> Result<Arc<RwLock<TerminalSessionInfo>>, ECPError> {
+        self.store.sessions.read().get(id).cloned()
+            .ok_or_else(|| ECPError::server_error(format!("Terminal not found: {id}")))
+    }
+
+    /// Where a new terminal's output goes.
+    fn output_stream(&self, id: &str, encoding: OutputEncoding, (cols, rows): (u16, u16)) -> SessionOutput {
+        let scrollback = self.limits.read().scrollback_lines;
+        let screen = Arc::new(RwLock::new(TerminalScreen::new(rows, cols, scrollback)));
+        let seq = Arc::new(AtomicU64::new(0));
+        let last_activity = Arc::new(AtomicU64::new(now_ms()));
+        let stream = OutputStream::new(
+            id,
+            encoding,
+            screen.clone(),
+            seq.clone(),
+            last_activity.clone(),
+            self.store.notify.clone(),
+        );
+        SessionOutput { stream, screen, seq, last_activity }
+    }
+
+    /// Start `cmd` on a pseudo-terminal — in the supervisor if there is one,
+    /// otherwise here — with its output going to `stream`. Returns the
+    /// backend and where to send input.
+    async fn start_pty(
+        &self,
+        id: &str,
+        cmd: CommandBuilder,
+        size: (u16, u16),
+        stream: OutputStream,
+        running: &Arc<AtomicBool>,
+        meta: SupervisedMeta,
+    ) -> Result<(Backend, mpsc::Sender<Vec<u8>>), ECPError> {
+        let Some(ref supervisor) = self.supervisor else {
+            let process = open_pty(id, cmd, size, running)?;
+            tokio::spawn(pump(stream, process.chunks, process.exit));
+            return Ok((Backend::Pty(process.pty), process.input_tx));
+        };
+
+        let mut argv = cmd.get_argv().iter().map(|arg| arg.to_string_lossy().into_owned());
+        let spec = TerminalSpec {
+            id: id.to_string(),
+            workspace: self.workspace_root.read().to_string_lossy().to_string(),
+            program: argv.next().unwrap_or_default(),
+            args: argv.collect(),
+            cwd: cmd.get_cwd().map(|cwd| cwd.to_string_lossy().into_owned()).unwrap_or_default(),
+            env: cmd.iter_extra_env_as_str().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            cols: size.0,
+            rows: size.1,
+            scrollback_lines: self.limits.read().scrollback_lines,
+            meta: serde_json::to_value(meta).unwrap_or_default(),
+        };
+        supervisor.create(&spec).await?;
+        let attached = supervisor.attach(id, running).await?;
+        stream.restore(&attached.replay);
+        tokio::spawn(pump(stream, attached.chunks, attached.exit));
+        Ok((Backend::Supervised(attached.handle), attached.input_tx))
+    }
+
+    /// Take over this workspace's terminals from the supervisor, as left by
+    /// an earlier server, repainting each from the supervisor's screen.
+    async fn adopt(&self, supervisor: &SupervisorClient) {
+        let workspace = self.workspace_root.read().to_string_lossy().to_string();
+        for hosted in supervisor.list(&workspace).await {
+            if self.store.sessions.read().contains_key(&hosted.id) {
+                continue;
+            }
+            let meta: SupervisedMeta = serde_json::from_value(hosted.meta).unwrap_or_default();
+            let running = Arc::new(AtomicBool::new(true));
+            let attached = match supervisor.attach(&hosted.id, &running).await {
+                Ok(attached) => attached,
+                Err(e) => {
+                    warn!("Failed to adopt terminal {}: {}", hosted.id, e.message);
+                    continue;
+                }
+            };
+            let output = self.output_stream(&hosted.id, meta.encoding, (hosted.cols, hosted.rows));
+            output.stream.restore(&attached.replay);
+            tokio::spawn(pump(output.stream, attached.chunks, attached.exit));
+
+            let info = TerminalSessionInfo {
+                id: hosted.id.clone(),
+                name: meta.name,
+                shell: meta.shell,
+                cwd: meta.cwd,
+                cols: hosted.cols,
+                rows: hosted.rows,
+                running,
+                screen: output.screen,
+                seq: output.seq,
+                last_activity: output.last_activity,
+                clients: BTreeSet::new(),
+                exited_detached_at: None,
+                scroll_offset: 0,
+                input_tx: attached.input_tx,
+                backend: Backend::Supervised(attached.handle),
+            };
+            info!("Adopted terminal {} from the supervisor", hosted.id);
+            self.store.sessions.write().insert(hosted.id, Arc::new(RwLock::new(info)));
+        }
+    }
+
+    /// Start a command in the background, reporting its output and exit to
+    /// the client that asked for it.
+    fn execute_async(&self, cmd: ExecuteCommand, client_id: Option<String>) -> HandlerResult {
+        let id = format!("exec-{}", uuid::Uuid::new_v4());
+        let execution = Execution::default();
+        self.executions.write().insert(id.clone(), RunningExecution {
+            client_id: client_id.clone(),
+            execution: execution.clone(),
+        });
+
+        let notify = self.store.notify.read().clone();
+        let executions = self.executions.clone();
+        let exec_id = id.clone();
+        tokio::spawn(async move {
+            let send = |method: &str, mut params: Value| {
+                if let Some(ref notify) = notify {
+                    if let Some(ref client_id) = client_id {
+                        params["_clients"] = json!([client_id]);
+                    }
+                    notify(method, params);
+                }
+            };
+            let mut partial = [Vec::new(), Vec::new()];
+            let result = terminal_execute::run(&cmd, &execution, |pipe, bytes| {
+                let text = decode_utf8(&mut partial[pipe as usize], bytes);
+                if !text.is_empty() {
+                    send(Notifications::TERMINAL_EXECUTE_OUTPUT, json!({
+                        "executionId": exec_id,
+                        "stream": pipe.name(),
+                        "data": text,
+                    }));
+                }
+            }).await;
+            executions.write().remove(&exec_id);
+
+            let mut params = json!({ "executionId": exec_id });
+            match result {
+                Ok(outcome) => {
+                    params["exitCode"] = json!(outcome.exit.code);
+                    params["signal"] = json!(outcome.exit.signal);
+                    params["timedOut"] = json!(outcome.timed_out);
+                    params["killed"] = json!(outcome.killed);
+                }
+                Err(e) => {
+                    warn!("Failed to execute {}: {e}", cmd.command);
+                    params["error"] = json!(format!("Failed to execute: {e}"));
+                }
+            }
+            send(Notifications::TERMINAL_EXECUTE_EXIT, params);
+        });
+
+        Ok(json!({ "executionId": id }))
+    }
+
+    fn insert(&self, info: TerminalSessionInfo) {
+        self.store.sessions.write().insert(info.id.clone(), Arc::new(RwLock::new(info)));
+    }
 }
 
 impl Service for TerminalService {
@@ -72,6 +597,14 @@ impl Service for TerminalService {
         "terminal"
     }
 
+    async fn init(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.store.start_reaping();
+        if let Some(ref supervisor) = self.supervisor {
+            self.adopt(supervisor).await;
+        }
+        Ok(())
+    }
+
     async fn handle(&self, method: &str, params: Option<serde_json::Value>) -> HandlerResult {
         match method {
             "terminal/create" => {
@@ -88,57 +621,18 @@ impl Service for TerminalService {
 
                 let id = format!("term-{}", uuid::Uuid::new_v4());
 
-                let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
-                let buffer = Arc::new(RwLock::new(String::new()));
-
-                // Spawn the shell process
-                let mut child = Command::new(&shell)
-                    .current_dir(&cwd)
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                    .map_err(|e| ECPError::server_error(format!("Failed to spawn terminal: {e}")))?;
-
-                let child_stdin = child.stdin.take();
-                let child_stdout = child.stdout.take();
-
-                // Spawn input forwarding task
-                let input_id = id.clone();
-                tokio::spawn(async move {
-                    let mut stdin = match child_stdin {
-                        Some(s) => s,
-                        None => return,
-                    };
-                    while let Some(data) = input_rx.recv().await {
-                        if stdin.write_all(&data).await.is_err() {
-                            break;
-                        }
-                    }
-                    debug!("Input task ended for {input_id}");
-                });
-
-                // Spawn output reading task
-                let output_buffer = buffer.clone();
-                let output_id = id.clone();
-                tokio::spawn(async move {
-                    let mut stdout = match child_stdout {
-                        Some(s) => s,
-                        None => return,
-                    };
-                    let mut buf = vec![0u8; 4096];
-                    loop {
-                        match stdout.read(&mut buf).await {
-                            Ok(0) => break,
-                            Ok(n) => {
-                                let text = String::from_utf8_lossy(&buf[..n]).to_string();
-                                output_buffer.write().push_str(&text);
-                            }
-                            Err(_) => break,
-                        }
-                    }
-                    debug!("Output task ended for {output_id}");
-                });
+                let output = self.output_stream(&id, p.encoding, (cols, rows));
+                let running = Arc::new(AtomicBool::new(true));
+                let mut cmd = CommandBuilder::new(&shell);
+                cmd.cwd(&cwd);
+                let integrated = p.shell_integration.unwrap_or(true) && terminal_shell::integrate(&mut cmd, &shell);
+                let meta = SupervisedMeta {
+                    name: name.clone(),
+                    shell: shell.clone(),
+                    cwd: cwd.clone(),
+                    encoding: p.encoding,
+                };
+                let (backend, input_tx) = self.start_pty(&id, cmd, (cols, rows), output.stream, &running, meta).await?;
 
                 let info = TerminalSessionInfo {
                     id: id.clone(),
@@ -147,18 +641,25 @@ impl Service for TerminalService {
                     cwd: cwd.clone(),
                     cols,
                     rows,
-                    running: true,
-                    buffer: buffer.clone(),
+                    running,
+                    screen: output.screen,
+                    seq: output.seq,
+                    last_activity: output.last_activity,
+                    clients: p.client_id.into_iter().collect(),
+                    exited_detached_at: None,
                     scroll_offset: 0,
                     input_tx,
+                    backend,
                 };
-
-                self.sessions.write().insert(id.clone(), Arc::new(RwLock::new(info)));
+                let pid = info.pid();
+                self.insert(info);
 
                 Ok(json!({
                     "terminalId": id,
                     "shell": shell,
                     "cwd": cwd,
+                    "pid": pid,
+                    "shellIntegration": integrated,
                 }))
             }
 
@@ -171,28 +672,35 @@ impl Service for TerminalService {
                 let id = format!("term-{}", uuid::Uuid::new_v4());
 
                 let (input_tx, _input_rx) = mpsc::channel::<Vec<u8>>(256);
-                let buffer = Arc::new(RwLock::new(String::new()));
+                let output = self.output_stream(&id, p.encoding, (80, 24));
+                let mut stream = output.stream.without_pty();
+                let running = Arc::new(AtomicBool::new(true));
 
                 let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".into());
-                let output_buffer = buffer.clone();
                 let spawn_id = id.clone();
-                let command = p.command.clone();
-                let shell_clone = shell.clone();
+                let spawn_running = running.clone();
+                let cmd = ExecuteCommand {
+                    command: p.command.clone(),
+                    shell: shell.clone(),
+                    cwd,
+                    env: HashMap::new(),
+                    stdin: None,
+                    timeout: None,
+                };
 
-                // Spawn command in background
+                // Spawn command in background, streaming stdout and stderr
+                // to the terminal as they are read
                 tokio::spawn(async move {
-                    let result = Command::new(&shell_clone)
-                        .args(["-c", &command])
-                        .current_dir(&cwd)
-                        .stdout(Stdio::piped())
-                        .stderr(Stdio::piped())
-                        .output()
-                        .await;
-
-                    if let Ok(output) = result {
-                        let text = String::from_utf8_lossy(&output.stdout);
-                        output_buffer.write().push_str(&text);
-                    }
+                    let result = terminal_execute::run(&cmd, &Execution::default(), |_, bytes| stream.push(bytes)).await;
+                    let exit = match result {
+                        Ok(outcome) => outcome.exit,
+                        Err(e) => {
+                            warn!("Failed to run {}: {e}", cmd.command);
+                            ExitInfo::default()
+                        }
+                    };
+                    spawn_running.store(false, Ordering::Relaxed);
+                    stream.exit(&exit);
                     debug!("Spawn task ended for {spawn_id}");
                 });
 
@@ -203,13 +711,17 @@ impl Service for TerminalService {
                     cwd: self.workspace_root.read().to_string_lossy().to_string(),
                     cols: 80,
                     rows: 24,
-                    running: true,
-                    buffer,
+                    running,
+                    screen: output.screen,
+                    seq: output.seq,
+                    last_activity: output.last_activity,
+                    clients: p.client_id.into_iter().collect(),
+                    exited_detached_at: None,
                     scroll_offset: 0,
                     input_tx,
+                    backend: Backend::Captured,
                 };
-
-                self.sessions.write().insert(id.clone(), Arc::new(RwLock::new(info)));
+                self.insert(info);
 
                 Ok(json!({ "terminalId": id, "title": title }))
             }
@@ -217,10 +729,10 @@ impl Service for TerminalService {
             "terminal/write" => {
                 let p: TerminalIdDataParam = parse_params(params)?;
                 let tx = {
-                    let sessions = self.sessions.read();
-                    let session = sessions.get(&p.id)
-                        .ok_or_else(|| ECPError::server_error(format!("Terminal not found: {}", p.id)))?;
-                    session.read().input_tx.clone()
+                    let session = self.session(&p.id)?;
+                    let info = session.read();
+                    info.last_activity.store(now_ms(), Ordering::Relaxed);
+                    info.input_tx.clone()
                 };
                 tx.send(p.data.into_bytes()).await
                     .map_err(|_| ECPError::server_error("Terminal input channel closed"))?;
@@ -229,28 +741,39 @@ impl Service for TerminalService {
             }
 
             "terminal/getBuffer" => {
-                let p: TerminalIdParam = parse_params(params)?;
-                let sessions = self.sessions.read();
-                let session = sessions.get(&p.id)
-                    .ok_or_else(|| ECPError::server_error(format!("Terminal not found: {}", p.id)))?;
+                let p: TerminalGetBufferParams = parse_params(params)?;
+                let session = self.session(&p.id)?;
+                let snapshot = session.read().snapshot(p.format);
+                Ok(snapshot)
+            }
 
-                let raw = session.read().buffer.read().clone();
-                let lines: Vec<&str> = raw.split('\n').collect();
-                let line_count = lines.len();
+            "terminal/attach" => {
+                let p: TerminalAttachParams = parse_params(params)?;
+                let session = self.session(&p.id)?;
+                let mut info = session.write();
+                if let Some(client_id) = p.client_id {
+                    info.clients.insert(client_id);
+                }
+                let mut result = info.snapshot(p.format);
+                result["terminalId"] = json!(info.id);
+                result["running"] = json!(info.running.load(Ordering::Relaxed));
+                Ok(result)
+            }
 
-                Ok(json!({
-                    "buffer": {
-                        "lines": lines,
-                        "cursorRow": line_count.saturating_sub(1),
-                        "cursorCol": lines.last().map(|l| l.len()).unwrap_or(0),
-                    }
-                }))
+            "terminal/detach" => {
+                let p: TerminalAttachParams = parse_params(params)?;
+                let session = self.session(&p.id)?;
+                if let Some(ref client_id) = p.client_id {
+                    session.write().clients.remove(client_id);
+                }
+                Ok(json!({ "success": true }))
             }
 
             "terminal/close" => {
                 let p: TerminalIdParam = parse_params(params)?;
-                let mut sessions = self.sessions.write();
-                if sessions.remove(&p.id).is_some() {
+                let removed = self.store.sessions.write().remove(&p.id);
+                if let Some(session) = removed {
+                    session.read().hang_up();
                     Ok(json!({ "success": true }))
                 } else {
                     Err(ECPError::server_error(format!("Terminal not found: {}", p.id)))
@@ -258,13 +781,15 @@ impl Service for TerminalService {
             }
 
             "terminal/closeAll" => {
-                let mut sessions = self.sessions.write();
-                sessions.clear();
+                let sessions = std::mem::take(&mut *self.store.sessions.write());
+                for session in sessions.values() {
+                    session.read().hang_up();
+                }
                 Ok(json!({ "success": true }))
             }
 
             "terminal/list" => {
-                let sessions = self.sessions.read();
+                let sessions = self.store.sessions.read();
                 let terminals: Vec<serde_json::Value> = sessions.values()
                     .map(|s| {
                         let info = s.read();
@@ -272,10 +797,15 @@ impl Service for TerminalService {
                             "id": info.id,
                             "name": info.name,
                             "shell": info.shell,
-                            "cwd": info.cwd,
+                            "cwd": info.live_cwd(),
                             "cols": info.cols,
                             "rows": info.rows,
-                            "running": info.running,
+                            "running": info.running.load(Ordering::Relaxed),
+                            "pid": info.pid(),
+                            "clients": info.clients,
+                            "detached": info.clients.is_empty(),
+                            "lastActivity": info.last_activity.load(Ordering::Relaxed),
+                            "supervised": matches!(info.backend, Backend::Supervised(_)),
                         })
                     })
                     .collect();
@@ -285,32 +815,33 @@ impl Service for TerminalService {
 
             "terminal/exists" => {
                 let p: TerminalIdParam = parse_params(params)?;
-                let exists = self.sessions.read().contains_key(&p.id);
+                let exists = self.store.sessions.read().contains_key(&p.id);
                 Ok(json!({ "exists": exists }))
             }
 
             "terminal/isRunning" => {
                 let p: TerminalIdParam = parse_params(params)?;
-                let sessions = self.sessions.read();
+                let sessions = self.store.sessions.read();
                 let running = sessions.get(&p.id)
-                    .map(|s| s.read().running)
+                    .map(|s| s.read().running.load(Ordering::Relaxed))
                     .unwrap_or(false);
                 Ok(json!({ "running": running }))
             }
 
             "terminal/getInfo" => {
                 let p: TerminalIdParam = parse_params(params)?;
-                let sessions = self.sessions.read();
+                let sessions = self.store.sessions.read();
                 if let Some(session) = sessions.get(&p.id) {
                     let info = session.read();
                     Ok(json!({
                         "info": {
                             "id": info.id,
                             "name": info.name,
-                            "cwd": info.cwd,
+                            "cwd": info.live_cwd(),
                             "shell": info.shell,
                             "rows": info.rows,
                             "cols": info.cols,
+                            "pid": info.pid(),
                         }
                     }))
                 } else {
@@ -318,24 +849,47 @@ impl Service for TerminalService {
                 }
             }
 
+            "terminal/commands" => {
+                let p: TerminalCommandsParams = parse_params(params)?;
+                let session = self.session(&p.id)?;
+                let info = session.read();
+                let mut screen = info.screen.write();
+                let log = screen.commands();
+                let cwd = log.cwd().map_or_else(|| info.cwd.clone(), str::to_string);
+                let mut commands: Vec<_> = log.commands().rev().take(p.limit.unwrap_or(usize::MAX)).cloned().collect();
+                commands.reverse();
+
+                let lines = p.output.then(|| screen.all_lines());
+                let commands: Vec<Value> = commands.into_iter()
+                    .map(|command| {
+                        let mut value = json!(command);
+                        if let Some(ref lines) = lines {
+                            // The running command's output so far
+                            let end = command.output.end.unwrap_or(u64::MAX);
+                            let text = lines.range(command.output.start, end)
+                                .map(|lines| lines.join("\n").trim_end().to_string());
+                            value["outputText"] = json!(text);
+                        }
+                        value
+                    })
+                    .collect();
+                Ok(json!({ "commands": commands, "cwd": cwd }))
+            }
+
             "terminal/resize" => {
                 let p: TerminalResizeParams = parse_params(params)?;
-                let sessions = self.sessions.read();
-                let session = sessions.get(&p.id)
-                    .ok_or_else(|| ECPError::server_error(format!("Terminal not found: {}", p.id)))?;
-                {
-                    let mut info = session.write();
-                    info.cols = p.cols;
-                    info.rows = p.rows;
-                }
+                let session = self.session(&p.id)?;
+                let mut info = session.write();
+                info.resize(p.cols, p.rows)?;
+                info.screen.write().resize(p.rows, p.cols);
+                info.cols = p.cols;
+                info.rows = p.rows;
                 Ok(json!({ "success": true }))
             }
 
             "terminal/scroll" => {
                 let p: TerminalScrollParams = parse_params(params)?;
-                let sessions = self.sessions.read();
-                let session = sessions.get(&p.id)
-                    .ok_or_else(|| ECPError::server_error(format!("Terminal not found: {}", p.id)))?;
+                let session = self.session(&p.id)?;
                 {
                     let mut info = session.write();
                     let new_offset = info.scroll_offset as i64 + p.lines as i64;
@@ -346,112 +900,104 @@ impl Service for TerminalService {
 
             "terminal/scrollToBottom" => {
                 let p: TerminalIdParam = parse_params(params)?;
-                let sessions = self.sessions.read();
-                let session = sessions.get(&p.id)
-                    .ok_or_else(|| ECPError::server_error(format!("Terminal not found: {}", p.id)))?;
-                session.write().scroll_offset = 0;
+                self.session(&p.id)?.write().scroll_offset = 0;
                 Ok(json!({ "success": true }))
             }
 
             "terminal/execute" => {
                 let p: TerminalExecuteParams = parse_params(params)?;
-                let cwd = p.cwd.unwrap_or_else(|| {
-                    self.workspace_root.read().to_string_lossy().to_string()
-                });
+                let cmd = ExecuteCommand {
+                    command: p.command,
+                    shell: p.shell.unwrap_or_else(|| {
+                        std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".into())
+                    }),
+                    cwd: p.cwd.unwrap_or_else(|| {
+                        self.workspace_root.read().to_string_lossy().to_string()
+                    }),
+                    env: p.env,
+                    stdin: p.stdin,
+                    timeout: p.timeout.map(Duration::from_millis),
+                };
+                if p.background {
+                    return self.execute_async(cmd, p.client_id);
+                }
 
-                let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".into());
-                let output = Command::new(&shell)
-                    .args(["-c", &p.command])
-                    .current_dir(&cwd)
-                    .output()
-                    .await
+                let max = self.limits.read().max_output_bytes;
+                let max = p.max_output_bytes.map_or(max, |m| m.min(max));
+                let (mut stdout, mut stderr) = (Capture::new(max), Capture::new(max));
+                let outcome = terminal_execute::run(&cmd, &Execution::default(), |pipe, bytes| match pipe {
+                    Pipe::Stdout => stdout.push(bytes),
+                    Pipe::Stderr => stderr.push(bytes),
+                }).await
                     .map_err(|e| ECPError::server_error(format!("Failed to execute: {e}")))?;
+                let truncated = stdout.truncated() || stderr.truncated();
 
                 Ok(json!({
-                    "stdout": String::from_utf8_lossy(&output.stdout),
-                    "stderr": String::from_utf8_lossy(&output.stderr),
-                    "exitCode": output.status.code(),
+                    "stdout": stdout.finish(),
+                    "stderr": stderr.finish(),
+                    "exitCode": outcome.exit.code,
+                    "signal": outcome.exit.signal,
+                    "timedOut": outcome.timed_out,
+                    "truncated": truncated,
                 }))
             }
 
+            "terminal/execute/kill" => {
+                let p: ExecutionIdParam = parse_params(params)?;
+                let executions = self.executions.read();
+                let running = executions.get(&p.execution_id)
+                    .ok_or_else(|| ECPError::server_error(format!("Execution not found: {}", p.execution_id)))?;
+                if running.client_id.is_some() && running.client_id != p.client_id {
+                    return Err(ECPError::server_error("Execution is owned by another client"));
+                }
+                running.execution.kill();
+                Ok(json!({ "success": true }))
+            }
+
             "terminal/attachTmux" => {
                 let p: TerminalAttachTmuxParams = parse_params(params)?;
                 let cols = p.cols.unwrap_or(80);
                 let rows = p.rows.unwrap_or(24);
                 let id = format!("term-{}", uuid::Uuid::new_v4());
 
-                let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
-                let buffer = Arc::new(RwLock::new(String::new()));
+                let output = self.output_stream(&id, p.encoding, (cols, rows));
+                let running = Arc::new(AtomicBool::new(true));
 
-                let mut cmd_args = Vec::new();
+                let mut cmd = CommandBuilder::new("tmux");
                 if let Some(ref socket) = p.socket {
-                    cmd_args.push(format!("-S{}", socket));
+                    cmd.arg(format!("-S{}", socket));
                 }
-                cmd_args.extend(["attach-session".to_string(), "-t".to_string(), p.session.clone()]);
-
-                let mut child = Command::new("tmux")
-                    .args(&cmd_args)
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                    .map_err(|e| ECPError::server_error(format!("Failed to attach tmux: {e}")))?;
-
-                let child_stdin = child.stdin.take();
-                let child_stdout = child.stdout.take();
-
-                // Input forwarding
-                let input_id = id.clone();
-                tokio::spawn(async move {
-                    let mut stdin = match child_stdin {
-                        Some(s) => s,
-                        None => return,
-                    };
-                    while let Some(data) = input_rx.recv().await {
-                        if stdin.write_all(&data).await.is_err() {
-                            break;
-                        }
-                    }
-                    debug!("Tmux input task ended for {input_id}");
-                });
-
-                // Output reading
-                let output_buffer = buffer.clone();
-                let output_id = id.clone();
-                tokio::spawn(async move {
-                    let mut stdout = match child_stdout {
-                        Some(s) => s,
-                        None => return,
-                    };
-                    let mut buf = vec![0u8; 4096];
-                    loop {
-                        match stdout.read(&mut buf).await {
-                            Ok(0) => break,
-                            Ok(n) => {
-                                let text = String::from_utf8_lossy(&buf[..n]).to_string();
-                                output_buffer.write().push_str(&text);
-                            }
-                            Err(_) => break,
-                        }
-                    }
-                    debug!("Tmux output task ended for {output_id}");
-                });
-
+                cmd.args(["attach-session", "-t", &p.session]);
                 let cwd = self.workspace_root.read().to_string_lossy().to_string();
+                cmd.cwd(&cwd);
+                let name = format!("tmux:{}", p.session);
+                let meta = SupervisedMeta {
+                    name: name.clone(),
+                    shell: "tmux".to_string(),
+                    cwd: cwd.clone(),
+                    encoding: p.encoding,
+                };
+                let (backend, input_tx) = self.start_pty(&id, cmd, (cols, rows), output.stream, &running, meta).await
+                    .map_err(|e| ECPError::server_error(format!("Failed to attach tmux: {}", e.message)))?;
+
                 let info = TerminalSessionInfo {
                     id: id.clone(),
-                    name: format!("tmux:{}", p.session),
+                    name,
                     shell: "tmux".to_string(),
                     cwd,
                     cols,
                     rows,
-                    running: true,
-                    buffer,
+                    running,
+                    screen: output.screen,
+                    seq: output.seq,
+                    last_activity: output.last_activity,
+                    clients: p.client_id.into_iter().collect(),
+                    exited_detached_at: None,
                     scroll_offset: 0,
                     input_tx,
+                    backend,
                 };
-
-                self.sessions.write().insert(id.clone(), Arc::new(RwLock::new(info)));
+                self.insert(info);
                 Ok(json!({ "terminalId": id }))
             }
 
@@ -460,9 +1006,41 @@ impl Service for TerminalService {
     }
 
     async fn shutdown(&self) {
-        let mut sessions = self.sessions.write();
-        sessions.clear();
-        info!("Terminal service shutdown: all sessions closed");
+        // Terminals stay in the store, running detached until the workspace
+        // reopens or the server stops
+        for running in self.executions.read().values() {
+            running.execution.kill();
+        }
+        info!("Terminal service shutdown: {} terminals left running", self.store.sessions.read().len());
+    }
+
+    fn client_disconnected(&self, client_id: &str) {
+        // Their terminals carry on detached
+        for session in self.store.sessions.read().values() {
+            session.write().clients.remove(client_id);
+        }
+        // Nobody is left to see their output
+        for running in self.executions.read().values() {
+            if running.client_id.as_deref() == Some(client_id) {
+                running.execution.kill();
+            }
+        }
+    }
+
+    async fn held_resources(&self, kind: ResourceKind) -> Vec<HeldResource> {
+        match kind {
+            // Charged to an attached client; a detached terminal keeps its last owner
+            ResourceKind::Terminal => self.store.sessions.read().iter().map(|(id, info)| HeldResource {
+                id: id.clone(),
+                owner: info.read().clients.iter().next().cloned(),
+            }).collect(),
+            // Finished executions leave the map, which frees their slots
+            ResourceKind::Execution => self.executions.read().iter().map(|(id, running)| HeldResource {
+                id: id.clone(),
+                owner: running.client_id.clone(),
+            }).collect(),
+            _ => Vec::new(),
+        }
     }
 }
 
@@ -473,17 +1051,31 @@ impl Service for TerminalService {
 #[derive(Deserialize, Default)]
 struct TerminalCreateParams {
     name: Option<String>,
+    /// How `terminal/output` carries data: "utf8" (default) or "base64"
+    #[serde(default)]
+    encoding: OutputEncoding,
     shell: Option<String>,
     cwd: Option<String>,
     cols: Option<u16>,
     rows: Option<u16>,
+    /// Load the shell integration script (default true) for bash, zsh and
+    /// fish
+    #[serde(rename = "shellIntegration")]
+    shell_integration: Option<bool>,
+    /// Attached to the new terminal
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct TerminalSpawnParams {
     command: String,
+    #[serde(default)]
+    encoding: OutputEncoding,
     cwd: Option<String>,
     title: Option<String>,
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -492,6 +1084,38 @@ struct TerminalIdParam {
     id: String,
 }
 
+#[derive(Deserialize)]
+struct TerminalGetBufferParams {
+    #[serde(alias = "terminalId")]
+    id: String,
+    /// "text" (default) for scrollback and screen as lines, "screen" for
+    /// the visible cells with attributes
+    #[serde(default)]
+    format: BufferFormat,
+}
+
+#[derive(Deserialize)]
+struct TerminalAttachParams {
+    #[serde(alias = "terminalId")]
+    id: String,
+    /// Snapshot format, as for `terminal/getBuffer`
+    #[serde(default)]
+    format: BufferFormat,
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TerminalCommandsParams {
+    #[serde(alias = "terminalId")]
+    id: String,
+    /// Only the most recent this many
+    limit: Option<usize>,
+    /// Include each command's output text while it is still in scrollback
+    #[serde(default)]
+    output: bool,
+}
+
 #[derive(Deserialize)]
 struct TerminalIdDataParam {
     #[serde(alias = "terminalId")]
@@ -500,10 +1124,34 @@ struct TerminalIdDataParam {
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct TerminalExecuteParams {
     command: String,
     cwd: Option<String>,
+    /// Milliseconds before the command's process group is killed
     timeout: Option<u64>,
+    /// Variables to set; `null` unsets one
+    #[serde(default)]
+    env: HashMap<String, Option<String>>,
+    /// Text fed to the command's stdin, which is otherwise empty
+    stdin: Option<String>,
+    /// Shell that runs the command with `-c`; defaults to `$SHELL`
+    shell: Option<String>,
+    /// Per-stream capture cap, below the configured one
+    max_output_bytes: Option<usize>,
+    /// Return an execution id at once and stream the output
+    #[serde(default, rename = "async")]
+    background: bool,
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExecutionIdParam {
+    #[serde(rename = "executionId")]
+    execution_id: String,
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -524,9 +1172,13 @@ struct TerminalScrollParams {
 #[derive(Deserialize)]
 struct TerminalAttachTmuxParams {
     session: String,
+    #[serde(default)]
+    encoding: OutputEncoding,
     socket: Option<String>,
     cols: Option<u16>,
     rows: Option<u16>,
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
 }
 
 fn parse_params<T: for<'de> Deserialize<'de>>(params: Option<serde_json::Value>) -> Result<T, ECPError> {
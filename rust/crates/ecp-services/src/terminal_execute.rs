@@ -0,0 +1,239 @@
+//! One-off commands for `terminal/execute`.
+//!
+//! A command runs through a shell in its own process group, so a timeout or
+//! `terminal/execute/kill` takes down everything it started, not just the
+//! shell. Captured output is capped per stream: past the cap the start and
+//! the end are kept and the middle is replaced by a truncation marker. In
+//! async mode output is handed over as it arrives instead of being captured.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::Notify;
+use tracing::debug;
+
+use crate::terminal_output::ExitInfo;
+
+/// Bytes read from a pipe at a time.
+const READ_CHUNK: usize = 8192;
+
+/// Which pipe output came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pipe {
+    Stdout,
+    Stderr,
+}
+
+impl Pipe {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
+}
+
+/// What to run.
+pub struct ExecuteCommand {
+    pub command: String,
+    pub shell: String,
+    pub cwd: String,
+    /// Variables to set, or with `None` to unset
+    pub env: HashMap<String, Option<String>>,
+    pub stdin: Option<String>,
+    pub timeout: Option<Duration>,
+}
+
+/// How a command ended.
+pub struct Outcome {
+    pub exit: ExitInfo,
+    pub timed_out: bool,
+    /// Stopped by [`Execution::kill`]
+    pub killed: bool,
+}
+
+/// Stops a running command from elsewhere.
+#[derive(Clone, Default)]
+pub struct Execution {
+    kill: Arc<Notify>,
+}
+
+impl Execution {
+    /// Kill the command's process group. A command that hasn't started yet
+    /// is killed as soon as it does.
+    pub fn kill(&self) {
+        self.kill.notify_one();
+    }
+}
+
+/// Run `cmd` to completion, passing its output to `sink` as it is read.
+pub async fn run(
+    cmd: &ExecuteCommand,
+    execution: &Execution,
+    mut sink: impl FnMut(Pipe, &[u8]),
+) -> std::io::Result<Outcome> {
+    let mut command = Command::new(&cmd.shell);
+    command.args(["-c", &cmd.command])
+        .current_dir(&cmd.cwd)
+        .stdin(if cmd.stdin.is_some() { std::process::Stdio::piped() } else { std::process::Stdio::null() })
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    for (key, value) in &cmd.env {
+        match value {
+            Some(value) => command.env(key, value),
+            None => command.env_remove(key),
+        };
+    }
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let mut child = command.spawn()?;
+    let pid = child.id();
+    if let (Some(input), Some(mut stdin)) = (cmd.stdin.clone(), child.stdin.take()) {
+        // Written alongside reading, so a command that answers before it has
+        // read everything can't deadlock with us
+        tokio::spawn(async move {
+            if let Err(e) = stdin.write_all(input.as_bytes()).await {
+                debug!("Failed to write command stdin: {e}");
+            }
+        });
+    }
+    let mut stdout = child.stdout.take();
+    let mut stderr = child.stderr.take();
+
+    let deadline = cmd.timeout.map(|t| tokio::time::Instant::now() + t);
+    let (mut timed_out, mut killed) = (false, false);
+    let mut out_buf = vec![0u8; READ_CHUNK];
+    let mut err_buf = vec![0u8; READ_CHUNK];
+    while stdout.is_some() || stderr.is_some() {
+        tokio::select! {
+            read = read_pipe(&mut stdout, &mut out_buf) => match read {
+                Some(n) => sink(Pipe::Stdout, &out_buf[..n]),
+                None => stdout = None,
+            },
+            read = read_pipe(&mut stderr, &mut err_buf) => match read {
+                Some(n) => sink(Pipe::Stderr, &err_buf[..n]),
+                None => stderr = None,
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                timed_out = true;
+                break;
+            }
+            _ = execution.kill.notified() => {
+                killed = true;
+                break;
+            }
+        }
+    }
+    // Output can close before the command ends, e.g. when it is redirected
+    let status = if timed_out || killed {
+        None
+    } else {
+        tokio::select! {
+            status = child.wait() => Some(status?),
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                timed_out = true;
+                None
+            }
+            _ = execution.kill.notified() => {
+                killed = true;
+                None
+            }
+        }
+    };
+    if timed_out || killed {
+        kill_group(pid);
+        let _ = child.start_kill();
+    }
+
+    let status = match status {
+        Some(status) => status,
+        None => child.wait().await?,
+    };
+    Ok(Outcome {
+        exit: ExitInfo::from(portable_pty::ExitStatus::from(status)),
+        timed_out,
+        killed,
+    })
+}
+
+/// Read from a pipe that is still open; `None` once it closes.
+async fn read_pipe<R: AsyncReadExt + Unpin>(pipe: &mut Option<R>, buf: &mut [u8]) -> Option<usize> {
+    match pipe {
+        Some(pipe) => match pipe.read(buf).await {
+            Ok(0) | Err(_) => None,
+            Ok(n) => Some(n),
+        },
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+fn kill_group(pid: Option<u32>) {
+    if let Some(pgid) = pid.and_then(|pid| i32::try_from(pid).ok()) {
+        unsafe {
+            libc::killpg(pgid, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_group(_pid: Option<u32>) {}
+
+/// Output captured up to a cap, keeping the start and the end.
+pub struct Capture {
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    max: usize,
+    /// Bytes dropped from between head and tail
+    dropped: usize,
+}
+
+impl Capture {
+    pub fn new(max: usize) -> Self {
+        Self { head: Vec::new(), tail: Vec::new(), max, dropped: 0 }
+    }
+
+    pub fn push(&mut self, mut bytes: &[u8]) {
+        let head_room = (self.max / 2).saturating_sub(self.head.len());
+        let take = head_room.min(bytes.len());
+        self.head.extend_from_slice(&bytes[..take]);
+        bytes = &bytes[take..];
+
+        self.tail.extend_from_slice(bytes);
+        let tail_max = self.max - self.max / 2;
+        // Trim in bulk rather than on every push
+        if self.tail.len() > tail_max * 2 {
+            self.trim_tail();
+        }
+    }
+
+    fn trim_tail(&mut self) {
+        let excess = self.tail.len().saturating_sub(self.max - self.max / 2);
+        self.tail.drain(..excess);
+        self.dropped += excess;
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.dropped > 0 || self.tail.len() > self.max - self.max / 2
+    }
+
+    /// The captured text, with a marker where output was dropped.
+    pub fn finish(mut self) -> String {
+        self.trim_tail();
+        if self.dropped == 0 {
+            self.head.append(&mut self.tail);
+            return String::from_utf8_lossy(&self.head).into_owned();
+        }
+        format!(
+            "{}\n[... {} bytes truncated ...]\n{}",
+            String::from_utf8_lossy(&self.head),
+            self.dropped,
+            String::from_utf8_lossy(&self.tail),
+        )
+    }
+}
@@ -0,0 +1,249 @@
+//! Terminal output streaming for [`TerminalService`](crate::terminal::TerminalService).
+//!
+//! Output read from a terminal is batched for [`BATCH_INTERVAL`] (or until
+//! [`BATCH_BYTES`] pile up) and pushed to clients as `terminal/output`, each
+//! message carrying the terminal's next sequence number so clients can spot
+//! gaps and line a `terminal/getBuffer` snapshot up with the stream. The same
+//! output drives the terminal's [`TerminalScreen`]; window titles it sees set
+//! with OSC 0/2 become `terminal/title`, commands shell integration reports
+//! finished become `terminal/commandFinished`, and `terminal/exit` follows
+//! the output written before the exit.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use base64::Engine;
+use ecp_protocol::Notifications;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::terminal_screen::TerminalScreen;
+use crate::watch::NotifySender;
+
+/// Longest output is held back to be sent with what follows.
+pub const BATCH_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Output that is sent at once rather than waiting out the interval.
+pub const BATCH_BYTES: usize = 64 * 1024;
+
+/// How long output still arriving after an exit is waited for before the
+/// exit is reported.
+const EXIT_GRACE: Duration = Duration::from_millis(50);
+
+/// How `terminal/output` carries data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEncoding {
+    /// Decoded text; a character split across reads waits for the rest
+    #[default]
+    Utf8,
+    /// The raw bytes
+    Base64,
+}
+
+/// Where terminal notifications go. Shared by a workspace's terminals and
+/// swapped when the workspace reopens, so detached terminals reach the
+/// clients that come back.
+pub(crate) type NotifySlot = Arc<RwLock<Option<NotifySender>>>;
+
+/// How a terminal's process ended.
+#[derive(Debug, Clone, Default)]
+pub struct ExitInfo {
+    pub code: Option<u32>,
+    /// Description of the signal that killed it
+    pub signal: Option<String>,
+}
+
+impl From<portable_pty::ExitStatus> for ExitInfo {
+    fn from(status: portable_pty::ExitStatus) -> Self {
+        // portable-pty only exposes the signal through Display
+        match status.to_string().strip_prefix("Terminated by ") {
+            Some(signal) => Self { code: None, signal: Some(signal.to_string()) },
+            None => Self { code: Some(status.exit_code()), signal: None },
+        }
+    }
+}
+
+/// Where one terminal's output goes: its screen and, with a sender set,
+/// clients.
+pub struct OutputStream {
+    id: String,
+    encoding: OutputEncoding,
+    screen: Arc<RwLock<TerminalScreen>>,
+    /// Output messages sent so far; bumped under the screen lock
+    seq: Arc<AtomicU64>,
+    /// When output last arrived, in milliseconds since the Unix epoch
+    activity: Arc<AtomicU64>,
+    notify: NotifySlot,
+    /// Show a bare `\n` on the screen as `\r\n`, as a terminal's line
+    /// discipline would, for output that didn't come through a PTY
+    translate_newlines: bool,
+    /// Incomplete UTF-8 character held over from the last chunk
+    partial: Vec<u8>,
+}
+
+impl OutputStream {
+    pub fn new(
+        id: &str,
+        encoding: OutputEncoding,
+        screen: Arc<RwLock<TerminalScreen>>,
+        seq: Arc<AtomicU64>,
+        activity: Arc<AtomicU64>,
+        notify: NotifySlot,
+    ) -> Self {
+        Self {
+            id: id.to_string(),
+            encoding,
+            screen,
+            seq,
+            activity,
+            notify,
+            translate_newlines: false,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Treat output as coming straight from a process rather than a PTY.
+    pub fn without_pty(mut self) -> Self {
+        self.translate_newlines = true;
+        self
+    }
+
+    /// Bring the screen up to date with output from before this stream,
+    /// without sending it.
+    pub fn restore(&self, bytes: &[u8]) {
+        self.screen.write().process(bytes);
+    }
+
+    /// Record a batch of output and send it on.
+    pub fn push(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let text = decode_utf8(&mut self.partial, bytes);
+        let (seq, events) = {
+            let mut screen = self.screen.write();
+            let events = if self.translate_newlines {
+                screen.process(&translate_newlines(bytes))
+            } else {
+                screen.process(bytes)
+            };
+            (self.seq.fetch_add(1, Ordering::Relaxed), events)
+        };
+        self.activity.store(now_ms(), Ordering::Relaxed);
+
+        let Some(notify) = self.notify.read().clone() else { return };
+        let (data, encoding) = match self.encoding {
+            OutputEncoding::Utf8 => (text, "utf8"),
+            OutputEncoding::Base64 => (base64::engine::general_purpose::STANDARD.encode(bytes), "base64"),
+        };
+        notify(Notifications::TERMINAL_OUTPUT, json!({
+            "terminalId": self.id,
+            "seq": seq,
+            "data": data,
+            "encoding": encoding,
+        }));
+        for title in events.titles {
+            notify(Notifications::TERMINAL_TITLE, json!({ "terminalId": self.id, "title": title }));
+        }
+        for command in events.finished {
+            notify(Notifications::TERMINAL_COMMAND_FINISHED, json!({ "terminalId": self.id, "command": command }));
+        }
+    }
+
+    pub fn exit(&self, exit: &ExitInfo) {
+        if let Some(ref notify) = *self.notify.read() {
+            notify(Notifications::TERMINAL_EXIT, json!({
+                "terminalId": self.id,
+                "exitCode": exit.code,
+                "signal": exit.signal,
+            }));
+        }
+    }
+}
+
+/// Forward output chunks in batches until the reader closes, reporting the
+/// exit after the output that came before it. Background jobs can hold the
+/// terminal open past the exit; their output still follows.
+pub async fn pump(
+    mut stream: OutputStream,
+    mut chunks: mpsc::UnboundedReceiver<Vec<u8>>,
+    mut exit: oneshot::Receiver<ExitInfo>,
+) {
+    let mut pending = Vec::new();
+    let mut deadline: Option<tokio::time::Instant> = None;
+    let mut exited = false;
+    loop {
+        tokio::select! {
+            chunk = chunks.recv() => match chunk {
+                Some(chunk) => {
+                    pending.extend_from_slice(&chunk);
+                    if pending.len() >= BATCH_BYTES {
+                        stream.push(&std::mem::take(&mut pending));
+                        deadline = None;
+                    } else {
+                        deadline.get_or_insert_with(|| tokio::time::Instant::now() + BATCH_INTERVAL);
+                    }
+                }
+                None => break,
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                stream.push(&std::mem::take(&mut pending));
+                deadline = None;
+            }
+            result = &mut exit, if !exited => {
+                exited = true;
+                // Output read just before the exit may still be on its way
+                let grace = tokio::time::Instant::now() + EXIT_GRACE;
+                while let Ok(Some(chunk)) = tokio::time::timeout_at(grace, chunks.recv()).await {
+                    pending.extend_from_slice(&chunk);
+                }
+                stream.push(&std::mem::take(&mut pending));
+                deadline = None;
+                if let Ok(exit) = result {
+                    stream.exit(&exit);
+                }
+            }
+        }
+    }
+    stream.push(&pending);
+    if !exited && let Ok(exit) = exit.await {
+        stream.exit(&exit);
+    }
+}
+
+/// Decode a chunk of output, holding back a character split across reads
+/// until the rest of it arrives.
+pub(crate) fn decode_utf8(partial: &mut Vec<u8>, bytes: &[u8]) -> String {
+    partial.extend_from_slice(bytes);
+    let complete = match std::str::from_utf8(partial) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => partial.len(),
+    };
+    let text = String::from_utf8_lossy(&partial[..complete]).into_owned();
+    partial.drain(..complete);
+    text
+}
+
+/// Milliseconds since the Unix epoch.
+pub(crate) fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// `bytes` with each `\n` not already after a `\r` turned into `\r\n`.
+fn translate_newlines(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    for (i, &byte) in bytes.iter().enumerate() {
+        if byte == b'\n' && (i == 0 || bytes[i - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(byte);
+    }
+    out
+}
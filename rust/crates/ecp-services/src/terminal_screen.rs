@@ -0,0 +1,337 @@
+//! Screen model for [`TerminalService`](crate::terminal::TerminalService).
+//!
+//! Each terminal's output is run through a VT100/xterm emulator (`vt100`)
+//! rather than kept as raw text, so `terminal/getBuffer` can hand a
+//! reconnecting client exactly what is on screen — every cell with its
+//! colors and attributes — or the plain text of the scrollback and screen.
+//! Rows scrolled off the top go to a scrollback ring capped at
+//! `scrollback_lines`; the oldest are dropped first.
+//!
+//! Lines are numbered from the terminal's first, counting every row that
+//! scrolled off the main screen, so a line keeps its number as output moves
+//! it into scrollback. Shell integration marks (see
+//! [`crate::terminal_shell`]) are recorded against these numbers.
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::terminal_shell::{CommandLog, CommandRecord, ShellMark};
+
+/// How `terminal/getBuffer` returns a terminal's contents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BufferFormat {
+    /// Scrollback and screen as plain text, one line per row
+    #[default]
+    Text,
+    /// The visible screen as cells with attributes
+    Screen,
+}
+
+/// What output did besides changing the screen.
+#[derive(Debug, Default)]
+pub struct ScreenEvents {
+    /// Window titles set by OSC 0/2, oldest first
+    pub titles: Vec<String>,
+    /// Commands the shell reported finished, oldest first
+    pub finished: Vec<CommandRecord>,
+}
+
+/// Collects what the emulator reports besides screen changes.
+#[derive(Default)]
+struct Events {
+    pending: ScreenEvents,
+    scroll: ScrollCount,
+    commands: CommandLog,
+}
+
+/// Counts rows scrolled off the top of the main screen into its scrollback
+/// ring, from a mark set before each piece of output.
+#[derive(Debug, Default, Clone, Copy)]
+struct ScrollCount {
+    /// Rows scrolled before the mark
+    scrolled: u64,
+    /// Rows in the ring at the mark
+    ring_len: usize,
+    /// How far the view was scrolled back at the mark, or 0 if it couldn't be
+    ring_offset: usize,
+}
+
+impl ScrollCount {
+    /// Scrolling moves a view that is scrolled back one row further back, so
+    /// once the ring is full and stops growing, the view still tells how many
+    /// rows went by.
+    fn mark(&mut self, screen: &mut vt100::Screen) {
+        self.ring_offset = 0;
+        if screen.alternate_screen() {
+            return;
+        }
+        screen.set_scrollback(usize::MAX);
+        self.ring_len = screen.scrollback();
+        screen.set_scrollback(1);
+        self.ring_offset = screen.scrollback();
+    }
+
+    /// Rows scrolled up to now.
+    fn scrolled(&self, screen: &mut vt100::Screen) -> u64 {
+        // The alternate screen has no scrollback; output is cut where it
+        // switches, so nothing scrolled since the mark
+        if screen.alternate_screen() {
+            return self.scrolled;
+        }
+        let offset = screen.scrollback();
+        screen.set_scrollback(usize::MAX);
+        let len = screen.scrollback();
+        screen.set_scrollback(offset);
+        let rows = match self.ring_offset {
+            0 => len.saturating_sub(self.ring_len),
+            marked => offset.saturating_sub(marked),
+        };
+        self.scrolled + rows as u64
+    }
+
+    /// Count what scrolled since the mark and put the view back.
+    fn unmark(&mut self, screen: &mut vt100::Screen) {
+        self.scrolled = self.scrolled(screen);
+        self.ring_offset = 0;
+        if !screen.alternate_screen() {
+            screen.set_scrollback(usize::MAX);
+            self.ring_len = screen.scrollback();
+            screen.set_scrollback(0);
+        }
+    }
+}
+
+impl vt100::Callbacks for Events {
+    fn set_window_title(&mut self, _: &mut vt100::Screen, title: &[u8]) {
+        self.pending.titles.push(String::from_utf8_lossy(title).into_owned());
+    }
+
+    fn unhandled_osc(&mut self, screen: &mut vt100::Screen, params: &[&[u8]]) {
+        let Some(mark) = ShellMark::parse(params) else { return };
+        let (row, col) = screen.cursor_position();
+        let mut line = self.scroll.scrolled(screen) + row as u64;
+        // Output ends after a partly written last line
+        if matches!(mark, ShellMark::CommandEnd { .. }) && col > 0 {
+            line += 1;
+        }
+        if let Some(command) = self.commands.apply(mark, line) {
+            self.pending.finished.push(command);
+        }
+    }
+}
+
+/// Lines of a terminal, numbered from `first`.
+pub struct Lines {
+    pub first: u64,
+    pub lines: Vec<String>,
+}
+
+impl Lines {
+    /// Lines `[start, end)`, or `None` once the first of them has left the
+    /// scrollback.
+    pub fn range(&self, start: u64, end: u64) -> Option<&[String]> {
+        let from = usize::try_from(start.checked_sub(self.first)?).ok()?;
+        let to = usize::try_from(end.saturating_sub(self.first)).ok()?.min(self.lines.len());
+        Some(self.lines.get(from..to.max(from)).unwrap_or_default())
+    }
+}
+
+/// One terminal's emulated screen and scrollback.
+pub struct TerminalScreen {
+    parser: vt100::Parser<Events>,
+    /// Largest piece of output run through at once, so no more rows scroll
+    /// than the scrollback ring can count
+    piece_bytes: usize,
+}
+
+impl TerminalScreen {
+    pub fn new(rows: u16, cols: u16, scrollback_lines: usize) -> Self {
+        Self {
+            parser: vt100::Parser::new_with_callbacks(rows, cols, scrollback_lines, Events::default()),
+            piece_bytes: scrollback_lines.saturating_sub(1).max(1),
+        }
+    }
+
+    /// Run output through the emulator, returning what it did besides
+    /// drawing.
+    pub fn process(&mut self, bytes: &[u8]) -> ScreenEvents {
+        // Rows scrolled into the scrollback ring are counted, whether by line
+        // feeds, wrapping or explicit scrolls; scroll regions keep rows on
+        // screen. A byte scrolls at most one row (bar scrolls by a count).
+        for piece in pieces(bytes, self.piece_bytes) {
+            let mut scroll = self.parser.callbacks().scroll;
+            scroll.mark(self.parser.screen_mut());
+            self.parser.callbacks_mut().scroll = scroll;
+            self.parser.process(piece);
+            let mut scroll = self.parser.callbacks().scroll;
+            scroll.unmark(self.parser.screen_mut());
+            self.parser.callbacks_mut().scroll = scroll;
+        }
+        std::mem::take(&mut self.parser.callbacks_mut().pending)
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.parser.screen_mut().set_size(rows, cols);
+    }
+
+    /// Scrollback and screen as text, with the cursor position in those
+    /// lines and the number of the first. Blank rows below the cursor are
+    /// left out.
+    pub fn text(&mut self) -> Value {
+        let (first_line, mut lines) = self.lines();
+        let scrollback = lines.len();
+        let screen = self.parser.screen();
+        let (_, cols) = screen.size();
+        let (cursor_row, cursor_col) = screen.cursor_position();
+        lines.extend(screen.rows(0, cols));
+        let keep = lines.iter().rposition(|line| !line.is_empty())
+            .unwrap_or(0)
+            .max(scrollback + cursor_row as usize);
+        lines.truncate(keep + 1);
+
+        json!({
+            "lines": lines,
+            "firstLine": first_line,
+            "cursorRow": scrollback + cursor_row as usize,
+            "cursorCol": cursor_col,
+        })
+    }
+
+    /// Scrollback and screen as text, one line per row.
+    pub fn all_lines(&mut self) -> Lines {
+        let (first, mut lines) = self.lines();
+        let screen = self.parser.screen();
+        lines.extend(screen.rows(0, screen.size().1));
+        Lines { first, lines }
+    }
+
+    /// Commands and working directory reported by shell integration.
+    pub fn commands(&self) -> &CommandLog {
+        &self.parser.callbacks().commands
+    }
+
+    /// Number of the first scrollback line, and the scrollback.
+    fn lines(&mut self) -> (u64, Vec<String>) {
+        let scrollback = self.scrollback();
+        let scrolled = self.parser.callbacks().scroll.scrolled;
+        (scrolled.saturating_sub(scrollback.len() as u64), scrollback)
+    }
+
+    /// The visible screen, cell by cell.
+    pub fn cells(&self) -> Value {
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+        let (cursor_row, cursor_col) = screen.cursor_position();
+        let lines: Vec<Value> = (0..rows)
+            .map(|row| {
+                let cells: Vec<Value> = (0..cols)
+                    .map(|col| screen.cell(row, col).map(cell_json).unwrap_or_else(|| json!({ "text": "" })))
+                    .collect();
+                json!({ "cells": cells, "wrapped": screen.row_wrapped(row) })
+            })
+            .collect();
+
+        json!({
+            "rows": rows,
+            "cols": cols,
+            "cursorRow": cursor_row,
+            "cursorCol": cursor_col,
+            "cursorVisible": !screen.hide_cursor(),
+            "alternateScreen": screen.alternate_screen(),
+            "lines": lines,
+        })
+    }
+
+    /// Output that rebuilds this screen and its scrollback when run through a
+    /// fresh one. Scrollback comes back as plain text; while a full-screen
+    /// program is on the alternate screen, the main screen's visible rows
+    /// are lost.
+    pub fn replay(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for line in self.scrollback() {
+            out.extend_from_slice(line.as_bytes());
+            out.extend_from_slice(b"\r\n");
+        }
+        // Scroll all of it off screen, however many rows there were
+        let (rows, _) = self.parser.screen().size();
+        out.extend(std::iter::repeat_n(b'\n', rows.saturating_sub(1) as usize));
+        if self.parser.screen().alternate_screen() {
+            out.extend_from_slice(b"\x1b[?1049h");
+        }
+        out.extend(self.parser.screen().state_formatted());
+        out
+    }
+
+    /// Text of the rows in the scrollback ring, oldest first.
+    fn scrollback(&mut self) -> Vec<String> {
+        // Scrollback rows are only reachable by scrolling the view back over
+        // them a screenful at a time
+        let screen = self.parser.screen_mut();
+        let (rows, cols) = screen.size();
+        screen.set_scrollback(usize::MAX);
+        let mut offset = screen.scrollback();
+        let mut lines = Vec::with_capacity(offset);
+        while offset > 0 {
+            screen.set_scrollback(offset);
+            let page = offset.min(rows.max(1) as usize);
+            lines.extend(screen.rows(0, cols).take(page));
+            offset -= page;
+        }
+        screen.set_scrollback(0);
+        lines
+    }
+}
+
+/// `bytes` in pieces of at most `max`, cut before and after each private
+/// mode sequence (`ESC [ ? … h`), as those can switch screens.
+fn pieces(bytes: &[u8], max: usize) -> Vec<&[u8]> {
+    let mut cuts = vec![0];
+    let mut at = 0;
+    while let Some(found) = bytes[at..].windows(3).position(|w| w == b"\x1b[?") {
+        let start = at + found;
+        let params = bytes[start + 3..].iter().position(|b| !(0x30..=0x3f).contains(b));
+        at = params.map_or(bytes.len(), |n| start + 3 + n + 1);
+        cuts.extend([start, at]);
+    }
+    cuts.push(bytes.len());
+    cuts.windows(2)
+        .filter(|cut| cut[1] > cut[0])
+        .flat_map(|cut| bytes[cut[0]..cut[1]].chunks(max))
+        .collect()
+}
+
+/// A cell's text plus whichever attributes differ from the default.
+fn cell_json(cell: &vt100::Cell) -> Value {
+    let mut out = Map::new();
+    out.insert("text".into(), cell.contents().into());
+    if let Some(fg) = color_json(cell.fgcolor()) {
+        out.insert("fg".into(), fg);
+    }
+    if let Some(bg) = color_json(cell.bgcolor()) {
+        out.insert("bg".into(), bg);
+    }
+    let flags = [
+        ("bold", cell.bold()),
+        ("dim", cell.dim()),
+        ("italic", cell.italic()),
+        ("underline", cell.underline()),
+        ("inverse", cell.inverse()),
+        ("wide", cell.is_wide()),
+    ];
+    for (name, set) in flags {
+        if set {
+            out.insert(name.into(), true.into());
+        }
+    }
+    Value::Object(out)
+}
+
+/// A palette index as a number, a true color as `#rrggbb`.
+fn color_json(color: vt100::Color) -> Option<Value> {
+    match color {
+        vt100::Color::Default => None,
+        vt100::Color::Idx(index) => Some(index.into()),
+        vt100::Color::Rgb(r, g, b) => Some(format!("#{r:02x}{g:02x}{b:02x}").into()),
+    }
+}
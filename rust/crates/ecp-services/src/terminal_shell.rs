@@ -0,0 +1,337 @@
+//! Shell integration for [`TerminalService`](crate::terminal::TerminalService).
+//!
+//! `terminal/create` starts bash, zsh and fish with a small init script
+//! that marks up what the shell prints: OSC 133 `A` where a prompt starts
+//! and `B` where it ends, `C` (with the command line as `cmdline_url=`) when
+//! a command starts and `D;<exit code>` when it finishes, and OSC 7 with the
+//! working directory at every prompt. The user's own rc files are still
+//! read. [`CommandLog`] turns the marks into a list of commands for
+//! `terminal/commands` and `terminal/commandFinished`.
+//!
+//! Output ranges are in lines counted from the terminal's first line, the
+//! same numbering as `firstLine` in `terminal/getBuffer`.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use portable_pty::CommandBuilder;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::terminal_output::now_ms;
+
+/// Finished commands kept per terminal; older ones are dropped first.
+pub const MAX_COMMANDS: usize = 1000;
+
+const BASH_SCRIPT: &str = r#"# ultra-ecp shell integration for bash
+if [ -r ~/.bashrc ]; then . ~/.bashrc; fi
+
+__ultra_urlencode() {
+    local LC_ALL=C s="$1" out="" c i
+    for (( i = 0; i < ${#s}; i++ )); do
+        c=${s:i:1}
+        case "$c" in
+            [a-zA-Z0-9.~_/-]) out+="$c" ;;
+            *) printf -v c '%%%02X' "'$c"; out+="$c" ;;
+        esac
+    done
+    printf '%s' "$out"
+}
+
+__ultra_at_prompt=0
+__ultra_ran=0
+
+__ultra_preexec() {
+    # Only the first command after a prompt, not the prompt's own
+    [ "$__ultra_at_prompt" = 1 ] || return
+    [ -n "$COMP_LINE" ] && return
+    [ "$BASH_COMMAND" = __ultra_precmd ] && return
+    __ultra_at_prompt=0
+    __ultra_ran=1
+    local cmd
+    cmd=$(HISTTIMEFORMAT= builtin history 1)
+    cmd=${cmd#*[0-9]  }
+    printf '\e]133;C;cmdline_url=%s\a' "$(__ultra_urlencode "$cmd")"
+}
+
+__ultra_precmd() {
+    local status=$?
+    __ultra_at_prompt=0
+    if [ "$__ultra_ran" = 1 ]; then printf '\e]133;D;%s\a' "$status"; fi
+    __ultra_ran=0
+    printf '\e]7;file://%s%s\a' "$HOSTNAME" "$(__ultra_urlencode "$PWD")"
+}
+
+__ultra_prompt_ready() {
+    case "$PS1" in
+        *'133;B'*) ;;
+        *) PS1='\[\e]133;A\a\]'"$PS1"'\[\e]133;B\a\]' ;;
+    esac
+    __ultra_at_prompt=1
+}
+
+trap '__ultra_preexec' DEBUG
+# Any existing hook may end in its own separator
+__ultra_rest=$PROMPT_COMMAND
+while [[ $__ultra_rest == *[[:space:]\;] ]]; do __ultra_rest=${__ultra_rest%?}; done
+PROMPT_COMMAND="__ultra_precmd${__ultra_rest:+; $__ultra_rest}; __ultra_prompt_ready"
+unset __ultra_rest
+"#;
+
+const ZSHENV_SCRIPT: &str = r#"# ultra-ecp shell integration for zsh
+if [[ -f "$ULTRA_USER_ZDOTDIR/.zshenv" ]]; then
+    __ultra_zdotdir=$ZDOTDIR
+    ZDOTDIR=$ULTRA_USER_ZDOTDIR
+    . "$ULTRA_USER_ZDOTDIR/.zshenv"
+    ZDOTDIR=$__ultra_zdotdir
+    unset __ultra_zdotdir
+fi
+"#;
+
+const ZSHRC_SCRIPT: &str = r#"# ultra-ecp shell integration for zsh
+ZDOTDIR=$ULTRA_USER_ZDOTDIR
+unset ULTRA_USER_ZDOTDIR
+[[ -f "$ZDOTDIR/.zshrc" ]] && . "$ZDOTDIR/.zshrc"
+
+__ultra_urlencode() {
+    local LC_ALL=C s=$1 out= c i
+    for (( i = 1; i <= ${#s}; i++ )); do
+        c=${s[i]}
+        case $c in
+            [a-zA-Z0-9.~_/-]) out+=$c ;;
+            *) out+=$(printf '%%%02X' "'$c") ;;
+        esac
+    done
+    print -rn -- "$out"
+}
+
+__ultra_ran=0
+
+__ultra_preexec() {
+    __ultra_ran=1
+    print -rn -- $'\e]133;C;cmdline_url='"$(__ultra_urlencode "$1")"$'\a'
+}
+
+__ultra_precmd() {
+    local ret=$?
+    if (( __ultra_ran )); then print -rn -- $'\e]133;D;'"$ret"$'\a'; fi
+    __ultra_ran=0
+    print -rn -- $'\e]7;file://'"$HOST$(__ultra_urlencode "$PWD")"$'\a'
+    if [[ $PS1 != *'133;B'* ]]; then PS1=$'%{\e]133;A\a%}'"$PS1"$'%{\e]133;B\a%}'; fi
+}
+
+# First, so it sees the command's status before other hooks run
+precmd_functions=(__ultra_precmd $precmd_functions)
+preexec_functions+=(__ultra_preexec)
+"#;
+
+const FISH_SCRIPT: &str = r#"# ultra-ecp shell integration for fish
+function __ultra_preexec --on-event fish_preexec
+    printf '\e]133;C;cmdline_url=%s\a' (string escape --style=url -- "$argv")
+end
+
+function __ultra_postexec --on-event fish_postexec
+    printf '\e]133;D;%s\a' $status
+end
+
+function __ultra_prompt --on-event fish_prompt
+    printf '\e]7;file://%s%s\a' (hostname) (string escape --style=url -- $PWD)
+    printf '\e]133;A\a'
+end
+"#;
+
+/// Set `cmd` up to load the integration script for `shell`. Returns false
+/// for shells without one, which start as they would have.
+pub fn integrate(cmd: &mut CommandBuilder, shell: &str) -> bool {
+    let name = shell.rsplit('/').next().unwrap_or(shell);
+    if !matches!(name, "bash" | "zsh" | "fish") {
+        return false;
+    }
+    let Some(dir) = scripts_dir() else { return false };
+    match name {
+        "bash" => {
+            cmd.arg("--rcfile");
+            cmd.arg(dir.join("ultra.bash"));
+        }
+        "zsh" => {
+            let user_zdotdir = std::env::var_os("ZDOTDIR")
+                .or_else(|| dirs::home_dir().map(Into::into))
+                .unwrap_or_default();
+            cmd.env("ULTRA_USER_ZDOTDIR", user_zdotdir);
+            cmd.env("ZDOTDIR", dir.join("zsh"));
+        }
+        _ => {
+            let script = dir.join("ultra.fish").to_string_lossy().replace('\\', "\\\\").replace('\'', "\\'");
+            cmd.args(["--init-command".to_string(), format!("source '{script}'")]);
+        }
+    }
+    true
+}
+
+/// `~/.ultra/shell-integration`, with the scripts written out the first
+/// time it is asked for.
+fn scripts_dir() -> Option<&'static Path> {
+    static DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = dirs::home_dir()?.join(".ultra/shell-integration");
+        let written = std::fs::create_dir_all(dir.join("zsh"))
+            .and_then(|_| std::fs::write(dir.join("ultra.bash"), BASH_SCRIPT))
+            .and_then(|_| std::fs::write(dir.join("zsh/.zshenv"), ZSHENV_SCRIPT))
+            .and_then(|_| std::fs::write(dir.join("zsh/.zshrc"), ZSHRC_SCRIPT))
+            .and_then(|_| std::fs::write(dir.join("ultra.fish"), FISH_SCRIPT));
+        match written {
+            Ok(()) => Some(dir),
+            Err(e) => {
+                warn!("Shell integration unavailable: failed to write {}: {e}", dir.display());
+                None
+            }
+        }
+    }).as_deref()
+}
+
+/// A shell integration mark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellMark {
+    PromptStart,
+    CommandStart { command: Option<String> },
+    CommandEnd { exit_code: Option<i32> },
+    Cwd(String),
+}
+
+impl ShellMark {
+    /// The mark in an OSC sequence's `;`-separated parameters, if it is one.
+    pub fn parse(params: &[&[u8]]) -> Option<Self> {
+        match params {
+            [b"133", b"A", ..] => Some(Self::PromptStart),
+            [b"133", b"C", options @ ..] => {
+                let command = options.iter()
+                    .find_map(|option| option.strip_prefix(b"cmdline_url="))
+                    .map(percent_decode);
+                Some(Self::CommandStart { command })
+            }
+            [b"133", b"D", rest @ ..] => {
+                let exit_code = rest.first()
+                    .and_then(|code| std::str::from_utf8(code).ok())
+                    .and_then(|code| code.parse().ok());
+                Some(Self::CommandEnd { exit_code })
+            }
+            [b"7", url] => {
+                // file://host/path — the host is ours
+                let rest = url.strip_prefix(b"file://")?;
+                let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+                Some(Self::Cwd(percent_decode(path)))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn percent_decode(bytes: &[u8]) -> String {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Lines `[start, end)`; `end` is unset while the command runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct LineRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+/// A command the shell ran.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandRecord {
+    pub id: u64,
+    pub command: String,
+    /// Working directory it ran in, as of the last prompt
+    pub cwd: Option<String>,
+    /// Line its prompt started on
+    pub prompt_line: Option<u64>,
+    /// Milliseconds since the Unix epoch
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub output: LineRange,
+}
+
+/// Commands and working directory reported by one terminal's shell.
+#[derive(Debug, Default)]
+pub struct CommandLog {
+    finished: VecDeque<CommandRecord>,
+    running: Option<CommandRecord>,
+    prompt_line: Option<u64>,
+    cwd: Option<String>,
+    next_id: u64,
+}
+
+impl CommandLog {
+    /// Apply a mark seen on `line`, returning the command it finished.
+    pub fn apply(&mut self, mark: ShellMark, line: u64) -> Option<CommandRecord> {
+        match mark {
+            ShellMark::PromptStart => {
+                self.prompt_line = Some(line);
+                // A prompt without `D` first: the shell didn't say how it went
+                self.finish(None, line)
+            }
+            ShellMark::CommandStart { command } => {
+                self.next_id += 1;
+                self.running = Some(CommandRecord {
+                    id: self.next_id,
+                    command: command.unwrap_or_default(),
+                    cwd: self.cwd.clone(),
+                    prompt_line: self.prompt_line.take(),
+                    started_at: now_ms(),
+                    finished_at: None,
+                    exit_code: None,
+                    output: LineRange { start: line, end: None },
+                });
+                None
+            }
+            ShellMark::CommandEnd { exit_code } => self.finish(exit_code, line),
+            ShellMark::Cwd(cwd) => {
+                self.cwd = Some(cwd);
+                None
+            }
+        }
+    }
+
+    fn finish(&mut self, exit_code: Option<i32>, line: u64) -> Option<CommandRecord> {
+        let mut command = self.running.take()?;
+        command.finished_at = Some(now_ms());
+        command.exit_code = exit_code;
+        command.output.end = Some(line.max(command.output.start));
+        if self.finished.len() == MAX_COMMANDS {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(command.clone());
+        Some(command)
+    }
+
+    /// Working directory from the shell's last report.
+    pub fn cwd(&self) -> Option<&str> {
+        self.cwd.as_deref()
+    }
+
+    /// Finished commands, oldest first, then the one running.
+    pub fn commands(&self) -> impl DoubleEndedIterator<Item = &CommandRecord> {
+        self.finished.iter().chain(self.running.iter())
+    }
+}
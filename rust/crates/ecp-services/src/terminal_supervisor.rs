@@ -0,0 +1,621 @@
+//! Terminal supervisor — keeps terminals running across `ultra-ecp` restarts.
+//!
+//! With `[terminal] supervisor = true`, `terminal/create` and
+//! `terminal/attachTmux` start their process in a small background process
+//! (`ultra-ecp terminal-supervisor`) rather than in the server. The
+//! supervisor owns the pseudo-terminals and keeps each one's screen; the
+//! server attaches to them over a Unix socket. When the server goes away the
+//! terminals carry on, and the next server re-adopts a workspace's terminals
+//! when the workspace is opened, repainting them from the supervisor's
+//! screen and scrollback. A terminal that exits with no server attached is
+//! kept for [`EXIT_KEPT`] so the next server can report the exit. The
+//! supervisor is started on first use and exits once it has had no
+//! terminals for [`IDLE_EXIT`].
+//!
+//! The protocol is JSON lines. A connection sends one request and reads one
+//! reply; after `attach` it stays open, carrying output and the exit from
+//! the supervisor and input, resizes and hang-ups to it.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use base64::Engine;
+use ecp_protocol::ECPError;
+use parking_lot::Mutex;
+use portable_pty::CommandBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use crate::terminal::{open_pty, Pty};
+use crate::terminal_output::ExitInfo;
+use crate::terminal_screen::TerminalScreen;
+
+/// How long the supervisor lingers with no terminals before exiting.
+pub const IDLE_EXIT: Duration = Duration::from_secs(30);
+
+/// How long the exit of a terminal no server was attached to is kept.
+pub const EXIT_KEPT: Duration = Duration::from_secs(10 * 60);
+
+/// How long to wait for a freshly started supervisor to listen.
+const LAUNCH_WAIT: Duration = Duration::from_secs(5);
+
+#[cfg(unix)]
+type Stream = tokio::net::UnixStream;
+#[cfg(not(unix))]
+type Stream = tokio::io::DuplexStream;
+
+async fn connect(socket: &Path) -> io::Result<Stream> {
+    #[cfg(unix)]
+    {
+        Stream::connect(socket).await
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = socket;
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the terminal supervisor needs Unix sockets"))
+    }
+}
+
+/// A terminal for the supervisor to start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TerminalSpec {
+    pub id: String,
+    /// Root of the workspace the terminal belongs to
+    pub workspace: String,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub cwd: String,
+    /// Variables set on top of the supervisor's environment
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub cols: u16,
+    pub rows: u16,
+    pub scrollback_lines: usize,
+    /// Handed back to whoever adopts the terminal
+    #[serde(default)]
+    pub meta: Value,
+}
+
+/// A terminal the supervisor hosts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostedTerminal {
+    pub id: String,
+    pub pid: Option<u32>,
+    pub running: bool,
+    pub cols: u16,
+    pub rows: u16,
+    #[serde(default)]
+    pub meta: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum Request {
+    Create(TerminalSpec),
+    List { workspace: String },
+    Attach { id: String },
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    terminal: Option<HostedTerminal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    terminals: Option<Vec<HostedTerminal>>,
+    /// Output that repaints the terminal's screen and scrollback, base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replay: Option<String>,
+}
+
+impl Reply {
+    fn error(message: impl Into<String>) -> Self {
+        Self { error: Some(message.into()), ..Default::default() }
+    }
+}
+
+/// Supervisor to server, after `attach`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerFrame {
+    Output { data: String },
+    Exit { code: Option<u32>, signal: Option<String> },
+}
+
+impl From<&ExitInfo> for ServerFrame {
+    fn from(exit: &ExitInfo) -> Self {
+        Self::Exit { code: exit.code, signal: exit.signal.clone() }
+    }
+}
+
+/// Server to supervisor, after `attach`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientFrame {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 },
+    Close,
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+fn decode(data: &str) -> Vec<u8> {
+    base64::engine::general_purpose::STANDARD.decode(data).unwrap_or_default()
+}
+
+async fn send_line<W: AsyncWrite + Unpin>(write: &mut W, message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    write.write_all(&line).await
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Supervisor process
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// A terminal running in the supervisor.
+struct Hosted {
+    spec: TerminalSpec,
+    pty: Pty,
+    input_tx: mpsc::Sender<Vec<u8>>,
+    running: Arc<AtomicBool>,
+    /// Hung up by a server, so nobody will come back for its exit
+    closed: AtomicBool,
+    state: Mutex<HostedState>,
+}
+
+struct HostedState {
+    screen: TerminalScreen,
+    size: (u16, u16),
+    /// Where output goes while a server is attached
+    client: Option<mpsc::UnboundedSender<ServerFrame>>,
+    /// Bumped by every attach, so a superseded connection leaves its
+    /// successor attached when it ends
+    generation: u64,
+    /// How the process ended and when, kept for the next server to attach
+    exit: Option<(ExitInfo, tokio::time::Instant)>,
+}
+
+impl Hosted {
+    fn listing(&self, state: &HostedState) -> HostedTerminal {
+        HostedTerminal {
+            id: self.spec.id.clone(),
+            pid: self.pty.pid,
+            running: self.running.load(Ordering::Relaxed),
+            cols: state.size.0,
+            rows: state.size.1,
+            meta: self.spec.meta.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Supervisor {
+    terminals: Mutex<HashMap<String, Arc<Hosted>>>,
+}
+
+/// Run a supervisor on `socket` until it has been idle for [`IDLE_EXIT`].
+/// Returns at once if another supervisor already listens there.
+pub async fn serve(socket: &Path) -> io::Result<()> {
+    if connect(socket).await.is_ok() {
+        info!("A terminal supervisor is already listening on {}", socket.display());
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(parent) = socket.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let _ = std::fs::remove_file(socket);
+        let listener = tokio::net::UnixListener::bind(socket)?;
+        std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+        info!("Terminal supervisor listening on {}", socket.display());
+
+        let supervisor = Arc::new(Supervisor::default());
+        let mut idle_since = Some(tokio::time::Instant::now());
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    tokio::spawn(supervisor.clone().handle(stream));
+                }
+                _ = tick.tick() => {
+                    supervisor.expire_exits();
+                    if !supervisor.terminals.lock().is_empty() {
+                        idle_since = None;
+                    } else if idle_since.get_or_insert_with(tokio::time::Instant::now).elapsed() >= IDLE_EXIT {
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = std::fs::remove_file(socket);
+        info!("Terminal supervisor exiting: no terminals left");
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the terminal supervisor needs Unix sockets"))
+    }
+}
+
+impl Supervisor {
+    async fn handle(self: Arc<Self>, stream: Stream) {
+        let (read, mut write) = tokio::io::split(stream);
+        let mut lines = BufReader::new(read).lines();
+        let Ok(Some(line)) = lines.next_line().await else { return };
+        let reply = match serde_json::from_str(&line) {
+            Ok(Request::Create(spec)) => match self.create(spec) {
+                Ok(terminal) => Reply { terminal: Some(terminal), ..Default::default() },
+                Err(e) => Reply::error(e.message),
+            },
+            Ok(Request::List { workspace }) => {
+                let terminals = self.terminals.lock().values()
+                    .filter(|t| t.spec.workspace == workspace)
+                    .map(|t| t.listing(&t.state.lock()))
+                    .collect();
+                Reply { terminals: Some(terminals), ..Default::default() }
+            }
+            Ok(Request::Attach { id }) => return self.attach(&id, lines, write).await,
+            Err(e) => Reply::error(format!("Invalid request: {e}")),
+        };
+        if let Err(e) = send_line(&mut write, &reply).await {
+            debug!("Failed to reply to terminal supervisor client: {e}");
+        }
+    }
+
+    fn create(self: &Arc<Self>, spec: TerminalSpec) -> Result<HostedTerminal, ECPError> {
+        if self.terminals.lock().contains_key(&spec.id) {
+            return Err(ECPError::server_error(format!("Terminal already exists: {}", spec.id)));
+        }
+        let mut cmd = CommandBuilder::new(&spec.program);
+        cmd.args(&spec.args);
+        cmd.cwd(&spec.cwd);
+        for (key, value) in &spec.env {
+            cmd.env(key, value);
+        }
+        let running = Arc::new(AtomicBool::new(true));
+        let process = open_pty(&spec.id, cmd, (spec.cols, spec.rows), &running)?;
+
+        let state = HostedState {
+            screen: TerminalScreen::new(spec.rows, spec.cols, spec.scrollback_lines),
+            size: (spec.cols, spec.rows),
+            client: None,
+            generation: 0,
+            exit: None,
+        };
+        let hosted = Arc::new(Hosted {
+            spec,
+            pty: process.pty,
+            input_tx: process.input_tx,
+            running,
+            closed: AtomicBool::new(false),
+            state: Mutex::new(state),
+        });
+        let terminal = hosted.listing(&hosted.state.lock());
+        self.terminals.lock().insert(terminal.id.clone(), hosted.clone());
+        tokio::spawn(self.clone().forward(hosted, process.chunks, process.exit));
+        Ok(terminal)
+    }
+
+    /// Keep a terminal's screen current and pass its output to the attached
+    /// server, if any.
+    async fn forward(
+        self: Arc<Self>,
+        hosted: Arc<Hosted>,
+        mut chunks: mpsc::UnboundedReceiver<Vec<u8>>,
+        exit: oneshot::Receiver<ExitInfo>,
+    ) {
+        while let Some(chunk) = chunks.recv().await {
+            let mut state = hosted.state.lock();
+            state.screen.process(&chunk);
+            if let Some(ref client) = state.client {
+                let _ = client.send(ServerFrame::Output { data: encode(&chunk) });
+            }
+        }
+        let exit = exit.await.unwrap_or_default();
+        {
+            let mut state = hosted.state.lock();
+            match state.client.take() {
+                Some(client) => {
+                    let _ = client.send(ServerFrame::from(&exit));
+                }
+                None if !hosted.closed.load(Ordering::Relaxed) => {
+                    state.exit = Some((exit, tokio::time::Instant::now()));
+                    return;
+                }
+                None => {}
+            }
+        }
+        self.terminals.lock().remove(&hosted.spec.id);
+    }
+
+    /// Forget terminals whose exit nobody came back for within
+    /// [`EXIT_KEPT`].
+    fn expire_exits(&self) {
+        self.terminals.lock().retain(|id, hosted| {
+            let expired = hosted.state.lock().exit.as_ref().is_some_and(|(_, at)| at.elapsed() >= EXIT_KEPT);
+            if expired {
+                debug!("Forgetting the exit of terminal {id}");
+            }
+            !expired
+        });
+    }
+
+    async fn attach(&self, id: &str, mut lines: Lines<BufReader<ReadHalf<Stream>>>, mut write: WriteHalf<Stream>) {
+        let Some(hosted) = self.terminals.lock().get(id).cloned() else {
+            let _ = send_line(&mut write, &Reply::error(format!("Terminal not found: {id}"))).await;
+            return;
+        };
+
+        // Replay and subscription happen under one lock, so no output falls
+        // between them
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (reply, generation, exited) = {
+            let mut state = hosted.state.lock();
+            let replay = state.screen.replay();
+            let exited = state.exit.take().map(|(exit, _)| exit);
+            state.generation += 1;
+            if exited.is_none() {
+                state.client = Some(tx);
+            }
+            let reply = Reply {
+                terminal: Some(hosted.listing(&state)),
+                replay: Some(encode(&replay)),
+                ..Default::default()
+            };
+            (reply, state.generation, exited)
+        };
+        if send_line(&mut write, &reply).await.is_err() {
+            return;
+        }
+        if let Some(exit) = exited {
+            self.terminals.lock().remove(id);
+            let _ = send_line(&mut write, &ServerFrame::from(&exit)).await;
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                frame = rx.recv() => {
+                    // None: another server attached
+                    let Some(frame) = frame else { break };
+                    let done = matches!(frame, ServerFrame::Exit { .. });
+                    if send_line(&mut write, &frame).await.is_err() || done {
+                        break;
+                    }
+                }
+                line = lines.next_line() => {
+                    // The server went away; the terminal keeps running
+                    let Ok(Some(line)) = line else { break };
+                    match serde_json::from_str(&line) {
+                        Ok(ClientFrame::Input { data }) => {
+                            let _ = hosted.input_tx.send(decode(&data)).await;
+                        }
+                        Ok(ClientFrame::Resize { cols, rows }) => {
+                            if let Err(e) = hosted.pty.resize(cols, rows) {
+                                debug!("Failed to resize terminal {id}: {}", e.message);
+                            }
+                            let mut state = hosted.state.lock();
+                            state.screen.resize(rows, cols);
+                            state.size = (cols, rows);
+                        }
+                        Ok(ClientFrame::Close) => {
+                            hosted.closed.store(true, Ordering::Relaxed);
+                            if hosted.running.load(Ordering::Relaxed)
+                                && let Err(e) = hosted.pty.hang_up()
+                            {
+                                debug!("Failed to hang up terminal {id}: {e}");
+                            }
+                        }
+                        Err(e) => debug!("Ignoring bad frame for terminal {id}: {e}"),
+                    }
+                }
+            }
+        }
+
+        let mut state = hosted.state.lock();
+        if state.generation == generation {
+            state.client = None;
+        }
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Server side
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// The server's connection to a supervisor, which is started on first use.
+pub struct SupervisorClient {
+    socket: PathBuf,
+    /// Program and arguments that start a supervisor on `socket`
+    launcher: Option<(PathBuf, Vec<String>)>,
+    /// Held while starting a supervisor, so workspaces don't start two
+    launching: tokio::sync::Mutex<()>,
+}
+
+/// A supervised terminal the server is attached to.
+pub(crate) struct Attached {
+    /// Output that repaints the terminal as the supervisor has it
+    pub replay: Vec<u8>,
+    pub handle: SupervisedTerminal,
+    /// Typed into the terminal
+    pub input_tx: mpsc::Sender<Vec<u8>>,
+    pub chunks: mpsc::UnboundedReceiver<Vec<u8>>,
+    pub exit: oneshot::Receiver<ExitInfo>,
+}
+
+/// Resizes and hangs up a supervised terminal.
+pub(crate) struct SupervisedTerminal {
+    control: mpsc::UnboundedSender<ClientFrame>,
+    pub pid: Option<u32>,
+}
+
+impl SupervisedTerminal {
+    pub fn resize(&self, cols: u16, rows: u16) {
+        let _ = self.control.send(ClientFrame::Resize { cols, rows });
+    }
+
+    pub fn hang_up(&self) {
+        let _ = self.control.send(ClientFrame::Close);
+    }
+}
+
+fn unavailable(e: io::Error) -> ECPError {
+    ECPError::server_error(format!("Terminal supervisor unavailable: {e}"))
+}
+
+impl SupervisorClient {
+    pub fn new(socket: PathBuf) -> Self {
+        Self { socket, launcher: None, launching: tokio::sync::Mutex::new(()) }
+    }
+
+    /// Start a supervisor with `program args` when none is listening.
+    pub fn with_launcher(mut self, program: PathBuf, args: Vec<String>) -> Self {
+        self.launcher = Some((program, args));
+        self
+    }
+
+    async fn connect(&self, launch: bool) -> io::Result<Stream> {
+        let first = connect(&self.socket).await;
+        let Some((ref program, ref args)) = self.launcher else { return first };
+        if first.is_ok() || !launch {
+            return first;
+        }
+
+        let _launching = self.launching.lock().await;
+        if let Ok(stream) = connect(&self.socket).await {
+            return Ok(stream);
+        }
+        let mut command = std::process::Command::new(program);
+        command.args(args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+        #[cfg(unix)]
+        {
+            // Out of our process group, so it outlives this server
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+        let mut child = command.spawn()?;
+        info!("Started terminal supervisor (pid {})", child.id());
+        // Reap it if it exits while we're still running
+        std::thread::spawn(move || child.wait());
+
+        let deadline = tokio::time::Instant::now() + LAUNCH_WAIT;
+        loop {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            match connect(&self.socket).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) if tokio::time::Instant::now() >= deadline => return Err(e),
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Send a request and read its reply, keeping the connection for `attach`.
+    async fn request(
+        &self,
+        request: &Request,
+        launch: bool,
+    ) -> io::Result<(Reply, Lines<BufReader<ReadHalf<Stream>>>, WriteHalf<Stream>)> {
+        let (read, mut write) = tokio::io::split(self.connect(launch).await?);
+        send_line(&mut write, request).await?;
+        let mut lines = BufReader::new(read).lines();
+        let line = lines.next_line().await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no reply"))?;
+        Ok((serde_json::from_str(&line)?, lines, write))
+    }
+
+    pub async fn create(&self, spec: &TerminalSpec) -> Result<HostedTerminal, ECPError> {
+        let (reply, _, _) = self.request(&Request::Create(spec.clone()), true).await.map_err(unavailable)?;
+        match reply.error {
+            Some(error) => Err(ECPError::server_error(error)),
+            None => reply.terminal.ok_or_else(|| ECPError::server_error("Terminal supervisor sent no terminal")),
+        }
+    }
+
+    /// Terminals hosted for `workspace`; none if no supervisor is running.
+    pub async fn list(&self, workspace: &str) -> Vec<HostedTerminal> {
+        let request = Request::List { workspace: workspace.to_string() };
+        match self.request(&request, false).await {
+            Ok((reply, _, _)) => reply.terminals.unwrap_or_default(),
+            Err(e) => {
+                debug!("No terminal supervisor to list: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Attach to a hosted terminal. `running` is cleared when it exits.
+    pub(crate) async fn attach(&self, id: &str, running: &Arc<AtomicBool>) -> Result<Attached, ECPError> {
+        let request = Request::Attach { id: id.to_string() };
+        let (reply, mut lines, mut write) = self.request(&request, false).await.map_err(unavailable)?;
+        if let Some(error) = reply.error {
+            return Err(ECPError::server_error(error));
+        }
+        let terminal = reply.terminal
+            .ok_or_else(|| ECPError::server_error("Terminal supervisor sent no terminal"))?;
+        let replay = decode(reply.replay.as_deref().unwrap_or_default());
+        running.store(terminal.running, Ordering::Relaxed);
+
+        let (chunk_tx, chunks) = mpsc::unbounded_channel();
+        let (exit_tx, exit) = oneshot::channel();
+        let output_id = id.to_string();
+        let output_running = running.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                match serde_json::from_str(&line) {
+                    Ok(ServerFrame::Output { data }) => {
+                        let _ = chunk_tx.send(decode(&data));
+                    }
+                    Ok(ServerFrame::Exit { code, signal }) => {
+                        output_running.store(false, Ordering::Relaxed);
+                        let _ = exit_tx.send(ExitInfo { code, signal });
+                        return;
+                    }
+                    Err(e) => debug!("Ignoring bad frame for terminal {output_id}: {e}"),
+                }
+            }
+            warn!("Lost the terminal supervisor connection for {output_id}");
+            output_running.store(false, Ordering::Relaxed);
+        });
+
+        let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
+        let (control, mut control_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let frame = tokio::select! {
+                    Some(data) = input_rx.recv() => ClientFrame::Input { data: encode(&data) },
+                    Some(frame) = control_rx.recv() => frame,
+                    // Dropped: detach, leaving the terminal running
+                    else => break,
+                };
+                if send_line(&mut write, &frame).await.is_err() {
+                    break;
+                }
+            }
+            let _ = write.shutdown().await;
+        });
+
+        let handle = SupervisedTerminal { control, pid: terminal.pid };
+        Ok(Attached { replay, handle, input_tx, chunks, exit })
+    }
+}
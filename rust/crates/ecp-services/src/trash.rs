@@ -0,0 +1,170 @@
+//! Workspace-local trash for `file/delete` and `file/deleteDir`.
+//!
+//! Deleted files and directories are moved to `<workspace>/.ultra/trash/<id>/`
+//! instead of being removed: `item` is the moved file or directory and
+//! `info.json` records where it came from. `file/trash/restore` moves it back
+//! and `file/trash/empty` removes it for good.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::journal::create_store_dir;
+
+/// Metadata for one trashed file or directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    pub id: String,
+    /// Workspace-relative path it was deleted from
+    pub original_path: String,
+    pub is_directory: bool,
+    /// Bytes, summed over all files for a directory
+    pub size: u64,
+    pub deleted_at: u64,
+}
+
+/// Trash for one workspace.
+pub struct Trash {
+    workspace_root: PathBuf,
+    dir: PathBuf,
+}
+
+impl Trash {
+    pub fn new(workspace_root: PathBuf) -> Self {
+        let dir = workspace_root.join(".ultra/trash");
+        Self { workspace_root, dir }
+    }
+
+    /// Whether `path` is the trash itself or inside it; such paths are
+    /// deleted permanently.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.dir)
+    }
+
+    /// Move `path` into the trash.
+    pub fn put(&self, path: &Path) -> std::io::Result<TrashEntry> {
+        let meta = std::fs::symlink_metadata(path)?;
+        let original_path = path.strip_prefix(&self.workspace_root)
+            .map(|r| r.to_string_lossy().to_string())
+            .map_err(|_| std::io::Error::other("path is outside the workspace"))?;
+        let entry = TrashEntry {
+            id: format!("t-{}", uuid::Uuid::new_v4()),
+            original_path,
+            is_directory: meta.is_dir(),
+            size: if meta.is_dir() { dir_size(path) } else { meta.len() },
+            deleted_at: now_ms(),
+        };
+
+        let slot = self.dir.join(&entry.id);
+        create_store_dir(&slot)?;
+        std::fs::write(slot.join("info.json"), serde_json::to_vec_pretty(&entry)?)?;
+        if let Err(e) = move_path(path, &slot.join("item")) {
+            let _ = std::fs::remove_dir_all(&slot);
+            return Err(e);
+        }
+        Ok(entry)
+    }
+
+    /// Trashed items, most recently deleted first.
+    pub fn list(&self) -> Vec<TrashEntry> {
+        let Ok(dirs) = std::fs::read_dir(&self.dir) else { return Vec::new() };
+        let mut entries: Vec<TrashEntry> = dirs
+            .flatten()
+            .filter_map(|d| std::fs::read(d.path().join("info.json")).ok())
+            .filter_map(|bytes| serde_json::from_slice(&bytes).ok())
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.deleted_at));
+        entries
+    }
+
+    pub fn get(&self, id: &str) -> Option<TrashEntry> {
+        // Ids name directories; refuse anything that could escape the trash
+        if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.') {
+            return None;
+        }
+        let bytes = std::fs::read(self.dir.join(id).join("info.json")).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Move a trashed item to `to`, which must not exist.
+    pub fn restore(&self, entry: &TrashEntry, to: &Path) -> std::io::Result<()> {
+        if std::fs::symlink_metadata(to).is_ok() {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "destination exists"));
+        }
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let slot = self.dir.join(&entry.id);
+        move_path(&slot.join("item"), to)?;
+        std::fs::remove_dir_all(&slot)
+    }
+
+    /// Permanently remove one trashed item.
+    pub fn remove(&self, entry: &TrashEntry) -> std::io::Result<()> {
+        std::fs::remove_dir_all(self.dir.join(&entry.id))
+    }
+
+    /// Where a trashed item goes back to by default.
+    pub fn original(&self, entry: &TrashEntry) -> PathBuf {
+        self.workspace_root.join(&entry.original_path)
+    }
+}
+
+/// Rename, falling back to copy-and-remove across filesystems.
+fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_recursive(from, to)?;
+            if from.is_dir() {
+                std::fs::remove_dir_all(from)
+            } else {
+                std::fs::remove_file(from)
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    let meta = std::fs::symlink_metadata(from)?;
+    if meta.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else if meta.file_type().is_symlink() {
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+        }
+        #[cfg(not(unix))]
+        {
+            std::fs::copy(from, to).map(|_| ())
+        }
+    } else {
+        std::fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else { return 0 };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(t) if t.is_dir() => dir_size(&entry.path()),
+            Ok(_) => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
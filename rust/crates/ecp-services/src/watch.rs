@@ -1,46 +1,783 @@
 //! File watcher service — monitors filesystem for changes using the `notify` crate.
 //!
-//! Exposes `file/watch` and `file/unwatch` methods and emits
-//! `file/didChange`, `file/didCreate`, `file/didDelete` notifications.
+//! Exposes `file/watch` and `file/unwatch` methods and emits notifications for
+//! paths under a client's watches. Raw events are collected for a debounce
+//! window and coalesced per path (create-then-delete cancels out, a rename is
+//! one event with both URIs), then filtered through `.gitignore` and each
+//! watch's exclude globs. A watch gets either one `file/didChangeBatch` per
+//! window or, by default, individual `file/didCreate`, `file/didChange`,
+//! `file/didDelete` and `file/didRename` notifications.
+//!
+//! Watches belong to the client that made them (`_clientId`, injected by the
+//! server): only that client can remove them, they go away when it
+//! disconnects, and their notifications are addressed to it alone. A watch
+//! path may be a glob (`src/**/*.rs`), which watches the directory before
+//! the first wildcard and keeps only matching paths. Watches on the same
+//! directory share one watcher registration.
+//!
+//! Watches use the native watcher where it can be trusted. Paths on network
+//! and shared-folder mounts, and paths the native watcher fails on, are
+//! polled instead (see [`crate::watch_poll`]); `watch/list` reports which
+//! backend carries each watch.
+//!
+//! In-process consumers (the file index) register an [`EventListener`]; the
+//! service then watches the whole workspace at init and hands them every event.
+//! inotify needs a watch per directory anyway, so there the workspace is
+//! watched one directory at a time, leaving out skipped and gitignored trees
+//! (`node_modules`, `target`), and directories are added as they appear and
+//! dropped when an ignore file change leaves them out.
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use ecp_protocol::{ECPError, HandlerResult};
-use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use ecp_protocol::{ECPError, HandlerResult, Notifications};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::Gitignore;
+use notify::event::{CreateKind, ModifyKind, RemoveKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher, WatcherKind};
 use parking_lot::RwLock;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
 
-use crate::Service;
+use crate::file_index::{root_ignore, skipped, walk_dirs};
+use crate::grep::{glob_set, matches_glob};
+use crate::watch_poll::{unreliable_filesystem, Baseline, Poller};
+use crate::{HeldResource, ResourceKind, Service};
+
+/// How long raw events are collected before clients are notified.
+const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// How often the polling backend rescans by default.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Which watcher backend carries a watch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchBackend {
+    /// Native, falling back to polling where it fails or can't be trusted
+    #[default]
+    Auto,
+    Native,
+    Poll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchSettings {
+    pub backend: WatchBackend,
+    pub poll_interval: Duration,
+}
+
+impl Default for WatchSettings {
+    fn default() -> Self {
+        Self { backend: WatchBackend::Auto, poll_interval: DEFAULT_POLL_INTERVAL }
+    }
+}
 
 /// Callback for emitting notifications to connected clients.
 pub type NotifySender = Arc<dyn Fn(&str, Value) + Send + Sync>;
 
+/// Callback receiving every raw filesystem event in the workspace.
+pub type EventListener = Arc<dyn Fn(&Event) + Send + Sync>;
+
 pub struct WatchService {
     workspace_root: PathBuf,
-    watcher: RwLock<Option<RecommendedWatcher>>,
-    watched_paths: RwLock<HashMap<String, WatchEntry>>,
+    /// Native watcher; `None` until started, or if it couldn't be created
+    watcher: Arc<RwLock<Option<RecommendedWatcher>>>,
+    /// Polling fallback, created on first use
+    poller: RwLock<Option<Poller>>,
+    settings: RwLock<WatchSettings>,
+    watched_paths: Arc<RwLock<HashMap<String, WatchEntry>>>,
+    /// Watcher registrations, shared by the watches on each directory
+    registrations: RwLock<HashMap<PathBuf, Registration>>,
     notify_tx: RwLock<Option<NotifySender>>,
     event_tx: RwLock<Option<mpsc::UnboundedSender<Event>>>,
+    listeners: Arc<RwLock<Vec<EventListener>>>,
+    /// Whether the whole workspace is watched on behalf of listeners
+    root_watched: AtomicBool,
+    /// Whether that is done one directory at a time, so that new
+    /// directories need watches of their own
+    per_directory: Arc<AtomicBool>,
+    /// Directories the per-directory root watch holds
+    root_dirs: Arc<RwLock<HashSet<PathBuf>>>,
+    debounce: RwLock<Duration>,
+    /// Workspace ignore rules, rebuilt when an ignore file changes
+    ignore: Arc<RwLock<Option<Gitignore>>>,
+}
+
+/// One directory registered with a watcher backend.
+struct Registration {
+    /// Watches relying on it
+    count: usize,
+    recursive: bool,
+    /// `Native` or `Poll`
+    backend: WatchBackend,
 }
 
 struct WatchEntry {
+    /// Directory watched; for a glob watch, the literal part of the pattern
     path: PathBuf,
+    /// Glob part of a glob watch, matched against paths relative to `path`
+    pattern: Option<(String, GlobSet)>,
     recursive: bool,
+    /// Exclude globs as given, and compiled
+    exclude_globs: Vec<String>,
+    excludes: Option<GlobSet>,
+    /// Drop gitignored paths (and `.git`, `node_modules`, `.ultra`)
+    respect_gitignore: bool,
+    /// Deliver changes as `file/didChangeBatch`
+    batch: bool,
+    /// Client that owns the watch; `None` for in-process watches, whose
+    /// events go to every client
+    client_id: Option<String>,
+    /// Identical `file/watch` requests from the owner sharing this entry
+    refs: usize,
+}
+
+impl WatchEntry {
+    fn covers(&self, path: &Path) -> bool {
+        let inside = if self.recursive {
+            path.starts_with(&self.path)
+        } else {
+            path == self.path || path.parent() == Some(&self.path)
+        };
+        inside && self.pattern.as_ref().is_none_or(|(_, set)| {
+            path.strip_prefix(&self.path).is_ok_and(|relative| set.is_match(relative))
+        })
+    }
+
+    /// Whether the watch needs events from inside the directory `dir`, even
+    /// once `dir` is ignored.
+    fn relies_on(&self, dir: &Path) -> bool {
+        self.path.starts_with(dir) || (!self.respect_gitignore && self.covers(dir))
+    }
+
+    /// Whether a new watch request asks for exactly this watch.
+    fn same_as(&self, other: &WatchEntry) -> bool {
+        self.client_id == other.client_id
+            && self.path == other.path
+            && self.pattern.as_ref().map(|(p, _)| p) == other.pattern.as_ref().map(|(p, _)| p)
+            && self.recursive == other.recursive
+            && self.exclude_globs == other.exclude_globs
+            && self.respect_gitignore == other.respect_gitignore
+            && self.batch == other.batch
+    }
+
+    /// How this watch sees one change, in `file/didChangeBatch` form. A
+    /// rename only half inside what it sees is a create or delete.
+    fn view(&self, path: &Path, change: &Change, filter: &Filter) -> Option<Value> {
+        let uri = |path: &Path| format!("file://{}", path.display());
+        let item = match change {
+            Change::Renamed(from) => match (self.accepts(from, filter), self.accepts(path, filter)) {
+                (true, true) => json!({ "type": "renamed", "uri": uri(path), "oldUri": uri(from) }),
+                (false, true) => json!({ "type": "created", "uri": uri(path) }),
+                (true, false) => json!({ "type": "deleted", "uri": uri(from) }),
+                (false, false) => return None,
+            },
+            _ if !self.accepts(path, filter) => return None,
+            Change::Created => json!({ "type": "created", "uri": uri(path) }),
+            Change::Changed => json!({ "type": "changed", "uri": uri(path) }),
+            Change::Deleted => json!({ "type": "deleted", "uri": uri(path) }),
+        };
+        Some(item)
+    }
+
+    /// Covered and not filtered out.
+    fn accepts(&self, path: &Path, filter: &Filter) -> bool {
+        if !self.covers(path) {
+            return false;
+        }
+        if self.excludes.as_ref().is_some_and(|set| matches_glob(set, &self.path, path)) {
+            return false;
+        }
+        if self.respect_gitignore {
+            let below = path.strip_prefix(&self.path).unwrap_or(path);
+            if skipped(&below.to_string_lossy()) || filter.is_ignored(path) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Workspace ignore rules as seen by one flush.
+struct Filter<'a> {
+    root: &'a Path,
+    ignore: &'a Gitignore,
+}
+
+impl Filter<'_> {
+    fn is_ignored(&self, path: &Path) -> bool {
+        match path.strip_prefix(self.root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => {
+                self.ignore.matched_path_or_any_parents(relative, false).is_ignore()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Net effect on one path over a debounce window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Change {
+    Created,
+    Changed,
+    Deleted,
+    /// Moved here from the given path
+    Renamed(PathBuf),
+}
+
+/// Raw events collected during one debounce window, coalesced per path in
+/// first-seen order.
+#[derive(Default)]
+struct Pending {
+    changes: HashMap<PathBuf, Change>,
+    order: Vec<PathBuf>,
+    /// `Name(From)` halves of renames waiting for their `Name(To)`, by cookie
+    rename_from: HashMap<usize, PathBuf>,
+}
+
+impl Pending {
+    fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.rename_from.is_empty()
+    }
+
+    fn add(&mut self, event: &Event) {
+        match event.kind {
+            EventKind::Create(_) => {
+                for path in &event.paths {
+                    self.record(path, Change::Created);
+                }
+            }
+            EventKind::Remove(_) => {
+                for path in &event.paths {
+                    self.record(path, Change::Deleted);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                self.rename(&event.paths[0], &event.paths[1]);
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                for path in &event.paths {
+                    match event.attrs.tracker() {
+                        Some(cookie) => { self.rename_from.insert(cookie, path.clone()); }
+                        None => self.record(path, Change::Deleted),
+                    }
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                for path in &event.paths {
+                    match event.attrs.tracker().and_then(|cookie| self.rename_from.remove(&cookie)) {
+                        Some(from) => self.rename(&from, path),
+                        None => self.record(path, Change::Created),
+                    }
+                }
+            }
+            // Rename halves the backend can't pair: whether the path exists now decides
+            EventKind::Modify(ModifyKind::Name(_)) => {
+                for path in &event.paths {
+                    let change = if path.exists() { Change::Created } else { Change::Deleted };
+                    self.record(path, change);
+                }
+            }
+            EventKind::Modify(_) => {
+                for path in &event.paths {
+                    self.record(path, Change::Changed);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn record(&mut self, path: &Path, change: Change) {
+        let merged = match (self.changes.get(path), change) {
+            (None, change) => Some(change),
+            (Some(Change::Created), Change::Deleted) => None,
+            (Some(Change::Created), _) => Some(Change::Created),
+            (Some(Change::Deleted), Change::Created | Change::Changed) => Some(Change::Changed),
+            // Moved here and then deleted: the original is what's gone
+            (Some(Change::Renamed(from)), Change::Deleted) => {
+                let from = from.clone();
+                self.take(path);
+                self.record(&from, Change::Deleted);
+                return;
+            }
+            (Some(Change::Renamed(from)), _) => Some(Change::Renamed(from.clone())),
+            (Some(_), change) => Some(change),
+        };
+        match merged {
+            Some(change) => self.put(path, change),
+            None => { self.take(path); }
+        }
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) {
+        let change = match self.take(from) {
+            // A file created this window (e.g. an atomic save's temp file) is
+            // simply created at its final name
+            Some(Change::Created) => Change::Created,
+            Some(Change::Renamed(original)) if original == to => Change::Changed,
+            Some(Change::Renamed(original)) => Change::Renamed(original),
+            _ => Change::Renamed(from.to_path_buf()),
+        };
+        self.put(to, change);
+    }
+
+    fn put(&mut self, path: &Path, change: Change) {
+        if self.changes.insert(path.to_path_buf(), change).is_none() {
+            self.order.push(path.to_path_buf());
+        }
+    }
+
+    fn take(&mut self, path: &Path) -> Option<Change> {
+        let change = self.changes.remove(path)?;
+        self.order.retain(|p| p != path);
+        Some(change)
+    }
+
+    /// Changes in first-seen order; unpaired rename halves count as deletes.
+    fn drain(&mut self) -> Vec<(PathBuf, Change)> {
+        for (_, from) in std::mem::take(&mut self.rename_from) {
+            self.record(&from, Change::Deleted);
+        }
+        let mut changes = std::mem::take(&mut self.changes);
+        std::mem::take(&mut self.order)
+            .into_iter()
+            .filter_map(|path| changes.remove(&path).map(|change| (path, change)))
+            .collect()
+    }
+}
+
+/// Notify clients of one window's changes. Each watch sees them through its
+/// own filters, and a client only hears about what its watches see.
+fn flush(
+    pending: &mut Pending,
+    watches: &HashMap<String, WatchEntry>,
+    filter: &Filter,
+    tx: &NotifySender,
+) {
+    let timestamp = now_ms();
+    // Batched changes per owner; `None` collects those of ownerless watches
+    let mut batches: HashMap<Option<&str>, Vec<Value>> = HashMap::new();
+    let mut order: Vec<Option<&str>> = Vec::new();
+
+    for (path, change) in pending.drain() {
+        // Distinct views of this change, each with whoever should get it
+        let mut views: Vec<(Value, bool, Vec<Option<&str>>)> = Vec::new();
+        for watch in watches.values() {
+            let Some(item) = watch.view(&path, &change, filter) else { continue };
+            let owner = watch.client_id.as_deref();
+            match views.iter_mut().find(|(v, batch, _)| *v == item && *batch == watch.batch) {
+                Some((_, _, owners)) if !owners.contains(&owner) => owners.push(owner),
+                Some(_) => {}
+                None => views.push((item, watch.batch, vec![owner])),
+            }
+        }
+
+        for (item, batch, owners) in views {
+            if !batch {
+                notify_one(tx, &item, timestamp, &owners);
+                continue;
+            }
+            for owner in owners {
+                if !batches.contains_key(&owner) {
+                    order.push(owner);
+                }
+                batches.entry(owner).or_default().push(item.clone());
+            }
+        }
+    }
+
+    for owner in order {
+        let changes = batches.remove(&owner).unwrap_or_default();
+        let params = json!({ "changes": changes, "timestamp": timestamp });
+        tx(Notifications::FILE_DID_CHANGE_BATCH, address(params, &[owner]));
+    }
+}
+
+/// Send one change as its own notification.
+fn notify_one(tx: &NotifySender, item: &Value, timestamp: u64, owners: &[Option<&str>]) {
+    let uri = item["uri"].clone();
+    // Match TypeScript ECP: didCreate/didDelete send { uri },
+    // didChange sends full event { uri, type, timestamp }
+    let (method, params) = match item["type"].as_str() {
+        Some("created") => (Notifications::FILE_DID_CREATE, json!({ "uri": uri })),
+        Some("deleted") => (Notifications::FILE_DID_DELETE, json!({ "uri": uri })),
+        Some("renamed") => (Notifications::FILE_DID_RENAME, json!({
+            "oldUri": item["oldUri"],
+            "newUri": uri,
+            "timestamp": timestamp,
+        })),
+        _ => (Notifications::FILE_DID_CHANGE, json!({
+            "uri": uri,
+            "type": "changed",
+            "timestamp": timestamp,
+        })),
+    };
+    tx(method, address(params, owners));
+}
+
+/// Whether the per-directory root watch leaves `path` out: it is in a
+/// skipped directory, or it or a parent is ignored.
+fn unwatched_dir(root: &Path, ignore: &Gitignore, path: &Path) -> bool {
+    match path.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => {
+            skipped(&relative.to_string_lossy())
+                || ignore.matched_path_or_any_parents(relative, true).is_ignore()
+        }
+        _ => false,
+    }
+}
+
+/// Whether `event` touches an ignore file's content. Reads don't count: the
+/// watcher's own reread of the rules would otherwise set it off again.
+fn changes_ignore_rules(event: &Event) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event.paths.iter().any(|p| p.file_name().is_some_and(|n| n == ".gitignore" || n == ".ignore"))
+}
+
+/// Watch directories created or moved into the workspace, and what they
+/// already hold, on the blocking pool. A changed ignore file re-walks the
+/// whole workspace (see [`rewatch_root`]).
+fn watch_new_dirs(
+    event: &Event,
+    watcher: &Arc<RwLock<Option<RecommendedWatcher>>>,
+    root: &Path,
+    ignore: &Arc<RwLock<Option<Gitignore>>>,
+    root_dirs: &Arc<RwLock<HashSet<PathBuf>>>,
+    watches: &Arc<RwLock<HashMap<String, WatchEntry>>>,
+) {
+    let rules_changed = changes_ignore_rules(event);
+    let candidates: Vec<PathBuf> = match event.kind {
+        _ if rules_changed => Vec::new(),
+        EventKind::Remove(RemoveKind::Folder) => {
+            root_dirs.write().retain(|dir| !event.paths.iter().any(|p| dir.starts_with(p)));
+            return;
+        }
+        EventKind::Create(CreateKind::Folder) => {
+            event.paths.iter().filter(|p| p.starts_with(root)).cloned().collect()
+        }
+        // Only a stat tells whether a renamed (or vaguely created) path is a directory
+        EventKind::Create(CreateKind::Any | CreateKind::Other) | EventKind::Modify(ModifyKind::Name(_)) => {
+            event.paths.iter().filter(|p| p.starts_with(root) && p.is_dir()).cloned().collect()
+        }
+        _ => return,
+    };
+    if !rules_changed && candidates.is_empty() {
+        return;
+    }
+    let (watcher, root, ignore) = (watcher.clone(), root.to_path_buf(), ignore.clone());
+    let (root_dirs, watches) = (root_dirs.clone(), watches.clone());
+    tokio::task::spawn_blocking(move || {
+        if rules_changed {
+            rewatch_root(&watcher, &root, &ignore, &root_dirs, &watches);
+            return;
+        }
+        for path in candidates {
+            let unwatched = {
+                let mut ignore = ignore.write();
+                let ignore = ignore.get_or_insert_with(|| root_ignore(&root));
+                unwatched_dir(&root, ignore, &path)
+            };
+            if unwatched {
+                continue;
+            }
+            let dirs: Vec<PathBuf> = walk_dirs(&path).collect();
+            if let Some(w) = watcher.write().as_mut() {
+                let mut root_dirs = root_dirs.write();
+                for dir in dirs {
+                    match w.watch(&dir, RecursiveMode::NonRecursive) {
+                        Ok(()) => { root_dirs.insert(dir); }
+                        Err(e) => debug!("Failed to watch {}: {e}", dir.display()),
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Bring the per-directory root watch in line with changed ignore rules:
+/// watch directories no longer ignored, and unwatch newly ignored ones
+/// unless a client watch still needs their events.
+fn rewatch_root(
+    watcher: &RwLock<Option<RecommendedWatcher>>,
+    root: &Path,
+    ignore: &RwLock<Option<Gitignore>>,
+    root_dirs: &RwLock<HashSet<PathBuf>>,
+    watches: &RwLock<HashMap<String, WatchEntry>>,
+) {
+    *ignore.write() = Some(root_ignore(root));
+    let wanted: HashSet<PathBuf> = walk_dirs(root).collect();
+    // Decided before taking the watcher, which `file/watch` takes under the watches
+    let dropped: Vec<PathBuf> = {
+        let watches = watches.read();
+        root_dirs.read().iter()
+            .filter(|dir| !wanted.contains(*dir) && !watches.values().any(|w| w.relies_on(dir)))
+            .cloned()
+            .collect()
+    };
+    let mut watcher = watcher.write();
+    let Some(w) = watcher.as_mut() else { return };
+    let mut root_dirs = root_dirs.write();
+    for dir in dropped {
+        let _ = w.unwatch(&dir);
+        root_dirs.remove(&dir);
+    }
+    for dir in wanted {
+        if root_dirs.contains(&dir) {
+            continue;
+        }
+        match w.watch(&dir, RecursiveMode::NonRecursive) {
+            Ok(()) => { root_dirs.insert(dir); }
+            Err(e) => debug!("Failed to watch {}: {e}", dir.display()),
+        }
+    }
+}
+
+/// Limit a notification to the owning clients (the `_clients` key, which
+/// the server strips). Any ownerless watch makes it a broadcast.
+fn address(mut params: Value, owners: &[Option<&str>]) -> Value {
+    if owners.iter().all(Option::is_some) {
+        params["_clients"] = json!(owners);
+    }
+    params
 }
 
 impl WatchService {
     pub fn new(workspace_root: PathBuf) -> Self {
         Self {
             workspace_root,
-            watcher: RwLock::new(None),
-            watched_paths: RwLock::new(HashMap::new()),
+            watcher: Arc::new(RwLock::new(None)),
+            poller: RwLock::new(None),
+            settings: RwLock::new(WatchSettings::default()),
+            watched_paths: Arc::new(RwLock::new(HashMap::new())),
+            registrations: RwLock::new(HashMap::new()),
             notify_tx: RwLock::new(None),
             event_tx: RwLock::new(None),
+            listeners: Arc::new(RwLock::new(Vec::new())),
+            root_watched: AtomicBool::new(false),
+            per_directory: Arc::new(AtomicBool::new(false)),
+            root_dirs: Arc::new(RwLock::new(HashSet::new())),
+            debounce: RwLock::new(DEFAULT_DEBOUNCE),
+            ignore: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// How long events are collected before clients hear of them. Takes
+    /// effect when the watcher starts.
+    pub fn set_debounce(&self, debounce: Duration) {
+        *self.debounce.write() = debounce;
+    }
+
+    /// Backend choice and polling interval. Takes effect when the watcher
+    /// starts.
+    pub fn set_settings(&self, settings: WatchSettings) {
+        *self.settings.write() = settings;
+    }
+
+    /// Whether the directory `path` is gitignored (or under a directory
+    /// never watched).
+    fn is_ignored(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.workspace_root).unwrap_or(path);
+        if skipped(&relative.to_string_lossy()) {
+            return true;
+        }
+        let mut ignore = self.ignore.write();
+        let ignore = ignore.get_or_insert_with(|| root_ignore(&self.workspace_root));
+        unwatched_dir(&self.workspace_root, ignore, path)
+    }
+
+    /// Receive every filesystem event in the workspace, whether or not a
+    /// client is watching. Takes effect at init.
+    pub fn add_event_listener(&self, listener: EventListener) {
+        self.listeners.write().push(listener);
+    }
+
+    /// Paths under the workspace are already covered by the root watch, so
+    /// client watches there need no watcher registration of their own (and
+    /// removing one must not drop the root's). Watched directory by
+    /// directory, the root leaves out skipped and ignored trees.
+    fn covered_by_root(&self, path: &Path) -> bool {
+        if !self.root_watched.load(Ordering::Relaxed) || !path.starts_with(&self.workspace_root) {
+            return false;
+        }
+        !self.per_directory.load(Ordering::Relaxed) || !self.is_ignored(path)
+    }
+
+    /// Watch the whole workspace for listeners: with inotify one directory
+    /// at a time, otherwise recursively from the root.
+    async fn watch_root(&self) -> Result<(), ECPError> {
+        let root = self.workspace_root.clone();
+        let settings = *self.settings.read();
+        let per_directory = RecommendedWatcher::kind() == WatcherKind::Inotify
+            && settings.backend != WatchBackend::Poll
+            && !(settings.backend == WatchBackend::Auto && unreliable_filesystem(&root).is_some())
+            && self.watcher.read().is_some();
+        if !per_directory {
+            let baseline = self.baseline(&root, true).await;
+            return self.register(&root, true, baseline);
+        }
+
+        let walk_root = root.clone();
+        let dirs = tokio::task::spawn_blocking(move || walk_dirs(&walk_root).collect::<Vec<_>>())
+            .await
+            .map_err(|e| ECPError::server_error(format!("Failed to list {}: {e}", root.display())))?;
+        if let Some(w) = self.watcher.write().as_mut() {
+            w.watch(&root, RecursiveMode::NonRecursive)
+                .map_err(|e| ECPError::server_error(format!("Failed to watch {}: {e}", root.display())))?;
+            let mut root_dirs = self.root_dirs.write();
+            root_dirs.insert(root.clone());
+            for dir in dirs.iter().filter(|dir| **dir != root) {
+                match w.watch(dir, RecursiveMode::NonRecursive) {
+                    Ok(()) => { root_dirs.insert(dir.clone()); }
+                    Err(e) => debug!("Failed to watch {}: {e}", dir.display()),
+                }
+            }
+        }
+        self.registrations.write().insert(root, Registration { count: 1, recursive: true, backend: WatchBackend::Native });
+        self.per_directory.store(true, Ordering::Relaxed);
+        info!("Watching {} directories of the workspace", dirs.len());
+        Ok(())
+    }
+
+    /// Take the poller's first scan of `path` on the blocking pool, if a new
+    /// registration there could be polled, so it isn't taken under the locks.
+    async fn baseline(&self, path: &Path, recursive: bool) -> Option<Baseline> {
+        if self.covered_by_root(path) {
+            return None;
+        }
+        if self.registrations.read().get(path).is_some_and(|r| r.recursive || !recursive) {
+            return None;
+        }
+        let backend = self.settings.read().backend;
+        let no_watcher = self.watcher.read().is_none();
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let polled = match backend {
+                WatchBackend::Poll => true,
+                WatchBackend::Auto => no_watcher || unreliable_filesystem(&path).is_some(),
+                WatchBackend::Native => no_watcher,
+            };
+            polled.then(|| Baseline::scan(&path, recursive).ok()).flatten()
+        }).await.ok().flatten()
+    }
+
+    /// Register `path` with a watcher backend for one more watch, polling
+    /// from `baseline` if it ends up polled.
+    fn register(&self, path: &Path, recursive: bool, baseline: Option<Baseline>) -> Result<(), ECPError> {
+        if self.covered_by_root(path) {
+            return Ok(());
+        }
+        let mut registrations = self.registrations.write();
+        match registrations.get_mut(path) {
+            // A recursive watch widens an existing non-recursive registration
+            Some(registration) if recursive && !registration.recursive => {
+                let backend = self.attach(path, true, baseline)?;
+                if backend != registration.backend {
+                    self.detach(path, registration.backend);
+                }
+                registration.backend = backend;
+                registration.recursive = true;
+                registration.count += 1;
+            }
+            Some(registration) => registration.count += 1,
+            None => {
+                let backend = self.attach(path, recursive, baseline)?;
+                registrations.insert(path.to_path_buf(), Registration { count: 1, recursive, backend });
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop one watch's hold on `path`, unwatching it after the last.
+    fn unregister(&self, path: &Path) {
+        if self.covered_by_root(path) {
+            return;
+        }
+        let mut registrations = self.registrations.write();
+        let Some(registration) = registrations.get_mut(path) else { return };
+        registration.count -= 1;
+        if registration.count == 0 {
+            let backend = registration.backend;
+            registrations.remove(path);
+            self.detach(path, backend);
+        }
+    }
+
+    /// Watch `path` natively, or poll it where native watching is off,
+    /// fails, or can't be trusted on its filesystem. Returns the backend used.
+    fn attach(&self, path: &Path, recursive: bool, baseline: Option<Baseline>) -> Result<WatchBackend, ECPError> {
+        let settings = *self.settings.read();
+        let unreliable = match settings.backend {
+            WatchBackend::Auto => unreliable_filesystem(path),
+            _ => None,
+        };
+        if settings.backend != WatchBackend::Poll {
+            match (&unreliable, self.watcher.write().as_mut()) {
+                (Some(fs_type), _) => info!("Polling {}: native watching is unreliable on {fs_type}", path.display()),
+                (None, Some(w)) => {
+                    let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+                    match w.watch(path, mode) {
+                        Ok(()) => return Ok(WatchBackend::Native),
+                        Err(e) if settings.backend == WatchBackend::Native => {
+                            return Err(ECPError::server_error(format!("Failed to watch {}: {e}", path.display())));
+                        }
+                        Err(e) => warn!("Native watch of {} failed, polling instead: {e}", path.display()),
+                    }
+                }
+                // Start-up already failed in native-only mode
+                (None, None) => {}
+            }
+        }
+
+        let mut poller = self.poller.write();
+        let poller = match poller.as_ref() {
+            Some(poller) => poller,
+            None => {
+                let tx = self.event_tx.read().clone()
+                    .ok_or_else(|| ECPError::server_error("File watcher not started"))?;
+                poller.insert(Poller::new(settings.poll_interval, tx))
+            }
+        };
+        poller.watch(path, recursive, baseline)
+            .map_err(|e| ECPError::server_error(format!("Failed to watch {}: {e}", path.display())))?;
+        Ok(WatchBackend::Poll)
+    }
+
+    fn detach(&self, path: &Path, backend: WatchBackend) {
+        match backend {
+            WatchBackend::Poll => {
+                if let Some(ref poller) = *self.poller.read() {
+                    poller.unwatch(path);
+                }
+            }
+            _ => {
+                if let Some(ref mut w) = *self.watcher.write() {
+                    let _ = w.unwatch(path);
+                }
+            }
+        }
+    }
+
+    /// Backend carrying the watches on `path`.
+    fn backend_of(&self, path: &Path) -> Option<WatchBackend> {
+        let registrations = self.registrations.read();
+        let registered = if self.covered_by_root(path) { &self.workspace_root } else { path };
+        registrations.get(registered).map(|r| r.backend)
+    }
+
+    /// Remove one reference to a watch, and the watch with the last.
+    fn release(&self, watches: &mut HashMap<String, WatchEntry>, id: &str) {
+        let Some(entry) = watches.get_mut(id) else { return };
+        entry.refs -= 1;
+        if entry.refs == 0 {
+            let entry = watches.remove(id).expect("watch exists");
+            self.unregister(&entry.path);
+            debug!("Unwatched: {}", entry.path.display());
         }
     }
 
@@ -53,15 +790,18 @@ impl WatchService {
         // Strip file:// prefix if present (clients may send file:// URIs)
         let stripped = path.strip_prefix("file://").unwrap_or(path);
         let p = std::path::Path::new(stripped);
-        if p.is_absolute() {
+        let path = if p.is_absolute() {
             p.to_path_buf()
         } else {
             self.workspace_root.join(stripped)
-        }
+        };
+        // Drop `.` components so event paths (reported relative to the
+        // watched path) come out clean
+        path.components().collect()
     }
 
     fn start_watcher(&self) -> Result<(), ECPError> {
-        if self.watcher.read().is_some() {
+        if self.event_tx.read().is_some() {
             return Ok(());
         }
 
@@ -69,45 +809,91 @@ impl WatchService {
         *self.event_tx.write() = Some(event_tx.clone());
 
         let notify_tx = self.notify_tx.read().clone();
-        // Spawn event processor
+        let watched_paths = self.watched_paths.clone();
+        let listeners = self.listeners.clone();
+        let watcher = self.watcher.clone();
+        let per_directory = self.per_directory.clone();
+        let root_dirs = self.root_dirs.clone();
+        let ignore = self.ignore.clone();
+        let root = self.workspace_root.clone();
+        let debounce = *self.debounce.read();
+        // Spawn event processor: listeners get raw events at once, clients
+        // get each window's coalesced changes when it closes
         tokio::spawn(async move {
-            while let Some(event) = event_rx.recv().await {
-                if let Some(ref tx) = notify_tx {
-                    for path in &event.paths {
-                        let uri = format!("file://{}", path.display());
-                        // Match TypeScript ECP: didCreate/didDelete send { uri },
-                        // didChange sends full event { uri, type, timestamp }
-                        match event.kind {
-                            EventKind::Create(_) => {
-                                tx("file/didCreate", json!({ "uri": uri }));
-                            }
-                            EventKind::Remove(_) => {
-                                tx("file/didDelete", json!({ "uri": uri }));
-                            }
-                            EventKind::Modify(_) => {
-                                tx("file/didChange", json!({
-                                    "uri": uri,
-                                    "type": "changed",
-                                    "timestamp": now_ms(),
-                                }));
+            let mut pending = Pending::default();
+            let mut deadline: Option<tokio::time::Instant> = None;
+            loop {
+                let event = match deadline {
+                    Some(at) => tokio::select! {
+                        event = event_rx.recv() => event,
+                        _ = tokio::time::sleep_until(at) => {
+                            deadline = None;
+                            if let Some(ref tx) = notify_tx {
+                                // Rules dropped by an ignore file change are reread off the runtime
+                                if ignore.read().is_none() {
+                                    let root = root.clone();
+                                    let rules = tokio::task::spawn_blocking(move || root_ignore(&root)).await
+                                        .unwrap_or_else(|_| Gitignore::empty());
+                                    ignore.write().get_or_insert(rules);
+                                }
+                                let ignore = ignore.read();
+                                let Some(ignore) = ignore.as_ref() else { continue };
+                                let filter = Filter { root: &root, ignore };
+                                flush(&mut pending, &watched_paths.read(), &filter, tx);
                             }
-                            _ => continue,
-                        };
-                    }
+                            continue;
+                        }
+                    },
+                    None => event_rx.recv().await,
+                };
+                let Some(event) = event else { break };
+
+                for listener in listeners.read().iter() {
+                    listener(&event);
+                }
+                if per_directory.load(Ordering::Relaxed) {
+                    watch_new_dirs(&event, &watcher, &root, &ignore, &root_dirs, &watched_paths);
+                }
+                if notify_tx.is_none() {
+                    continue;
+                }
+                if changes_ignore_rules(&event) {
+                    *ignore.write() = None;
+                }
+                if !event.paths.iter().any(|p| watched_paths.read().values().any(|w| w.covers(p))) {
+                    continue;
+                }
+                pending.add(&event);
+                if !pending.is_empty() {
+                    deadline.get_or_insert_with(|| tokio::time::Instant::now() + debounce);
                 }
             }
         });
 
+        let backend = self.settings.read().backend;
+        if backend == WatchBackend::Poll {
+            info!("File watcher started (polling)");
+            return Ok(());
+        }
         let tx_clone = event_tx;
         let watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
             match res {
                 Ok(event) => { let _ = tx_clone.send(event); }
                 Err(e) => warn!("File watcher error: {e}"),
             }
-        }).map_err(|e| ECPError::server_error(format!("Failed to create watcher: {e}")))?;
-
-        *self.watcher.write() = Some(watcher);
-        info!("File watcher started");
+        });
+        match watcher {
+            Ok(watcher) => {
+                *self.watcher.write() = Some(watcher);
+                info!("File watcher started");
+            }
+            Err(e) if backend == WatchBackend::Native => {
+                *self.event_tx.write() = None;
+                return Err(ECPError::server_error(format!("Failed to create watcher: {e}")));
+            }
+            // Every watch will be polled
+            Err(e) => warn!("Failed to create native file watcher, polling instead: {e}"),
+        }
         Ok(())
     }
 }
@@ -125,22 +911,42 @@ impl Service for WatchService {
         match method {
             "watch/start" | "file/watch" => {
                 let p: WatchParams = parse_params(params)?;
-                let path = self.resolve_path(&p.path);
-                let recursive = p.recursive.unwrap_or(true);
+                let (path, pattern, recursive) = match split_glob(&p.path) {
+                    // Only a pattern that crosses directories needs a recursive watch
+                    Some((base, glob)) => {
+                        let recursive = glob.contains('/') || glob.contains("**");
+                        let set = pattern_set(&glob)?;
+                        (self.resolve_path(&base), Some((glob, set)), recursive)
+                    }
+                    None => (self.resolve_path(&p.path), None, p.recursive.unwrap_or(true)),
+                };
+                let excludes = glob_set(&p.excludes)?;
+                // Watching an ignored directory on purpose: don't filter it away
+                let respect_gitignore = p.respect_gitignore && !self.is_ignored(&path);
+                let entry = WatchEntry {
+                    path: path.clone(),
+                    pattern,
+                    recursive,
+                    exclude_globs: p.excludes,
+                    excludes,
+                    respect_gitignore,
+                    batch: p.batch,
+                    client_id: p.client_id,
+                    refs: 1,
+                };
 
                 self.start_watcher()?;
+                let baseline = self.baseline(&path, recursive).await;
 
-                let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
-                if let Some(ref mut w) = *self.watcher.write() {
-                    w.watch(&path, mode)
-                        .map_err(|e| ECPError::server_error(format!("Failed to watch {}: {e}", path.display())))?;
+                let mut watches = self.watched_paths.write();
+                // The same client asking for the same watch again shares it
+                if let Some((id, existing)) = watches.iter_mut().find(|(_, w)| w.same_as(&entry)) {
+                    existing.refs += 1;
+                    return Ok(json!({ "watchId": id, "path": path.to_string_lossy() }));
                 }
-
-                let id = format!("w-{}", now_ms());
-                self.watched_paths.write().insert(id.clone(), WatchEntry {
-                    path: path.clone(),
-                    recursive,
-                });
+                self.register(&path, recursive, baseline)?;
+                let id = format!("w-{}", uuid::Uuid::new_v4());
+                watches.insert(id.clone(), entry);
 
                 debug!("Watching: {}", path.display());
                 Ok(json!({ "watchId": id, "path": path.to_string_lossy() }))
@@ -148,16 +954,17 @@ impl Service for WatchService {
 
             "watch/stop" | "file/unwatch" => {
                 let p: UnwatchParams = parse_params(params)?;
-                let mut paths = self.watched_paths.write();
+                let mut watches = self.watched_paths.write();
 
-                if let Some(entry) = paths.remove(&p.watch_id) {
-                    if let Some(ref mut w) = *self.watcher.write() {
-                        let _ = w.unwatch(&entry.path);
+                match watches.get(&p.watch_id) {
+                    None => Ok(json!({ "success": false, "error": "Watch ID not found" })),
+                    Some(entry) if entry.client_id.is_some() && entry.client_id != p.client_id => {
+                        Ok(json!({ "success": false, "error": "Watch is owned by another client" }))
+                    }
+                    Some(_) => {
+                        self.release(&mut watches, &p.watch_id);
+                        Ok(json!({ "success": true }))
                     }
-                    debug!("Unwatched: {}", entry.path.display());
-                    Ok(json!({ "success": true }))
-                } else {
-                    Ok(json!({ "success": false, "error": "Watch ID not found" }))
                 }
             }
 
@@ -167,7 +974,14 @@ impl Service for WatchService {
                     json!({
                         "watchId": id,
                         "path": entry.path.to_string_lossy(),
+                        "pattern": entry.pattern.as_ref().map(|(glob, _)| glob),
                         "recursive": entry.recursive,
+                        "excludes": entry.exclude_globs,
+                        "respectGitignore": entry.respect_gitignore,
+                        "batch": entry.batch,
+                        "clientId": entry.client_id,
+                        "backend": self.backend_of(&entry.path),
+                        "refs": entry.refs,
                     })
                 }).collect();
                 Ok(json!({ "watches": watches }))
@@ -177,10 +991,52 @@ impl Service for WatchService {
         }
     }
 
+    async fn init(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.listeners.read().is_empty() {
+            return Ok(());
+        }
+        self.start_watcher()?;
+        match self.watch_root().await {
+            Ok(()) => self.root_watched.store(true, Ordering::Relaxed),
+            Err(e) => warn!("Failed to watch workspace {}: {}", self.workspace_root.display(), e.message),
+        }
+        Ok(())
+    }
+
+    fn client_disconnected(&self, client_id: &str) {
+        let mut watches = self.watched_paths.write();
+        let owned: Vec<String> = watches.iter()
+            .filter(|(_, w)| w.client_id.as_deref() == Some(client_id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in owned {
+            if let Some(entry) = watches.remove(&id) {
+                self.unregister(&entry.path);
+            }
+        }
+        debug!("Dropped watches of disconnected client {client_id}");
+    }
+
+    async fn held_resources(&self, kind: ResourceKind) -> Vec<HeldResource> {
+        if kind != ResourceKind::Watch {
+            return Vec::new();
+        }
+        self.watched_paths.read().iter().map(|(id, entry)| HeldResource {
+            id: id.clone(),
+            owner: entry.client_id.clone(),
+        }).collect()
+    }
+
     async fn shutdown(&self) {
         // Drop the watcher to stop all watches
         *self.watcher.write() = None;
+        *self.poller.write() = None;
+        *self.event_tx.write() = None;
+        self.root_watched.store(false, Ordering::Relaxed);
+        self.per_directory.store(false, Ordering::Relaxed);
+        self.root_dirs.write().clear();
         self.watched_paths.write().clear();
+        self.registrations.write().clear();
         info!("File watcher stopped");
     }
 }
@@ -195,12 +1051,52 @@ struct WatchParams {
     #[serde(alias = "uri")]
     path: String,
     recursive: Option<bool>,
+    /// Globs matched against paths relative to the watched directory, or
+    /// bare file names
+    #[serde(default, alias = "exclude")]
+    excludes: Vec<String>,
+    #[serde(default = "default_true", rename = "respectGitignore")]
+    respect_gitignore: bool,
+    #[serde(default)]
+    batch: bool,
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Deserialize)]
 struct UnwatchParams {
     #[serde(rename = "watchId")]
     watch_id: String,
+    #[serde(default, rename = "_clientId")]
+    client_id: Option<String>,
+}
+
+/// Split a glob watch path (`src/**/*.rs`) into the directory before the
+/// first wildcard and the pattern below it.
+fn split_glob(path: &str) -> Option<(String, String)> {
+    let path = path.strip_prefix("file://").unwrap_or(path);
+    let is_glob = |c: &std::path::Component| c.as_os_str().to_string_lossy().contains(['*', '?', '[', '{']);
+    let components: Vec<_> = Path::new(path).components().collect();
+    let split = components.iter().position(is_glob)?;
+    let base: PathBuf = components[..split].iter().collect();
+    let glob: Vec<String> = components[split..].iter()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Some((base.to_string_lossy().to_string(), glob.join("/")))
+}
+
+/// A watch pattern; unlike search globs, `*` stays within one directory.
+fn pattern_set(glob: &str) -> Result<GlobSet, ECPError> {
+    let pattern = GlobBuilder::new(glob)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| ECPError::invalid_params(format!("Invalid glob {glob}: {e}")))?;
+    GlobSetBuilder::new().add(pattern).build()
+        .map_err(|e| ECPError::invalid_params(format!("Invalid glob {glob}: {e}")))
 }
 
 fn parse_params<T: for<'de> Deserialize<'de>>(params: Option<Value>) -> Result<T, ECPError> {
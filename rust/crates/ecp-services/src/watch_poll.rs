@@ -0,0 +1,293 @@
+//! Polling fallback for [`WatchService`](crate::watch::WatchService).
+//!
+//! Native watchers (inotify, FSEvents) miss changes made on the far side of
+//! network and shared-folder mounts: NFS, SMB, SSHFS, 9p and the FUSE mounts
+//! Docker Desktop and WSL use. [`Poller`] rescans its directories on an
+//! interval instead, compares each entry's mtime, size and inode with the
+//! previous scan and reports the differences as ordinary `notify` events, so
+//! everything downstream of the watcher works unchanged. An inode that moves
+//! to a new path is reported as a rename.
+//!
+//! Each scan waits one extra interval per [`DIRS_PER_INTERVAL`] directories it
+//! covered (up to [`MAX_SCALE`] intervals), so a large tree on a slow mount
+//! isn't rescanned back to back.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+
+use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
+use notify::{Event, EventKind};
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::file_index::skipped;
+
+/// Directories scanned per extra interval of waiting.
+const DIRS_PER_INTERVAL: usize = 1000;
+
+/// Longest wait between scans, in intervals.
+const MAX_SCALE: u32 = 10;
+
+/// Filesystems whose changes native watchers don't reliably see, by the
+/// type `/proc/self/mounts` reports.
+const UNRELIABLE_FILESYSTEMS: &[&str] = &[
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "9p", "drvfs", "virtiofs", "vboxsf", "prl_fs",
+    "vmhgfs", "fuse.sshfs", "fuse.grpcfuse", "fuse.osxfs", "fuse.rclone", "fuse.vmhgfs-fuse",
+];
+
+/// Whether native watchers are known to miss changes on `fs_type`.
+pub fn is_unreliable(fs_type: &str) -> bool {
+    UNRELIABLE_FILESYSTEMS.contains(&fs_type)
+}
+
+/// Type of the filesystem holding `path` (`nfs4`, `fuse.sshfs`, ...), where
+/// the platform tells us.
+pub fn filesystem_type(path: &Path) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        // The path may not exist yet; its nearest existing ancestor decides
+        let path = path.ancestors().find_map(|p| p.canonicalize().ok())?;
+        let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+        mounts.lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let mount_point = PathBuf::from(unescape_mount(fields.nth(1)?));
+                let fs_type = fields.next()?;
+                path.starts_with(&mount_point).then(|| (mount_point, fs_type.to_string()))
+            })
+            // Later mounts over the same point shadow earlier ones
+            .fold(None, |best: Option<(PathBuf, String)>, (mount_point, fs_type)| match best {
+                Some((ref best_point, _)) if best_point.as_os_str().len() > mount_point.as_os_str().len() => best,
+                _ => Some((mount_point, fs_type)),
+            })
+            .map(|(_, fs_type)| fs_type)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// The filesystem type of `path`, if native watching can't be trusted there.
+pub fn unreliable_filesystem(path: &Path) -> Option<String> {
+    filesystem_type(path).filter(|fs_type| is_unreliable(fs_type))
+}
+
+/// Undo the octal escapes (`\040` for a space) in `/proc/self/mounts`.
+#[cfg(target_os = "linux")]
+fn unescape_mount(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+        match octal {
+            Some(byte) => { out.push(byte); i += 4; }
+            None => { out.push(bytes[i]); i += 1; }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// What a scan remembers about one entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    size: u64,
+    /// 0 where the platform has no inode numbers
+    inode: u64,
+    is_dir: bool,
+}
+
+impl Stamp {
+    fn of(meta: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(meta);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Self { modified: meta.modified().ok(), size: meta.len(), inode, is_dir: meta.is_dir() }
+    }
+}
+
+type Snapshot = HashMap<PathBuf, Stamp>;
+
+struct Root {
+    recursive: bool,
+    /// `None` until the polling thread takes the first scan
+    snapshot: Option<Snapshot>,
+}
+
+/// The first scan of a directory about to be polled, taken up front (on the
+/// blocking pool) so [`Poller::watch`] has no filesystem walk to do.
+pub struct Baseline {
+    recursive: bool,
+    snapshot: Snapshot,
+}
+
+impl Baseline {
+    pub fn scan(path: &Path, recursive: bool) -> std::io::Result<Self> {
+        std::fs::metadata(path)?;
+        let mut snapshot = Snapshot::new();
+        scan(path, recursive, &mut snapshot);
+        Ok(Self { recursive, snapshot })
+    }
+}
+
+/// Scanned directories, shared with the polling thread.
+struct State {
+    roots: HashMap<PathBuf, Root>,
+    /// Directories covered by the last scan
+    dirs: usize,
+}
+
+/// Polls directories for changes and feeds them to the watch pipeline.
+/// Polling stops when it is dropped.
+pub struct Poller {
+    state: Arc<Mutex<State>>,
+}
+
+impl Poller {
+    pub fn new(interval: Duration, tx: mpsc::UnboundedSender<Event>) -> Self {
+        let state = Arc::new(Mutex::new(State { roots: HashMap::new(), dirs: 0 }));
+        let weak = Arc::downgrade(&state);
+        let interval = interval.max(Duration::from_millis(10));
+        let spawned = std::thread::Builder::new()
+            .name("ecp-watch-poll".into())
+            .spawn(move || poll_loop(weak, interval, tx));
+        if let Err(e) = spawned {
+            warn!("Failed to start watch poller: {e}");
+        }
+        Self { state }
+    }
+
+    /// Start polling `path`, or change how deep it is polled. Changes are
+    /// reported from the `baseline` scan on; without one, from the polling
+    /// thread's first scan.
+    pub fn watch(&self, path: &Path, recursive: bool, baseline: Option<Baseline>) -> std::io::Result<()> {
+        std::fs::metadata(path)?;
+        let snapshot = baseline.filter(|b| b.recursive == recursive).map(|b| b.snapshot);
+        self.state.lock().roots.insert(path.to_path_buf(), Root { recursive, snapshot });
+        Ok(())
+    }
+
+    pub fn unwatch(&self, path: &Path) {
+        self.state.lock().roots.remove(path);
+    }
+}
+
+fn poll_loop(state: Weak<Mutex<State>>, interval: Duration, tx: mpsc::UnboundedSender<Event>) {
+    loop {
+        let dirs = match state.upgrade() {
+            Some(state) => state.lock().dirs,
+            None => return,
+        };
+        let scale = (1 + dirs / DIRS_PER_INTERVAL).min(MAX_SCALE as usize) as u32;
+        std::thread::sleep(interval * scale);
+
+        let Some(state) = state.upgrade() else { return };
+        // Scan without the lock so watch calls aren't held up by a slow mount
+        let roots: Vec<(PathBuf, bool)> = state.lock().roots.iter()
+            .map(|(path, root)| (path.clone(), root.recursive))
+            .collect();
+        let mut dirs = 0;
+        for (path, recursive) in roots {
+            let mut snapshot = Snapshot::new();
+            dirs += scan(&path, recursive, &mut snapshot);
+            let mut state = state.lock();
+            // Unwatched (or rewatched) mid-scan
+            let Some(root) = state.roots.get_mut(&path).filter(|r| r.recursive == recursive) else { continue };
+            let previous = root.snapshot.replace(snapshot);
+            // A root watched without a baseline starts from this scan
+            let (Some(previous), Some(current)) = (previous, &root.snapshot) else { continue };
+            for event in diff(&previous, current) {
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+        state.lock().dirs = dirs;
+    }
+}
+
+/// Record the entries under `dir`, returning how many directories were read.
+fn scan(dir: &Path, recursive: bool, snapshot: &mut Snapshot) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else { return 0 };
+    let mut dirs = 1;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(meta) = std::fs::symlink_metadata(&path) else { continue };
+        let stamp = Stamp::of(&meta);
+        if stamp.is_dir && skipped(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        snapshot.insert(path.clone(), stamp);
+        if stamp.is_dir && recursive {
+            dirs += scan(&path, recursive, snapshot);
+        }
+    }
+    dirs
+}
+
+/// Events turning `old` into `new`.
+fn diff(old: &Snapshot, new: &Snapshot) -> Vec<Event> {
+    let mut removed: Vec<&PathBuf> = old.keys().filter(|p| !new.contains_key(*p)).collect();
+    let mut created: Vec<&PathBuf> = new.keys().filter(|p| !old.contains_key(*p)).collect();
+    removed.sort();
+    created.sort();
+
+    // An inode that left one path and appeared at another was renamed
+    let removed_by_inode: HashMap<(u64, bool), &PathBuf> = removed.iter()
+        .map(|p| (old[*p], *p))
+        .filter(|(stamp, _)| stamp.inode != 0)
+        .map(|(stamp, p)| ((stamp.inode, stamp.is_dir), p))
+        .collect();
+    let renames: HashMap<&PathBuf, &PathBuf> = created.iter()
+        .filter_map(|to| {
+            let stamp = new[*to];
+            (stamp.inode != 0).then_some(())?;
+            removed_by_inode.get(&(stamp.inode, stamp.is_dir)).map(|from| (*to, *from))
+        })
+        .collect();
+    let renamed_from: HashSet<&PathBuf> = renames.values().copied().collect();
+    let moves: HashSet<(&Path, &Path)> = renames.iter().map(|(to, from)| (from.as_path(), to.as_path())).collect();
+
+    let mut events = Vec::new();
+    for path in &removed {
+        if renamed_from.contains(path) {
+            continue;
+        }
+        let kind = if old[*path].is_dir { RemoveKind::Folder } else { RemoveKind::File };
+        events.push(Event::new(EventKind::Remove(kind)).add_path((*path).clone()));
+    }
+    for path in &created {
+        match renames.get(path) {
+            // Children of a renamed directory moved with it
+            Some(from) if from.parent().zip(path.parent()).is_some_and(|dirs| moves.contains(&dirs)) => {}
+            Some(from) => events.push(
+                Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                    .add_path((*from).clone())
+                    .add_path((*path).clone()),
+            ),
+            None => {
+                let kind = if new[*path].is_dir { CreateKind::Folder } else { CreateKind::File };
+                events.push(Event::new(EventKind::Create(kind)).add_path((*path).clone()));
+            }
+        }
+    }
+    for (path, stamp) in new {
+        // Directory mtimes only move because of the child changes above
+        if let Some(previous) = old.get(path)
+            && !stamp.is_dir
+            && previous != stamp
+        {
+            events.push(Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any))).add_path(path.clone()));
+        }
+    }
+    events
+}
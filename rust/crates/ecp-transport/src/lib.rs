@@ -6,11 +6,14 @@
 //! - Authentication handshake
 //! - Heartbeat / stale connection detection
 //! - Notification broadcasting to authenticated clients
+//! - Optional JSONL recording of all traffic (see [`recorder`])
 //!
 //! The transport is decoupled from the server logic via the `RequestHandler` trait.
 
 pub mod client;
+pub mod recorder;
 pub mod server;
 
 pub use client::ClientConnection;
-pub use server::{TransportServer, TransportConfig, TlsConfig, RequestHandler};
+pub use recorder::Recorder;
+pub use server::{TransportServer, TransportConfig, TlsConfig, LiveSettings, RequestHandler};
@@ -0,0 +1,151 @@
+//! Session recording for debugging and replay.
+//!
+//! When enabled, the transport appends one JSON object per line for every
+//! connection event, authenticated request, response and delivered
+//! notification. The first line is a header describing the server the
+//! recording was taken from. `ultra-ecp replay` reads the same format.
+//!
+//! Handshake messages are never recorded (they carry the auth token), but
+//! request params are written verbatim — treat recordings as sensitive.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+/// Recording format version, written in the header line.
+pub const RECORDING_VERSION: u32 = 1;
+
+/// Kind of a recorded line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordKind {
+    Header,
+    Connect,
+    Disconnect,
+    Request,
+    Response,
+    Notification,
+}
+
+/// A single line of a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedEvent {
+    /// Milliseconds since the Unix epoch
+    pub ts: u64,
+    pub kind: RecordKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Workspace the client was scoped to when the request arrived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>,
+    /// The JSON-RPC message (or header payload). Unparseable input is kept as a string.
+    #[serde(default)]
+    pub message: Value,
+}
+
+/// Appends recorded events to a JSONL file.
+pub struct Recorder {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    /// Create (or truncate) the recording file and write the header line.
+    pub fn create(path: &Path, header: Value) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = File::create(path)?;
+
+        // Recordings contain request params verbatim — owner-only access
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600));
+        }
+
+        let recorder = Self { writer: Mutex::new(BufWriter::new(file)) };
+        let mut header = header;
+        if let Value::Object(ref mut obj) = header {
+            obj.insert("version".into(), RECORDING_VERSION.into());
+        }
+        recorder.write(RecordKind::Header, None, None, header);
+        Ok(recorder)
+    }
+
+    pub fn record_connect(&self, client_id: &str) {
+        self.write(RecordKind::Connect, Some(client_id), None, Value::Null);
+    }
+
+    pub fn record_disconnect(&self, client_id: &str) {
+        self.write(RecordKind::Disconnect, Some(client_id), None, Value::Null);
+    }
+
+    pub fn record_request(&self, client_id: &str, workspace_id: Option<&str>, text: &str) {
+        self.write(RecordKind::Request, Some(client_id), workspace_id, parse_message(text));
+    }
+
+    pub fn record_response(&self, client_id: &str, text: &str) {
+        self.write(RecordKind::Response, Some(client_id), None, parse_message(text));
+    }
+
+    pub fn record_notification(&self, client_id: &str, text: &str) {
+        self.write(RecordKind::Notification, Some(client_id), None, parse_message(text));
+    }
+
+    fn write(&self, kind: RecordKind, client_id: Option<&str>, workspace_id: Option<&str>, message: Value) {
+        let event = RecordedEvent {
+            ts: now_ms(),
+            kind,
+            client_id: client_id.map(String::from),
+            workspace_id: workspace_id.map(String::from),
+            message,
+        };
+        let Ok(line) = serde_json::to_string(&event) else { return };
+
+        // Flush every line so a crash still leaves a usable recording
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(writer, "{line}").and_then(|_| writer.flush()) {
+            warn!("Failed to write recording: {e}");
+        }
+    }
+}
+
+/// Read every event from a recording file, skipping blank lines.
+pub fn read_recording(path: &Path) -> std::io::Result<Vec<RecordedEvent>> {
+    let content = std::fs::read_to_string(path)?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("line {}: {e}", i + 1))
+            })
+        })
+        .collect()
+}
+
+fn parse_message(text: &str) -> Value {
+    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
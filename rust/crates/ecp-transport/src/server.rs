@@ -4,6 +4,7 @@
 //! heartbeat pings, and message routing to the ECP server.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::Duration;
 
 use axum::{
@@ -17,10 +18,10 @@ use axum::{
     routing::get,
 };
 use ecp_protocol::{
-    ECPNotification, ECPResponse, ECPError, RequestContext,
+    AddressedNotification, ECPNotification, ECPResponse, ECPError, RequestContext,
     auth::{
         AuthConfig, AuthErrorCode, AuthRequiredParams,
-        HandshakeParams, HandshakeResult,
+        HandshakeClientInfo, HandshakeParams, HandshakeResult,
     },
     jsonrpc::RequestId,
 };
@@ -29,6 +30,8 @@ use serde_json::json;
 use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, info, warn, error};
 
+use crate::recorder::Recorder;
+
 /// Trait implemented by the ECP server to handle incoming requests.
 /// The transport layer calls this for every authenticated JSON-RPC request.
 pub trait RequestHandler: Send + Sync + 'static {
@@ -40,6 +43,10 @@ pub trait RequestHandler: Send + Sync + 'static {
         context: RequestContext,
     ) -> impl std::future::Future<Output = ecp_protocol::HandlerResult> + Send;
 
+    /// Called once a client is authenticated (or immediately when auth is
+    /// disabled), with the client info it sent in the handshake.
+    fn on_client_connected(&self, _client_id: &str, _client: Option<HandshakeClientInfo>) {}
+
     /// Called when a client disconnects (graceful or not).
     fn on_client_disconnected(
         &self,
@@ -49,10 +56,11 @@ pub trait RequestHandler: Send + Sync + 'static {
     }
 
     /// Get a per-workspace notification receiver (called after workspace/open).
+    /// Notifications addressed to other clients are skipped.
     fn workspace_notification_rx(
         &self,
         _workspace_id: &str,
-    ) -> Option<broadcast::Receiver<String>> {
+    ) -> Option<broadcast::Receiver<AddressedNotification>> {
         None
     }
 
@@ -92,6 +100,8 @@ pub struct TransportConfig {
     pub tls: Option<TlsConfig>,
     /// SHA-256 fingerprint of the TLS certificate
     pub cert_fingerprint: Option<String>,
+    /// Record all traffic to a JSONL file (`--record`)
+    pub recorder: Option<Arc<Recorder>>,
 }
 
 impl Default for TransportConfig {
@@ -106,14 +116,72 @@ impl Default for TransportConfig {
             verbose_logging: false,
             tls: None,
             cert_fingerprint: None,
+            recorder: None,
+        }
+    }
+}
+
+/// Connection settings that can be changed while the server is running.
+/// Seeded from [`TransportConfig`]; new values apply to new connections
+/// (timeouts) or to the next heartbeat tick (interval).
+#[derive(Debug)]
+pub struct LiveSettings {
+    handshake_timeout_ms: AtomicU64,
+    /// 0 disables heartbeats
+    heartbeat_interval_ms: AtomicU64,
+    /// 0 means unlimited
+    max_connections: AtomicUsize,
+}
+
+impl LiveSettings {
+    fn from_config(config: &TransportConfig) -> Self {
+        Self {
+            handshake_timeout_ms: AtomicU64::new(
+                config.auth.as_ref().map(|a| a.handshake_timeout_ms).unwrap_or(10_000),
+            ),
+            heartbeat_interval_ms: AtomicU64::new(
+                config.auth.as_ref().map(|a| a.heartbeat_interval_ms).unwrap_or(30_000),
+            ),
+            max_connections: AtomicUsize::new(config.max_connections.unwrap_or(0)),
+        }
+    }
+
+    pub fn handshake_timeout_ms(&self) -> u64 {
+        self.handshake_timeout_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn set_handshake_timeout_ms(&self, ms: u64) {
+        self.handshake_timeout_ms.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        match self.heartbeat_interval_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
         }
     }
+
+    pub fn set_heartbeat_interval_ms(&self, ms: u64) {
+        self.heartbeat_interval_ms.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn max_connections(&self) -> Option<usize> {
+        match self.max_connections.load(Ordering::Relaxed) {
+            0 => None,
+            max => Some(max),
+        }
+    }
+
+    pub fn set_max_connections(&self, max: Option<usize>) {
+        self.max_connections.store(max.unwrap_or(0), Ordering::Relaxed);
+    }
 }
 
 /// Shared state for the transport server.
 struct AppState<H: RequestHandler> {
     handler: Arc<H>,
     config: TransportConfig,
+    live: Arc<LiveSettings>,
     /// Broadcast channel for notifications (server → all clients)
     notification_tx: broadcast::Sender<String>,
     /// Connected client count (for health check)
@@ -132,6 +200,8 @@ pub struct TransportServer {
     port: u16,
     /// Whether TLS is enabled
     tls_enabled: bool,
+    /// Settings that can be changed without restarting
+    live: Arc<LiveSettings>,
 }
 
 impl TransportServer {
@@ -155,10 +225,12 @@ impl TransportServer {
         let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
 
         let client_count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let live = Arc::new(LiveSettings::from_config(&config));
 
         let state = Arc::new(AppState {
             handler,
             config: config.clone(),
+            live: live.clone(),
             notification_tx: notification_tx.clone(),
             client_count: client_count.clone(),
         });
@@ -234,6 +306,7 @@ impl TransportServer {
             handle: Some(handle),
             port: actual_port,
             tls_enabled,
+            live,
         })
     }
 
@@ -260,6 +333,11 @@ impl TransportServer {
         self.tls_enabled
     }
 
+    /// Handle for changing connection settings at runtime.
+    pub fn live_settings(&self) -> Arc<LiveSettings> {
+        self.live.clone()
+    }
+
     /// Gracefully stop the server.
     pub async fn stop(&mut self) {
         if let Some(tx) = self.shutdown_tx.take() {
@@ -281,7 +359,7 @@ async fn ws_upgrade_handler<H: RequestHandler>(
     State(state): State<Arc<AppState<H>>>,
 ) -> impl IntoResponse {
     // Check connection limit
-    if let Some(max) = state.config.max_connections {
+    if let Some(max) = state.live.max_connections() {
         let current = state.client_count.load(std::sync::atomic::Ordering::Relaxed);
         if current >= max {
             warn!("Connection rejected: max connections reached ({max})");
@@ -316,26 +394,27 @@ async fn handle_ws_connection<H: RequestHandler>(
     info!("Client connected: {client_id}");
 
     let (mut ws_tx, mut ws_rx) = socket.split();
+    let recorder = state.config.recorder.clone();
 
     // Subscribe to global broadcast notifications
     let mut notification_rx = state.notification_tx.subscribe();
 
     // Per-connection workspace state (set after workspace/open)
     let mut workspace_id: Option<String> = None;
-    let mut workspace_notification_rx: Option<broadcast::Receiver<String>> = None;
+    let mut workspace_notification_rx: Option<broadcast::Receiver<AddressedNotification>> = None;
 
     // Determine initial auth state
     let requires_auth = state.config.auth.is_some();
     let mut authenticated = !requires_auth;
 
     // Send auth/required or welcome
+    let timeout_ms = state.live.handshake_timeout_ms();
     if requires_auth {
-        let auth_config = state.config.auth.as_ref().unwrap();
         let auth_required = ECPNotification::new(
             "auth/required",
             Some(serde_json::to_value(AuthRequiredParams {
                 server_version: "0.1.0".into(),
-                timeout: auth_config.handshake_timeout_ms,
+                timeout: timeout_ms,
             }).unwrap()),
         );
         if let Err(e) = ws_tx.send(Message::Text(serde_json::to_string(&auth_required).unwrap().into())).await {
@@ -345,6 +424,10 @@ async fn handle_ws_connection<H: RequestHandler>(
         }
     } else {
         send_welcome(&mut ws_tx, &client_id, &state.config).await;
+        state.handler.on_client_connected(&client_id, None);
+        if let Some(ref rec) = recorder {
+            rec.record_connect(&client_id);
+        }
         // Auto-subscribe to default workspace when auth is disabled
         if let Some(default_ws_id) = state.handler.default_workspace_id() {
             workspace_id = Some(default_ws_id.clone());
@@ -353,15 +436,16 @@ async fn handle_ws_connection<H: RequestHandler>(
     }
 
     // Auth timeout — use a concrete sleep that we pin
-    let timeout_ms = state.config.auth.as_ref()
-        .map(|a| a.handshake_timeout_ms)
-        .unwrap_or(10_000);
     let auth_deadline = if requires_auth {
         Some(tokio::time::Instant::now() + Duration::from_millis(timeout_ms))
     } else {
         None
     };
 
+    // Heartbeat — ping on an interval, drop clients silent for two intervals
+    let mut last_ping = tokio::time::Instant::now();
+    let mut last_seen = tokio::time::Instant::now();
+
     loop {
         // Build the auth timeout future for this iteration
         let auth_sleep = async {
@@ -370,20 +454,32 @@ async fn handle_ws_connection<H: RequestHandler>(
                 None => std::future::pending::<()>().await,
             }
         };
+        let heartbeat_interval = state.live.heartbeat_interval();
+        let heartbeat_sleep = async {
+            match heartbeat_interval {
+                Some(interval) => tokio::time::sleep_until(last_ping + interval).await,
+                None => std::future::pending::<()>().await,
+            }
+        };
 
         tokio::select! {
             // Incoming WebSocket message
             msg = ws_rx.next() => {
+                last_seen = tokio::time::Instant::now();
                 match msg {
                     Some(Ok(Message::Text(text))) => {
                         if !authenticated {
                             // Try to handle as handshake
                             match handle_handshake(&text, &state.config, &client_id) {
-                                HandshakeOutcome::Authenticated(response) => {
+                                HandshakeOutcome::Authenticated(response, client_info) => {
                                     authenticated = true;
                                     let _ = ws_tx.send(Message::Text(response.into())).await;
                                     send_welcome(&mut ws_tx, &client_id, &state.config).await;
                                     debug!("Client authenticated: {client_id}");
+                                    state.handler.on_client_connected(&client_id, client_info);
+                                    if let Some(ref rec) = recorder {
+                                        rec.record_connect(&client_id);
+                                    }
 
                                     // Auto-subscribe to default workspace notifications
                                     // for clients that won't explicitly call workspace/open
@@ -410,9 +506,17 @@ async fn handle_ws_connection<H: RequestHandler>(
                             workspace_id: workspace_id.clone(),
                         };
 
+                        if let Some(ref rec) = recorder {
+                            rec.record_request(&client_id, workspace_id.as_deref(), &text);
+                        }
+
                         // Parse and route authenticated message
                         let response = handle_message(&text, &state.handler, context).await;
 
+                        if let Some(ref rec) = recorder {
+                            rec.record_response(&client_id, &response);
+                        }
+
                         // Check if this was a workspace/open success — update local state
                         if let Some((ws_id, ws_rx)) = extract_workspace_open_result(&response, &state.handler) {
                             workspace_id = Some(ws_id);
@@ -444,12 +548,15 @@ async fn handle_ws_connection<H: RequestHandler>(
 
             // Global broadcast notifications
             notification = notification_rx.recv() => {
-                if authenticated {
-                    if let Ok(msg) = notification {
-                        if let Err(e) = ws_tx.send(Message::Text(msg.into())).await {
-                            error!("Failed to broadcast to {client_id}: {e}");
-                            break;
-                        }
+                if authenticated
+                    && let Ok(msg) = notification
+                {
+                    if let Some(ref rec) = recorder {
+                        rec.record_notification(&client_id, &msg);
+                    }
+                    if let Err(e) = ws_tx.send(Message::Text(msg.into())).await {
+                        error!("Failed to broadcast to {client_id}: {e}");
+                        break;
                     }
                 }
             }
@@ -461,16 +568,34 @@ async fn handle_ws_connection<H: RequestHandler>(
                     None => std::future::pending().await,
                 }
             } => {
-                if authenticated {
-                    if let Ok(msg) = notification {
-                        if let Err(e) = ws_tx.send(Message::Text(msg.into())).await {
-                            error!("Failed to send workspace notification to {client_id}: {e}");
-                            break;
-                        }
+                if authenticated
+                    && let Ok(msg) = notification
+                    && msg.is_for(&client_id)
+                {
+                    if let Some(ref rec) = recorder {
+                        rec.record_notification(&client_id, &msg.json);
+                    }
+                    if let Err(e) = ws_tx.send(Message::Text(msg.json.into())).await {
+                        error!("Failed to send workspace notification to {client_id}: {e}");
+                        break;
                     }
                 }
             }
 
+            // Heartbeat
+            _ = heartbeat_sleep, if authenticated => {
+                let interval = heartbeat_interval.unwrap_or_default();
+                if last_seen.elapsed() > interval * 2 {
+                    warn!("Client {client_id} missed heartbeats — disconnecting");
+                    break;
+                }
+                last_ping = tokio::time::Instant::now();
+                if let Err(e) = ws_tx.send(Message::Ping(Vec::new().into())).await {
+                    debug!("Failed to ping {client_id}: {e}");
+                    break;
+                }
+            }
+
             // Auth timeout
             _ = auth_sleep, if !authenticated => {
                 warn!("Auth timeout for client {client_id}");
@@ -489,6 +614,9 @@ async fn handle_ws_connection<H: RequestHandler>(
 
     // Notify the handler that this client disconnected
     state.handler.on_client_disconnected(&client_id).await;
+    if authenticated && let Some(ref rec) = recorder {
+        rec.record_disconnect(&client_id);
+    }
 
     state.client_count.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
     info!("Client disconnected: {client_id} (total: {})",
@@ -516,7 +644,8 @@ async fn send_welcome(
 }
 
 enum HandshakeOutcome {
-    Authenticated(String),
+    /// Handshake response and the client info the client identified itself with
+    Authenticated(String, Option<HandshakeClientInfo>),
     Rejected(String),
     NotHandshake(String),
 }
@@ -570,7 +699,8 @@ fn handle_handshake(
                 id.unwrap_or(RequestId::Number(0)),
                 serde_json::to_value(result).unwrap(),
             );
-            return HandshakeOutcome::Authenticated(serde_json::to_string(&resp).unwrap());
+            let client_info = params.and_then(|p| p.client);
+            return HandshakeOutcome::Authenticated(serde_json::to_string(&resp).unwrap(), client_info);
         }
     };
 
@@ -587,7 +717,7 @@ fn handle_handshake(
                 id.unwrap_or(RequestId::Number(0)),
                 serde_json::to_value(result).unwrap(),
             );
-            HandshakeOutcome::Authenticated(serde_json::to_string(&resp).unwrap())
+            HandshakeOutcome::Authenticated(serde_json::to_string(&resp).unwrap(), p.client)
         }
         _ => {
             let err = ECPResponse::error(
@@ -649,7 +779,7 @@ async fn handle_message<H: RequestHandler>(
 fn extract_workspace_open_result<H: RequestHandler>(
     response_json: &str,
     handler: &Arc<H>,
-) -> Option<(String, Option<broadcast::Receiver<String>>)> {
+) -> Option<(String, Option<broadcast::Receiver<AddressedNotification>>)> {
     let parsed: serde_json::Value = serde_json::from_str(response_json).ok()?;
     // Only check successful responses that have a workspaceId in the result
     let result = parsed.get("result")?;
@@ -660,10 +790,9 @@ fn extract_workspace_open_result<H: RequestHandler>(
 
 /// Check if the response is a successful workspace/close.
 fn is_workspace_close_success(response_json: &str) -> bool {
-    if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(response_json) {
-        if let Some(result) = parsed.get("result") {
-            return result.get("workspaceClosed").and_then(|v| v.as_bool()).unwrap_or(false);
-        }
+    if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(response_json)
+        && let Some(result) = parsed.get("result") {
+        return result.get("workspaceClosed").and_then(|v| v.as_bool()).unwrap_or(false);
     }
     false
 }
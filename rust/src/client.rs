@@ -0,0 +1,498 @@
+//! Headless CLI client — `ultra-ecp call|watch|workspaces|clients`.
+//!
+//! Connects to the running server described by `~/.ultra/server.json`.
+//! For `wss` servers the TLS certificate is pinned to the `certFingerprint`
+//! recorded there, so the self-signed certificate is trusted without
+//! disabling verification altogether.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::{
+    self,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+/// Client name sent in the handshake (shows up in `ultra-ecp clients`).
+const CLIENT_NAME: &str = "headless-cli";
+
+#[derive(Args, Debug)]
+pub struct CallArgs {
+    /// Method to call, e.g. `file/read`
+    method: String,
+
+    /// JSON params (`-` reads them from stdin)
+    params: Option<String>,
+
+    /// Open this workspace before calling (defaults to the server's default workspace)
+    #[arg(long)]
+    workspace: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Glob over notification methods, e.g. `file/*` or `terminal/output`
+    #[arg(default_value = "*")]
+    pattern: String,
+
+    /// Open this workspace to receive its notifications
+    #[arg(long)]
+    workspace: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    /// Print raw JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Subcommands
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// `ultra-ecp call <method> [params]` — prints the result as JSON.
+/// Exit code 1 for a JSON-RPC error, 2 if the call could not be made.
+pub async fn call(args: CallArgs) -> i32 {
+    let params = match args.params.as_deref() {
+        None => None,
+        Some(raw) => {
+            let raw = if raw == "-" {
+                let mut buf = String::new();
+                if let Err(e) = std::io::Read::read_to_string(&mut std::io::stdin(), &mut buf) {
+                    eprintln!("Failed to read params from stdin: {e}");
+                    return 2;
+                }
+                buf
+            } else {
+                raw.to_string()
+            };
+            match serde_json::from_str::<Value>(&raw) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    eprintln!("Invalid JSON params: {e}");
+                    return 2;
+                }
+            }
+        }
+    };
+
+    let mut conn = match connect_with_workspace(args.workspace.as_ref()).await {
+        Ok(conn) => conn,
+        Err(e) => return fail(&e),
+    };
+    match conn.call(&args.method, params).await {
+        Ok(Ok(result)) => {
+            println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+            0
+        }
+        Ok(Err(error)) => {
+            print_rpc_error(&error);
+            1
+        }
+        Err(e) => fail(&e),
+    }
+}
+
+/// `ultra-ecp watch [pattern]` — prints one JSON line per matching notification
+/// until the server goes away or the process is interrupted.
+pub async fn watch(args: WatchArgs) -> i32 {
+    let pattern = match glob::Pattern::new(&args.pattern) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Invalid pattern '{}': {e}", args.pattern);
+            return 2;
+        }
+    };
+    let mut conn = match connect_with_workspace(args.workspace.as_ref()).await {
+        Ok(conn) => conn,
+        Err(e) => return fail(&e),
+    };
+
+    loop {
+        tokio::select! {
+            notification = conn.next_notification() => match notification {
+                Ok(Some(n)) => {
+                    let method = n.get("method").and_then(|m| m.as_str()).unwrap_or("");
+                    if pattern.matches(method) {
+                        println!("{n}");
+                    }
+                }
+                Ok(None) => {
+                    eprintln!("Server closed the connection");
+                    return 0;
+                }
+                Err(e) => return fail(&e),
+            },
+            _ = tokio::signal::ctrl_c() => return 0,
+        }
+    }
+}
+
+/// `ultra-ecp workspaces` — open workspaces and the clients scoped to them.
+pub async fn workspaces(args: ListArgs) -> i32 {
+    let result = match simple_call("workspace/list").await {
+        Ok(result) => result,
+        Err(code) => return code,
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+        return 0;
+    }
+
+    let default_id = result["defaultWorkspaceId"].as_str();
+    let workspaces = result["workspaces"].as_array().cloned().unwrap_or_default();
+    if workspaces.is_empty() {
+        println!("No open workspaces");
+        return 0;
+    }
+    println!("{:<38} {:>7}  PATH", "WORKSPACE", "CLIENTS");
+    for ws in &workspaces {
+        let id = ws["workspaceId"].as_str().unwrap_or("");
+        let marker = if Some(id) == default_id { " (default)" } else { "" };
+        println!(
+            "{:<38} {:>7}  {}{marker}",
+            id,
+            ws["clients"].as_array().map(|c| c.len()).unwrap_or(0),
+            ws["path"].as_str().unwrap_or(""),
+        );
+    }
+    0
+}
+
+/// `ultra-ecp clients` — connected clients, including this one.
+pub async fn clients(args: ListArgs) -> i32 {
+    let result = match simple_call("server/clients").await {
+        Ok(result) => result,
+        Err(code) => return code,
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+        return 0;
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    println!("{:<38} {:<16} {:>9}  WORKSPACE", "CLIENT", "NAME", "CONNECTED");
+    for client in result["clients"].as_array().into_iter().flatten() {
+        let connected_at = client["connectedAt"].as_u64().unwrap_or(now);
+        println!(
+            "{:<38} {:<16} {:>9}  {}",
+            client["clientId"].as_str().unwrap_or(""),
+            client["name"].as_str().unwrap_or("-"),
+            format_age(now.saturating_sub(connected_at)),
+            client["workspaceId"].as_str().unwrap_or("-"),
+        );
+    }
+    0
+}
+
+async fn simple_call(method: &str) -> Result<Value, i32> {
+    let mut conn = Connection::connect(&ServerInfo::load().map_err(|e| fail(&e))?)
+        .await
+        .map_err(|e| fail(&e))?;
+    match conn.call(method, None).await {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(error)) => {
+            print_rpc_error(&error);
+            Err(1)
+        }
+        Err(e) => Err(fail(&e)),
+    }
+}
+
+async fn connect_with_workspace(workspace: Option<&PathBuf>) -> Result<Connection, String> {
+    let mut conn = Connection::connect(&ServerInfo::load()?).await?;
+    if let Some(path) = workspace {
+        let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if let Err(error) = conn.call("workspace/open", Some(json!({ "path": path }))).await? {
+            return Err(format!(
+                "workspace/open failed: {}",
+                error["message"].as_str().unwrap_or("unknown error"),
+            ));
+        }
+    }
+    Ok(conn)
+}
+
+fn fail(message: &str) -> i32 {
+    eprintln!("{message}");
+    2
+}
+
+fn print_rpc_error(error: &Value) {
+    eprintln!(
+        "Error {}: {}",
+        error["code"],
+        error["message"].as_str().unwrap_or("unknown error"),
+    );
+    if let Some(data) = error.get("data") {
+        eprintln!("{}", serde_json::to_string_pretty(data).unwrap_or_default());
+    }
+}
+
+pub fn format_age(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86_399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86_400),
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Server discovery
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Connection details from `~/.ultra/server.json`.
+pub struct ServerInfo {
+    pub host: String,
+    pub port: u16,
+    pub scheme: String,
+    pub token: String,
+    pub cert_fingerprint: Option<String>,
+    pub pid: Option<u32>,
+    /// The raw file, for fields only some commands need
+    pub raw: Value,
+}
+
+impl ServerInfo {
+    pub fn path() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".into());
+        PathBuf::from(home).join(".ultra/server.json")
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let path = Self::path();
+        let content = std::fs::read_to_string(&path).map_err(|_| {
+            format!("No running server found ({} is missing). Start one with `ultra-ecp`.", path.display())
+        })?;
+        let json: Value = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid {}: {e}", path.display()))?;
+
+        let field = |name: &str| {
+            json[name].as_str().map(String::from)
+                .ok_or_else(|| format!("{} has no '{name}'", path.display()))
+        };
+        Ok(Self {
+            host: field("host")?,
+            port: json["port"].as_u64()
+                .and_then(|p| u16::try_from(p).ok())
+                .ok_or_else(|| format!("{} has no valid 'port'", path.display()))?,
+            scheme: field("scheme")?,
+            token: field("token")?,
+            cert_fingerprint: json["certFingerprint"].as_str().map(String::from),
+            pid: json["pid"].as_u64().and_then(|p| u32::try_from(p).ok()),
+            raw: json.clone(),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("{}://{}:{}/ws", self.scheme, self.host, self.port)
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Connection
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Byte stream under the WebSocket — plain TCP or pinned TLS.
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// An authenticated JSON-RPC connection to the server.
+pub struct Connection {
+    ws: WebSocketStream<Box<dyn Io>>,
+    next_id: u64,
+    /// Notifications that arrived while waiting for a response
+    notifications: VecDeque<Value>,
+}
+
+impl Connection {
+    pub async fn connect(info: &ServerInfo) -> Result<Self, String> {
+        let addr = format!("{}:{}", info.host, info.port);
+        let tcp = tokio::net::TcpStream::connect(&addr)
+            .await
+            .map_err(|e| format!("Failed to connect to {addr}: {e} (is the server running?)"))?;
+
+        let stream: Box<dyn Io> = match info.scheme.as_str() {
+            "ws" => Box::new(tcp),
+            "wss" => {
+                let fingerprint = info.cert_fingerprint.clone().ok_or_else(|| {
+                    format!("{} has no certFingerprint to pin the TLS certificate", ServerInfo::path().display())
+                })?;
+                Box::new(pinned_tls(tcp, &info.host, fingerprint).await?)
+            }
+            other => return Err(format!("Unsupported scheme '{other}'")),
+        };
+
+        let url = format!("{}://{addr}/ws", info.scheme);
+        let (ws, _) = tokio_tungstenite::client_async(url.as_str(), stream)
+            .await
+            .map_err(|e| format!("WebSocket handshake failed: {e}"))?;
+
+        let mut conn = Self { ws, next_id: 1, notifications: VecDeque::new() };
+        let handshake = json!({
+            "token": info.token,
+            "client": { "name": CLIENT_NAME, "version": env!("CARGO_PKG_VERSION") },
+        });
+        if let Err(error) = conn.call("auth/handshake", Some(handshake)).await? {
+            return Err(format!(
+                "Authentication failed: {}",
+                error["message"].as_str().unwrap_or("unknown error"),
+            ));
+        }
+        // auth/required and server/connected are connection noise
+        conn.notifications.clear();
+        Ok(conn)
+    }
+
+    /// Send a request and wait for its response. The outer error is a
+    /// transport failure; the inner one is the JSON-RPC error object.
+    pub async fn call(&mut self, method: &str, params: Option<Value>) -> Result<Result<Value, Value>, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut request = json!({ "jsonrpc": "2.0", "id": id, "method": method });
+        if let Some(params) = params {
+            request["params"] = params;
+        }
+        self.ws.send(Message::Text(request.to_string().into()))
+            .await
+            .map_err(|e| format!("Failed to send request: {e}"))?;
+
+        loop {
+            let message = self.read().await?.ok_or("Server closed the connection")?;
+            if message.get("id").and_then(|v| v.as_u64()) == Some(id) {
+                return Ok(match message.get("error") {
+                    Some(error) => Err(error.clone()),
+                    None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                });
+            }
+            if message.get("id").is_none() {
+                self.notifications.push_back(message);
+            }
+        }
+    }
+
+    /// Wait for the next notification. `None` once the connection closes.
+    pub async fn next_notification(&mut self) -> Result<Option<Value>, String> {
+        if let Some(n) = self.notifications.pop_front() {
+            return Ok(Some(n));
+        }
+        loop {
+            match self.read().await? {
+                Some(message) if message.get("id").is_none() => return Ok(Some(message)),
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    async fn read(&mut self) -> Result<Option<Value>, String> {
+        loop {
+            match self.ws.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    return serde_json::from_str(text.as_str())
+                        .map(Some)
+                        .map_err(|e| format!("Invalid message from server: {e}"));
+                }
+                Some(Ok(Message::Ping(data))) => {
+                    let _ = self.ws.send(Message::Pong(data)).await;
+                }
+                Some(Ok(Message::Close(_))) | None => return Ok(None),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(format!("Connection error: {e}")),
+            }
+        }
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Certificate pinning
+// ─────────────────────────────────────────────────────────────────────────────
+
+async fn pinned_tls(
+    tcp: tokio::net::TcpStream,
+    host: &str,
+    fingerprint: String,
+) -> Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>, String> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("TLS setup failed: {e}"))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier { fingerprint, provider }))
+        .with_no_client_auth();
+
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|e| format!("Invalid server name '{host}': {e}"))?;
+    tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await
+        .map_err(|e| format!("TLS handshake failed: {e}"))
+}
+
+/// Accepts exactly the certificate whose SHA-256 matches `server.json`.
+/// Handshake signatures are still verified against that certificate.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    /// `"sha256:<hex>"`
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let actual = format!("sha256:{}", hex::encode(Sha256::digest(end_entity.as_ref())));
+        if actual.eq_ignore_ascii_case(&self.fingerprint) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "certificate fingerprint mismatch (expected {}, got {actual})",
+                self.fingerprint,
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
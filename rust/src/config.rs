@@ -0,0 +1,705 @@
+//! Server configuration file — `~/.ultra/server.toml`.
+//!
+//! Every key is optional. Precedence is command-line flag > file > built-in
+//! default. Example:
+//!
+//! ```toml
+//! [server]
+//! port = 7070
+//! hostname = "127.0.0.1"
+//! max_connections = 32
+//! log_file = "~/.ultra/logs/ecp.log"
+//!
+//! [tls]
+//! enabled = true
+//! cert = "/path/to/cert.pem"
+//! key = "/path/to/key.pem"
+//!
+//! [auth]
+//! handshake_timeout_ms = 10000
+//! heartbeat_interval_ms = 30000   # 0 disables heartbeats
+//!
+//! [notifications]
+//! global_buffer = 1024
+//! workspace_buffer = 256
+//!
+//! [bridge]
+//! enabled = true
+//! bun_path = "/opt/homebrew/bin/bun"
+//! restart = "on-failure"          # or "never"
+//! max_restarts = 5
+//! restart_delay_ms = 1000
+//!
+//! [terminal]
+//! scrollback_lines = 5000         # rows kept per terminal once scrolled off screen
+//! max_output_bytes = 1048576      # terminal/execute output captured per stream
+//! supervisor = false              # host terminals in a process that survives restarts
+//!
+//! # Local file history under <workspace>/.ultra/history
+//! [history]
+//! max_entries_per_file = 50
+//! max_age_days = 30               # 0 keeps versions forever
+//! max_file_bytes = 5242880
+//!
+//! # Change journal under <workspace>/.ultra/journal
+//! [journal]
+//! max_entries = 1000
+//! max_age_days = 30               # 0 keeps entries forever
+//! max_file_bytes = 16777216       # larger files are recorded without content
+//! max_tree_bytes = 67108864       # and so are larger directories
+//!
+//! # File watching: "auto" polls network and shared-folder mounts and falls
+//! # back to polling where native watching fails; "native" or "poll" force one
+//! [watch]
+//! backend = "auto"
+//! poll_interval_ms = 1000
+//!
+//! [lsp.servers.rust]
+//! command = "rust-analyzer"
+//! args = []
+//!
+//! # Resource quotas per workspace and per client (0 = unlimited)
+//! [quotas.workspace]
+//! terminals = 32
+//! concurrent_executes = 8
+//! lsp_clients = 8
+//! db_connections = 16
+//! watches = 256
+//!
+//! [quotas.client]
+//! terminals = 16
+//!
+//! # Confine file, watch, terminal cwd, git and document paths to the
+//! # workspace plus these directories
+//! [sandbox]
+//! enabled = true
+//! allow = ["/tmp"]
+//! audit_log = "~/.ultra/audit.jsonl"
+//! ```
+//!
+//! The file is watched while the server runs. Auth timeouts, heartbeat,
+//! `max_connections`, terminal limits, history and journal retention, LSP
+//! servers, quotas, the sandbox and the bridge restart policy are applied
+//! immediately, except that a sandbox is only ever tightened at runtime;
+//! everything else is reported as requiring a restart. Either
+//! way clients get a `server/configChanged` notification.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ecp_ai_bridge::{AIBridge, RestartPolicy};
+use ecp_protocol::{ECPNotification, Notifications};
+use ecp_server::{QuotaLimits, Quotas, SandboxConfig, SharedQuotas, SharedSandbox};
+use ecp_services::history::{HistoryLimits, SharedHistoryLimits};
+use ecp_services::journal::{JournalLimits, SharedJournalLimits};
+use ecp_services::lsp::{ServerConfig as LspServerConfig, SharedServerConfigs};
+use ecp_services::watch::{WatchBackend, WatchSettings};
+use ecp_services::terminal::{SharedTerminalLimits, TerminalLimits};
+use ecp_transport::LiveSettings;
+use notify::Watcher;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+
+pub fn config_path() -> PathBuf {
+    expand_home("~/.ultra/server.toml")
+}
+
+/// Expand a leading `~/` to the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => {
+            let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".into());
+            PathBuf::from(home).join(rest)
+        }
+        None => PathBuf::from(path),
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// File format
+// ─────────────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub server: ServerSection,
+    pub tls: TlsSection,
+    pub auth: AuthSection,
+    pub notifications: NotificationsSection,
+    pub bridge: BridgeSection,
+    pub terminal: TerminalSection,
+    pub history: HistorySection,
+    pub journal: JournalSection,
+    pub watch: WatchSection,
+    pub lsp: LspSection,
+    pub quotas: QuotasSection,
+    pub sandbox: SandboxSection,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub port: u16,
+    pub hostname: String,
+    /// 0 means unlimited
+    pub max_connections: usize,
+    pub log_file: Option<String>,
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self {
+            port: 7070,
+            hostname: "127.0.0.1".into(),
+            max_connections: 32,
+            log_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    pub enabled: bool,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl Default for TlsSection {
+    fn default() -> Self {
+        Self { enabled: true, cert: None, key: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    pub handshake_timeout_ms: u64,
+    pub heartbeat_interval_ms: u64,
+}
+
+impl Default for AuthSection {
+    fn default() -> Self {
+        Self {
+            handshake_timeout_ms: 10_000,
+            heartbeat_interval_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsSection {
+    pub global_buffer: usize,
+    pub workspace_buffer: usize,
+}
+
+impl Default for NotificationsSection {
+    fn default() -> Self {
+        Self {
+            global_buffer: 1024,
+            workspace_buffer: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartMode {
+    Never,
+    OnFailure,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BridgeSection {
+    pub enabled: bool,
+    pub bun_path: Option<String>,
+    pub restart: RestartMode,
+    pub max_restarts: u32,
+    pub restart_delay_ms: u64,
+}
+
+impl Default for BridgeSection {
+    fn default() -> Self {
+        let policy = RestartPolicy::default();
+        Self {
+            enabled: true,
+            bun_path: None,
+            restart: if policy.on_failure { RestartMode::OnFailure } else { RestartMode::Never },
+            max_restarts: policy.max_restarts,
+            restart_delay_ms: policy.delay_ms,
+        }
+    }
+}
+
+impl BridgeSection {
+    pub fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy {
+            on_failure: self.restart == RestartMode::OnFailure,
+            max_restarts: self.max_restarts,
+            delay_ms: self.restart_delay_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerminalSection {
+    pub scrollback_lines: usize,
+    pub max_output_bytes: usize,
+    /// Run terminals in `ultra-ecp terminal-supervisor` so they outlive the
+    /// server
+    pub supervisor: bool,
+}
+
+impl Default for TerminalSection {
+    fn default() -> Self {
+        let limits = TerminalLimits::default();
+        Self {
+            scrollback_lines: limits.scrollback_lines,
+            max_output_bytes: limits.max_output_bytes,
+            supervisor: false,
+        }
+    }
+}
+
+impl TerminalSection {
+    pub fn limits(&self) -> TerminalLimits {
+        TerminalLimits {
+            scrollback_lines: self.scrollback_lines,
+            max_output_bytes: self.max_output_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistorySection {
+    pub max_entries_per_file: usize,
+    /// 0 keeps versions forever
+    pub max_age_days: u64,
+    pub max_file_bytes: u64,
+}
+
+impl Default for HistorySection {
+    fn default() -> Self {
+        let limits = HistoryLimits::default();
+        Self {
+            max_entries_per_file: limits.max_entries_per_file,
+            max_age_days: limits.max_age_days,
+            max_file_bytes: limits.max_file_bytes,
+        }
+    }
+}
+
+impl HistorySection {
+    pub fn limits(&self) -> HistoryLimits {
+        HistoryLimits {
+            max_entries_per_file: self.max_entries_per_file,
+            max_age_days: self.max_age_days,
+            max_file_bytes: self.max_file_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JournalSection {
+    pub max_entries: usize,
+    /// 0 keeps entries forever
+    pub max_age_days: u64,
+    pub max_file_bytes: u64,
+    pub max_tree_bytes: u64,
+}
+
+impl Default for JournalSection {
+    fn default() -> Self {
+        let limits = JournalLimits::default();
+        Self {
+            max_entries: limits.max_entries,
+            max_age_days: limits.max_age_days,
+            max_file_bytes: limits.max_file_bytes,
+            max_tree_bytes: limits.max_tree_bytes,
+        }
+    }
+}
+
+impl JournalSection {
+    pub fn limits(&self) -> JournalLimits {
+        JournalLimits {
+            max_entries: self.max_entries,
+            max_age_days: self.max_age_days,
+            max_file_bytes: self.max_file_bytes,
+            max_tree_bytes: self.max_tree_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchSection {
+    pub backend: WatchBackend,
+    pub poll_interval_ms: u64,
+}
+
+impl Default for WatchSection {
+    fn default() -> Self {
+        let settings = WatchSettings::default();
+        Self {
+            backend: settings.backend,
+            poll_interval_ms: settings.poll_interval.as_millis() as u64,
+        }
+    }
+}
+
+impl WatchSection {
+    pub fn settings(&self) -> WatchSettings {
+        WatchSettings {
+            backend: self.backend,
+            poll_interval: Duration::from_millis(self.poll_interval_ms),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LspSection {
+    /// Language server per language ID
+    pub servers: HashMap<String, LspServerEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LspServerEntry {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub initialization_options: Option<Value>,
+    #[serde(default)]
+    pub settings: Option<Value>,
+}
+
+impl LspSection {
+    pub fn server_configs(&self) -> HashMap<String, LspServerConfig> {
+        self.servers.iter().map(|(lang, entry)| {
+            (lang.clone(), LspServerConfig {
+                command: entry.command.clone(),
+                args: entry.args.clone(),
+                initialization_options: entry.initialization_options.clone(),
+                settings: entry.settings.clone(),
+                env: entry.env.clone(),
+            })
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotasSection {
+    pub workspace: QuotaLimitsSection,
+    pub client: QuotaLimitsSection,
+}
+
+impl Default for QuotasSection {
+    fn default() -> Self {
+        let quotas = Quotas::default();
+        Self {
+            workspace: QuotaLimitsSection::from(quotas.workspace),
+            client: QuotaLimitsSection::from(quotas.client),
+        }
+    }
+}
+
+/// One scope's limits. Keys left out of the file keep their defaults.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaLimitsSection {
+    pub terminals: Option<usize>,
+    pub concurrent_executes: Option<usize>,
+    pub lsp_clients: Option<usize>,
+    pub db_connections: Option<usize>,
+    pub watches: Option<usize>,
+}
+
+impl From<QuotaLimits> for QuotaLimitsSection {
+    fn from(limits: QuotaLimits) -> Self {
+        Self {
+            terminals: Some(limits.terminals),
+            concurrent_executes: Some(limits.concurrent_executes),
+            lsp_clients: Some(limits.lsp_clients),
+            db_connections: Some(limits.db_connections),
+            watches: Some(limits.watches),
+        }
+    }
+}
+
+impl QuotaLimitsSection {
+    fn resolve(&self, defaults: QuotaLimits) -> QuotaLimits {
+        QuotaLimits {
+            terminals: self.terminals.unwrap_or(defaults.terminals),
+            concurrent_executes: self.concurrent_executes.unwrap_or(defaults.concurrent_executes),
+            lsp_clients: self.lsp_clients.unwrap_or(defaults.lsp_clients),
+            db_connections: self.db_connections.unwrap_or(defaults.db_connections),
+            watches: self.watches.unwrap_or(defaults.watches),
+        }
+    }
+}
+
+impl QuotasSection {
+    pub fn quotas(&self) -> Quotas {
+        let defaults = Quotas::default();
+        Quotas {
+            workspace: self.workspace.resolve(defaults.workspace),
+            client: self.client.resolve(defaults.client),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxSection {
+    pub enabled: bool,
+    /// Directories outside the workspace that stay accessible (`~/` expanded)
+    pub allow: Vec<String>,
+    pub audit_log: Option<String>,
+}
+
+impl Default for SandboxSection {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allow: vec!["/tmp".into()],
+            audit_log: Some("~/.ultra/audit.jsonl".into()),
+        }
+    }
+}
+
+impl SandboxSection {
+    pub fn sandbox(&self) -> SandboxConfig {
+        SandboxConfig {
+            enabled: self.enabled,
+            allow: self.allow.iter().map(|dir| expand_home(dir)).collect(),
+            deny: protected_paths(self.audit_log.as_deref()),
+            audit_log: self.audit_log.as_deref().map(expand_home),
+        }
+    }
+}
+
+/// What a sandboxed request may never touch: this file, which could turn the
+/// sandbox off, the auth token and TLS key, the pid lock, the supervisor
+/// socket and the audit log.
+fn protected_paths(audit_log: Option<&str>) -> Vec<PathBuf> {
+    ["server.toml", "server.json", "server.pid", "auth-token", "tls", "terminals.sock"]
+        .iter()
+        .map(|name| expand_home("~/.ultra").join(name))
+        .chain(audit_log.map(expand_home))
+        .collect()
+}
+
+/// Settings given on the command line — these always win over the file.
+#[derive(Debug, Clone, Default)]
+pub struct Flags {
+    pub port: Option<u16>,
+    pub hostname: Option<String>,
+    pub max_connections: Option<usize>,
+    pub log_file: Option<String>,
+    pub no_tls: bool,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub no_bridge: bool,
+    pub bun_path: Option<String>,
+    pub sandbox: bool,
+}
+
+impl ServerConfig {
+    /// Read and parse a config file. A missing file yields the defaults.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        }
+    }
+
+    /// Overlay command-line flags onto the file's values.
+    pub fn with_flags(mut self, flags: &Flags) -> Self {
+        if let Some(port) = flags.port {
+            self.server.port = port;
+        }
+        if let Some(ref hostname) = flags.hostname {
+            self.server.hostname = hostname.clone();
+        }
+        if let Some(max) = flags.max_connections {
+            self.server.max_connections = max;
+        }
+        if flags.log_file.is_some() {
+            self.server.log_file = flags.log_file.clone();
+        }
+        if flags.no_tls {
+            self.tls.enabled = false;
+        }
+        if flags.tls_cert.is_some() && flags.tls_key.is_some() {
+            self.tls.cert = flags.tls_cert.clone();
+            self.tls.key = flags.tls_key.clone();
+        }
+        if flags.no_bridge {
+            self.bridge.enabled = false;
+        }
+        if flags.bun_path.is_some() {
+            self.bridge.bun_path = flags.bun_path.clone();
+        }
+        if flags.sandbox {
+            self.sandbox.enabled = true;
+        }
+        self
+    }
+
+    /// Keys whose values differ, split into (applied at runtime, needs restart).
+    pub fn diff(&self, other: &Self) -> (Vec<&'static str>, Vec<&'static str>) {
+        let mut applied = Vec::new();
+        let mut restart = Vec::new();
+        let mut check = |key: &'static str, changed: bool, live: bool| {
+            if changed {
+                if live { applied.push(key) } else { restart.push(key) }
+            }
+        };
+
+        check("server.port", self.server.port != other.server.port, false);
+        check("server.hostname", self.server.hostname != other.server.hostname, false);
+        check("server.max_connections", self.server.max_connections != other.server.max_connections, true);
+        check("server.log_file", self.server.log_file != other.server.log_file, false);
+        check("tls", self.tls != other.tls, false);
+        check("auth.handshake_timeout_ms", self.auth.handshake_timeout_ms != other.auth.handshake_timeout_ms, true);
+        check("auth.heartbeat_interval_ms", self.auth.heartbeat_interval_ms != other.auth.heartbeat_interval_ms, true);
+        check("notifications", self.notifications != other.notifications, false);
+        check("bridge.enabled", self.bridge.enabled != other.bridge.enabled, false);
+        check("bridge.bun_path", self.bridge.bun_path != other.bridge.bun_path, false);
+        check("bridge.restart", self.bridge.restart_policy() != other.bridge.restart_policy(), true);
+        check("terminal", self.terminal.limits() != other.terminal.limits(), true);
+        check("terminal.supervisor", self.terminal.supervisor != other.terminal.supervisor, false);
+        check("history", self.history != other.history, true);
+        check("journal", self.journal != other.journal, true);
+        check("watch", self.watch != other.watch, false);
+        check("lsp.servers", self.lsp != other.lsp, true);
+        check("quotas", self.quotas.quotas() != other.quotas.quotas(), true);
+        check("sandbox", self.sandbox != other.sandbox, true);
+
+        (applied, restart)
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Live reload
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Handles to the settings that can change while the server runs.
+pub struct LiveHandles {
+    pub transport: Arc<LiveSettings>,
+    pub terminal_limits: SharedTerminalLimits,
+    pub history_limits: SharedHistoryLimits,
+    pub journal_limits: SharedJournalLimits,
+    pub lsp_servers: SharedServerConfigs,
+    pub quotas: SharedQuotas,
+    pub sandbox: SharedSandbox,
+    pub bridge: Option<Arc<AIBridge>>,
+}
+
+impl LiveHandles {
+    /// Apply what can change at runtime. Returns the keys left as they
+    /// were, which need a restart: a sandbox is never loosened while running,
+    /// as a sandboxed agent could otherwise rewrite its own limits.
+    pub fn apply(&self, config: &ServerConfig) -> Vec<&'static str> {
+        let mut refused = Vec::new();
+        self.transport.set_handshake_timeout_ms(config.auth.handshake_timeout_ms);
+        self.transport.set_heartbeat_interval_ms(config.auth.heartbeat_interval_ms);
+        self.transport.set_max_connections(match config.server.max_connections {
+            0 => None,
+            max => Some(max),
+        });
+        *self.terminal_limits.write() = config.terminal.limits();
+        *self.history_limits.write() = config.history.limits();
+        *self.journal_limits.write() = config.journal.limits();
+        *self.lsp_servers.write() = config.lsp.server_configs();
+        *self.quotas.write() = config.quotas.quotas();
+        let sandbox = config.sandbox.sandbox();
+        if sandbox.loosens(&self.sandbox.read()) {
+            warn!("Not loosening the sandbox while running; restart to apply it");
+            refused.push("sandbox");
+        } else {
+            *self.sandbox.write() = sandbox;
+        }
+        if let Some(ref bridge) = self.bridge {
+            bridge.set_restart_policy(config.bridge.restart_policy());
+        }
+        refused
+    }
+}
+
+/// Watch `path` and hot-apply changes, announcing each reload with
+/// `server/configChanged`. Runs until the returned watcher is dropped.
+pub fn watch(
+    path: PathBuf,
+    flags: Flags,
+    startup: ServerConfig,
+    handles: LiveHandles,
+    notification_tx: broadcast::Sender<String>,
+) -> notify::Result<notify::RecommendedWatcher> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+    let file_name = path.file_name().map(|n| n.to_os_string());
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event
+            && event.paths.iter().any(|p| p.file_name() == file_name.as_deref())
+        {
+            let _ = tx.send(());
+        }
+    })?;
+    // Watch the directory so the file can be created, replaced or deleted
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let _ = std::fs::create_dir_all(&dir);
+    watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+
+    tokio::spawn(async move {
+        let mut current = startup.clone();
+        while rx.recv().await.is_some() {
+            // Editors write in several steps — wait for the burst to settle
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            while rx.try_recv().is_ok() {}
+
+            let params = match ServerConfig::load(&path) {
+                Ok(loaded) => {
+                    let next = loaded.with_flags(&flags);
+                    if next == current {
+                        continue;
+                    }
+                    let (mut applied, _) = current.diff(&next);
+                    // Pending restarts are relative to what the server started with
+                    let (_, mut requires_restart) = startup.diff(&next);
+                    let refused = handles.apply(&next);
+                    applied.retain(|key| !refused.contains(key));
+                    requires_restart.extend(refused);
+                    current = next;
+                    info!("Reloaded {} (applied: {applied:?}, requires restart: {requires_restart:?})", path.display());
+                    json!({ "applied": applied, "requiresRestart": requires_restart })
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid config: {e}");
+                    json!({ "applied": [], "requiresRestart": [], "error": e })
+                }
+            };
+            let notification = ECPNotification::new(Notifications::SERVER_CONFIG_CHANGED, Some(params));
+            if let Ok(json) = serde_json::to_string(&notification) {
+                let _ = notification_tx.send(json);
+            }
+        }
+    });
+
+    Ok(watcher)
+}
@@ -0,0 +1,412 @@
+//! Single-instance management — pid lock, `status`, `stop`, `restart`.
+//!
+//! A running server owns `~/.ultra/server.pid` (held with `flock`) and
+//! `~/.ultra/server.json`. Both are removed on graceful shutdown. After a
+//! crash the lock is gone with the process, and a `server.json` naming a dead
+//! pid is cleaned up by the next server start or `ultra-ecp status` (on unix;
+//! elsewhere there is no liveness check and stale files are removed by hand).
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use clap::{Args, ValueEnum};
+use serde_json::Value;
+
+use crate::client::{format_age, Connection, ServerInfo};
+
+/// What to do when another server is already running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnExisting {
+    /// Exit with an error
+    Fail,
+    /// Print the running server's connection info and exit successfully
+    Attach,
+}
+
+#[derive(Args, Debug)]
+pub struct StatusArgs {
+    /// Print raw JSON instead of a summary
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct StopArgs {
+    /// Seconds to wait for the server to exit
+    #[arg(long, default_value = "10")]
+    timeout: u64,
+}
+
+fn ultra_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".into());
+    PathBuf::from(home).join(".ultra")
+}
+
+fn pid_path() -> PathBuf {
+    ultra_dir().join("server.pid")
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Pid lock
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Exclusive ownership of `~/.ultra/server.pid` for this process.
+pub struct PidLock {
+    path: PathBuf,
+    /// Holds the `flock` for as long as the server runs
+    #[cfg(unix)]
+    file: Option<std::fs::File>,
+}
+
+impl PidLock {
+    /// Claim the pid file, which a live server keeps `flock`ed: the kernel
+    /// drops the lock when its owner dies, so a file left by a crash is free to
+    /// take over. Returns the owner's pid (if it has written it yet) when
+    /// another server runs.
+    #[cfg(unix)]
+    pub fn acquire() -> Result<Self, Option<u32>> {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::io::AsRawFd;
+
+        let path = pid_path();
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        // Servers from before the pid file existed only left server.json behind
+        if let Some(pid) = running_server_pid() {
+            return Err(Some(pid));
+        }
+
+        loop {
+            let opened = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path);
+            let mut file = match opened {
+                Ok(file) => file,
+                Err(e) => {
+                    // Can't lock (read-only home?) — run unguarded rather than refuse to start
+                    tracing::warn!("Failed to create pid file {}: {e}", path.display());
+                    return Ok(Self { path, file: None });
+                }
+            };
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                return Err(locked_owner(&path));
+            }
+            // The last owner removed the file between our open and lock: lock the new one
+            let current = std::fs::metadata(&path).ok().map(|m| (m.dev(), m.ino()));
+            let locked = file.metadata().ok().map(|m| (m.dev(), m.ino()));
+            if current.is_none() || current != locked {
+                continue;
+            }
+            let _ = file.set_len(0);
+            let _ = write!(file, "{}", std::process::id());
+            return Ok(Self { path, file: Some(file) });
+        }
+    }
+
+    /// Claim the pid file, linking it into place fully written so no other
+    /// server can see it empty. Without a liveness check on this platform
+    /// (see [`process_alive`]) a file left by a crash is never stale: remove
+    /// `~/.ultra/server.pid` by hand.
+    #[cfg(not(unix))]
+    pub fn acquire() -> Result<Self, Option<u32>> {
+        let path = pid_path();
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        if let Some(pid) = running_server_pid() {
+            return Err(Some(pid));
+        }
+
+        let temp = path.with_extension(format!("pid.{}", std::process::id()));
+        let linked = std::fs::write(&temp, std::process::id().to_string())
+            .and_then(|()| std::fs::hard_link(&temp, &path));
+        let _ = std::fs::remove_file(&temp);
+        match linked {
+            Ok(()) => Ok(Self { path }),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => match read_pid(&path) {
+                Some(pid) if pid == std::process::id() => Ok(Self { path }),
+                pid => Err(pid),
+            },
+            Err(e) => {
+                // Can't lock (read-only home?) — run unguarded rather than refuse to start
+                tracing::warn!("Failed to create pid file {}: {e}", path.display());
+                Ok(Self { path })
+            }
+        }
+    }
+
+    /// Remove the pid file if it still names this process.
+    pub fn release(self) {
+        // Removed before the lock goes, so nobody locks a file on its way out
+        if read_pid(&self.path) == Some(std::process::id()) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+        #[cfg(unix)]
+        drop(self.file);
+    }
+}
+
+/// Pid of the server holding the lock on `path`. An empty file is a server
+/// between taking the lock and writing its pid, not a stale one: give it a
+/// moment to finish.
+#[cfg(unix)]
+fn locked_owner(path: &std::path::Path) -> Option<u32> {
+    let deadline = std::time::Instant::now() + Duration::from_secs(2);
+    loop {
+        match read_pid(path) {
+            Some(pid) => return Some(pid),
+            None if std::time::Instant::now() >= deadline => return None,
+            None => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+fn read_pid(path: &std::path::Path) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Pid of the live server recorded in `server.json`, removing the file if that
+/// process is gone.
+pub fn running_server_pid() -> Option<u32> {
+    let path = ServerInfo::path();
+    let json: Value = serde_json::from_str(&std::fs::read_to_string(&path).ok()?).ok()?;
+    let pid = json["pid"].as_u64().and_then(|p| u32::try_from(p).ok())?;
+    if pid == std::process::id() {
+        return None;
+    }
+    if process_alive(pid) {
+        Some(pid)
+    } else {
+        tracing::info!("Removing stale {} (pid {pid} is not running)", path.display());
+        let _ = std::fs::remove_file(&path);
+        None
+    }
+}
+
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = i32::try_from(pid) else { return false };
+    // Signal 0 checks for existence; EPERM means it exists but isn't ours
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// No liveness check off unix: every recorded pid counts as running, so stale
+/// `server.pid` and `server.json` files are only cleaned up on unix.
+#[cfg(not(unix))]
+pub fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) {
+    if let Ok(pid) = i32::try_from(pid) {
+        unsafe {
+            libc::kill(pid, libc::SIGTERM);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(_pid: u32) {}
+
+/// Print the existing server's connection info (for `--on-existing attach`).
+pub fn print_existing(pid: Option<u32>) {
+    let pid = pid.map_or_else(|| "pid unknown".to_string(), |pid| format!("pid {pid}"));
+    match ServerInfo::load() {
+        Ok(info) => println!("Attached to running ultra-ecp ({pid}): {}", info.url()),
+        Err(_) => println!("Attached to running ultra-ecp ({pid})"),
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Subcommands
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// `ultra-ecp status` — exit code 0 if a server is running, 1 if not.
+pub async fn status(args: StatusArgs) -> i32 {
+    let Some(pid) = running_server_pid() else {
+        if args.json {
+            println!("{}", serde_json::json!({ "running": false }));
+        } else {
+            println!("ultra-ecp is not running");
+        }
+        return 1;
+    };
+    let info = match ServerInfo::load() {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+
+    // Live counts are best-effort — the server may be busy or mid-shutdown
+    let (workspaces, clients) = match Connection::connect(&info).await {
+        Ok(mut conn) => {
+            let workspaces = conn.call("workspace/list", None).await.ok().and_then(Result::ok);
+            let clients = conn.call("server/clients", None).await.ok().and_then(Result::ok);
+            (workspaces, clients)
+        }
+        Err(_) => (None, None),
+    };
+    let workspace_count = workspaces.as_ref().and_then(|w| w["workspaces"].as_array()).map(|a| a.len());
+    // Don't count this status connection
+    let client_count = clients.as_ref().and_then(|c| c["clients"].as_array()).map(|a| a.len().saturating_sub(1));
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let started_at = info.raw["startedAt"].as_u64();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "running": true,
+            "pid": pid,
+            "url": info.url(),
+            "serverVersion": info.raw["serverVersion"],
+            "startedAt": started_at,
+            "workspaces": workspace_count,
+            "clients": client_count,
+        })).unwrap_or_default());
+        return 0;
+    }
+
+    let count = |c: Option<usize>| c.map(|n| n.to_string()).unwrap_or_else(|| "?".into());
+    println!("ultra-ecp is running");
+    println!("  PID:         {pid}");
+    println!("  URL:         {}", info.url());
+    println!("  Version:     {}", info.raw["serverVersion"].as_str().unwrap_or("?"));
+    if let Some(started_at) = started_at {
+        println!("  Uptime:      {}", format_age(now.saturating_sub(started_at)));
+    }
+    println!("  Workspaces:  {}", count(workspace_count));
+    println!("  Clients:     {}", count(client_count));
+    0
+}
+
+/// `ultra-ecp stop` — graceful shutdown via `server/shutdown`, falling back
+/// to SIGTERM if the server can't be reached over the API.
+pub async fn stop(args: StopArgs) -> i32 {
+    let Some(pid) = running_server_pid() else {
+        println!("ultra-ecp is not running");
+        return 0;
+    };
+
+    if let Err(e) = request_shutdown(pid).await {
+        eprintln!("{e}");
+        return 1;
+    }
+    if !wait_for_exit(pid, Duration::from_secs(args.timeout)).await {
+        eprintln!("ultra-ecp (pid {pid}) did not exit within {}s", args.timeout);
+        return 1;
+    }
+    println!("ultra-ecp stopped (pid {pid})");
+    0
+}
+
+/// `ultra-ecp restart` — stop the running server and start it again with the
+/// arguments it was originally started with.
+pub async fn restart(args: StopArgs) -> i32 {
+    let Some(pid) = running_server_pid() else {
+        eprintln!("ultra-ecp is not running");
+        return 1;
+    };
+    let info = match ServerInfo::load() {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+    let exe = info.raw["exe"].as_str().map(PathBuf::from)
+        .or_else(|| std::env::current_exe().ok());
+    let Some(exe) = exe else {
+        eprintln!("Cannot determine the server executable");
+        return 2;
+    };
+    let mut argv: Vec<String> = info.raw["argv"].as_array().into_iter().flatten()
+        .filter_map(|a| a.as_str().map(String::from))
+        .collect();
+    // The restarted server has no parent holding its stdin open
+    if !argv.iter().any(|a| a == "--no-stdin-watch") {
+        argv.push("--no-stdin-watch".into());
+    }
+
+    if let Err(e) = request_shutdown(pid).await {
+        eprintln!("{e}");
+        return 1;
+    }
+    if !wait_for_exit(pid, Duration::from_secs(args.timeout)).await {
+        eprintln!("ultra-ecp (pid {pid}) did not exit within {}s", args.timeout);
+        return 1;
+    }
+
+    let mut command = std::process::Command::new(&exe);
+    command.args(&argv)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    {
+        // Detach from our process group so the terminal's Ctrl+C doesn't reach it
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Failed to start {}: {e}", exe.display());
+            return 1;
+        }
+    };
+
+    // Wait for the new instance to publish its connection info
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(args.timeout);
+    while tokio::time::Instant::now() < deadline {
+        if let Ok(info) = ServerInfo::load()
+            && info.pid == Some(child.id())
+        {
+            println!("ultra-ecp restarted (pid {}): {}", child.id(), info.url());
+            return 0;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    eprintln!("Restarted server (pid {}) did not come up within {}s", child.id(), args.timeout);
+    1
+}
+
+async fn request_shutdown(pid: u32) -> Result<(), String> {
+    let info = ServerInfo::load()?;
+    let result = match Connection::connect(&info).await {
+        Ok(mut conn) => conn.call("server/shutdown", None).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(error)) => Err(format!(
+            "server/shutdown failed: {}",
+            error["message"].as_str().unwrap_or("unknown error"),
+        )),
+        Err(e) => {
+            tracing::warn!("Graceful shutdown unavailable ({e}); sending SIGTERM to {pid}");
+            terminate(pid);
+            Ok(())
+        }
+    }
+}
+
+async fn wait_for_exit(pid: u32, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    while process_alive(pid) {
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    true
+}
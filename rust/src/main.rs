@@ -10,11 +10,23 @@
 //!   ultra-ecp --port 8080                        # Custom port
 //!   ultra-ecp --workspace /path/to/project       # Pre-open a default workspace
 //!   ultra-ecp --token mysecret                   # Custom auth token
+//!   ultra-ecp --record session.jsonl             # Record all traffic
+//!   ultra-ecp replay session.jsonl               # Replay a recording and diff responses
+//!   ultra-ecp status | stop | restart            # Manage the running instance
+//!
+//! Settings are read from `~/.ultra/server.toml` (see `config.rs`); flags
+//! override the file.
+//!
+//! Client subcommands (talk to the server in ~/.ultra/server.json):
+//!   ultra-ecp call file/read '{"path":"README.md"}'
+//!   ultra-ecp watch 'file/*'
+//!   ultra-ecp workspaces
+//!   ultra-ecp clients
 
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use ecp_ai_bridge::{AIBridge, AIBridgeConfig};
 use ecp_protocol::auth::AuthConfig;
 use ecp_server::{ECPServer, WorkspaceRegistry};
@@ -24,24 +36,34 @@ use ecp_services::{
     document::DocumentService,
     models::ModelsService,
     secret::SecretService,
+    terminal_supervisor::{self, SupervisorClient},
 };
 use ecp_transport::server::{TransportConfig, TlsConfig, TransportServer};
-use ecp_transport::RequestHandler;
-use parking_lot::Mutex;
+use ecp_transport::{Recorder, RequestHandler};
+use parking_lot::{Mutex, RwLock};
 use tokio::sync::broadcast;
 use tracing::{error, warn};
 use tracing_subscriber::EnvFilter;
 
+mod client;
+mod config;
+mod daemon;
+mod replay;
+
 #[derive(Parser, Debug)]
 #[command(name = "ultra-ecp", about = "Ultra ECP Server — Editor Command Protocol")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Port to listen on (0 for OS-assigned)
-    #[arg(long, default_value = "7070")]
-    port: u16,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Port to listen on (0 for OS-assigned) [default: 7070]
+    #[arg(long)]
+    port: Option<u16>,
 
-    /// Hostname to bind to
-    #[arg(long, default_value = "127.0.0.1")]
-    hostname: String,
+    /// Hostname to bind to [default: 127.0.0.1]
+    #[arg(long)]
+    hostname: Option<String>,
 
     /// Workspace root directory (pre-opens a default workspace for backward compat)
     #[arg(long)]
@@ -51,9 +73,9 @@ struct Cli {
     #[arg(long)]
     token: Option<String>,
 
-    /// Maximum concurrent connections
-    #[arg(long, default_value = "32")]
-    max_connections: usize,
+    /// Maximum concurrent connections [default: 32]
+    #[arg(long)]
+    max_connections: Option<usize>,
 
     /// Enable verbose logging
     #[arg(long)]
@@ -82,18 +104,60 @@ struct Cli {
     /// Write logs to a file (defaults to ~/.ultra/logs/ecp.log if no path given)
     #[arg(long, default_missing_value = "DEFAULT", num_args = 0..=1)]
     log_file: Option<String>,
+
+    /// Record every request, response and notification to a JSONL file
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// What to do if another server is already running
+    #[arg(long, value_enum, default_value = "fail")]
+    on_existing: daemon::OnExisting,
+
+    /// Keep running when stdin closes (for servers without a parent process)
+    #[arg(long)]
+    no_stdin_watch: bool,
+
+    /// Confine file access to the workspace plus the allowlist in server.toml
+    #[arg(long)]
+    sandbox: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replay a `--record` file against a fresh server and diff the responses
+    Replay(replay::ReplayArgs),
+    /// Call a method on the running server and print the result
+    Call(client::CallArgs),
+    /// Stream notifications from the running server
+    Watch(client::WatchArgs),
+    /// List workspaces open on the running server
+    Workspaces(client::ListArgs),
+    /// List clients connected to the running server
+    Clients(client::ListArgs),
+    /// Show whether a server is running
+    Status(daemon::StatusArgs),
+    /// Gracefully stop the running server
+    Stop(daemon::StopArgs),
+    /// Restart the running server with its original arguments
+    Restart(daemon::StopArgs),
+    /// Host terminals for servers with `[terminal] supervisor = true`
+    #[command(hide = true)]
+    TerminalSupervisor {
+        /// Unix socket to listen on
+        #[arg(long)]
+        socket: PathBuf,
+    },
 }
 
 /// Resolve the bun binary path, checking common installation locations.
 /// Mac apps don't inherit the user's shell PATH, so we need to find bun ourselves.
 fn resolve_bun_path() -> String {
     // First check PATH (works when launched from a terminal)
-    if let Ok(output) = std::process::Command::new("which").arg("bun").output() {
-        if output.status.success() {
-            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !path.is_empty() {
-                return path;
-            }
+    if let Ok(output) = std::process::Command::new("which").arg("bun").output()
+        && output.status.success() {
+        let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !path.is_empty() {
+            return path;
         }
     }
 
@@ -194,20 +258,51 @@ fn ensure_tls_certs() -> Result<(PathBuf, PathBuf, String), Box<dyn std::error::
 async fn main() {
     let cli = Cli::parse();
 
+    // Pick the rustls crypto provider explicitly — with both aws-lc-rs and ring
+    // compiled in (e.g. via dependency feature unification) rustls can't choose.
+    let _ = tokio_rustls::rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+    // Server settings: ~/.ultra/server.toml, with command-line flags on top
+    let flags = config::Flags {
+        port: cli.port,
+        hostname: cli.hostname.clone(),
+        max_connections: cli.max_connections,
+        log_file: cli.log_file.clone(),
+        no_tls: cli.no_tls,
+        tls_cert: cli.tls_cert.clone(),
+        tls_key: cli.tls_key.clone(),
+        no_bridge: cli.no_bridge,
+        bun_path: cli.bun_path.clone(),
+        sandbox: cli.sandbox,
+    };
+    let config_path = config::config_path();
+    let config = if cli.command.is_some() {
+        config::ServerConfig::default()
+    } else {
+        match config::ServerConfig::load(&config_path) {
+            Ok(config) => config.with_flags(&flags),
+            Err(e) => {
+                eprintln!("Invalid server config {e}");
+                std::process::exit(1);
+            }
+        }
+    };
+
     // Initialize tracing
     let filter = if cli.verbose {
         EnvFilter::new("debug")
+    } else if cli.command.is_some() {
+        EnvFilter::new("warn")
     } else {
         EnvFilter::new("info")
     };
 
-    if let Some(ref log_file_arg) = cli.log_file {
+    if let Some(ref log_file_arg) = config.server.log_file {
         // Resolve log file path
-        let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".into());
         let log_path = if log_file_arg == "DEFAULT" {
-            PathBuf::from(&home).join(".ultra/logs/ecp.log")
+            config::expand_home("~/.ultra/logs/ecp.log")
         } else {
-            PathBuf::from(log_file_arg)
+            config::expand_home(log_file_arg)
         };
 
         if let Some(parent) = log_path.parent() {
@@ -233,10 +328,59 @@ async fn main() {
             .init();
     };
 
+    if let Some(command) = cli.command {
+        let code = match command {
+            Command::Replay(args) => replay::run(args).await,
+            Command::Call(args) => client::call(args).await,
+            Command::Watch(args) => client::watch(args).await,
+            Command::Workspaces(args) => client::workspaces(args).await,
+            Command::Clients(args) => client::clients(args).await,
+            Command::Status(args) => daemon::status(args).await,
+            Command::Stop(args) => daemon::stop(args).await,
+            Command::Restart(args) => daemon::restart(args).await,
+            Command::TerminalSupervisor { socket } => match terminal_supervisor::serve(&socket).await {
+                Ok(()) => 0,
+                Err(e) => {
+                    error!("Terminal supervisor failed: {e}");
+                    1
+                }
+            },
+        };
+        std::process::exit(code);
+    }
+
+    // Single instance — claim the pid file before touching any shared state
+    let pid_lock = match daemon::PidLock::acquire() {
+        Ok(lock) => lock,
+        Err(pid) => match cli.on_existing {
+            daemon::OnExisting::Attach => {
+                daemon::print_existing(pid);
+                std::process::exit(0);
+            }
+            daemon::OnExisting::Fail => {
+                let pid = pid.map_or_else(String::new, |pid| format!(" (pid {pid})"));
+                eprintln!("ultra-ecp is already running{pid}. Use `ultra-ecp stop` or `--on-existing attach`.");
+                std::process::exit(1);
+            }
+        },
+    };
+
     // Resolve workspace root if provided
-    let workspace_root = cli.workspace.map(|w| {
-        let w = w.canonicalize().unwrap_or(w);
-        w
+    let workspace_root = cli.workspace.map(|w| w.canonicalize().unwrap_or(w));
+
+    // Open the recording file before anything else can produce traffic
+    let recorder = cli.record.as_ref().map(|path| {
+        let header = serde_json::json!({
+            "serverVersion": env!("CARGO_PKG_VERSION"),
+            "workspaceRoot": workspace_root.as_ref().map(|w| w.to_string_lossy().to_string()),
+        });
+        match Recorder::create(path, header) {
+            Ok(recorder) => Arc::new(recorder),
+            Err(e) => {
+                error!("Failed to open recording file {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
     });
 
     // Resolve auth token — reuse persisted token, or generate and persist a new one.
@@ -279,9 +423,9 @@ async fn main() {
     });
 
     // Resolve TLS configuration and cert fingerprint
-    let (tls_config, cert_fingerprint) = if cli.no_tls {
+    let (tls_config, cert_fingerprint) = if !config.tls.enabled {
         (None, None)
-    } else if let (Some(cert), Some(key)) = (&cli.tls_cert, &cli.tls_key) {
+    } else if let (Some(cert), Some(key)) = (&config.tls.cert, &config.tls.key) {
         let fp = compute_cert_fingerprint_from_pem(cert);
         (Some(TlsConfig {
             cert_path: cert.clone(),
@@ -311,11 +455,20 @@ async fn main() {
     } else {
         println!("  Workspace:  (none — clients must call workspace/open)");
     }
-    println!("  Port:       {}", cli.port);
-    println!("  Binding:    {} (localhost only)", cli.hostname);
+    println!("  Port:       {}", config.server.port);
+    println!("  Binding:    {} (localhost only)", config.server.hostname);
     match &tls_config {
         Some(tls) => println!("  TLS:        enabled (cert: {})", tls.cert_path.display()),
-        None => println!("  TLS:        disabled (--no-tls)"),
+        None => println!("  TLS:        disabled"),
+    }
+    if config_path.exists() {
+        println!("  Config:     {}", config_path.display());
+    }
+    if config.sandbox.enabled {
+        println!("  Sandbox:    workspace + {}", config.sandbox.allow.join(", "));
+    }
+    if let Some(ref path) = cli.record {
+        println!("  Recording:  {}", path.display());
     }
     println!();
 
@@ -327,11 +480,37 @@ async fn main() {
     ));
 
     // Create shared notification channel — global notifications (theme, config)
-    let (notification_tx, _) = broadcast::channel::<String>(1024);
+    let (notification_tx, _) = broadcast::channel::<String>(config.notifications.global_buffer.max(1));
+
+    // Settings shared with every workspace so config reloads reach them
+    let terminal_limits = Arc::new(RwLock::new(config.terminal.limits()));
+    let history_limits = Arc::new(RwLock::new(config.history.limits()));
+    let journal_limits = Arc::new(RwLock::new(config.journal.limits()));
+    let lsp_servers = Arc::new(RwLock::new(config.lsp.server_configs()));
+    let quotas = Arc::new(RwLock::new(config.quotas.quotas()));
+    let sandbox = Arc::new(RwLock::new(config.sandbox.sandbox()));
 
     // Create workspace registry and ECP server
-    let registry = WorkspaceRegistry::new(global_chat_db);
+    let mut registry = WorkspaceRegistry::new(global_chat_db);
+    registry.set_notification_buffer(config.notifications.workspace_buffer);
+    registry.set_terminal_limits(terminal_limits.clone());
+    registry.set_history_limits(history_limits.clone());
+    registry.set_journal_limits(journal_limits.clone());
+    registry.set_watch_settings(config.watch.settings());
+    registry.set_lsp_servers(lsp_servers.clone());
+    if config.terminal.supervisor {
+        // Started on first use by whichever server needs it
+        let socket = PathBuf::from(&home).join(".ultra/terminals.sock");
+        let mut supervisor = SupervisorClient::new(socket.clone());
+        if let Ok(exe) = std::env::current_exe() {
+            let args = vec!["terminal-supervisor".into(), "--socket".into(), socket.to_string_lossy().into_owned()];
+            supervisor = supervisor.with_launcher(exe, args);
+        }
+        registry.set_terminal_supervisor(Arc::new(supervisor));
+    }
     let mut ecp_server = ECPServer::new(registry);
+    ecp_server.set_quotas(quotas.clone());
+    ecp_server.set_sandbox(sandbox.clone());
     ecp_server.set_notification_sender(notification_tx.clone());
 
     // Register global services
@@ -339,9 +518,10 @@ async fn main() {
     ecp_server.register_service(DocumentService::new());
 
     // ── AI Bridge — TypeScript subprocess for AI SDK services ────────────
-    let bridge_arc: Option<Arc<AIBridge>> = if !cli.no_bridge {
+    let bridge_arc: Option<Arc<AIBridge>> = if config.bridge.enabled {
         let mut bridge = AIBridge::new();
         bridge.set_notification_sender(notification_tx.clone());
+        bridge.set_restart_policy(config.bridge.restart_policy());
 
         // Resolve the bridge binary/script path relative to the executable
         let exe_dir = std::env::current_exe()
@@ -372,7 +552,7 @@ async fn main() {
 
         // Resolve bun runtime — only needed when running TS source (not compiled binary)
         let bun_runtime = if compiled_binary.is_none() {
-            cli.bun_path.clone().unwrap_or_else(resolve_bun_path)
+            config.bridge.bun_path.clone().unwrap_or_else(resolve_bun_path)
         } else {
             String::new()
         };
@@ -398,6 +578,7 @@ async fn main() {
         match bridge.start(config).await {
             Ok(()) => {
                 let bridge = Arc::new(bridge);
+                bridge.supervise();
 
                 // Register bridge-delegated services (global)
                 ecp_server.register_service(AIService::new(bridge.clone()));
@@ -417,7 +598,7 @@ async fn main() {
             }
         }
     } else {
-        println!("  AI Bridge:  disabled (--no-bridge)");
+        println!("  AI Bridge:  disabled");
         None
     };
     // Register ModelsService — delegates to bridge when available, falls back to file read
@@ -446,6 +627,7 @@ async fn main() {
 
     // Wrap ECPServer in Arc — shared between transport and bridge callback handler
     let ecp_server = Arc::new(ecp_server);
+    let shutdown_requested = ecp_server.shutdown_requested();
 
     // Wire the bridge callback handler now that the ECPServer is in an Arc.
     if let Some(ref bridge) = bridge_arc {
@@ -459,24 +641,28 @@ async fn main() {
 
     // Configure transport
     let transport_config = TransportConfig {
-        port: cli.port,
-        hostname: cli.hostname.clone(),
+        port: config.server.port,
+        hostname: config.server.hostname.clone(),
         auth: Some(AuthConfig {
             token: auth_token.clone(),
-            handshake_timeout_ms: 10_000,
+            handshake_timeout_ms: config.auth.handshake_timeout_ms,
             allow_legacy_auth: true,
-            heartbeat_interval_ms: 30_000,
+            heartbeat_interval_ms: config.auth.heartbeat_interval_ms,
         }),
         enable_cors: false,
-        max_connections: Some(cli.max_connections),
+        max_connections: match config.server.max_connections {
+            0 => None,
+            max => Some(max),
+        },
         workspace_root: workspace_root.as_ref().map(|w| w.to_string_lossy().to_string()),
         verbose_logging: cli.verbose,
         tls: tls_config,
         cert_fingerprint: cert_fingerprint.clone(),
+        recorder,
     };
 
     // Start transport server with the shared notification channel and Arc<ECPServer>
-    let mut transport = match TransportServer::start_with_sender(transport_config, ecp_server, notification_tx).await {
+    let mut transport = match TransportServer::start_with_sender(transport_config, ecp_server.clone(), notification_tx.clone()).await {
         Ok(t) => t,
         Err(e) => {
             error!("Failed to start transport: {e}");
@@ -486,13 +672,13 @@ async fn main() {
 
     let actual_port = transport.port();
     let scheme = if transport.is_tls() { "wss" } else { "ws" };
-    let ws_url = format!("{scheme}://{}:{}/ws", cli.hostname, actual_port);
+    let ws_url = format!("{scheme}://{}:{}/ws", config.server.hostname, actual_port);
 
     // Write connection info file for client discovery
     let server_json_path = PathBuf::from(&home).join(".ultra/server.json");
     {
         let server_info = serde_json::json!({
-            "host": cli.hostname,
+            "host": config.server.hostname,
             "port": actual_port,
             "scheme": scheme,
             "token": auth_token,
@@ -503,6 +689,9 @@ async fn main() {
                 .unwrap_or_default()
                 .as_millis() as u64,
             "pid": std::process::id(),
+            // For `ultra-ecp restart`
+            "exe": std::env::current_exe().ok(),
+            "argv": std::env::args().skip(1).collect::<Vec<_>>(),
         });
         if let Ok(json_str) = serde_json::to_string_pretty(&server_info) {
             let _ = std::fs::write(&server_json_path, &json_str);
@@ -517,6 +706,25 @@ async fn main() {
         }
     }
 
+    // Hot-apply edits to server.toml while running
+    let live_handles = config::LiveHandles {
+        transport: transport.live_settings(),
+        terminal_limits,
+        history_limits,
+        journal_limits,
+        lsp_servers,
+        quotas,
+        sandbox,
+        bridge: bridge_arc.clone(),
+    };
+    let _config_watcher = match config::watch(config_path, flags, config, live_handles, notification_tx) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("Failed to watch server config: {e}");
+            None
+        }
+    };
+
     println!("────────────────────────────────────────────────────────────────");
     println!();
     println!("  Server running!");
@@ -546,7 +754,7 @@ async fn main() {
     // The Mac app passes a Pipe() as stdin — when the GUI is killed, the pipe
     // closes and we detect EOF here, preventing orphaned server processes.
     let shutdown_notify = Arc::new(tokio::sync::Notify::new());
-    {
+    if !cli.no_stdin_watch {
         let notify = shutdown_notify.clone();
         std::thread::spawn(move || {
             use std::io::Read;
@@ -564,19 +772,41 @@ async fn main() {
         });
     }
 
+    #[cfg(unix)]
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("Failed to install SIGTERM handler");
+    let terminated = async {
+        #[cfg(unix)]
+        sigterm.recv().await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    };
+
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {}
+        _ = terminated => {}
         _ = shutdown_notify.notified() => {
             eprintln!("stdin closed (parent process gone) — shutting down");
         }
+        _ = shutdown_requested.notified() => {
+            eprintln!("server/shutdown requested — shutting down");
+        }
     }
 
     println!();
     println!("  Shutting down...");
+    // Finish in-flight requests while clients are still connected to get the responses
+    ecp_server.drain(std::time::Duration::from_secs(5)).await;
     transport.stop().await;
 
-    // Clean up server.json on graceful shutdown
-    let _ = std::fs::remove_file(&server_json_path);
+    // Clean up server.json on graceful shutdown — unless another instance replaced it
+    let recorded_pid = std::fs::read_to_string(&server_json_path).ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .and_then(|v| v["pid"].as_u64());
+    if recorded_pid == Some(std::process::id() as u64) {
+        let _ = std::fs::remove_file(&server_json_path);
+    }
+    pid_lock.release();
 
     println!("  Server stopped.");
 }
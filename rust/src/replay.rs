@@ -0,0 +1,391 @@
+//! `ultra-ecp replay` — play a `--record` recording against a fresh server.
+//!
+//! Every workspace the recording touched is copied into a scratch directory,
+//! a new in-process server is started on the copies, and each recorded
+//! request is re-sent with its original client id. Responses are compared
+//! against the recorded ones after rewriting workspace paths and ignoring
+//! volatile fields (timestamps, generated ids). Generated ids are remapped
+//! so later requests that reference them still hit the replayed objects.
+//!
+//! The copy is taken from the workspace as it is *now*; for a faithful
+//! replay point `--workspace` at a snapshot taken before the recording.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::Args;
+use ecp_protocol::RequestContext;
+use ecp_server::{ECPServer, WorkspaceRegistry};
+use ecp_services::{chat::ChatDb, document::DocumentService, secret::SecretService};
+use ecp_transport::recorder::{read_recording, RecordKind, RecordedEvent};
+use ecp_transport::RequestHandler;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+
+/// Namespaces served by the AI bridge subprocess, which replay never starts.
+const BRIDGE_NAMESPACES: &[&str] = &["ai", "auth", "agent", "workflow", "syntax", "models"];
+
+/// Directories left out of workspace copies: version control, dependencies
+/// and the server's own `.ultra` stores.
+const SKIPPED_DIRS: &[&str] = &[".git", "node_modules", ".ultra"];
+
+/// Response fields that legitimately differ between runs.
+const VOLATILE_KEYS: &[&str] = &[
+    "timestamp", "createdAt", "updatedAt", "completedAt", "startedAt", "modTime",
+    "createTime", "mtime", "workspaceId", "journalEntryId", "entryId", "sessionId",
+    "terminalId", "watchId", "documentId",
+];
+
+/// Maximum differences printed per mismatching request.
+const MAX_DIFFS_SHOWN: usize = 10;
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    /// Recording file written by `--record`
+    file: PathBuf,
+
+    /// Workspace to copy for the recording's default workspace
+    /// (defaults to the path stored in the recording)
+    #[arg(long)]
+    workspace: Option<PathBuf>,
+
+    /// Additional response field to ignore when diffing (repeatable)
+    #[arg(long = "ignore", value_name = "KEY")]
+    ignore: Vec<String>,
+
+    /// Keep the scratch directory with the workspace copies
+    #[arg(long)]
+    keep: bool,
+}
+
+/// Run a replay. Returns the process exit code: 0 if every response matched,
+/// 1 on mismatches, 2 if the replay could not run.
+pub async fn run(args: ReplayArgs) -> i32 {
+    let events = match read_recording(&args.file) {
+        Ok(events) => events,
+        Err(e) => {
+            eprintln!("Failed to read recording {}: {e}", args.file.display());
+            return 2;
+        }
+    };
+    let Some(header) = events.first().filter(|e| e.kind == RecordKind::Header) else {
+        eprintln!("{} is not an ultra-ecp recording (missing header)", args.file.display());
+        return 2;
+    };
+
+    let scratch = std::env::temp_dir().join(format!("ultra-ecp-replay-{}", std::process::id()));
+    let code = match replay(&args, header, &events, &scratch).await {
+        Ok(report) => report.print(),
+        Err(e) => {
+            eprintln!("Replay failed: {e}");
+            2
+        }
+    };
+
+    if args.keep {
+        println!("Workspace copies kept at {}", scratch.display());
+    } else {
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+    code
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Replay
+// ─────────────────────────────────────────────────────────────────────────────
+
+async fn replay(
+    args: &ReplayArgs,
+    header: &RecordedEvent,
+    events: &[RecordedEvent],
+    scratch: &Path,
+) -> Result<Report, String> {
+    std::fs::create_dir_all(scratch).map_err(|e| format!("Failed to create {}: {e}", scratch.display()))?;
+
+    // Copy every workspace the recording touched
+    let default_root = header.message.get("workspaceRoot").and_then(|v| v.as_str()).map(String::from);
+    let mut roots: Vec<String> = default_root.iter().cloned().collect();
+    for event in events.iter().filter(|e| e.kind == RecordKind::Request) {
+        if event.message.get("method").and_then(|m| m.as_str()) == Some("workspace/open")
+            && let Some(path) = event.message.pointer("/params/path").and_then(|p| p.as_str())
+            && !roots.iter().any(|r| r == path)
+        {
+            roots.push(path.to_string());
+        }
+    }
+
+    let mut rewriter = Rewriter::default();
+    for (i, root) in roots.iter().enumerate() {
+        let source = match (&args.workspace, &default_root) {
+            (Some(ws), Some(default)) if default == root => ws.clone(),
+            _ => PathBuf::from(root),
+        };
+        let copy = scratch.join(format!("ws-{i}"));
+        copy_workspace(&source, &copy, scratch)
+            .map_err(|e| format!("Failed to copy workspace {}: {e}", source.display()))?;
+        let copy = copy.canonicalize().unwrap_or(copy);
+        rewriter.add_path(root, &copy.to_string_lossy());
+    }
+
+    // Fresh server with the same global services as `ultra-ecp --no-bridge`
+    let chat_db = ChatDb::open(&scratch.join("chat.db")).map_err(|e| format!("Failed to open chat database: {e}"))?;
+    let registry = WorkspaceRegistry::new(Arc::new(Mutex::new(chat_db)));
+    let mut server = ECPServer::new(registry);
+    server.register_service(SecretService::new_with_file_path(scratch.join("secrets.json")));
+    server.register_service(DocumentService::new());
+    server.initialize().await.map_err(|e| format!("Failed to initialize server: {e}"))?;
+
+    let mut default_ws = None;
+    if let Some(ref root) = default_root {
+        let copy = PathBuf::from(rewriter.to_replay(&Value::String(root.clone())).as_str().unwrap_or(root));
+        let (ws_id, _rx) = server.workspace_registry().open(&copy, "__default__").await
+            .map_err(|e| format!("Failed to open default workspace: {}", e.message))?;
+        server.set_default_workspace(ws_id.clone());
+        default_ws = Some(ws_id);
+    }
+
+    // Index recorded responses by (client, request id), in arrival order
+    let mut responses: HashMap<(String, String), VecDeque<Value>> = HashMap::new();
+    for event in events.iter().filter(|e| e.kind == RecordKind::Response) {
+        let key = (event.client_id.clone().unwrap_or_default(), event.message["id"].to_string());
+        responses.entry(key).or_default().push_back(event.message.clone());
+    }
+
+    let mut ignore: HashSet<&str> = VOLATILE_KEYS.iter().copied().collect();
+    ignore.extend(args.ignore.iter().map(String::as_str));
+
+    let mut report = Report::default();
+    let mut client_ws: HashMap<String, Option<String>> = HashMap::new();
+
+    for event in events {
+        let client_id = event.client_id.clone().unwrap_or_default();
+        match event.kind {
+            RecordKind::Connect => {
+                client_ws.insert(client_id, default_ws.clone());
+            }
+            RecordKind::Disconnect => {
+                server.on_client_disconnected(&client_id).await;
+                client_ws.remove(&client_id);
+            }
+            RecordKind::Request => {
+                let Some(method) = event.message.get("method").and_then(|m| m.as_str()) else {
+                    report.skipped += 1;
+                    continue;
+                };
+                let namespace = method.split('/').next().unwrap_or("");
+                if BRIDGE_NAMESPACES.contains(&namespace) {
+                    report.skipped += 1;
+                    continue;
+                }
+
+                let key = (client_id.clone(), event.message["id"].to_string());
+                let Some(expected) = responses.get_mut(&key).and_then(|q| q.pop_front()) else {
+                    report.skipped += 1;
+                    continue;
+                };
+
+                let params = event.message.get("params").map(|p| rewriter.to_replay(p));
+                let context = RequestContext {
+                    client_id: client_id.clone(),
+                    workspace_id: client_ws.get(&client_id).cloned().flatten(),
+                };
+                let result = server.handle_request(method, params, context).await;
+
+                // Mirror the transport's per-connection workspace tracking
+                if let Ok(ref value) = result {
+                    if let Some(ws_id) = value.get("workspaceId").and_then(|v| v.as_str()) {
+                        client_ws.insert(client_id.clone(), Some(ws_id.to_string()));
+                    } else if value.get("workspaceClosed").and_then(|v| v.as_bool()) == Some(true) {
+                        client_ws.insert(client_id.clone(), None);
+                    }
+                }
+
+                let actual = match result {
+                    Ok(value) => json!({ "result": rewriter.to_recorded(&value) }),
+                    Err(e) => json!({ "error": rewriter.to_recorded(&serde_json::to_value(&e).unwrap_or_default()) }),
+                };
+                let expected = match expected.get("error") {
+                    Some(err) => json!({ "error": err }),
+                    None => json!({ "result": expected.get("result").cloned().unwrap_or(Value::Null) }),
+                };
+
+                let mut diffs = Vec::new();
+                compare("", &expected, &actual, &ignore, &mut rewriter, &mut diffs);
+                report.replayed += 1;
+                if !diffs.is_empty() {
+                    report.mismatches.push(Mismatch {
+                        method: method.to_string(),
+                        id: event.message["id"].clone(),
+                        diffs,
+                    });
+                }
+            }
+            RecordKind::Header | RecordKind::Response | RecordKind::Notification => {}
+        }
+    }
+
+    server.workspace_registry().shutdown_all().await;
+    Ok(report)
+}
+
+/// Recursively compare two JSON values, collecting `(pointer, expected, actual)`
+/// differences. Volatile keys are skipped; differing volatile string values
+/// are registered as id substitutions for later requests.
+fn compare(
+    pointer: &str,
+    expected: &Value,
+    actual: &Value,
+    ignore: &HashSet<&str>,
+    rewriter: &mut Rewriter,
+    diffs: &mut Vec<(String, Value, Value)>,
+) {
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            let keys: std::collections::BTreeSet<&String> = e.keys().chain(a.keys()).collect();
+            for key in keys {
+                let child = format!("{pointer}/{key}");
+                let (ev, av) = (e.get(key).unwrap_or(&Value::Null), a.get(key).unwrap_or(&Value::Null));
+                if ignore.contains(key.as_str()) {
+                    if let (Value::String(from), Value::String(to)) = (ev, av)
+                        && from != to
+                    {
+                        rewriter.add_id(from, to);
+                    }
+                    continue;
+                }
+                compare(&child, ev, av, ignore, rewriter, diffs);
+            }
+        }
+        (Value::Array(e), Value::Array(a)) if e.len() == a.len() => {
+            for (i, (ev, av)) in e.iter().zip(a).enumerate() {
+                compare(&format!("{pointer}/{i}"), ev, av, ignore, rewriter, diffs);
+            }
+        }
+        _ if expected != actual => {
+            let pointer = if pointer.is_empty() { "/" } else { pointer };
+            diffs.push((pointer.to_string(), expected.clone(), actual.clone()));
+        }
+        _ => {}
+    }
+}
+
+/// Rewrites strings between the recorded world and the replayed one:
+/// workspace paths and generated ids.
+#[derive(Default)]
+struct Rewriter {
+    /// (recorded path, replay path), longest recorded path first
+    paths: Vec<(String, String)>,
+    /// recorded id → replayed id
+    ids: HashMap<String, String>,
+}
+
+impl Rewriter {
+    fn add_path(&mut self, recorded: &str, replay: &str) {
+        self.paths.push((recorded.to_string(), replay.to_string()));
+        self.paths.sort_by_key(|(r, _)| std::cmp::Reverse(r.len()));
+    }
+
+    fn add_id(&mut self, recorded: &str, replay: &str) {
+        self.ids.insert(recorded.to_string(), replay.to_string());
+    }
+
+    fn to_replay(&self, value: &Value) -> Value {
+        map_strings(value, &|s| {
+            if let Some(id) = self.ids.get(s) {
+                return id.clone();
+            }
+            replace_paths(s, self.paths.iter().map(|(r, p)| (r.as_str(), p.as_str())))
+        })
+    }
+
+    fn to_recorded(&self, value: &Value) -> Value {
+        map_strings(value, &|s| replace_paths(s, self.paths.iter().map(|(r, p)| (p.as_str(), r.as_str()))))
+    }
+}
+
+/// Replace workspace paths anywhere in a string — they also appear inside
+/// error messages, not just as whole values.
+fn replace_paths<'a>(s: &str, pairs: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+    let mut out = s.to_string();
+    for (from, to) in pairs {
+        if out.contains(from) {
+            out = out.replace(from, to);
+        }
+    }
+    out
+}
+
+fn map_strings(value: &Value, f: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::String(s) => Value::String(f(s)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| map_strings(v, f)).collect()),
+        Value::Object(obj) => Value::Object(obj.iter().map(|(k, v)| (k.clone(), map_strings(v, f))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Copy a workspace, leaving out [`SKIPPED_DIRS`] and the replay's own
+/// scratch directory, which is inside the workspace when
+/// that is the temp directory or one of its parents.
+fn copy_workspace(from: &Path, to: &Path, scratch: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    copy_dir(&from.canonicalize()?, to, &scratch.canonicalize()?)
+}
+
+fn copy_dir(from: &Path, to: &Path, scratch: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            let path = entry.path();
+            if path == scratch || SKIPPED_DIRS.iter().any(|dir| entry.file_name() == *dir) {
+                continue;
+            }
+            copy_dir(&path, &target, scratch)?;
+        } else if file_type.is_file() {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Report
+// ─────────────────────────────────────────────────────────────────────────────
+
+struct Mismatch {
+    method: String,
+    id: Value,
+    diffs: Vec<(String, Value, Value)>,
+}
+
+#[derive(Default)]
+struct Report {
+    replayed: usize,
+    skipped: usize,
+    mismatches: Vec<Mismatch>,
+}
+
+impl Report {
+    fn print(&self) -> i32 {
+        for m in &self.mismatches {
+            println!("✗ {} (id {})", m.method, m.id);
+            for (pointer, expected, actual) in m.diffs.iter().take(MAX_DIFFS_SHOWN) {
+                println!("    {pointer}");
+                println!("      recorded: {expected}");
+                println!("      replayed: {actual}");
+            }
+            if m.diffs.len() > MAX_DIFFS_SHOWN {
+                println!("    … {} more", m.diffs.len() - MAX_DIFFS_SHOWN);
+            }
+        }
+        println!(
+            "Replayed {} requests ({} skipped): {} mismatches",
+            self.replayed, self.skipped, self.mismatches.len(),
+        );
+        if self.mismatches.is_empty() { 0 } else { 1 }
+    }
+}
@@ -79,6 +79,7 @@ async fn start_test_server_with_services() -> (u16, String) {
         verbose_logging: false,
         tls: None,
         cert_fingerprint: None,
+        recorder: None,
     };
 
     let transport = TransportServer::start(config, ecp_server).await.unwrap();
@@ -586,6 +587,7 @@ async fn start_test_server_no_workspace() -> (u16, String) {
         verbose_logging: false,
         tls: None,
         cert_fingerprint: None,
+        recorder: None,
     };
 
     let transport = TransportServer::start(config, ecp_server).await.unwrap();
@@ -735,3 +737,655 @@ async fn server_writes_server_json_and_auth_token() {
     // Verify auth-token persists (not cleaned up)
     assert!(token_path.exists(), "auth-token should persist after shutdown");
 }
+
+#[tokio::test]
+async fn record_then_replay_matches() {
+    let bin = binary_path();
+    let fake_home = TempDir::new().unwrap();
+    let workspace = TempDir::new().unwrap();
+    std::fs::write(workspace.path().join("hello.txt"), "hello").unwrap();
+    let recording = fake_home.path().join("session.jsonl");
+
+    let mut child = std::process::Command::new(&bin)
+        .args(["--no-bridge", "--no-tls", "--port", "0", "--record"])
+        .arg(&recording)
+        .arg("--workspace")
+        .arg(workspace.path())
+        .env("HOME", fake_home.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to spawn ultra-ecp");
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let server_json: Value = serde_json::from_str(
+        &std::fs::read_to_string(fake_home.path().join(".ultra/server.json")).unwrap()
+    ).unwrap();
+    let port = server_json["port"].as_u64().unwrap() as u16;
+    let token = server_json["token"].as_str().unwrap().to_string();
+
+    let mut ws = connect_and_auth(port, &token).await;
+    let resp = send_request(&mut ws, 1, "file/read", Some(json!({"path": "hello.txt"}))).await;
+    assert_eq!(resp["result"]["content"], "hello");
+    let resp = send_request(&mut ws, 2, "file/write", Some(json!({"path": "new.txt", "content": "x"}))).await;
+    assert_eq!(resp["result"]["success"], true);
+    let resp = send_request(&mut ws, 3, "file/read", Some(json!({"path": "missing.txt"}))).await;
+    assert!(resp.get("error").is_some());
+    ws.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    drop(child.stdin.take());
+    tokio::task::spawn_blocking(move || child.wait()).await.unwrap().unwrap();
+
+    // Every line is a JSON event; the handshake token is never written
+    let content = std::fs::read_to_string(&recording).unwrap();
+    assert!(!content.contains(&token), "recording must not contain the auth token");
+    let events: Vec<Value> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(events[0]["kind"], "header");
+    assert_eq!(events.iter().filter(|e| e["kind"] == "request").count(), 3);
+    assert_eq!(events.iter().filter(|e| e["kind"] == "response").count(), 3);
+    assert!(events.iter().all(|e| e["ts"].as_u64().is_some()));
+
+    // Replay against a pristine copy of the workspace
+    let pristine = TempDir::new().unwrap();
+    std::fs::write(pristine.path().join("hello.txt"), "hello").unwrap();
+    let output = std::process::Command::new(&bin)
+        .arg("replay")
+        .arg(&recording)
+        .arg("--workspace")
+        .arg(pristine.path())
+        .env("HOME", fake_home.path())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "replay should match: {stdout}");
+    assert!(stdout.contains("Replayed 3 requests (0 skipped): 0 mismatches"), "{stdout}");
+    assert!(!pristine.path().join("new.txt").exists(), "replay must work on a copy");
+
+    // A workspace that differs from the recording is reported
+    std::fs::write(pristine.path().join("hello.txt"), "changed").unwrap();
+    let output = std::process::Command::new(&bin)
+        .arg("replay")
+        .arg(&recording)
+        .arg("--workspace")
+        .arg(pristine.path())
+        .env("HOME", fake_home.path())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("/result/content"), "{stdout}");
+}
+
+#[tokio::test]
+async fn cli_client_subcommands_against_running_server() {
+    let bin = binary_path();
+    let fake_home = TempDir::new().unwrap();
+    let workspace = TempDir::new().unwrap();
+    std::fs::write(workspace.path().join("hello.txt"), "hello from cli").unwrap();
+
+    // TLS enabled: the client must pin the generated self-signed certificate
+    let mut child = std::process::Command::new(&bin)
+        .args(["--no-bridge", "--port", "0", "--workspace"])
+        .arg(workspace.path())
+        .env("HOME", fake_home.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to spawn ultra-ecp");
+
+    // Certificate generation can take a moment in debug builds
+    let server_json_path = fake_home.path().join(".ultra/server.json");
+    for _ in 0..100 {
+        if server_json_path.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let run = |args: &[&str]| {
+        std::process::Command::new(&bin)
+            .args(args)
+            .env("HOME", fake_home.path())
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["call", "file/read", r#"{"path":"hello.txt"}"#]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let result: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["content"], "hello from cli");
+
+    // JSON-RPC errors exit with 1 and go to stderr
+    let output = run(&["call", "nope/nothing"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("-32601"));
+
+    let output = run(&["workspaces", "--json"]);
+    let result: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let workspaces = result["workspaces"].as_array().unwrap();
+    assert_eq!(workspaces.len(), 1);
+    assert_eq!(result["defaultWorkspaceId"], workspaces[0]["workspaceId"]);
+
+    let output = run(&["clients", "--json"]);
+    let result: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let clients = result["clients"].as_array().unwrap();
+    assert_eq!(clients.len(), 1, "only the CLI itself is connected: {result}");
+    assert_eq!(clients[0]["name"], "headless-cli");
+
+    // A certificate that doesn't match the pinned fingerprint is refused
+    let original = std::fs::read_to_string(&server_json_path).unwrap();
+    let mut info: Value = serde_json::from_str(&original).unwrap();
+    assert_eq!(info["scheme"], "wss");
+    info["certFingerprint"] = json!(format!("sha256:{}", "00".repeat(32)));
+    std::fs::write(&server_json_path, info.to_string()).unwrap();
+    let output = run(&["call", "file/read", r#"{"path":"hello.txt"}"#]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("fingerprint mismatch"));
+    std::fs::write(&server_json_path, original).unwrap();
+
+    drop(child.stdin.take());
+    tokio::task::spawn_blocking(move || child.wait()).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn single_instance_status_stop_restart() {
+    let bin = binary_path();
+    let fake_home = TempDir::new().unwrap();
+    let ultra_dir = fake_home.path().join(".ultra");
+    let server_json_path = ultra_dir.join("server.json");
+    let pid_path = ultra_dir.join("server.pid");
+    let run = |args: &[&str]| {
+        std::process::Command::new(&bin)
+            .args(args)
+            .env("HOME", fake_home.path())
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let recorded_pid = || -> u64 {
+        let info: Value = serde_json::from_str(&std::fs::read_to_string(&server_json_path).unwrap()).unwrap();
+        info["pid"].as_u64().unwrap()
+    };
+
+    let mut child = std::process::Command::new(&bin)
+        .args(["--no-bridge", "--no-tls", "--port", "0"])
+        .env("HOME", fake_home.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to spawn ultra-ecp");
+    let first_pid = child.id() as u64;
+    let stdin = child.stdin.take();
+    // Reap the server as soon as it exits so liveness checks see it gone
+    let reaper = std::thread::spawn(move || child.wait().unwrap());
+
+    for _ in 0..100 {
+        if server_json_path.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(recorded_pid(), first_pid);
+    assert_eq!(std::fs::read_to_string(&pid_path).unwrap().trim(), first_pid.to_string());
+
+    // A second server refuses to start, or attaches on request
+    let output = run(&["--no-bridge", "--no-tls", "--port", "0"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already running"));
+    let output = run(&["--no-bridge", "--no-tls", "--port", "0", "--on-existing", "attach"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!("pid {first_pid}")));
+    assert_eq!(recorded_pid(), first_pid, "second instance must not clobber server.json");
+
+    let output = run(&["status", "--json"]);
+    assert!(output.status.success());
+    let status: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["running"], true);
+    assert_eq!(status["pid"].as_u64(), Some(first_pid));
+    assert_eq!(status["clients"], 0);
+
+    // restart stops the server and brings it back with the same arguments
+    let output = run(&["restart"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let status = reaper.join().unwrap();
+    assert!(status.success(), "graceful shutdown should exit cleanly");
+    let second_pid = recorded_pid();
+    assert_ne!(second_pid, first_pid);
+
+    let output = run(&["stop"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!server_json_path.exists(), "server.json removed on stop");
+    assert!(!pid_path.exists(), "pid file removed on stop");
+    drop(stdin);
+
+    let output = run(&["status"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    // server.json left behind by a dead process is cleaned up
+    let mut dead = std::process::Command::new("true").spawn().unwrap();
+    let dead_pid = dead.id();
+    dead.wait().unwrap();
+    std::fs::write(&server_json_path, json!({
+        "host": "127.0.0.1", "port": 1, "scheme": "ws", "token": "x", "pid": dead_pid,
+    }).to_string()).unwrap();
+    let output = run(&["status"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!server_json_path.exists(), "stale server.json should be removed");
+
+    // A pid file nobody holds the lock on (a crash mid-write) doesn't block a start
+    std::fs::write(&pid_path, "").unwrap();
+    let mut child = std::process::Command::new(&bin)
+        .args(["--no-bridge", "--no-tls", "--port", "0"])
+        .env("HOME", fake_home.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to spawn ultra-ecp");
+    let third_pid = child.id() as u64;
+    for _ in 0..100 {
+        if server_json_path.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(recorded_pid(), third_pid);
+    assert_eq!(std::fs::read_to_string(&pid_path).unwrap().trim(), third_pid.to_string());
+    drop(child.stdin.take());
+    tokio::task::spawn_blocking(move || child.wait()).await.unwrap().unwrap();
+    assert!(!pid_path.exists(), "pid file removed on shutdown");
+}
+
+/// Read messages until a notification with the given method arrives; returns its params.
+async fn next_notification(
+    ws: &mut tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+    method: &str,
+) -> Value {
+    loop {
+        let msg = timeout(Duration::from_secs(10), ws.next())
+            .await
+            .unwrap_or_else(|_| panic!("Timeout waiting for {method}"))
+            .expect("Stream ended")
+            .expect("WebSocket error");
+        let Ok(text) = msg.into_text() else { continue };
+        let parsed: Value = serde_json::from_str(&text).unwrap_or_default();
+        if parsed["method"] == method {
+            return parsed["params"].clone();
+        }
+    }
+}
+
+#[tokio::test]
+async fn server_config_file_and_live_reload() {
+    let bin = binary_path();
+    let fake_home = TempDir::new().unwrap();
+    let workspace = TempDir::new().unwrap();
+    let ultra_dir = fake_home.path().join(".ultra");
+    let config_path = ultra_dir.join("server.toml");
+    std::fs::create_dir_all(&ultra_dir).unwrap();
+    // The --port flag overrides the file; everything else comes from the file
+    std::fs::write(&config_path, r#"
+[server]
+port = 1
+
+[tls]
+enabled = false
+
+[bridge]
+enabled = false
+"#).unwrap();
+
+    let mut child = std::process::Command::new(&bin)
+        .args(["--port", "0", "--token", "config-test-token", "--workspace"])
+        .arg(workspace.path())
+        .env("HOME", fake_home.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to spawn ultra-ecp");
+
+    let server_json_path = ultra_dir.join("server.json");
+    for _ in 0..100 {
+        if server_json_path.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let info: Value = serde_json::from_str(&std::fs::read_to_string(&server_json_path).unwrap()).unwrap();
+    assert_eq!(info["scheme"], "ws", "TLS disabled by the config file");
+    let port = info["port"].as_u64().unwrap() as u16;
+    assert_ne!(port, 1);
+
+    let mut ws = connect_and_auth(port, "config-test-token").await;
+
+    std::fs::write(&config_path, r#"
+[server]
+port = 2
+hostname = "localhost"
+
+[tls]
+enabled = false
+
+[bridge]
+enabled = false
+
+[quotas.workspace]
+terminals = 1
+"#).unwrap();
+    let params = next_notification(&mut ws, "server/configChanged").await;
+    assert_eq!(params["applied"], json!(["quotas"]));
+    assert_eq!(params["requiresRestart"], json!(["server.hostname"]), "port is pinned by the flag");
+
+    // The new terminal quota applies to the running workspace
+    let resp = send_request(&mut ws, 1, "terminal/create", Some(json!({"shell": "/bin/sh"}))).await;
+    assert!(resp["result"]["terminalId"].is_string(), "{resp}");
+    let resp = send_request(&mut ws, 2, "terminal/create", Some(json!({"shell": "/bin/sh"}))).await;
+    assert_eq!(resp["error"]["code"], -32031, "{resp}");
+
+    // An invalid file is reported and the previous settings stay in effect
+    std::fs::write(&config_path, "[quotas.workspace]\nterminals = \"lots\"\n").unwrap();
+    let params = next_notification(&mut ws, "server/configChanged").await;
+    assert!(params["error"].as_str().unwrap().contains("terminals"), "{params}");
+    let resp = send_request(&mut ws, 3, "terminal/create", Some(json!({"shell": "/bin/sh"}))).await;
+    assert!(resp.get("error").is_some(), "{resp}");
+
+    drop(child.stdin.take());
+    tokio::task::spawn_blocking(move || child.wait()).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn quotas_limit_per_client_and_workspace() {
+    use ecp_protocol::RequestContext;
+    use ecp_server::{ECPServer, QuotaLimits, Quotas, WorkspaceRegistry};
+    use ecp_services::chat::ChatDb;
+    use ecp_transport::RequestHandler;
+
+    let tmp = TempDir::new().unwrap();
+    let global_chat_db = Arc::new(Mutex::new(
+        ChatDb::open(&tmp.path().join(".ultra-global/chat.db")).unwrap(),
+    ));
+    let mut server = ECPServer::new(WorkspaceRegistry::new(global_chat_db));
+    let limits = |terminals, concurrent_executes| QuotaLimits {
+        terminals,
+        concurrent_executes,
+        lsp_clients: 0,
+        db_connections: 0,
+        watches: 0,
+    };
+    server.set_quotas(Arc::new(parking_lot::RwLock::new(Quotas {
+        workspace: limits(3, 0),
+        client: limits(2, 1),
+    })));
+    server.initialize().await.unwrap();
+
+    let workspace = TempDir::new().unwrap();
+    let open = |client: &str| {
+        let context = RequestContext { client_id: client.into(), workspace_id: None };
+        server.handle_request("workspace/open", Some(json!({"path": workspace.path()})), context)
+    };
+    let ws_id = open("a").await.unwrap()["workspaceId"].as_str().unwrap().to_string();
+    open("b").await.unwrap();
+    let ctx = |client: &str| RequestContext { client_id: client.into(), workspace_id: Some(ws_id.clone()) };
+    let create = json!({"shell": "/bin/sh"});
+
+    let first = server.handle_request("terminal/create", Some(create.clone()), ctx("a")).await.unwrap();
+    server.handle_request("terminal/create", Some(create.clone()), ctx("a")).await.unwrap();
+    let err = server.handle_request("terminal/create", Some(create.clone()), ctx("a")).await.unwrap_err();
+    assert_eq!(err.code, -32031);
+    assert_eq!(err.data.as_ref().unwrap()["scope"], "client");
+
+    server.handle_request("terminal/create", Some(create.clone()), ctx("b")).await.unwrap();
+    let err = server.handle_request("terminal/create", Some(create.clone()), ctx("b")).await.unwrap_err();
+    assert_eq!(err.data.as_ref().unwrap()["scope"], "workspace");
+    assert_eq!(err.data.as_ref().unwrap()["limit"], 3);
+
+    let resources = server.handle_request("workspace/resources", None, ctx("a")).await.unwrap();
+    assert_eq!(resources["workspaceId"], ws_id.as_str());
+    assert_eq!(resources["resources"]["terminals"], json!({"used": 3, "limit": 3}));
+    assert_eq!(resources["resources"]["watches"]["limit"], Value::Null);
+    let client_a = resources["clients"].as_array().unwrap().iter()
+        .find(|c| c["clientId"] == "a").unwrap();
+    assert_eq!(client_a["resources"]["terminals"], json!({"used": 2, "limit": 2}));
+
+    // Closing frees the slot
+    server.handle_request("terminal/close", Some(json!({"id": first["terminalId"]})), ctx("a")).await.unwrap();
+    server.handle_request("terminal/create", Some(create.clone()), ctx("b")).await.unwrap();
+
+    // Concurrent execute slots are held only while the command runs
+    let slow = server.handle_request("terminal/execute", Some(json!({"command": "sleep 0.5"})), ctx("a"));
+    let fast = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server.handle_request("terminal/execute", Some(json!({"command": "true"})), ctx("a")).await
+    };
+    let (slow, fast) = tokio::join!(slow, fast);
+    assert!(slow.is_ok());
+    assert_eq!(fast.unwrap_err().data.unwrap()["resource"], "concurrentExecutes");
+    server.handle_request("terminal/execute", Some(json!({"command": "true"})), ctx("a")).await.unwrap();
+
+    server.drain(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn quotas_free_what_a_disconnected_client_held() {
+    use ecp_protocol::RequestContext;
+    use ecp_server::{ECPServer, QuotaLimits, Quotas, WorkspaceRegistry};
+    use ecp_services::chat::ChatDb;
+    use ecp_transport::RequestHandler;
+
+    let tmp = TempDir::new().unwrap();
+    let global_chat_db = Arc::new(Mutex::new(
+        ChatDb::open(&tmp.path().join(".ultra-global/chat.db")).unwrap(),
+    ));
+    let mut server = ECPServer::new(WorkspaceRegistry::new(global_chat_db));
+    let limits = QuotaLimits { terminals: 0, concurrent_executes: 0, lsp_clients: 0, db_connections: 0, watches: 1 };
+    server.set_quotas(Arc::new(parking_lot::RwLock::new(Quotas { workspace: limits, client: limits })));
+    server.initialize().await.unwrap();
+
+    let workspace = TempDir::new().unwrap();
+    let open = |client: &str| {
+        let context = RequestContext { client_id: client.into(), workspace_id: None };
+        server.handle_request("workspace/open", Some(json!({"path": workspace.path()})), context)
+    };
+    let ws_id = open("a").await.unwrap()["workspaceId"].as_str().unwrap().to_string();
+    open("b").await.unwrap();
+    let ctx = |client: &str| RequestContext { client_id: client.into(), workspace_id: Some(ws_id.clone()) };
+    let watch = json!({"path": workspace.path()});
+
+    server.handle_request("watch/start", Some(watch.clone()), ctx("a")).await.unwrap();
+    let err = server.handle_request("watch/start", Some(watch.clone()), ctx("b")).await.unwrap_err();
+    assert_eq!(err.code, -32031);
+
+    // The watch goes with its client, and so does the slot
+    server.on_client_disconnected("a").await;
+    server.handle_request("watch/start", Some(watch.clone()), ctx("b")).await.unwrap();
+    let resources = server.handle_request("workspace/resources", None, ctx("b")).await.unwrap();
+    assert_eq!(resources["resources"]["watches"], json!({"used": 1, "limit": 1}));
+
+    server.drain(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn async_executes_hold_their_slot_until_they_end() {
+    use ecp_protocol::RequestContext;
+    use ecp_server::{ECPServer, QuotaLimits, Quotas, WorkspaceRegistry};
+    use ecp_services::chat::ChatDb;
+    use ecp_transport::RequestHandler;
+
+    let tmp = TempDir::new().unwrap();
+    let global_chat_db = Arc::new(Mutex::new(
+        ChatDb::open(&tmp.path().join(".ultra-global/chat.db")).unwrap(),
+    ));
+    let mut server = ECPServer::new(WorkspaceRegistry::new(global_chat_db));
+    let limits = QuotaLimits { terminals: 0, concurrent_executes: 2, lsp_clients: 0, db_connections: 0, watches: 0 };
+    server.set_quotas(Arc::new(parking_lot::RwLock::new(Quotas { workspace: limits, client: limits })));
+    server.initialize().await.unwrap();
+
+    let workspace = TempDir::new().unwrap();
+    let context = RequestContext { client_id: "a".into(), workspace_id: None };
+    let opened = server.handle_request("workspace/open", Some(json!({"path": workspace.path()})), context)
+        .await.unwrap();
+    let ctx = RequestContext { client_id: "a".into(), workspace_id: Some(opened["workspaceId"].as_str().unwrap().to_string()) };
+    let execute = |command: &str| server.handle_request("terminal/execute", Some(json!({
+        "command": command, "async": true,
+    })), ctx.clone());
+
+    let first = execute("sleep 30").await.unwrap();
+    execute("sleep 30").await.unwrap();
+    let err = execute("sleep 30").await.unwrap_err();
+    assert_eq!(err.code, -32031);
+    assert_eq!(server.handle_request("terminal/execute", Some(json!({"command": "true"})), ctx.clone())
+        .await.unwrap_err().code, -32031);
+    let resources = server.handle_request("workspace/resources", None, ctx.clone()).await.unwrap();
+    assert_eq!(resources["resources"]["concurrentExecutes"], json!({"used": 2, "limit": 2}));
+
+    // A killed command gives its slot back
+    let kill = json!({"executionId": first["executionId"]});
+    server.handle_request("terminal/execute/kill", Some(kill), ctx.clone()).await.unwrap();
+    let mut freed = false;
+    for _ in 0..50 {
+        if execute("true").await.is_ok() {
+            freed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(freed);
+
+    server.on_client_disconnected("a").await;
+    server.drain(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn sandbox_confines_paths_to_workspace_and_allowlist() {
+    use ecp_protocol::RequestContext;
+    use ecp_server::{ECPServer, SandboxConfig, WorkspaceRegistry};
+    use ecp_services::chat::ChatDb;
+    use ecp_transport::RequestHandler;
+
+    let tmp = TempDir::new().unwrap();
+    let workspace = tmp.path().join("workspace");
+    let outside = tmp.path().join("outside");
+    let allowed = tmp.path().join("allowed");
+    for dir in [&workspace, &outside, &allowed] {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    std::fs::write(workspace.join("inside.txt"), "inside").unwrap();
+    std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+    std::fs::write(allowed.join("shared.txt"), "shared").unwrap();
+    std::os::unix::fs::symlink(&outside, workspace.join("escape")).unwrap();
+    std::os::unix::fs::symlink(outside.join("planted.txt"), workspace.join("dangling")).unwrap();
+
+    let global_chat_db = Arc::new(Mutex::new(
+        ChatDb::open(&tmp.path().join(".ultra-global/chat.db")).unwrap(),
+    ));
+    let mut server = ECPServer::new(WorkspaceRegistry::new(global_chat_db));
+    let audit_log = tmp.path().join("audit.jsonl");
+    let sandbox = Arc::new(parking_lot::RwLock::new(SandboxConfig {
+        enabled: true,
+        allow: vec![allowed.clone()],
+        deny: vec![allowed.join("server.toml")],
+        audit_log: Some(audit_log.clone()),
+    }));
+    server.set_sandbox(sandbox.clone());
+    server.initialize().await.unwrap();
+
+    let open_ctx = RequestContext { client_id: "agent".into(), workspace_id: None };
+    let opened = server.handle_request("workspace/open", Some(json!({"path": workspace})), open_ctx)
+        .await.unwrap();
+    let ctx = RequestContext {
+        client_id: "agent".into(),
+        workspace_id: Some(opened["workspaceId"].as_str().unwrap().to_string()),
+    };
+    let call = |method: &'static str, params: Value| server.handle_request(method, Some(params), ctx.clone());
+
+    // Inside the workspace and allowlist
+    assert_eq!(call("file/read", json!({"path": "inside.txt"})).await.unwrap()["content"], "inside");
+    let shared = allowed.join("shared.txt");
+    assert_eq!(call("file/read", json!({"path": shared})).await.unwrap()["content"], "shared");
+
+    // Escapes via `..`, symlinks, dangling symlinks and globs
+    let err = call("file/read", json!({"path": "../outside/secret.txt"})).await.unwrap_err();
+    assert_eq!(err.code, -32032);
+    let outside_canonical = outside.canonicalize().unwrap();
+    assert_eq!(
+        err.data.as_ref().unwrap()["resolved"],
+        outside_canonical.join("secret.txt").to_string_lossy().as_ref(),
+    );
+    let err = call("file/read", json!({"uri": format!("file://{}/escape/secret.txt", workspace.display())}))
+        .await.unwrap_err();
+    assert_eq!(err.code, -32032);
+    let err = call("file/write", json!({"path": "dangling", "content": "x"})).await.unwrap_err();
+    assert_eq!(err.code, -32032);
+    assert!(!outside.join("planted.txt").exists());
+    assert_eq!(call("file/glob", json!({"pattern": "../outside/*"})).await.unwrap_err().code, -32032);
+    assert!(call("file/glob", json!({"pattern": "**/*.txt"})).await.is_ok());
+
+    // Other path-taking namespaces
+    let err = call("terminal/create", json!({"cwd": outside})).await.unwrap_err();
+    assert_eq!(err.code, -32032);
+    let err = call("watch/start", json!({"uri": outside})).await.unwrap_err();
+    assert_eq!(err.code, -32032);
+    let err = call("git/diff", json!({"path": "../outside/secret.txt"})).await.unwrap_err();
+    assert_eq!(err.code, -32032);
+    let uri = format!("file://{}", outside.join("secret.txt").display());
+    let err = call("document/open", json!({"uri": uri})).await.unwrap_err();
+    assert_eq!(err.code, -32032);
+    let token = ecp_services::file_tree::expand_token(&workspace.join("escape"), &Default::default());
+    let err = call("file/tree", json!({"expandToken": token})).await.unwrap_err();
+    assert_eq!(err.code, -32032);
+
+    // Denied files stay out of reach inside an allowed directory
+    let config = allowed.join("server.toml");
+    let err = call("file/write", json!({"path": config, "content": "[sandbox]\nenabled = false\n"})).await.unwrap_err();
+    assert_eq!(err.code, -32032);
+    assert!(!config.exists());
+
+    // Each violation is audited
+    let audit = std::fs::read_to_string(&audit_log).unwrap();
+    let entries: Vec<Value> = audit.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(entries.len(), 10);
+    assert_eq!(entries[0]["method"], "file/read");
+    assert_eq!(entries[0]["clientId"], "agent");
+    assert_eq!(entries[0]["path"], "../outside/secret.txt");
+    assert_eq!(entries[4]["param"], "cwd");
+
+    // Reloads may tighten the sandbox but not loosen it
+    let current = sandbox.read().clone();
+    let tighter = SandboxConfig { allow: Vec::new(), ..current.clone() };
+    assert!(!tighter.loosens(&current));
+    assert!(SandboxConfig { enabled: false, ..current.clone() }.loosens(&current));
+    assert!(SandboxConfig { allow: vec!["/".into()], ..current.clone() }.loosens(&current));
+    assert!(SandboxConfig { deny: Vec::new(), ..current.clone() }.loosens(&current));
+
+    // Turning the sandbox off restores unrestricted access
+    sandbox.write().enabled = false;
+    assert_eq!(call("file/read", json!({"path": "../outside/secret.txt"})).await.unwrap()["content"], "secret");
+
+    server.drain(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn terminal_output_and_exit_reach_workspace_clients() {
+    let (port, token) = start_test_server_no_workspace().await;
+    let mut ws = connect_and_auth(port, &token).await;
+    let workspace = TempDir::new().unwrap();
+    let resp = send_request(&mut ws, 1, "workspace/open", Some(json!({"path": workspace.path()}))).await;
+    assert!(resp["result"]["workspaceId"].is_string(), "{resp}");
+
+    let resp = send_request(&mut ws, 2, "terminal/spawn", Some(json!({
+        "command": "sleep 0.2; echo streamed; exit 7",
+    }))).await;
+    let id = resp["result"]["terminalId"].clone();
+
+    let output = next_notification(&mut ws, "terminal/output").await;
+    assert_eq!(output["terminalId"], id);
+    assert_eq!(output["seq"], 0);
+    assert!(output["data"].as_str().unwrap().contains("streamed"));
+    let exit = next_notification(&mut ws, "terminal/exit").await;
+    assert_eq!(exit["exitCode"], 7);
+}
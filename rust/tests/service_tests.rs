@@ -4,6 +4,9 @@
 //! verifying JSON-RPC request/response behavior exactly as the Mac client experiences it.
 //! Wire format parity with the TypeScript ECP is validated here.
 
+// Length comparisons read closer to the wire format checks they make.
+#![allow(clippy::len_zero)]
+
 use serde_json::json;
 use tempfile::TempDir;
 
@@ -650,6 +653,107 @@ mod file {
         assert_eq!(exists["exists"], false);
     }
 
+    #[tokio::test]
+    async fn delete_moves_to_trash_and_restores() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        std::fs::create_dir_all(tmp.path().join("dir/nested")).unwrap();
+        std::fs::write(tmp.path().join("dir/nested/a.txt"), "aaa").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "bb").unwrap();
+
+        let result = s.handle("file/deleteDir", Some(json!({"path": "dir"}))).await.unwrap();
+        let dir_id = result["trashId"].as_str().unwrap().to_string();
+        s.handle("file/delete", Some(json!({"path": "b.txt"}))).await.unwrap();
+        assert!(!tmp.path().join("dir").exists());
+        // file/delete still refuses directories
+        std::fs::create_dir(tmp.path().join("other")).unwrap();
+        assert!(s.handle("file/delete", Some(json!({"path": "other"}))).await.is_err());
+
+        let list = s.handle("file/trash/list", None).await.unwrap();
+        let items = list["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        let dir = items.iter().find(|i| i["id"] == dir_id.as_str()).unwrap();
+        assert_eq!(dir["originalPath"], "dir");
+        assert_eq!(dir["isDirectory"], true);
+        assert_eq!(dir["size"], 3);
+
+        // Restoring over an existing path is a conflict
+        std::fs::create_dir(tmp.path().join("dir")).unwrap();
+        let err = s.handle("file/trash/restore", Some(json!({"id": &dir_id}))).await.unwrap_err();
+        assert_eq!(err.code, -32030);
+        std::fs::remove_dir(tmp.path().join("dir")).unwrap();
+        s.handle("file/trash/restore", Some(json!({"id": &dir_id}))).await.unwrap();
+        assert_eq!(std::fs::read_to_string(tmp.path().join("dir/nested/a.txt")).unwrap(), "aaa");
+
+        let result = s.handle("file/trash/empty", None).await.unwrap();
+        assert_eq!(result["removed"], 1);
+        let list = s.handle("file/trash/list", None).await.unwrap();
+        assert!(list["items"].as_array().unwrap().is_empty());
+        let err = s.handle("file/trash/restore", Some(json!({"id": "../../etc"}))).await.unwrap_err();
+        assert_eq!(err.code, -32602);
+    }
+
+    #[tokio::test]
+    async fn permanent_delete_skips_trash() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        std::fs::write(tmp.path().join("gone.txt"), "x").unwrap();
+
+        let result = s.handle("file/delete", Some(json!({"path": "gone.txt", "permanent": true}))).await.unwrap();
+        assert!(result["trashId"].is_null());
+        assert!(!tmp.path().join("gone.txt").exists());
+        let list = s.handle("file/trash/list", None).await.unwrap();
+        assert!(list["items"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tree_lists_depth_sorted_with_expand_tokens() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        std::fs::create_dir_all(tmp.path().join("src/deep")).unwrap();
+        std::fs::write(tmp.path().join("src/deep/x.rs"), "x").unwrap();
+        std::fs::write(tmp.path().join("src/lib.rs"), "lib").unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "bbbb").unwrap();
+        std::fs::write(tmp.path().join("A.md"), "a").unwrap();
+
+        let tree = s.handle("file/tree", Some(json!({"depth": 2}))).await.unwrap();
+        let names: Vec<&str> = tree["entries"].as_array().unwrap().iter()
+            .map(|e| e["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["src", "A.md", "b.txt"]); // directories first, case-insensitive
+        assert_eq!(tree["complete"], true);
+        let src = &tree["entries"][0];
+        assert_eq!(src["type"], "directory");
+        let children = src["children"].as_array().unwrap();
+        assert_eq!(children[0]["name"], "deep");
+        assert_eq!(children[1]["size"], 3);
+        assert!(children[1]["modTime"].is_u64());
+
+        // Past the depth limit: a token lists the directory with the same options
+        assert!(children[0].get("children").is_none());
+        let token = children[0]["expandToken"].as_str().unwrap();
+        let deep = s.handle("file/tree", Some(json!({"expandToken": token}))).await.unwrap();
+        assert_eq!(deep["entries"][0]["name"], "x.rs");
+
+        let by_size = s.handle("file/tree", Some(json!({
+            "sortBy": "size", "descending": true, "directoriesFirst": false,
+        }))).await.unwrap();
+        assert_eq!(by_size["entries"][0]["name"], "b.txt");
+
+        // Cut short at maxEntries, resumable from the token
+        let capped = s.handle("file/tree", Some(json!({"maxEntries": 2}))).await.unwrap();
+        assert_eq!(capped["entries"].as_array().unwrap().len(), 2);
+        assert_eq!(capped["truncated"], true);
+        assert!(capped["expandToken"].is_string());
+
+        // Forged tokens can't reach outside the workspace
+        let outside = ecp_services::file_tree::expand_token(std::path::Path::new("/etc"), &Default::default());
+        assert!(s.handle("file/tree", Some(json!({"expandToken": outside}))).await.is_err());
+        let outside = TempDir::new().unwrap();
+        std::os::unix::fs::symlink(outside.path(), tmp.path().join("escape")).unwrap();
+        let escape = ecp_services::file_tree::expand_token(&tmp.path().join("escape"), &Default::default());
+        assert!(s.handle("file/tree", Some(json!({"expandToken": escape}))).await.is_err());
+    }
+
     #[tokio::test]
     async fn path_helpers() {
         let tmp = TempDir::new().unwrap();
@@ -683,6 +787,928 @@ mod file {
         let stat = s.handle("file/stat", Some(json!({"path": "a/b/c"}))).await.unwrap();
         assert_eq!(stat["isDirectory"], true);
     }
+
+    #[tokio::test]
+    async fn write_and_edit_check_preconditions() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+
+        let written = s.handle("file/write", Some(json!({"path": "a.txt", "content": "one"}))).await.unwrap();
+        let read = s.handle("file/read", Some(json!({"path": "a.txt"}))).await.unwrap();
+        assert_eq!(read["hash"], written["hash"]);
+        assert_eq!(read["modTime"], written["modTime"]);
+
+        // Chaining on the returned hash succeeds
+        let edited = s.handle("file/edit", Some(json!({
+            "uri": "a.txt", "oldString": "one", "newString": "two", "expectedHash": written["hash"],
+        }))).await.unwrap();
+        assert_ne!(edited["hash"], written["hash"]);
+
+        // A stale hash or mtime is rejected with the current metadata
+        let err = s.handle("file/write", Some(json!({
+            "path": "a.txt", "content": "three", "expectedHash": written["hash"],
+        }))).await.unwrap_err();
+        assert_eq!(err.code, -32030);
+        let data = err.data.unwrap();
+        assert_eq!(data["hash"], edited["hash"]);
+        assert_eq!(data["size"], 3);
+
+        std::fs::write(tmp.path().join("a.txt"), "human").unwrap();
+        let err = s.handle("file/edit", Some(json!({
+            "uri": "a.txt", "oldString": "human", "newString": "agent", "expectedMtime": 1,
+        }))).await.unwrap_err();
+        assert_eq!(err.code, -32030);
+        assert_eq!(std::fs::read_to_string(tmp.path().join("a.txt")).unwrap(), "human");
+
+        // Expecting an existing file that is gone is a conflict too
+        let err = s.handle("file/write", Some(json!({
+            "path": "gone.txt", "content": "x", "expectedHash": edited["hash"],
+        }))).await.unwrap_err();
+        assert_eq!(err.data.unwrap()["exists"], false);
+    }
+
+    #[tokio::test]
+    async fn edit_applies_multiple_edits_atomically() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        std::fs::write(tmp.path().join("m.rs"), "let a = 1;\nlet b = 1;\nlet c = 2;\n").unwrap();
+
+        let result = s.handle("file/edit", Some(json!({
+            "uri": "m.rs",
+            "edits": [
+                {"oldString": "= 1", "newString": "= 10", "expectedOccurrences": 2},
+                {"range": {"start": {"line": 2, "column": 4}, "end": {"line": 2, "column": 5}}, "newText": "z"},
+                {"oldString": "let z", "newString": "const z"},
+            ],
+        }))).await.unwrap();
+        assert_eq!(result["editsApplied"], 3);
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("m.rs")).unwrap(),
+            "let a = 10;\nlet b = 10;\nconst z = 2;\n",
+        );
+        let diff = result["diff"].as_str().unwrap();
+        assert!(diff.starts_with("--- a/m.rs\n+++ b/m.rs\n"), "{diff}");
+        assert!(diff.contains("-let c = 2;\n") && diff.contains("+const z = 2;\n"), "{diff}");
+
+        // A missing anchor fails the request and leaves the file alone
+        let before = std::fs::read_to_string(tmp.path().join("m.rs")).unwrap();
+        let err = s.handle("file/edit", Some(json!({
+            "uri": "m.rs",
+            "edits": [
+                {"oldString": "const", "newString": "let"},
+                {"oldString": "missing", "newString": "x"},
+            ],
+        }))).await.unwrap_err();
+        assert_eq!(err.code, -32033);
+        let data = err.data.unwrap();
+        assert_eq!(data["editIndex"], 1);
+        assert_eq!(data["reason"], "notFound");
+        assert_eq!(std::fs::read_to_string(tmp.path().join("m.rs")).unwrap(), before);
+
+        // An ambiguous single replacement reports where the anchor occurs
+        let err = s.handle("file/edit", Some(json!({
+            "uri": "m.rs", "oldString": "= 10", "newString": "= 0",
+        }))).await.unwrap_err();
+        let data = err.data.unwrap();
+        assert_eq!(data["reason"], "ambiguous");
+        assert_eq!(data["lines"], json!([1, 2]));
+
+        let err = s.handle("file/edit", Some(json!({
+            "uri": "m.rs",
+            "edits": [{"range": {"start": {"line": 9, "column": 0}, "end": {"line": 9, "column": 0}}, "newText": "x"}],
+        }))).await.unwrap_err();
+        assert_eq!(err.data.unwrap()["reason"], "outOfBounds");
+    }
+
+    #[tokio::test]
+    async fn edit_applies_unified_diff_patch() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        let original: String = (1..=10).map(|i| format!("line {i}\n")).collect();
+        std::fs::write(tmp.path().join("p.txt"), &original).unwrap();
+
+        // Second hunk's line numbers are stale by one; it still matches uniquely
+        let patch = "--- a/p.txt\n+++ b/p.txt\n\
+            @@ -1,3 +1,3 @@\n line 1\n-line 2\n+line two\n line 3\n\
+            @@ -9,2 +9,3 @@\n line 9\n line 10\n+line 11\n\\ No newline at end of file\n";
+        let result = s.handle("file/edit", Some(json!({"uri": "p.txt", "patch": patch}))).await.unwrap();
+        let content = std::fs::read_to_string(tmp.path().join("p.txt")).unwrap();
+        assert_eq!(content, original.replace("line 2\n", "line two\n") + "line 11");
+        assert!(result["diff"].as_str().unwrap().contains("+line two"));
+
+        // Context that no longer matches anywhere is an error
+        let err = s.handle("file/edit", Some(json!({
+            "uri": "p.txt",
+            "patch": "@@ -4,2 +4,2 @@\n line 4\n-line five\n+line 5\n",
+        }))).await.unwrap_err();
+        assert_eq!(err.code, -32033);
+        assert_eq!(err.data.unwrap()["reason"], "notFound");
+    }
+
+    #[tokio::test]
+    async fn read_detects_encodings() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        std::fs::write(tmp.path().join("img.png"), [0x89, b'P', b'N', b'G', 0, 1, 2]).unwrap();
+        std::fs::write(tmp.path().join("bom.txt"), b"\xEF\xBB\xBFhi").unwrap();
+        std::fs::write(tmp.path().join("wide.txt"), [0xFF, 0xFE, b'h', 0, b'i', 0]).unwrap();
+        std::fs::write(tmp.path().join("latin.txt"), b"caf\xE9").unwrap();
+
+        let read = |path: &'static str, encoding: &'static str| {
+            s.handle("file/read", Some(json!({"path": path, "encoding": encoding})))
+        };
+        let result = read("img.png", "auto").await.unwrap();
+        assert_eq!(result["encoding"], "base64");
+        assert_eq!(result["content"], "iVBORwABAg==");
+        let result = read("bom.txt", "auto").await.unwrap();
+        assert_eq!((result["content"].as_str(), result["encoding"].as_str()), (Some("hi"), Some("utf-8")));
+        let result = read("wide.txt", "auto").await.unwrap();
+        assert_eq!((result["content"].as_str(), result["encoding"].as_str()), (Some("hi"), Some("utf-16le")));
+        let result = read("latin.txt", "auto").await.unwrap();
+        assert_eq!((result["content"].as_str(), result["encoding"].as_str()), (Some("café"), Some("latin1")));
+
+        assert!(read("latin.txt", "utf8").await.is_err());
+        let result = read("latin.txt", "base64").await.unwrap();
+        assert_eq!(result["content"], "Y2Fm6Q==");
+    }
+
+    #[tokio::test]
+    async fn read_byte_and_line_ranges() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        std::fs::write(tmp.path().join("log.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+        std::fs::write(tmp.path().join("utf.txt"), "aé€b").unwrap();
+
+        let result = s.handle("file/read", Some(json!({"path": "log.txt", "startLine": 2, "endLine": 3}))).await.unwrap();
+        assert_eq!(result["content"], "two\nthree\n");
+        assert_eq!(result["offset"], 4);
+        assert_eq!(result["totalLines"], 4);
+        assert_eq!(result["size"], 19);
+        assert_eq!(result["eof"], false);
+
+        let result = s.handle("file/read", Some(json!({"path": "log.txt", "offset": 8, "length": 100}))).await.unwrap();
+        assert_eq!(result["content"], "three\nfour\n");
+        assert_eq!(result["eof"], true);
+
+        // Ranges that split a character are trimmed to whole characters
+        let result = s.handle("file/read", Some(json!({"path": "utf.txt", "offset": 2, "length": 4}))).await.unwrap();
+        assert_eq!(result["content"], "€");
+        assert_eq!(result["offset"], 3);
+        assert_eq!(result["length"], 3);
+
+        let err = s.handle("file/read", Some(json!({"path": "log.txt", "offset": 0, "startLine": 1}))).await.unwrap_err();
+        assert_eq!(err.code, -32602);
+    }
+
+    #[tokio::test]
+    async fn read_streams_chunks() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        s.set_notify_sender(std::sync::Arc::new(move |method: &str, params: serde_json::Value| {
+            let _ = tx.send((method.to_string(), params));
+        }));
+        // Multi-byte characters straddle the 4 KiB chunk boundaries
+        let content = "é".repeat(5000);
+        std::fs::write(tmp.path().join("big.txt"), &content).unwrap();
+
+        let result = s.handle("file/read", Some(json!({"path": "big.txt", "stream": true, "chunkSize": 4097}))).await.unwrap();
+        assert_eq!(result["size"], 10000);
+        let stream_id = result["streamId"].as_str().unwrap().to_string();
+
+        let mut received = String::new();
+        let mut chunks = 0;
+        loop {
+            let (method, params) = rx.recv().await.unwrap();
+            assert_eq!(method, "file/read/chunk");
+            assert_eq!(params["streamId"], stream_id.as_str());
+            if params["done"] == true {
+                assert_eq!(params["bytesRead"], 10000);
+                break;
+            }
+            assert_eq!(params["encoding"], "utf-8");
+            received.push_str(params["content"].as_str().unwrap());
+            chunks += 1;
+        }
+        assert_eq!(chunks, 3);
+        assert_eq!(received, content);
+    }
+
+    #[tokio::test]
+    async fn read_stream_waits_for_acks() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        s.set_notify_sender(std::sync::Arc::new(move |_: &str, params: serde_json::Value| {
+            let _ = tx.send(params);
+        }));
+        std::fs::write(tmp.path().join("big.txt"), "x".repeat(10 * 4096)).unwrap();
+
+        let result = s.handle("file/read", Some(json!({
+            "path": "big.txt", "stream": true, "chunkSize": 4096, "_clientId": "a",
+        }))).await.unwrap();
+        let stream_id = result["streamId"].as_str().unwrap().to_string();
+        let quiet = || tokio::time::sleep(std::time::Duration::from_millis(200));
+
+        // A window of unacknowledged chunks goes out, then the stream waits
+        quiet().await;
+        for index in 0..4 {
+            let params = rx.try_recv().expect("chunk within the window");
+            assert_eq!(params["chunk"], index);
+            assert_eq!(params["_clients"], json!(["a"]));
+        }
+        assert!(rx.try_recv().ok().is_none());
+
+        s.handle("file/read/ack", Some(json!({"streamId": &stream_id, "chunk": 1}))).await.unwrap();
+        quiet().await;
+        assert_eq!(rx.try_recv().ok().unwrap()["chunk"], 4);
+        assert_eq!(rx.try_recv().ok().unwrap()["chunk"], 5);
+        assert!(rx.try_recv().ok().is_none());
+
+        // Its client going away ends it
+        s.client_disconnected("a");
+        quiet().await;
+        let done = rx.try_recv().ok().unwrap();
+        assert_eq!(done["done"], true);
+        assert_eq!(done["error"], "Stream cancelled");
+        let ack = s.handle("file/read/ack", Some(json!({"streamId": &stream_id, "chunk": 5}))).await.unwrap();
+        assert_eq!(ack["success"], false);
+    }
+
+    #[tokio::test]
+    async fn write_base64_and_append() {
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+
+        let result = s.handle("file/write", Some(json!({
+            "path": "bin.dat", "content": "AAEC", "encoding": "base64",
+        }))).await.unwrap();
+        assert_eq!(result["bytesWritten"], 3);
+        let result = s.handle("file/write", Some(json!({
+            "path": "bin.dat", "content": "/w==", "encoding": "base64", "append": true,
+        }))).await.unwrap();
+        assert_eq!(result["bytesWritten"], 1);
+        assert_eq!(std::fs::read(tmp.path().join("bin.dat")).unwrap(), [0, 1, 2, 255]);
+
+        // Appending doesn't read the file back to hash it
+        assert!(result["hash"].is_null());
+
+        s.handle("file/write", Some(json!({"path": "log.txt", "content": "a\n", "append": true}))).await.unwrap();
+        s.handle("file/write", Some(json!({"path": "log.txt", "content": "b\n", "append": true}))).await.unwrap();
+        assert_eq!(std::fs::read_to_string(tmp.path().join("log.txt")).unwrap(), "a\nb\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn write_is_atomic_and_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let s = FileService::new(tmp.path().to_path_buf());
+        let script = tmp.path().join("run.sh");
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink(&script, tmp.path().join("link.sh")).unwrap();
+
+        s.handle("file/write", Some(json!({"path": "link.sh", "content": "#!/bin/sh\necho hi\n"}))).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), "#!/bin/sh\necho hi\n");
+        assert_eq!(std::fs::metadata(&script).unwrap().permissions().mode() & 0o777, 0o755);
+        assert!(std::fs::symlink_metadata(tmp.path().join("link.sh")).unwrap().is_symlink());
+
+        // No temp files left behind
+        let names: Vec<_> = std::fs::read_dir(tmp.path()).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names.len(), 2, "{names:?}");
+    }
+
+    fn search_fixture() -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        for (path, content) in [
+            ("src/components/Button.tsx", ""),
+            ("src/bin/tool.rs", ""),
+            ("alpha/main.rs", ""),
+            ("bravo/main.rs", ""),
+            (".github/workflows/ci.yml", ""),
+            ("node_modules/ui/button.js", ""),
+            ("dist/button.min.js", ""),
+            (".gitignore", "dist/\n"),
+        ] {
+            let full = tmp.path().join(path);
+            std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+            std::fs::write(full, content).unwrap();
+        }
+        tmp
+    }
+
+    #[tokio::test]
+    async fn search_fuzzy_scores_with_highlights() {
+        let tmp = search_fixture();
+        let s = FileService::new(tmp.path().to_path_buf());
+
+        let result = s.handle("file/search", Some(json!({"pattern": "btx"}))).await.unwrap();
+        let results = result["results"].as_array().unwrap();
+        // node_modules and gitignored files are not indexed
+        assert_eq!(results.len(), 1);
+        let hit = &results[0];
+        assert_eq!(hit["path"], "src/components/Button.tsx");
+        assert_eq!(hit["name"], "Button.tsx");
+        assert!(hit["score"].as_u64().unwrap() > 0);
+
+        // Highlight ranges pick out the matched characters
+        let path: Vec<char> = hit["path"].as_str().unwrap().chars().collect();
+        let highlighted: String = hit["ranges"].as_array().unwrap().iter()
+            .flat_map(|r| r["start"].as_u64().unwrap()..r["end"].as_u64().unwrap())
+            .map(|i| path[i as usize].to_ascii_lowercase())
+            .collect();
+        assert_eq!(highlighted, "btx");
+
+        // Hidden files are searchable; better matches rank first
+        let result = s.handle("file/search", Some(json!({"pattern": "ci.yml"}))).await.unwrap();
+        assert_eq!(result["results"][0]["path"], ".github/workflows/ci.yml");
+        let result = s.handle("file/search", Some(json!({"pattern": "tool"}))).await.unwrap();
+        assert_eq!(result["results"][0]["path"], "src/bin/tool.rs");
+        assert_eq!(result["indexedFiles"], 6);
+
+        let result = s.handle("file/search", Some(json!({"pattern": "BTX", "caseSensitive": true}))).await.unwrap();
+        assert!(result["results"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_index_follows_watcher_events() {
+        use ecp_services::watch::WatchService;
+
+        let tmp = search_fixture();
+        let s = FileService::new(tmp.path().to_path_buf());
+        let watch = WatchService::new(tmp.path().to_path_buf());
+        let index = s.file_index();
+        watch.add_event_listener(std::sync::Arc::new(move |event| index.apply_event(event)));
+        s.init().await.unwrap();
+        watch.init().await.unwrap();
+        s.file_index().ready().await;
+
+        let search = |pattern: &'static str| s.handle("file/search", Some(json!({"pattern": pattern})));
+        let paths = |result: serde_json::Value| -> Vec<String> {
+            result["results"].as_array().unwrap().iter()
+                .map(|r| r["path"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        std::fs::create_dir_all(tmp.path().join("src/widgets")).unwrap();
+        std::fs::write(tmp.path().join("src/widgets/Slider.tsx"), "").unwrap();
+        std::fs::remove_file(tmp.path().join("src/bin/tool.rs")).unwrap();
+        let mut found = false;
+        for _ in 0..50 {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let slider = paths(search("slider").await.unwrap());
+            let tool = paths(search("tool.rs").await.unwrap());
+            if slider == vec!["src/widgets/Slider.tsx"] && tool.is_empty() {
+                found = true;
+                break;
+            }
+        }
+        assert!(found, "index did not pick up the created and deleted files");
+        watch.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn search_boosts_recent_session_files() {
+        use ecp_services::session::SessionService;
+
+        let tmp = search_fixture();
+        let s = FileService::new(tmp.path().to_path_buf());
+        let session = SessionService::new_with_sessions_dir(tmp.path().to_path_buf(), tmp.path().join("sessions"));
+        session.set_file_index(s.file_index());
+
+        let result = s.handle("file/search", Some(json!({"pattern": "main"}))).await.unwrap();
+        assert_eq!(result["results"][0]["path"], "alpha/main.rs");
+        assert_eq!(result["results"][0]["recent"], false);
+
+        let bravo = tmp.path().join("bravo/main.rs");
+        session.handle("session/setCurrent", Some(json!({
+            "state": {"openFiles": [format!("file://{}", bravo.display())]},
+        }))).await.unwrap();
+
+        let result = s.handle("file/search", Some(json!({"pattern": "main"}))).await.unwrap();
+        assert_eq!(result["results"][0]["path"], "bravo/main.rs");
+        assert_eq!(result["results"][0]["recent"], true);
+
+        // An empty query lists recent files
+        let result = s.handle("file/search", Some(json!({"pattern": ""}))).await.unwrap();
+        assert_eq!(result["results"], json!([{
+            "uri": format!("file://{}", bravo.display()),
+            "path": "bravo/main.rs",
+            "name": "main.rs",
+            "score": 0,
+            "ranges": [],
+            "recent": true,
+        }]));
+    }
+
+    fn grep_fixture() -> (TempDir, FileService) {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {\n    let x = foo(1);\n    Foo::new();\n}\n").unwrap();
+        std::fs::write(root.join("src/notes.txt"), "foo and food\n").unwrap();
+        std::fs::write(root.join("target/out.rs"), "foo\n").unwrap();
+        std::fs::write(root.join("a:b.txt"), "café foo\n").unwrap();
+        std::fs::write(root.join("blob.bin"), b"foo\0bar").unwrap();
+        let s = FileService::new(root.to_path_buf());
+        (tmp, s)
+    }
+
+    #[tokio::test]
+    async fn grep_respects_gitignore_with_exact_columns() {
+        let (tmp, s) = grep_fixture();
+        let result = s.handle("file/grep", Some(json!({"pattern": "foo"}))).await.unwrap();
+        let matches = result["matches"].as_array().unwrap();
+
+        let files: Vec<&str> = matches.iter().map(|m| m["file"].as_str().unwrap()).collect();
+        let root = tmp.path().display().to_string();
+        assert_eq!(files, vec![
+            format!("{root}/a:b.txt"),
+            format!("{root}/src/main.rs"),
+            format!("{root}/src/notes.txt"),
+        ]);
+        assert_eq!(result["matchCount"], 3);
+        assert_eq!(result["fileCount"], 3);
+        assert_eq!(result["truncated"], false);
+
+        // Columns count characters, not bytes
+        assert_eq!(matches[0]["column"], 6);
+        assert_eq!(matches[0]["ranges"], json!([{"start": 5, "end": 8}]));
+        assert_eq!(matches[1]["line"], 2);
+        assert_eq!(matches[1]["column"], 13);
+        assert_eq!(matches[1]["text"], "    let x = foo(1);");
+        assert_eq!(matches[2]["ranges"], json!([{"start": 0, "end": 3}, {"start": 8, "end": 11}]));
+
+        let all = s.handle("file/grep", Some(json!({"pattern": "foo", "respectIgnore": false}))).await.unwrap();
+        assert_eq!(all["fileCount"], 4);
+    }
+
+    #[tokio::test]
+    async fn grep_matching_options() {
+        let (_tmp, s) = grep_fixture();
+        let grep = |params: serde_json::Value| s.handle("file/grep", Some(params));
+
+        let literal = grep(json!({"pattern": "foo(1)", "literal": true})).await.unwrap();
+        assert_eq!(literal["matchCount"], 1);
+        assert!(grep(json!({"pattern": "foo("})).await.is_err());
+
+        let insensitive = grep(json!({"pattern": "foo", "caseSensitive": false, "include": ["*.rs"]})).await.unwrap();
+        assert_eq!(insensitive["matchCount"], 2);
+
+        let word = grep(json!({"pattern": "foo", "wholeWord": true, "exclude": ["src"]})).await.unwrap();
+        assert_eq!(word["matchCount"], 1);
+        assert_eq!(word["matches"][0]["ranges"], json!([{"start": 5, "end": 8}]));
+
+        let context = grep(json!({"pattern": "let", "contextLines": 1})).await.unwrap();
+        assert_eq!(context["matches"][0]["before"], json!(["fn main() {"]));
+        assert_eq!(context["matches"][0]["after"], json!(["    Foo::new();"]));
+
+        let multiline = grep(json!({"pattern": r"\{\n\s+let", "multiline": true})).await.unwrap();
+        let m = &multiline["matches"][0];
+        assert_eq!((m["line"].as_u64(), m["endLine"].as_u64()), (Some(1), Some(2)));
+        assert_eq!(m["ranges"], json!([{"start": 10, "end": 19}]));
+        let single = grep(json!({"pattern": r"\{\n\s+let"})).await.unwrap();
+        assert_eq!(single["matchCount"], 0);
+
+        let capped = grep(json!({"pattern": "o", "maxResults": 2})).await.unwrap();
+        assert_eq!(capped["matchCount"], 2);
+        assert_eq!(capped["truncated"], true);
+    }
+
+    #[tokio::test]
+    async fn grep_streams_results_then_summary() {
+        let (_tmp, s) = grep_fixture();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        s.set_notify_sender(std::sync::Arc::new(move |method: &str, params: serde_json::Value| {
+            let _ = tx.send((method.to_string(), params));
+        }));
+
+        let result = s.handle("file/grep", Some(json!({"pattern": "foo", "stream": true, "_clientId": "a"}))).await.unwrap();
+        let search_id = result["searchId"].as_str().unwrap().to_string();
+
+        let mut files = 0;
+        loop {
+            let (method, params) = rx.recv().await.unwrap();
+            assert_eq!(method, "file/grep/result");
+            assert_eq!(params["searchId"], search_id.as_str());
+            assert_eq!(params["_clients"], json!(["a"]));
+            if params["done"] == true {
+                assert_eq!(params["matchCount"], 3);
+                assert_eq!(params["fileCount"], files);
+                break;
+            }
+            assert!(!params["matches"].as_array().unwrap().is_empty());
+            files += 1;
+        }
+        assert_eq!(files, 3);
+    }
+
+    #[tokio::test]
+    async fn replace_all_previews_then_applies_with_undo() {
+        use ecp_services::journal::{Journal, JournalService};
+
+        let (tmp, s) = grep_fixture();
+        let journal = std::sync::Arc::new(Journal::new(tmp.path().to_path_buf()));
+        s.set_journal(journal.clone());
+        let journal = JournalService::new(journal);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        s.set_notify_sender(std::sync::Arc::new(move |method: &str, params: serde_json::Value| {
+            let _ = tx.send((method.to_string(), params));
+        }));
+        let main_rs = std::fs::read_to_string(tmp.path().join("src/main.rs")).unwrap();
+        let params = json!({
+            "pattern": r"foo\((\d+)\)",
+            "replacement": "bar($1, 0)",
+            "include": ["*.rs"],
+            "dryRun": true,
+        });
+
+        let preview = s.handle("file/replaceAll", Some(params.clone())).await.unwrap();
+        assert_eq!(preview["fileCount"], 1);
+        assert_eq!(preview["replacementCount"], 1);
+        let file = &preview["files"][0];
+        assert_eq!(file["path"], "src/main.rs");
+        let hunk = &file["hunks"][0];
+        assert_eq!(hunk["oldStart"], 1);
+        assert!(hunk["lines"].as_array().unwrap().contains(&json!({
+            "type": "+", "content": "    let x = bar(1, 0);", "newLineNum": 2,
+        })));
+        // Nothing written yet
+        assert_eq!(std::fs::read_to_string(tmp.path().join("src/main.rs")).unwrap(), main_rs);
+
+        let mut apply = params.clone();
+        apply["dryRun"] = json!(false);
+        apply["expectedHashes"] = json!({ "src/main.rs": file["hash"] });
+        let result = s.handle("file/replaceAll", Some(apply.clone())).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("src/main.rs")).unwrap(),
+            main_rs.replace("foo(1)", "bar(1, 0)"),
+        );
+        let (method, event) = rx.recv().await.unwrap();
+        assert_eq!(method, "file/didChange");
+        assert!(event["uri"].as_str().unwrap().ends_with("src/main.rs"));
+
+        // Applying the stale preview again is a conflict
+        std::fs::write(tmp.path().join("src/main.rs"), "foo(2)\n").unwrap();
+        let err = s.handle("file/replaceAll", Some(apply)).await.unwrap_err();
+        assert_eq!(err.code, -32030);
+        std::fs::write(tmp.path().join("src/main.rs"), main_rs.replace("foo(1)", "bar(1, 0)")).unwrap();
+
+        // The undo token reverts the whole replacement
+        let token = result["undoToken"].as_str().unwrap();
+        journal.handle("journal/revert", Some(json!({"entryId": token}))).await.unwrap();
+        assert_eq!(std::fs::read_to_string(tmp.path().join("src/main.rs")).unwrap(), main_rs);
+    }
+
+    #[tokio::test]
+    async fn replace_all_literal_does_not_expand_captures() {
+        let (tmp, s) = grep_fixture();
+        let result = s.handle("file/replaceAll", Some(json!({
+            "pattern": "foo(1)", "replacement": "$1", "literal": true, "path": "src/main.rs",
+        }))).await.unwrap();
+        assert_eq!(result["replacementCount"], 1);
+        assert!(std::fs::read_to_string(tmp.path().join("src/main.rs")).unwrap().contains("let x = $1;"));
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Journal service tests
+// ─────────────────────────────────────────────────────────────────────────────
+
+mod journal {
+    use super::*;
+    use std::sync::Arc;
+    use ecp_services::file::FileService;
+    use ecp_services::history::{FileHistory, HistoryEntry, HistoryLimits};
+    use ecp_services::journal::{Journal, JournalLimits, JournalService};
+    use ecp_services::Service;
+
+    fn services(tmp: &TempDir) -> (FileService, JournalService) {
+        let journal = Arc::new(Journal::new(tmp.path().to_path_buf()));
+        let files = FileService::new(tmp.path().to_path_buf());
+        files.set_journal(journal.clone());
+        (files, JournalService::new(journal))
+    }
+
+    #[tokio::test]
+    async fn write_is_journaled_with_diff() {
+        let tmp = TempDir::new().unwrap();
+        let (files, journal) = services(&tmp);
+
+        files.handle("file/write", Some(json!({"path": "a.txt", "content": "one\n"}))).await.unwrap();
+        let result = files.handle("file/write", Some(json!({"path": "a.txt", "content": "two\n"}))).await.unwrap();
+        let entry_id = result["journalEntryId"].as_str().unwrap().to_string();
+
+        let list = journal.handle("journal/list", None).await.unwrap();
+        let entries = list["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["id"], entry_id.as_str()); // newest first
+        assert_eq!(entries[0]["paths"][0], "a.txt");
+
+        let diff = journal.handle("journal/diff", Some(json!({"entryId": &entry_id}))).await.unwrap();
+        let file = &diff["files"][0];
+        assert_eq!(file["status"], "modified");
+        assert!(file["diff"].as_str().unwrap().contains("-one\n+two"));
+    }
+
+    #[tokio::test]
+    async fn revert_execution_restores_all_files() {
+        let tmp = TempDir::new().unwrap();
+        let (files, journal) = services(&tmp);
+        std::fs::write(tmp.path().join("keep.txt"), "original").unwrap();
+        std::fs::write(tmp.path().join("gone.txt"), "precious").unwrap();
+
+        let exec = json!("exec-1");
+        files.handle("file/write", Some(json!({"path": "keep.txt", "content": "agent", "_executionId": exec}))).await.unwrap();
+        files.handle("file/write", Some(json!({"path": "new.txt", "content": "created", "_executionId": exec}))).await.unwrap();
+        files.handle("file/delete", Some(json!({"path": "gone.txt", "_executionId": exec}))).await.unwrap();
+        files.handle("file/rename", Some(json!({"from": "keep.txt", "to": "moved.txt", "_executionId": exec}))).await.unwrap();
+        // Unrelated edit outside the execution must survive the revert
+        files.handle("file/write", Some(json!({"path": "human.txt", "content": "mine"}))).await.unwrap();
+
+        let result = journal.handle("journal/revert", Some(json!({"executionId": "exec-1"}))).await.unwrap();
+        assert_eq!(result["reverted"].as_array().unwrap().len(), 4);
+
+        let read = |name: &str| std::fs::read_to_string(tmp.path().join(name)).ok();
+        assert_eq!(read("keep.txt").as_deref(), Some("original"));
+        assert_eq!(read("gone.txt").as_deref(), Some("precious"));
+        assert_eq!(read("new.txt"), None);
+        assert_eq!(read("moved.txt"), None);
+        assert_eq!(read("human.txt").as_deref(), Some("mine"));
+
+        // Reverting twice has nothing left to do
+        let err = journal.handle("journal/revert", Some(json!({"executionId": "exec-1"}))).await;
+        assert!(err.is_err());
+    }
+
+    #[tokio::test]
+    async fn revert_directory_rename() {
+        let tmp = TempDir::new().unwrap();
+        let (files, journal) = services(&tmp);
+        std::fs::create_dir_all(tmp.path().join("src/nested")).unwrap();
+        std::fs::write(tmp.path().join("src/a.txt"), "a").unwrap();
+        std::fs::write(tmp.path().join("src/nested/b.txt"), "b").unwrap();
+
+        let result = files.handle("file/rename", Some(json!({"from": "src", "to": "lib"}))).await.unwrap();
+        let entry_id = result["journalEntryId"].as_str().unwrap().to_string();
+        let diff = journal.handle("journal/diff", Some(json!({"entryId": &entry_id}))).await.unwrap();
+        let mut changed: Vec<(String, String)> = diff["files"].as_array().unwrap().iter()
+            .map(|f| (f["path"].as_str().unwrap().to_string(), f["status"].as_str().unwrap().to_string()))
+            .collect();
+        changed.sort();
+        assert_eq!(changed, [
+            ("lib/a.txt".to_string(), "added".to_string()),
+            ("lib/nested/b.txt".to_string(), "added".to_string()),
+            ("src/a.txt".to_string(), "deleted".to_string()),
+            ("src/nested/b.txt".to_string(), "deleted".to_string()),
+        ]);
+
+        journal.handle("journal/revert", Some(json!({"entryId": &entry_id}))).await.unwrap();
+        assert_eq!(std::fs::read_to_string(tmp.path().join("src/a.txt")).unwrap(), "a");
+        assert_eq!(std::fs::read_to_string(tmp.path().join("src/nested/b.txt")).unwrap(), "b");
+        assert!(!tmp.path().join("lib").exists());
+    }
+
+    #[tokio::test]
+    async fn journal_skips_large_trees_and_prunes_old_entries() {
+        let tmp = TempDir::new().unwrap();
+        let mut journal = Journal::new(tmp.path().to_path_buf());
+        journal.set_limits(Arc::new(parking_lot::RwLock::new(JournalLimits {
+            max_entries: 2,
+            max_age_days: 0,
+            max_file_bytes: 1024,
+            max_tree_bytes: 1024,
+        })));
+        let journal = Arc::new(journal);
+        let files = FileService::new(tmp.path().to_path_buf());
+        files.set_journal(journal.clone());
+        let service = JournalService::new(journal);
+
+        std::fs::create_dir(tmp.path().join("big")).unwrap();
+        std::fs::write(tmp.path().join("big/a.bin"), vec![0u8; 800]).unwrap();
+        std::fs::write(tmp.path().join("big/b.bin"), vec![0u8; 800]).unwrap();
+        files.handle("file/deleteDir", Some(json!({"path": "big", "permanent": true}))).await.unwrap();
+        let list = service.handle("journal/list", None).await.unwrap();
+        assert_eq!(list["entries"][0]["skipped"], json!(["big"]));
+        assert_eq!(list["entries"][0]["paths"], json!([]));
+        let blobs = tmp.path().join(".ultra/journal/blobs");
+        assert!(!blobs.exists() || std::fs::read_dir(&blobs).unwrap().next().is_none());
+
+        for content in ["one", "two", "three", "four"] {
+            files.handle("file/write", Some(json!({"path": "a.txt", "content": content}))).await.unwrap();
+        }
+        let list = service.handle("journal/list", None).await.unwrap();
+        let entries = list["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1]["method"], "file/write");
+        // "one" went with the entries that referred to it
+        assert_eq!(std::fs::read_dir(&blobs).unwrap().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn appends_are_journaled_as_truncations() {
+        let tmp = TempDir::new().unwrap();
+        let (files, journal) = services(&tmp);
+        std::fs::write(tmp.path().join("log.txt"), "start\n").unwrap();
+
+        let append = |content: &str| files.handle("file/write", Some(json!({
+            "path": "log.txt", "content": content, "append": true, "_executionId": "exec-1",
+        })));
+        let first = append("one\n").await.unwrap();
+        append("two\n").await.unwrap();
+
+        // Only the appended bytes are kept
+        let entry = Journal::new(tmp.path().to_path_buf()).entries().remove(0);
+        assert_eq!(entry.changes[0].appended_at, Some(6));
+        assert_eq!(entry.changes[0].before, None);
+        let diff = journal.handle("journal/diff", Some(json!({"entryId": first["journalEntryId"]}))).await.unwrap();
+        assert_eq!(diff["files"][0]["status"], "appended");
+        assert!(diff["files"][0]["diff"].as_str().unwrap().contains("+one"));
+
+        // Another writer appending in between is a conflict
+        std::fs::write(tmp.path().join("log.txt"), "start\none\ntwo\nthree\n").unwrap();
+        let err = journal.handle("journal/revert", Some(json!({"executionId": "exec-1"}))).await.unwrap_err();
+        assert_eq!(err.code, -32030);
+
+        std::fs::write(tmp.path().join("log.txt"), "start\none\ntwo\n").unwrap();
+        journal.handle("journal/revert", Some(json!({"executionId": "exec-1"}))).await.unwrap();
+        assert_eq!(std::fs::read_to_string(tmp.path().join("log.txt")).unwrap(), "start\n");
+    }
+
+    #[tokio::test]
+    async fn revert_conflict_requires_force() {
+        let tmp = TempDir::new().unwrap();
+        let (files, journal) = services(&tmp);
+
+        let result = files.handle("file/write", Some(json!({"path": "c.txt", "content": "agent"}))).await.unwrap();
+        let entry_id = result["journalEntryId"].as_str().unwrap().to_string();
+        // Changed behind the journal's back
+        std::fs::write(tmp.path().join("c.txt"), "human").unwrap();
+
+        let err = journal.handle("journal/revert", Some(json!({"entryId": &entry_id}))).await.unwrap_err();
+        assert_eq!(err.code, -32030);
+        assert_eq!(err.data.unwrap()["conflicts"][0]["path"], "c.txt");
+        assert!(tmp.path().join("c.txt").exists());
+
+        journal.handle("journal/revert", Some(json!({"entryId": &entry_id, "force": true}))).await.unwrap();
+        assert!(!tmp.path().join("c.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn stores_stay_out_of_git() {
+        let tmp = TempDir::new().unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git").args(args).current_dir(tmp.path()).output().unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+        git(&["init", "-q"]);
+        std::fs::write(tmp.path().join("a.txt"), "one\n").unwrap();
+        std::fs::write(tmp.path().join("secret.env"), "TOKEN=x\n").unwrap();
+        git(&["add", "-A"]);
+        git(&["-c", "user.email=t@t", "-c", "user.name=t", "commit", "-qm", "init"]);
+
+        let (files, _journal) = services(&tmp);
+        files.set_history(Arc::new(FileHistory::new(tmp.path().to_path_buf())));
+        files.handle("file/write", Some(json!({"path": "a.txt", "content": "two\n"}))).await.unwrap();
+        files.handle("file/delete", Some(json!({"path": "secret.env"}))).await.unwrap();
+
+        // Journal, history and trash all went under .ultra, which git ignores
+        for store in ["journal", "history", "trash"] {
+            assert!(tmp.path().join(".ultra").join(store).is_dir(), "{store}");
+        }
+        assert_eq!(git(&["status", "--porcelain"]), " M a.txt\n D secret.env\n");
+    }
+
+    #[tokio::test]
+    async fn history_tracks_versions_and_restores() {
+        let tmp = TempDir::new().unwrap();
+        let (files, _journal) = services(&tmp);
+        let history = Arc::new(FileHistory::new(tmp.path().to_path_buf()));
+        files.set_history(history.clone());
+
+        files.handle("file/write", Some(json!({"path": "h.txt", "content": "one\n"}))).await.unwrap();
+        // Changed outside ECP: kept as an external version before the next save
+        std::fs::write(tmp.path().join("h.txt"), "two\n").unwrap();
+        files.handle("file/write", Some(json!({"path": "h.txt", "content": "three\n"}))).await.unwrap();
+        // Writing the same content again records nothing
+        files.handle("file/write", Some(json!({"path": "h.txt", "content": "three\n"}))).await.unwrap();
+
+        let list = files.handle("file/history/list", Some(json!({"path": "h.txt"}))).await.unwrap();
+        let entries = list["entries"].as_array().unwrap();
+        let sources: Vec<&str> = entries.iter().map(|e| e["source"].as_str().unwrap()).collect();
+        assert_eq!(sources, ["save", "external", "save"]); // newest first
+        let first = entries[2]["id"].as_str().unwrap().to_string();
+
+        let version = files.handle("file/history/get", Some(json!({"id": &first}))).await.unwrap();
+        assert_eq!(version["content"], "one\n");
+
+        let diff = files.handle("file/history/diff", Some(json!({"id": &first}))).await.unwrap();
+        assert!(diff["diff"].as_str().unwrap().contains("-one\n+three"));
+
+        // Restore honours preconditions, is journaled and becomes a version itself
+        let err = files.handle("file/history/restore", Some(json!({"id": &first, "expectedHash": "stale"}))).await.unwrap_err();
+        assert_eq!(err.code, -32030);
+        let result = files.handle("file/history/restore", Some(json!({"id": &first}))).await.unwrap();
+        assert!(result["journalEntryId"].is_string());
+        assert_eq!(std::fs::read_to_string(tmp.path().join("h.txt")).unwrap(), "one\n");
+        let list = files.handle("file/history/list", Some(json!({"path": "h.txt", "limit": 1}))).await.unwrap();
+        assert_eq!(list["entries"][0]["source"], "restore");
+
+        let err = files.handle("file/history/get", Some(json!({"id": "h-missing"}))).await.unwrap_err();
+        assert_eq!(err.code, -32602);
+    }
+
+    #[tokio::test]
+    async fn history_prunes_past_limits() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("p.txt");
+        let mut history = FileHistory::new(tmp.path().to_path_buf());
+        history.set_limits(Arc::new(parking_lot::RwLock::new(HistoryLimits {
+            max_entries_per_file: 2,
+            max_age_days: 0,
+            max_file_bytes: 8,
+        })));
+
+        for content in ["a", "b", "c"] {
+            std::fs::write(&path, content).unwrap();
+            history.record(&path, "save").unwrap();
+        }
+        let entries = history.list(Some(&path), 10);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(history.read(&entries[1]).unwrap(), b"b");
+        // The dropped version's blob is gone too
+        assert_eq!(std::fs::read_dir(tmp.path().join(".ultra/history/blobs")).unwrap().count(), 2);
+        // Pruning appends a tombstone instead of rewriting the log...
+        let log = tmp.path().join(".ultra/history/entries.jsonl");
+        assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 4);
+        // ...which is compacted before tombstones outnumber live versions
+        for content in ["d", "e", "f", "g", "h"] {
+            std::fs::write(&path, content).unwrap();
+            history.record(&path, "save").unwrap();
+            assert!(std::fs::read_to_string(&log).unwrap().lines().count() <= 4);
+        }
+        let entries = history.list(Some(&path), 10);
+        assert_eq!(history.read(&entries[0]).unwrap(), b"h");
+        assert_eq!(history.read(&entries[1]).unwrap(), b"g");
+
+        // Oversized files are not snapshotted
+        std::fs::write(&path, "much too large").unwrap();
+        assert!(history.record(&path, "save").is_none());
+
+        // Reloaded from disk, tombstones applied
+        let reloaded = FileHistory::new(tmp.path().to_path_buf());
+        let ids: Vec<String> = reloaded.list(Some(&path), 10).into_iter().map(|e| e.id).collect();
+        assert_eq!(ids, entries.into_iter().map(|e| e.id).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn history_drops_old_versions_of_every_file() {
+        let tmp = TempDir::new().unwrap();
+        let (old, recent) = (tmp.path().join("old.txt"), tmp.path().join("recent.txt"));
+        let limits = Arc::new(parking_lot::RwLock::new(HistoryLimits { max_age_days: 0, ..Default::default() }));
+        let mut history = FileHistory::new(tmp.path().to_path_buf());
+        history.set_limits(limits.clone());
+        std::fs::write(&old, "old").unwrap();
+        let entry = history.record(&old, "save").unwrap();
+
+        // Backdate the version past the age limit, as if recorded long ago
+        let entries = tmp.path().join(".ultra/history/entries.jsonl");
+        let content = std::fs::read_to_string(&entries).unwrap()
+            .replace(&format!("\"timestamp\":{}", entry.timestamp), "\"timestamp\":1");
+        std::fs::write(&entries, content).unwrap();
+        limits.write().max_age_days = 30;
+
+        // Loading drops it, though nothing is recorded for that file again
+        let mut reloaded = FileHistory::new(tmp.path().to_path_buf());
+        reloaded.set_limits(limits.clone());
+        assert!(reloaded.list(Some(&old), 10).is_empty());
+        assert!(!std::fs::read_to_string(&entries).unwrap().contains("old.txt"));
+        assert!(!tmp.path().join(".ultra/history/blobs").join(&entry.hash).exists());
+
+        // Recording another file prunes expired versions of every file
+        std::fs::write(&old, "older").unwrap();
+        let entry = history.record(&old, "save").unwrap();
+        let mut lines: Vec<HistoryEntry> = std::fs::read_to_string(&entries).unwrap().lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        lines.iter_mut().for_each(|e| e.timestamp = 1);
+        let content: String = lines.iter().map(|e| serde_json::to_string(e).unwrap() + "\n").collect();
+        std::fs::write(&entries, content).unwrap();
+        let mut loaded = FileHistory::new(tmp.path().to_path_buf());
+        loaded.set_limits(Arc::new(parking_lot::RwLock::new(HistoryLimits { max_age_days: 0, ..Default::default() })));
+        assert_eq!(loaded.list(Some(&old), 10).len(), 1);
+        loaded.set_limits(limits);
+        std::fs::write(&recent, "new").unwrap();
+        loaded.record(&recent, "save").unwrap();
+        assert!(loaded.get(&entry.id).is_none());
+        assert_eq!(loaded.list(None, 10).len(), 1);
+    }
 }
 
 // ─────────────────────────────────────────────────────────────────────────────
@@ -1766,6 +2792,31 @@ mod git {
         assert_eq!(result["isRepo"], true);
     }
 
+    #[tokio::test]
+    async fn file_tree_decorates_status_and_ignores() {
+        let (tmp, _s) = init_repo().await;
+        let files = ecp_services::file::FileService::new(tmp.path().to_path_buf());
+        std::fs::write(tmp.path().join(".gitignore"), "build/\n").unwrap();
+        std::fs::create_dir_all(tmp.path().join("build")).unwrap();
+        std::fs::write(tmp.path().join("build/out.o"), "o").unwrap();
+        std::fs::create_dir_all(tmp.path().join("src")).unwrap();
+        std::fs::write(tmp.path().join("src/new.rs"), "new").unwrap();
+        std::fs::write(tmp.path().join("README.md"), "# changed").unwrap();
+
+        let tree = files.handle("file/tree", None).await.unwrap();
+        let entries = tree["entries"].as_array().unwrap();
+        let entry = |name: &str| entries.iter().find(|e| e["name"] == name).cloned();
+        assert!(entry(".git").is_none());
+        assert!(entry("build").is_none());
+        assert_eq!(entry("README.md").unwrap()["gitStatus"], "modified");
+        assert_eq!(entry("src").unwrap()["gitStatus"], "untracked");
+
+        let tree = files.handle("file/tree", Some(json!({"showIgnored": true, "depth": 3}))).await.unwrap();
+        let build = tree["entries"].as_array().unwrap().iter().find(|e| e["name"] == "build").unwrap().clone();
+        assert_eq!(build["ignored"], true);
+        assert!(build.get("children").is_none(), "ignored directories stay collapsed");
+    }
+
     #[tokio::test]
     async fn is_not_repo() {
         let tmp = TempDir::new().unwrap();
@@ -1960,6 +3011,125 @@ mod terminal {
         assert!(result["stdout"].as_str().unwrap().contains("subdir"));
     }
 
+    #[tokio::test]
+    async fn execute_with_env_stdin_and_shell() {
+        let tmp = TempDir::new().unwrap();
+        let s = TerminalService::new(tmp.path().to_path_buf());
+
+        let result = s.handle("terminal/execute", Some(json!({
+            "command": "echo $GREETING-${HOME:-unset}; cat",
+            "shell": "/bin/sh",
+            "env": {"GREETING": "hi", "HOME": null},
+            "stdin": "from-stdin",
+        }))).await.unwrap();
+        assert_eq!(result["stdout"], "hi-unset\nfrom-stdin");
+        assert_eq!(result["timedOut"], false);
+        assert_eq!(result["truncated"], false);
+    }
+
+    #[tokio::test]
+    async fn execute_timeout_kills_the_process_group() {
+        let tmp = TempDir::new().unwrap();
+        let s = TerminalService::new(tmp.path().to_path_buf());
+        let pid_file = tmp.path().join("pid");
+
+        let started = std::time::Instant::now();
+        let result = s.handle("terminal/execute", Some(json!({
+            "command": format!("sleep 30 & echo $! > {}; echo begun; wait", pid_file.display()),
+            "timeout": 300,
+        }))).await.unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert_eq!(result["timedOut"], true);
+        assert_eq!(result["exitCode"], serde_json::Value::Null);
+        assert!(result["signal"].is_string());
+        assert_eq!(result["stdout"], "begun\n");
+
+        // The backgrounded sleep went down with the shell
+        let pid = std::fs::read_to_string(&pid_file).unwrap().trim().to_string();
+        let mut alive = true;
+        for _ in 0..20 {
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+            alive = !stat.is_empty() && !stat.contains(") Z ");
+            if !alive {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(!alive);
+    }
+
+    #[tokio::test]
+    async fn execute_timeout_applies_after_output_closes() {
+        let tmp = TempDir::new().unwrap();
+        let s = TerminalService::new(tmp.path().to_path_buf());
+
+        let started = std::time::Instant::now();
+        let result = s.handle("terminal/execute", Some(json!({
+            "command": "sleep 600 >/dev/null 2>&1",
+            "timeout": 1000,
+        }))).await.unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert_eq!(result["timedOut"], true);
+        assert!(result["signal"].is_string());
+    }
+
+    #[tokio::test]
+    async fn execute_caps_output_with_a_marker() {
+        let tmp = TempDir::new().unwrap();
+        let s = TerminalService::new(tmp.path().to_path_buf());
+
+        let result = s.handle("terminal/execute", Some(json!({
+            "command": "head -c 5000 /dev/zero | tr '\\0' x; printf end",
+            "maxOutputBytes": 100,
+        }))).await.unwrap();
+        assert_eq!(result["truncated"], true);
+        let stdout = result["stdout"].as_str().unwrap();
+        assert!(stdout.starts_with(&format!("{}\n[... 4903 bytes truncated ...]\n", "x".repeat(50))), "{stdout}");
+        assert!(stdout.ends_with("xxxend"), "{stdout}");
+        assert_eq!(stdout.len(), 100 + "\n[... 4903 bytes truncated ...]\n".len());
+    }
+
+    #[tokio::test]
+    async fn execute_async_streams_output_and_can_be_killed() {
+        let tmp = TempDir::new().unwrap();
+        let (s, mut rx) = notifying_service(&tmp);
+
+        let result = s.handle("terminal/execute", Some(json!({
+            "command": "echo started; echo oops >&2; sleep 30",
+            "async": true,
+            "_clientId": "client-1",
+        }))).await.unwrap();
+        let id = result["executionId"].as_str().unwrap();
+        assert!(id.starts_with("exec-"));
+
+        let mut streams = Vec::new();
+        while streams.len() < 2 {
+            let (method, params) = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await
+                .expect("no output").unwrap();
+            assert_eq!(method, "terminal/execute/output");
+            assert_eq!(params["executionId"], id);
+            assert_eq!(params["_clients"], json!(["client-1"]));
+            streams.push((params["stream"].as_str().unwrap().to_string(), params["data"].as_str().unwrap().to_string()));
+        }
+        streams.sort();
+        assert_eq!(streams, [("stderr".into(), "oops\n".into()), ("stdout".into(), "started\n".into())]);
+
+        // Only the client that started it can kill it
+        let err = s.handle("terminal/execute/kill", Some(json!({
+            "executionId": id, "_clientId": "client-2",
+        }))).await.unwrap_err();
+        assert!(err.message.contains("owned by another client"));
+        s.handle("terminal/execute/kill", Some(json!({"executionId": id, "_clientId": "client-1"}))).await.unwrap();
+        let (method, params) = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await
+            .expect("never exited").unwrap();
+        assert_eq!(method, "terminal/execute/exit");
+        assert_eq!(params["killed"], true);
+        assert_eq!(params["timedOut"], false);
+
+        let err = s.handle("terminal/execute/kill", Some(json!({"executionId": id}))).await.unwrap_err();
+        assert!(err.message.contains("Execution not found"));
+    }
+
     #[tokio::test]
     async fn create_and_list_terminal() {
         let tmp = TempDir::new().unwrap();
@@ -2060,6 +3230,474 @@ mod terminal {
         assert!(result["buffer"]["lines"].as_array().is_some());
     }
 
+    #[tokio::test]
+    async fn limits_cap_scrollback() {
+        use ecp_services::terminal::TerminalLimits;
+        use std::sync::Arc;
+
+        let tmp = TempDir::new().unwrap();
+        let mut s = TerminalService::new(tmp.path().to_path_buf());
+        let limits = Arc::new(parking_lot::RwLock::new(TerminalLimits {
+            scrollback_lines: 10,
+            ..TerminalLimits::default()
+        }));
+        s.set_limits(limits);
+
+        let spawn = s.handle("terminal/spawn", Some(json!({"command": "seq 1 100"}))).await.unwrap();
+        let id = spawn["terminalId"].as_str().unwrap();
+
+        // Only the newest rows are kept: 10 of scrollback and a screenful
+        let mut lines = Vec::new();
+        for _ in 0..50 {
+            let result = s.handle("terminal/getBuffer", Some(json!({"id": id}))).await.unwrap();
+            lines = result["buffer"]["lines"].as_array().unwrap().clone();
+            if lines.iter().any(|l| l == "100") {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(lines.len(), 10 + 24);
+        assert_eq!(lines[0], "68");
+        assert_eq!(lines[32], "100");
+    }
+
+    #[tokio::test]
+    async fn get_buffer_renders_screen_cells() {
+        let tmp = TempDir::new().unwrap();
+        let (s, mut rx) = notifying_service(&tmp);
+        let spawn = s.handle("terminal/spawn", Some(json!({
+            "command": "printf '\\033[1;31mred\\033[0m \\033[38;2;0;128;255mblue\\033[0m\\nnext'",
+        }))).await.unwrap();
+        let id = spawn["terminalId"].as_str().unwrap();
+        until_exit(&mut rx).await;
+
+        let text = s.handle("terminal/getBuffer", Some(json!({"id": id}))).await.unwrap();
+        assert_eq!(text["buffer"]["lines"], json!(["red blue", "next"]));
+        assert_eq!(text["buffer"]["cursorRow"], 1);
+        assert_eq!(text["buffer"]["cursorCol"], 4);
+
+        let screen = s.handle("terminal/getBuffer", Some(json!({"id": id, "format": "screen"}))).await.unwrap();
+        let screen = &screen["screen"];
+        assert_eq!((screen["rows"].as_u64(), screen["cols"].as_u64()), (Some(24), Some(80)));
+        assert_eq!(screen["lines"].as_array().unwrap().len(), 24);
+        let cells = &screen["lines"][0]["cells"];
+        assert_eq!(cells.as_array().unwrap().len(), 80);
+        assert_eq!(cells[0], json!({"text": "r", "fg": 1, "bold": true}));
+        assert_eq!(cells[3], json!({"text": " "}));
+        assert_eq!(cells[4], json!({"text": "b", "fg": "#0080ff"}));
+        assert_eq!(screen["lines"][1]["cells"][0]["text"], "n");
+        assert_eq!((screen["cursorRow"].as_u64(), screen["cursorCol"].as_u64()), (Some(1), Some(4)));
+        assert_eq!(screen["alternateScreen"], false);
+    }
+
+    /// Buffer text once it contains `needle`.
+    async fn wait_for_output(s: &TerminalService, id: &str, needle: &str) -> String {
+        for _ in 0..50 {
+            let result = s.handle("terminal/getBuffer", Some(json!({"id": id}))).await.unwrap();
+            let lines: Vec<&str> = result["buffer"]["lines"].as_array().unwrap()
+                .iter().filter_map(|l| l.as_str()).collect();
+            let text = lines.join("\n");
+            if text.contains(needle) {
+                return text;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("terminal output never contained {needle:?}");
+    }
+
+    #[tokio::test]
+    async fn spawn_streams_stdout_and_stderr_while_running() {
+        use ecp_services::ResourceKind;
+
+        let tmp = TempDir::new().unwrap();
+        let s = TerminalService::new(tmp.path().to_path_buf());
+        let spawn = s.handle("terminal/spawn", Some(json!({
+            "command": "echo early; echo oops >&2; sleep 3", "_clientId": "a",
+        }))).await.unwrap();
+        let id = spawn["terminalId"].as_str().unwrap();
+
+        // Both streams show up long before the command ends
+        let text = wait_for_output(&s, id, "oops").await;
+        assert!(text.contains("early"), "{text}");
+        let running = s.handle("terminal/isRunning", Some(json!({"id": id}))).await.unwrap();
+        assert_eq!(running["running"], true);
+
+        // The spawning client is charged for the terminal
+        let held = s.held_resources(ResourceKind::Terminal).await;
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].owner.as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn create_runs_on_a_pty_and_resizes() {
+        let tmp = TempDir::new().unwrap();
+        let s = TerminalService::new(tmp.path().to_path_buf());
+
+        let create = s.handle("terminal/create", Some(json!({
+            "shell": "/bin/sh", "cols": 90, "rows": 20,
+        }))).await.unwrap();
+        let id = create["terminalId"].as_str().unwrap();
+        assert!(create["pid"].as_u64().is_some());
+
+        s.handle("terminal/write", Some(json!({
+            "id": id, "data": "test -t 0 && echo on-a-$TERM; stty size; echo std''err >&2\n",
+        }))).await.unwrap();
+        let text = wait_for_output(&s, id, "20 90").await;
+        assert!(text.contains("on-a-xterm-256color"), "{text}");
+        // Input is echoed, so look for the expanded text
+        wait_for_output(&s, id, "stderr").await;
+
+        s.handle("terminal/resize", Some(json!({"id": id, "cols": 120, "rows": 40}))).await.unwrap();
+        s.handle("terminal/write", Some(json!({"id": id, "data": "stty size\n"}))).await.unwrap();
+        wait_for_output(&s, id, "40 120").await;
+
+        s.handle("terminal/write", Some(json!({"id": id, "data": "exit\n"}))).await.unwrap();
+        let mut running = true;
+        for _ in 0..50 {
+            let result = s.handle("terminal/isRunning", Some(json!({"id": id}))).await.unwrap();
+            running = result["running"].as_bool().unwrap();
+            if !running {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(!running);
+    }
+
+    type Received = tokio::sync::mpsc::UnboundedReceiver<(String, serde_json::Value)>;
+
+    fn notifying_service(tmp: &TempDir) -> (TerminalService, Received) {
+        let s = TerminalService::new(tmp.path().to_path_buf());
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        s.set_notify_sender(std::sync::Arc::new(move |method: &str, params: serde_json::Value| {
+            let _ = tx.send((method.to_string(), params));
+        }));
+        (s, rx)
+    }
+
+    /// Notifications up to and including `terminal/exit`.
+    async fn until_exit(rx: &mut Received) -> Vec<(String, serde_json::Value)> {
+        let mut received = Vec::new();
+        loop {
+            let next = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await
+                .expect("terminal never exited").unwrap();
+            let done = next.0 == "terminal/exit";
+            received.push(next);
+            if done {
+                return received;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_output_titles_and_exit() {
+        let tmp = TempDir::new().unwrap();
+        let (s, mut rx) = notifying_service(&tmp);
+        let create = s.handle("terminal/create", Some(json!({"shell": "/bin/sh"}))).await.unwrap();
+        let id = create["terminalId"].as_str().unwrap();
+
+        s.handle("terminal/write", Some(json!({
+            "id": id, "data": "printf '\\033]0;my-title\\007'; echo out-$((1+1)); exit 3\n",
+        }))).await.unwrap();
+        let received = until_exit(&mut rx).await;
+
+        let output: Vec<&serde_json::Value> = received.iter()
+            .filter(|(method, _)| method == "terminal/output")
+            .map(|(_, params)| params)
+            .collect();
+        let seqs: Vec<u64> = output.iter().map(|p| p["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, (0..seqs.len() as u64).collect::<Vec<_>>());
+        assert!(output.iter().all(|p| p["terminalId"] == id && p["encoding"] == "utf8"));
+        let text: String = output.iter().map(|p| p["data"].as_str().unwrap()).collect();
+        assert!(text.contains("out-2"), "{text}");
+
+        let title = received.iter().find(|(method, _)| method == "terminal/title").unwrap();
+        assert_eq!(title.1, json!({"terminalId": id, "title": "my-title"}));
+        let exit = &received.last().unwrap().1;
+        assert_eq!(exit["exitCode"], 3);
+        assert_eq!(exit["signal"], serde_json::Value::Null);
+
+        let buffer = s.handle("terminal/getBuffer", Some(json!({"id": id}))).await.unwrap();
+        assert_eq!(buffer["seq"], seqs.len());
+    }
+
+    #[tokio::test]
+    async fn exit_is_reported_while_background_jobs_hold_the_terminal() {
+        let tmp = TempDir::new().unwrap();
+        let (s, mut rx) = notifying_service(&tmp);
+        let create = s.handle("terminal/create", Some(json!({"shell": "/bin/sh"}))).await.unwrap();
+        let id = create["terminalId"].as_str().unwrap();
+
+        s.handle("terminal/write", Some(json!({
+            "id": id, "data": "sleep 30 & echo bye; exit 4\n",
+        }))).await.unwrap();
+        let received = until_exit(&mut rx).await;
+        let text: String = received.iter()
+            .filter(|(method, _)| method == "terminal/output")
+            .map(|(_, params)| params["data"].as_str().unwrap())
+            .collect();
+        assert!(text.contains("bye"), "{text}");
+        assert_eq!(received.last().unwrap().1["exitCode"], 4);
+        s.handle("terminal/close", Some(json!({"id": id}))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn streams_raw_bytes_as_base64() {
+        let tmp = TempDir::new().unwrap();
+        let (s, mut rx) = notifying_service(&tmp);
+        s.handle("terminal/spawn", Some(json!({
+            "command": "printf 'hi\\377'", "encoding": "base64",
+        }))).await.unwrap();
+
+        let received = until_exit(&mut rx).await;
+        assert_eq!(received[0].0, "terminal/output");
+        assert_eq!(received[0].1["encoding"], "base64");
+        assert_eq!(received[0].1["data"], "aGn/");
+        assert_eq!(received[1].1["exitCode"], 0);
+    }
+
+    #[tokio::test]
+    async fn detached_terminals_keep_running_for_the_next_instance() {
+        use ecp_services::terminal::SharedTerminalSessions;
+
+        let tmp = TempDir::new().unwrap();
+        let store = SharedTerminalSessions::default();
+        let mut a = TerminalService::new(tmp.path().to_path_buf());
+        a.set_sessions(store.clone());
+
+        let create = a.handle("terminal/create", Some(json!({
+            "shell": "/bin/sh", "_clientId": "client-1",
+        }))).await.unwrap();
+        let id = create["terminalId"].as_str().unwrap().to_string();
+        let list = a.handle("terminal/list", None).await.unwrap();
+        assert_eq!(list["terminals"][0]["clients"], json!(["client-1"]));
+        assert_eq!(list["terminals"][0]["detached"], false);
+
+        // The client goes and the workspace closes; the terminal carries on
+        a.client_disconnected("client-1");
+        let list = a.handle("terminal/list", None).await.unwrap();
+        assert_eq!(list["terminals"][0]["detached"], true);
+        assert!(list["terminals"][0]["lastActivity"].as_u64().unwrap() > 0);
+        a.shutdown().await;
+        drop(a);
+
+        let mut b = TerminalService::new(tmp.path().to_path_buf());
+        b.set_sessions(store);
+        let list = b.handle("terminal/list", None).await.unwrap();
+        assert_eq!(list["terminals"][0]["id"], id.as_str());
+        assert_eq!(list["terminals"][0]["running"], true);
+        b.handle("terminal/write", Some(json!({"id": id, "data": "echo back-$((1+1))\n"}))).await.unwrap();
+        wait_for_output(&b, &id, "back-2").await;
+
+        let attach = b.handle("terminal/attach", Some(json!({
+            "id": id, "_clientId": "client-2",
+        }))).await.unwrap();
+        assert_eq!(attach["terminalId"], id.as_str());
+        assert!(attach["buffer"]["lines"].as_array().is_some());
+        assert!(attach["seq"].as_u64().is_some());
+        let list = b.handle("terminal/list", None).await.unwrap();
+        assert_eq!(list["terminals"][0]["clients"], json!(["client-2"]));
+
+        b.handle("terminal/detach", Some(json!({"id": id, "_clientId": "client-2"}))).await.unwrap();
+        let list = b.handle("terminal/list", None).await.unwrap();
+        assert_eq!(list["terminals"][0]["detached"], true);
+        b.handle("terminal/close", Some(json!({"id": id}))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn exited_detached_terminals_are_dropped() {
+        use ecp_services::terminal::SharedTerminalSessions;
+
+        let tmp = TempDir::new().unwrap();
+        let store = SharedTerminalSessions::default();
+        let mut s = TerminalService::new(tmp.path().to_path_buf());
+        s.set_sessions(store.clone());
+        let mut ids = Vec::new();
+        for client in [json!("client-1"), json!(null)] {
+            let create = s.handle("terminal/create", Some(json!({
+                "shell": "/bin/sh", "_clientId": client,
+            }))).await.unwrap();
+            let id = create["terminalId"].as_str().unwrap().to_string();
+            s.handle("terminal/write", Some(json!({"id": id, "data": "exit\n"}))).await.unwrap();
+            ids.push(id);
+        }
+        let running = s.handle("terminal/create", Some(json!({"shell": "/bin/sh"}))).await.unwrap();
+        let running = running["terminalId"].as_str().unwrap().to_string();
+        for id in &ids {
+            for _ in 0..50 {
+                let result = s.handle("terminal/isRunning", Some(json!({"id": id}))).await.unwrap();
+                if result["running"] == false {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+
+        // Kept while within the grace period, attached or still running
+        store.reap_exited(std::time::Duration::from_secs(60));
+        assert_eq!(s.handle("terminal/list", None).await.unwrap()["terminals"].as_array().unwrap().len(), 3);
+        store.reap_exited(std::time::Duration::ZERO);
+        let list = s.handle("terminal/list", None).await.unwrap();
+        let mut listed: Vec<&str> = list["terminals"].as_array().unwrap().iter()
+            .map(|t| t["id"].as_str().unwrap())
+            .collect();
+        listed.sort();
+        let mut expected = vec![ids[0].as_str(), running.as_str()];
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        // Detaching the last client starts the grace period over
+        s.client_disconnected("client-1");
+        store.reap_exited(std::time::Duration::from_secs(60));
+        let exists = s.handle("terminal/exists", Some(json!({"id": ids[0]}))).await.unwrap();
+        assert_eq!(exists["exists"], true);
+        store.reap_exited(std::time::Duration::ZERO);
+        let list = s.handle("terminal/list", None).await.unwrap();
+        assert_eq!(list["terminals"][0]["id"], running.as_str());
+        assert_eq!(list["terminals"].as_array().unwrap().len(), 1);
+        s.handle("terminal/close", Some(json!({"id": running}))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn supervised_terminals_survive_a_restart() {
+        use ecp_services::terminal_supervisor::{self, SupervisorClient};
+        use std::sync::Arc;
+
+        let tmp = TempDir::new().unwrap();
+        let socket = tmp.path().join("terminals.sock");
+        let serve_socket = socket.clone();
+        tokio::spawn(async move { terminal_supervisor::serve(&serve_socket).await });
+        for _ in 0..50 {
+            if socket.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        let supervisor = Arc::new(SupervisorClient::new(socket));
+
+        let mut a = TerminalService::new(tmp.path().to_path_buf());
+        a.set_supervisor(supervisor.clone());
+        a.init().await.unwrap();
+        let create = a.handle("terminal/create", Some(json!({"shell": "/bin/sh", "name": "build"}))).await.unwrap();
+        let id = create["terminalId"].as_str().unwrap().to_string();
+        assert!(create["pid"].as_u64().is_some());
+        a.handle("terminal/write", Some(json!({"id": id, "data": "echo before-$((1+1))\n"}))).await.unwrap();
+        wait_for_output(&a, &id, "before-2").await;
+
+        // The server goes away without hanging up
+        a.shutdown().await;
+        drop(a);
+
+        let mut b = TerminalService::new(tmp.path().to_path_buf());
+        b.set_supervisor(supervisor.clone());
+        b.init().await.unwrap();
+        let list = b.handle("terminal/list", None).await.unwrap();
+        let terminal = &list["terminals"][0];
+        assert_eq!(terminal["id"], id.as_str());
+        assert_eq!(terminal["name"], "build");
+        assert_eq!(terminal["supervised"], true);
+        assert_eq!(terminal["detached"], true);
+        assert_eq!(terminal["running"], true);
+
+        // Scrollback came across, and the shell still answers
+        wait_for_output(&b, &id, "before-2").await;
+        b.handle("terminal/write", Some(json!({"id": id, "data": "echo after-$((2+2))\n"}))).await.unwrap();
+        wait_for_output(&b, &id, "after-4").await;
+
+        b.handle("terminal/close", Some(json!({"id": id}))).await.unwrap();
+        let workspace = tmp.path().to_string_lossy().to_string();
+        let mut hosted = supervisor.list(&workspace).await;
+        for _ in 0..50 {
+            if hosted.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            hosted = supervisor.list(&workspace).await;
+        }
+        assert!(hosted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tracks_shell_integration_marks() {
+        let tmp = TempDir::new().unwrap();
+        let (s, mut rx) = notifying_service(&tmp);
+        let spawn = s.handle("terminal/spawn", Some(json!({
+            "command": r"printf '\033]7;file://host/tmp/a%%20b\007\033]133;A\007$ ls -l\n\033]133;C;cmdline_url=ls%%20-l\007one\ntwo\n\033]133;D;3\007\033]133;A\007$ '",
+        }))).await.unwrap();
+        let id = spawn["terminalId"].as_str().unwrap();
+
+        let received = until_exit(&mut rx).await;
+        let finished: Vec<_> = received.iter().filter(|(m, _)| m == "terminal/commandFinished").collect();
+        assert_eq!(finished.len(), 1, "{received:?}");
+        let command = &finished[0].1["command"];
+        assert_eq!(finished[0].1["terminalId"], id);
+        assert_eq!(command["command"], "ls -l");
+        assert_eq!(command["exitCode"], 3);
+        assert_eq!(command["cwd"], "/tmp/a b");
+        assert_eq!(command["output"], json!({"start": 1, "end": 3}));
+
+        let result = s.handle("terminal/commands", Some(json!({"id": id, "output": true}))).await.unwrap();
+        assert_eq!(result["cwd"], "/tmp/a b");
+        let commands = result["commands"].as_array().unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0]["outputText"], "one\ntwo");
+
+        let info = s.handle("terminal/getInfo", Some(json!({"id": id}))).await.unwrap();
+        assert_eq!(info["info"]["cwd"], "/tmp/a b");
+    }
+
+    #[test]
+    fn line_numbers_count_wrapped_rows_past_a_full_scrollback() {
+        use ecp_services::terminal_screen::TerminalScreen;
+
+        let mut screen = TerminalScreen::new(3, 10, 4);
+        // Wrapping scrolls as much as line feeds do, long after the ring fills
+        let mut output = "x".repeat(95).into_bytes();
+        // A full-screen program comes and goes in the same output
+        output.extend_from_slice(b"\r\n\x1b[?1049hvim\r\n\r\n\r\n\x1b[?1049l");
+        output.extend_from_slice(b"\x1b]133;C\x07first\r\nsecond\r\n\x1b]133;D;0\x07");
+        let events = screen.process(&output);
+
+        assert_eq!(events.finished.len(), 1);
+        let range = &events.finished[0].output;
+        // 95 columns make 10 rows; the prompt went to the next row
+        assert_eq!((range.start, range.end), (10, Some(12)));
+        let lines = screen.all_lines();
+        assert_eq!(lines.range(range.start, range.end.unwrap()).unwrap(), ["first", "second"]);
+        // Four rows of scrollback above the screen's lines 10-12
+        assert_eq!(lines.first, 6);
+    }
+
+    #[tokio::test]
+    async fn bash_reports_commands_and_cwd() {
+        let tmp = TempDir::new().unwrap();
+        let (s, mut rx) = notifying_service(&tmp);
+        let create = s.handle("terminal/create", Some(json!({"shell": "/bin/bash"}))).await.unwrap();
+        let id = create["terminalId"].as_str().unwrap();
+        assert_eq!(create["shellIntegration"], true);
+
+        s.handle("terminal/write", Some(json!({"id": id, "data": "cd /tmp\necho hi; false\n"}))).await.unwrap();
+        let finished = loop {
+            let (method, params) = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await
+                .expect("command never finished").unwrap();
+            if method == "terminal/commandFinished" && params["command"]["exitCode"] == 1 {
+                break params["command"].clone();
+            }
+        };
+        assert_eq!(finished["command"], "echo hi; false");
+        assert_eq!(finished["cwd"], "/tmp");
+
+        let result = s.handle("terminal/commands", Some(json!({"id": id, "limit": 1, "output": true}))).await.unwrap();
+        let commands = result["commands"].as_array().unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0]["command"], "echo hi; false");
+        assert_eq!(commands[0]["outputText"], "hi");
+
+        let info = s.handle("terminal/getInfo", Some(json!({"id": id}))).await.unwrap();
+        assert_eq!(info["info"]["cwd"], "/tmp");
+        s.handle("terminal/close", Some(json!({"id": id}))).await.unwrap();
+    }
+
     #[tokio::test]
     async fn unknown_method() {
         let tmp = TempDir::new().unwrap();
@@ -2425,6 +4063,244 @@ mod watch {
         let list = s.handle("watch/list", None).await.unwrap();
         assert_eq!(list["watches"].as_array().unwrap().len(), 0);
     }
+
+    type Received = tokio::sync::mpsc::UnboundedReceiver<(String, serde_json::Value)>;
+
+    fn watch_service(tmp: &TempDir) -> (WatchService, Received) {
+        let s = WatchService::new(tmp.path().to_path_buf());
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        s.set_notify_sender(std::sync::Arc::new(move |method: &str, params: serde_json::Value| {
+            let _ = tx.send((method.to_string(), params));
+        }));
+        s.set_debounce(std::time::Duration::from_millis(300));
+        (s, rx)
+    }
+
+    async fn next(rx: &mut Received) -> (String, serde_json::Value) {
+        tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await
+            .expect("no notification").unwrap()
+    }
+
+    #[tokio::test]
+    async fn batches_coalesced_filtered_changes() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        std::fs::create_dir(root.join("target")).unwrap();
+        std::fs::write(root.join("old.txt"), "o").unwrap();
+        let (s, mut rx) = watch_service(&tmp);
+        s.handle("file/watch", Some(json!({
+            "path": ".", "batch": true, "excludes": ["*.log"],
+        }))).await.unwrap();
+
+        std::fs::write(root.join("a.txt"), "1").unwrap();
+        std::fs::write(root.join("a.txt"), "2").unwrap();
+        std::fs::write(root.join("scratch"), "x").unwrap();
+        std::fs::remove_file(root.join("scratch")).unwrap();
+        std::fs::write(root.join("debug.log"), "x").unwrap();
+        std::fs::write(root.join("target/out.o"), "x").unwrap();
+        std::fs::rename(root.join("old.txt"), root.join("new.txt")).unwrap();
+
+        let (method, params) = next(&mut rx).await;
+        assert_eq!(method, "file/didChangeBatch");
+        let uri = |name: &str| format!("file://{}", root.join(name).display());
+        let changes = params["changes"].as_array().unwrap();
+        assert_eq!(changes.len(), 2, "{changes:?}");
+        assert_eq!(changes[0], json!({"type": "created", "uri": uri("a.txt")}));
+        assert_eq!(changes[1], json!({"type": "renamed", "uri": uri("new.txt"), "oldUri": uri("old.txt")}));
+    }
+
+    #[tokio::test]
+    async fn individual_notifications_report_renames() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(tmp.path().join("from.txt"), "x").unwrap();
+        std::fs::write(tmp.path().join("edit.txt"), "x").unwrap();
+        let (s, mut rx) = watch_service(&tmp);
+        s.handle("file/watch", Some(json!({"path": "."}))).await.unwrap();
+
+        std::fs::rename(tmp.path().join("from.txt"), tmp.path().join("to.txt")).unwrap();
+        std::fs::write(tmp.path().join("edit.txt"), "y").unwrap();
+
+        let (method, params) = next(&mut rx).await;
+        assert_eq!(method, "file/didRename");
+        assert!(params["oldUri"].as_str().unwrap().ends_with("from.txt"));
+        assert!(params["newUri"].as_str().unwrap().ends_with("to.txt"));
+        let (method, params) = next(&mut rx).await;
+        assert_eq!(method, "file/didChange");
+        assert!(params["uri"].as_str().unwrap().ends_with("edit.txt"));
+    }
+
+    #[tokio::test]
+    async fn watches_are_owned_and_routed_per_client() {
+        let tmp = TempDir::new().unwrap();
+        let (s, mut rx) = watch_service(&tmp);
+        let a = s.handle("file/watch", Some(json!({"path": ".", "_clientId": "a"}))).await.unwrap();
+        let a_again = s.handle("file/watch", Some(json!({"path": ".", "_clientId": "a"}))).await.unwrap();
+        assert_eq!(a["watchId"], a_again["watchId"]);
+        let b = s.handle("file/watch", Some(json!({"path": ".", "_clientId": "b"}))).await.unwrap();
+
+        // Only the owner may remove a watch; one unwatch drops one reference
+        let result = s.handle("file/unwatch", Some(json!({"watchId": a["watchId"], "_clientId": "b"}))).await.unwrap();
+        assert_eq!(result["success"], false);
+        s.handle("file/unwatch", Some(json!({"watchId": a["watchId"], "_clientId": "a"}))).await.unwrap();
+        let list = s.handle("watch/list", None).await.unwrap();
+        assert_eq!(list["watches"].as_array().unwrap().len(), 2);
+        s.handle("file/unwatch", Some(json!({"watchId": a["watchId"], "_clientId": "a"}))).await.unwrap();
+
+        std::fs::write(tmp.path().join("new.txt"), "x").unwrap();
+        let (method, params) = next(&mut rx).await;
+        assert_eq!(method, "file/didCreate");
+        assert_eq!(params["_clients"], json!(["b"]));
+
+        s.client_disconnected("b");
+        let list = s.handle("watch/list", None).await.unwrap();
+        assert!(list["watches"].as_array().unwrap().is_empty());
+        let result = s.handle("file/unwatch", Some(json!({"watchId": b["watchId"], "_clientId": "b"}))).await.unwrap();
+        assert_eq!(result["success"], false);
+    }
+
+    #[tokio::test]
+    async fn glob_watch_sees_only_matching_files() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::create_dir_all(tmp.path().join("src/nested")).unwrap();
+        let (s, mut rx) = watch_service(&tmp);
+        let result = s.handle("file/watch", Some(json!({
+            "path": "src/**/*.rs", "batch": true, "_clientId": "a",
+        }))).await.unwrap();
+        assert!(result["path"].as_str().unwrap().ends_with("src"));
+
+        std::fs::write(tmp.path().join("src/nested/lib.rs"), "x").unwrap();
+        std::fs::write(tmp.path().join("src/notes.md"), "x").unwrap();
+        std::fs::write(tmp.path().join("top.rs"), "x").unwrap();
+
+        let (method, params) = next(&mut rx).await;
+        assert_eq!(method, "file/didChangeBatch");
+        assert_eq!(params["_clients"], json!(["a"]));
+        let changes = params["changes"].as_array().unwrap();
+        assert_eq!(changes.len(), 1, "{changes:?}");
+        assert!(changes[0]["uri"].as_str().unwrap().ends_with("src/nested/lib.rs"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn root_watch_leaves_out_skipped_and_ignored_trees() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        std::fs::create_dir_all(root.join("node_modules/pkg")).unwrap();
+        std::fs::create_dir(root.join("target")).unwrap();
+        let (s, mut rx) = watch_service(&tmp);
+        let (tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+        s.add_event_listener(std::sync::Arc::new(move |event: &notify::Event| {
+            for path in &event.paths {
+                let _ = tx.send(path.clone());
+            }
+        }));
+        s.init().await.unwrap();
+        // A client watching an ignored directory still hears from it
+        s.handle("file/watch", Some(json!({"path": "target", "_clientId": "a"}))).await.unwrap();
+
+        std::fs::write(root.join("node_modules/pkg/index.js"), "x").unwrap();
+        std::fs::create_dir(root.join("src")).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        std::fs::write(root.join("src/lib.rs"), "x").unwrap();
+        std::fs::write(root.join("target/out.o"), "x").unwrap();
+
+        // New directories are watched as they appear
+        loop {
+            let path = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv()).await
+                .expect("no event from the new directory").unwrap();
+            assert!(!path.starts_with(root.join("node_modules/pkg")), "{}", path.display());
+            if path == root.join("src/lib.rs") {
+                break;
+            }
+        }
+        let (method, params) = next(&mut rx).await;
+        assert_eq!(method, "file/didCreate");
+        assert!(params["uri"].as_str().unwrap().ends_with("target/out.o"));
+        s.shutdown().await;
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn root_watch_follows_ignore_file_changes() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        std::fs::create_dir(root.join("gen")).unwrap();
+        let (s, _rx) = watch_service(&tmp);
+        let (tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+        s.add_event_listener(std::sync::Arc::new(move |event: &notify::Event| {
+            for path in &event.paths {
+                let _ = tx.send(path.clone());
+            }
+        }));
+        s.init().await.unwrap();
+        let mut heard_until = async |marker: &str| {
+            let mut heard = Vec::new();
+            loop {
+                let path = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv()).await
+                    .expect("no event for the marker").unwrap();
+                if path == root.join(marker) {
+                    return heard;
+                }
+                heard.push(path);
+            }
+        };
+
+        // Newly ignored, the directory is unwatched
+        std::fs::write(root.join(".gitignore"), "gen/\n").unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        std::fs::write(root.join("gen/a.txt"), "x").unwrap();
+        std::fs::write(root.join("marker-1"), "x").unwrap();
+        let heard = heard_until("marker-1").await;
+        assert!(!heard.contains(&root.join("gen/a.txt")), "{heard:?}");
+
+        // And watched again once no longer ignored
+        std::fs::write(root.join(".gitignore"), "").unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        std::fs::write(root.join("gen/b.txt"), "x").unwrap();
+        std::fs::write(root.join("marker-2"), "x").unwrap();
+        let heard = heard_until("marker-2").await;
+        assert!(heard.contains(&root.join("gen/b.txt")), "{heard:?}");
+        s.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn polling_backend_reports_changes() {
+        use ecp_services::watch::{WatchBackend, WatchSettings};
+        use ecp_services::watch_poll::is_unreliable;
+        assert!(is_unreliable("nfs4") && is_unreliable("fuse.sshfs") && !is_unreliable("ext4"));
+
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(tmp.path().join("old.txt"), "x").unwrap();
+        std::fs::write(tmp.path().join("edit.txt"), "x").unwrap();
+        let (s, mut rx) = watch_service(&tmp);
+        s.set_settings(WatchSettings {
+            backend: WatchBackend::Poll,
+            poll_interval: std::time::Duration::from_millis(50),
+        });
+        s.handle("file/watch", Some(json!({"path": ".", "batch": true}))).await.unwrap();
+        let list = s.handle("watch/list", None).await.unwrap();
+        assert_eq!(list["watches"][0]["backend"], "poll");
+
+        std::fs::rename(tmp.path().join("old.txt"), tmp.path().join("new.txt")).unwrap();
+        std::fs::write(tmp.path().join("edit.txt"), "longer").unwrap();
+        std::fs::write(tmp.path().join("added.txt"), "x").unwrap();
+
+        // A poll may land mid-way, splitting the changes across batches
+        let mut changes = Vec::new();
+        while changes.len() < 3 {
+            let (method, params) = next(&mut rx).await;
+            assert_eq!(method, "file/didChangeBatch");
+            changes.extend(params["changes"].as_array().unwrap().iter().cloned());
+        }
+        let kind = |name: &str| changes.iter()
+            .find(|c| c["uri"].as_str().unwrap().ends_with(name))
+            .map(|c| c["type"].as_str().unwrap().to_string());
+        assert_eq!(kind("new.txt").as_deref(), Some("renamed"), "{changes:?}");
+        assert_eq!(kind("edit.txt").as_deref(), Some("changed"));
+        assert_eq!(kind("added.txt").as_deref(), Some("created"));
+    }
 }
 
 // ─────────────────────────────────────────────────────────────────────────────